@@ -0,0 +1,224 @@
+//! The derive macros of Hydroconf, re-exported by the `hydroconf` crate with
+//! the `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{
+    parse_macro_input, parse_quote, Data, DeriveInput, Expr, Field, Fields,
+    GenericArgument, LitStr, PathArguments, Type,
+};
+
+/// Derive `hydroconf::HydrateValidate`, checking the fields annotated with
+/// `#[hydro(...)]`:
+///
+/// * `non_empty`: the string, collection or `Option` must not be empty;
+/// * `range(min = 1, max = 65535)`: the value must be within the bounds,
+///   which are both optional and inclusive;
+/// * `nested`: the field must be valid too, its type deriving
+///   `HydrateValidate`.
+///
+/// The `Option` fields set to `None` are not checked by `range` and
+/// `nested`. Unless the struct is generic, Hydroconf runs the checks after
+/// deserializing the settings.
+#[proc_macro_derive(HydrateValidate, attributes(hydro))]
+pub fn derive_hydrate_validate(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(unsupported(input)),
+        },
+        _ => return Err(unsupported(input)),
+    };
+    let mut checks = Vec::new();
+    let mut generics = input.generics.clone();
+    for field in fields {
+        let (field_checks, nested) = field_checks(field)?;
+        checks.extend(field_checks);
+        // The nested fields of a generic type must be validated too.
+        if nested && !input.generics.params.is_empty() {
+            let ty = option_content(&field.ty).unwrap_or(&field.ty);
+            generics
+                .make_where_clause()
+                .predicates
+                .push(parse_quote!(#ty: ::hydroconf::HydrateValidate));
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    // The checks of a generic struct can't be registered for every type
+    // argument.
+    let registration = if input.generics.params.is_empty() {
+        quote! {
+            ::hydroconf::__private::inventory::submit! {
+                ::hydroconf::__private::Registration::new::<#name>()
+            }
+        }
+    } else {
+        quote!()
+    };
+    Ok(quote! {
+        impl #impl_generics ::hydroconf::HydrateValidate
+            for #name #ty_generics #where_clause
+        {
+            #[allow(unused_variables)]
+            fn validate_at(
+                &self,
+                prefix: &str,
+                errors: &mut ::std::vec::Vec<::std::string::String>,
+            ) {
+                #(#checks)*
+            }
+        }
+
+        #registration
+    })
+}
+
+fn unsupported(input: &DeriveInput) -> syn::Error {
+    syn::Error::new_spanned(
+        &input.ident,
+        "HydrateValidate can only be derived for structs with named fields",
+    )
+}
+
+/// The checks of the `#[hydro(...)]` attributes of `field`, and whether
+/// it's `nested`.
+fn field_checks(field: &Field) -> syn::Result<(Vec<TokenStream2>, bool)> {
+    let ident = field.ident.as_ref().expect("named field");
+    let key = ident.to_string().trim_start_matches("r#").to_string();
+    let optional = option_content(&field.ty).is_some();
+    let mut checks = Vec::new();
+    let mut is_nested = false;
+    for attr in field.attrs.iter().filter(|a| a.path().is_ident("hydro")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("non_empty") {
+                let message = LitStr::new(
+                    &format!("{{}}{} must not be empty", key),
+                    ident.span(),
+                );
+                checks.push(quote! {
+                    if ::hydroconf::__private::IsEmpty::is_empty_value(
+                        &self.#ident,
+                    ) {
+                        errors.push(::std::format!(#message, prefix));
+                    }
+                });
+                Ok(())
+            } else if meta.path.is_ident("range") {
+                let mut min = None;
+                let mut max = None;
+                meta.parse_nested_meta(|bound| {
+                    if bound.path.is_ident("min") {
+                        min = Some(bound.value()?.parse::<Expr>()?);
+                    } else if bound.path.is_ident("max") {
+                        max = Some(bound.value()?.parse::<Expr>()?);
+                    } else {
+                        return Err(bound.error("expected `min` or `max`"));
+                    }
+                    Ok(())
+                })?;
+                let (condition, expected) = match (min, max) {
+                    (Some(min), Some(max)) => (
+                        quote!(*value >= #min && *value <= #max),
+                        quote!(::std::format!("between {} and {}", #min, #max)),
+                    ),
+                    (Some(min), None) => (
+                        quote!(*value >= #min),
+                        quote!(::std::format!("at least {}", #min)),
+                    ),
+                    (None, Some(max)) => (
+                        quote!(*value <= #max),
+                        quote!(::std::format!("at most {}", #max)),
+                    ),
+                    (None, None) => {
+                        return Err(meta.error("expected `min` or `max`"))
+                    }
+                };
+                let message = LitStr::new(
+                    &format!("{{}}{} must be {{}}", key),
+                    ident.span(),
+                );
+                checks.push(for_value(
+                    ident,
+                    optional,
+                    quote! {
+                        if !(#condition) {
+                            errors.push(::std::format!(#message, prefix, #expected));
+                        }
+                    },
+                ));
+                Ok(())
+            } else if meta.path.is_ident("nested") {
+                is_nested = true;
+                let nested =
+                    LitStr::new(&format!("{{}}{}.", key), ident.span());
+                checks.push(for_value(
+                    ident,
+                    optional,
+                    quote! {
+                        ::hydroconf::HydrateValidate::validate_at(
+                            value,
+                            &::std::format!(#nested, prefix),
+                            errors,
+                        );
+                    },
+                ));
+                Ok(())
+            } else {
+                Err(meta.error("expected `non_empty`, `range` or `nested`"))
+            }
+        })?;
+    }
+    Ok((checks, is_nested))
+}
+
+/// Run `check` on `value`, the field or, if it's an `Option`, its content.
+fn for_value(
+    ident: &syn::Ident,
+    optional: bool,
+    check: TokenStream2,
+) -> TokenStream2 {
+    if optional {
+        quote! {
+            if let ::std::option::Option::Some(value) = &self.#ident {
+                #check
+            }
+        }
+    } else {
+        quote! {
+            {
+                let value = &self.#ident;
+                #check
+            }
+        }
+    }
+}
+
+/// The type `T` of `ty` if it's an `Option<T>`.
+fn option_content(ty: &Type) -> Option<&Type> {
+    let segment = match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            path.path.segments.last()?
+        }
+        _ => return None,
+    };
+    if segment.ident != "Option" {
+        return None;
+    }
+    match &segment.arguments {
+        PathArguments::AngleBracketed(args) => match args.args.first()? {
+            GenericArgument::Type(ty) => Some(ty),
+            _ => None,
+        },
+        _ => None,
+    }
+}
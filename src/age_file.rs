@@ -0,0 +1,134 @@
+//! Decryption of the settings files encrypted with age, such as
+//! `.secrets.toml.age`.
+
+use std::path::Path;
+
+use config::ConfigError;
+
+/// Whether a settings file is encrypted with age.
+pub fn is_encrypted(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "age")
+}
+
+/// Decrypt an age file, binary or armored, in memory. The identities are
+/// read from `identity_file` and from the `AGE_KEY_FOR_HYDRO` environment
+/// variable.
+#[cfg(feature = "age")]
+pub fn decrypt(
+    ciphertext: &[u8],
+    identity_file: Option<&Path>,
+) -> Result<String, ConfigError> {
+    use std::io::Read;
+
+    use age::armor::ArmoredReader;
+    use age::{Decryptor, Identity, IdentityFile};
+
+    let error = |message: String| {
+        ConfigError::Message(format!(
+            "could not decrypt with age: {}",
+            message
+        ))
+    };
+    let mut identities = Vec::new();
+    if let Some(path) = identity_file {
+        let file = IdentityFile::from_file(path.to_string_lossy().into())
+            .map_err(|e| error(format!("{}: {}", path.display(), e)))?;
+        identities
+            .extend(file.into_identities().map_err(|e| error(e.to_string()))?);
+    }
+    if let Ok(key) = std::env::var("AGE_KEY_FOR_HYDRO") {
+        let file = IdentityFile::from_buffer(key.as_bytes())
+            .map_err(|e| error(format!("AGE_KEY_FOR_HYDRO: {}", e)))?;
+        identities
+            .extend(file.into_identities().map_err(|e| error(e.to_string()))?);
+    }
+    if identities.is_empty() {
+        return Err(error(
+            "no identity, set AGE_KEY_FOR_HYDRO or AGE_IDENTITY_FOR_HYDRO"
+                .into(),
+        ));
+    }
+
+    let decryptor = Decryptor::new_buffered(ArmoredReader::new(ciphertext))
+        .map_err(|e| error(e.to_string()))?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref() as &dyn Identity))
+        .map_err(|e| error(e.to_string()))?;
+    let mut plaintext = String::new();
+    reader
+        .read_to_string(&mut plaintext)
+        .map_err(|e| error(e.to_string()))?;
+    Ok(plaintext)
+}
+
+#[cfg(not(feature = "age"))]
+pub fn decrypt(
+    _ciphertext: &[u8],
+    _identity_file: Option<&Path>,
+) -> Result<String, ConfigError> {
+    Err(ConfigError::Message(
+        "age files can't be decrypted: hydroconf was built without the \
+         `age` feature"
+            .into(),
+    ))
+}
+
+#[cfg(all(test, feature = "age"))]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use age::secrecy::ExposeSecret;
+    use age::x25519;
+
+    #[test]
+    fn test_decrypt() {
+        let identity = x25519::Identity::generate();
+        let ciphertext =
+            age::encrypt(&identity.to_public(), b"[default]\nport = 1\n")
+                .unwrap();
+        let dir = TempDir::new("age");
+        let key_file = dir.join("key.txt");
+        let key = identity.to_string();
+        std::fs::write(
+            &key_file,
+            format!("# created: now\n{}\n", key.expose_secret()),
+        )
+        .unwrap();
+
+        let text = decrypt(&ciphertext, Some(&key_file)).unwrap();
+        assert_eq!(text, "[default]\nport = 1\n");
+        let armored =
+            age::encrypt_and_armor(&identity.to_public(), b"x = 1").unwrap();
+        let text = decrypt(armored.as_bytes(), Some(&key_file)).unwrap();
+        assert_eq!(text, "x = 1");
+
+        let other = dir.join("other.txt");
+        let other_key = x25519::Identity::generate().to_string();
+        std::fs::write(&other, other_key.expose_secret()).unwrap();
+        assert!(decrypt(&ciphertext, Some(&other)).is_err());
+    }
+
+    #[test]
+    fn test_hydrate() {
+        let identity = x25519::Identity::generate();
+        let dir = TempDir::new("age-hydrate");
+        let key_file = dir.join("key.txt");
+        let key = identity.to_string();
+        std::fs::write(&key_file, key.expose_secret()).unwrap();
+        std::fs::write(dir.join("settings.toml"), "[default]\nport = 1\n")
+            .unwrap();
+        let secrets = b"[default]\npassword = \"a password\"\n";
+        let ciphertext = age::encrypt(&identity.to_public(), secrets).unwrap();
+        std::fs::write(dir.join(".secrets.toml.age"), ciphertext).unwrap();
+
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(dir.to_path_buf())
+                .set_age_identity(key_file)
+                .set_envvar_prefix("AGETEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("port").unwrap(), 1);
+        assert_eq!(hydro.get_str("password").unwrap(), "a password");
+    }
+}
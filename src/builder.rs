@@ -0,0 +1,404 @@
+use std::path::PathBuf;
+
+use config::{Config, File, FileFormat, Source};
+use regex::Regex;
+
+use crate::command::CommandSource;
+use crate::formats::{Format, FormatRegistry};
+use crate::hydro::Hydroconf;
+use crate::settings::{HydroSettings, Layer, ListMerge, UnknownKeys};
+use crate::validate::Validator;
+
+/// A builder that gathers every knob of `Hydroconf` (the `HydroSettings`
+/// fields and the injected sources) in one place.
+///
+/// ```rust
+/// # use hydroconf::{FileFormat, Hydroconf};
+/// let hydro = Hydroconf::builder()
+///     .env("production")
+///     .envvar_prefix("MYAPP")
+///     .with_inline("[default]\nport = 8080", FileFormat::Toml)
+///     .skip_discovery()
+///     .build();
+/// ```
+#[derive(Debug, Default)]
+pub struct HydroconfBuilder {
+    settings: HydroSettings,
+    sources: Vec<Box<dyn Source + Send + Sync>>,
+    directories: Vec<PathBuf>,
+    secrets_directories: Vec<PathBuf>,
+    skip_discovery: bool,
+    base_config: Option<Config>,
+    validators: Vec<(String, Validator)>,
+}
+
+impl HydroconfBuilder {
+    pub fn new(settings: HydroSettings) -> Self {
+        Self {
+            settings,
+            sources: Vec::new(),
+            directories: Vec::new(),
+            secrets_directories: Vec::new(),
+            skip_discovery: false,
+            base_config: None,
+            validators: Vec::new(),
+        }
+    }
+
+    pub fn root_path<P: Into<PathBuf>>(mut self, p: P) -> Self {
+        self.settings = self.settings.set_root_path(p.into());
+        self
+    }
+
+    pub fn root_path_for_env<E, P>(mut self, e: E, p: P) -> Self
+    where
+        E: Into<String>,
+        P: Into<PathBuf>,
+    {
+        self.settings =
+            self.settings.set_root_path_for_env(e.into(), p.into());
+        self
+    }
+
+    pub fn settings_file<P: Into<PathBuf>>(mut self, p: P) -> Self {
+        self.settings = self.settings.set_settings_file(p.into());
+        self
+    }
+
+    pub fn add_settings_file<P: Into<PathBuf>>(mut self, p: P) -> Self {
+        self.settings = self.settings.add_settings_file(p.into());
+        self
+    }
+
+    pub fn app_name<S: Into<String>>(mut self, n: S) -> Self {
+        self.settings = self.settings.set_app_name(n.into());
+        self
+    }
+
+    pub fn max_walk_depth(mut self, n: usize) -> Self {
+        self.settings = self.settings.set_max_walk_depth(n);
+        self
+    }
+
+    pub fn cascade_settings(mut self, c: bool) -> Self {
+        self.settings = self.settings.set_cascade_settings(c);
+        self
+    }
+
+    pub fn settings_dirs<I, P>(mut self, dirs: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<PathBuf>,
+    {
+        self.settings = self
+            .settings
+            .set_settings_dirs(dirs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn settings_from_stdin(mut self, format: Format) -> Self {
+        self.settings = self.settings.set_settings_from_stdin(format);
+        self
+    }
+
+    pub fn secrets_file<P: Into<PathBuf>>(mut self, p: P) -> Self {
+        self.settings = self.settings.set_secrets_file(p.into());
+        self
+    }
+
+    pub fn env<S: Into<String>>(mut self, e: S) -> Self {
+        self.settings = self.settings.set_env(e.into());
+        self
+    }
+
+    pub fn envvar_prefix<S: Into<String>>(mut self, p: S) -> Self {
+        self.settings = self.settings.set_envvar_prefix(p.into());
+        self
+    }
+
+    pub fn encoding<S: Into<String>>(mut self, e: S) -> Self {
+        self.settings = self.settings.set_encoding(e.into());
+        self
+    }
+
+    pub fn envvar_nested_sep<S: Into<String>>(mut self, s: S) -> Self {
+        self.settings = self.settings.set_envvar_nested_sep(s.into());
+        self
+    }
+
+    pub fn env_regex<S: Into<String>>(
+        mut self,
+        re: Regex,
+        template: S,
+    ) -> Self {
+        self.settings = self.settings.add_env_regex(re, template.into());
+        self
+    }
+
+    pub fn trim_values(mut self, t: bool) -> Self {
+        self.settings = self.settings.set_trim_values(t);
+        self
+    }
+
+    pub fn secrets_win_over_env(mut self, w: bool) -> Self {
+        self.settings = self.settings.set_secrets_win_over_env(w);
+        self
+    }
+
+    pub fn namespace<S: Into<String>>(mut self, n: S) -> Self {
+        self.settings = self.settings.set_namespace(n.into());
+        self
+    }
+
+    pub fn docker_secrets(mut self, d: bool) -> Self {
+        self.settings = self.settings.set_docker_secrets(d);
+        self
+    }
+
+    pub fn credentials_directory<P: Into<PathBuf>>(mut self, p: P) -> Self {
+        self.settings = self.settings.set_credentials_directory(p.into());
+        self
+    }
+
+    pub fn command(mut self, c: CommandSource) -> Self {
+        self.settings = self.settings.set_command(c);
+        self
+    }
+
+    pub fn format_registry(mut self, registry: FormatRegistry) -> Self {
+        self.settings = self.settings.set_format_registry(registry);
+        self
+    }
+
+    /// See `Hydroconf::add_secrets_directory`.
+    pub fn add_secrets_directory<P: Into<PathBuf>>(mut self, p: P) -> Self {
+        self.secrets_directories.push(p.into());
+        self
+    }
+
+    #[cfg(feature = "age")]
+    pub fn age_identity<P: Into<PathBuf>>(mut self, p: P) -> Self {
+        self.settings = self.settings.set_age_identity(p.into());
+        self
+    }
+
+    #[cfg(feature = "consul")]
+    pub fn consul_prefix(mut self, prefix: String) -> Self {
+        self.settings = self.settings.set_consul_prefix(prefix);
+        self
+    }
+
+    #[cfg(feature = "git")]
+    pub fn git_repo<S: Into<String>>(
+        mut self,
+        url: S,
+        reference: Option<S>,
+    ) -> Self {
+        self.settings = self
+            .settings
+            .set_git_repo(url.into(), reference.map(Into::into));
+        self
+    }
+
+    #[cfg(feature = "onepassword")]
+    pub fn onepassword_ref<S: Into<String>>(
+        mut self,
+        key: S,
+        reference: S,
+    ) -> Self {
+        self.settings = self
+            .settings
+            .add_onepassword_ref(key.into(), reference.into());
+        self
+    }
+
+    #[cfg(feature = "vault")]
+    pub fn vault(mut self, v: crate::remote::VaultSettings) -> Self {
+        self.settings = self.settings.set_vault(v);
+        self
+    }
+
+    #[cfg(feature = "xml")]
+    pub fn xml_attributes(mut self, a: crate::XmlAttributes) -> Self {
+        self.settings = self.settings.set_xml_attributes(a);
+        self
+    }
+
+    /// See `Hydroconf::add_source`.
+    pub fn add_source<T>(mut self, source: T) -> Self
+    where
+        T: Source + Send + Sync + 'static,
+    {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    /// See `Hydroconf::add_directory`.
+    pub fn add_directory<P: Into<PathBuf>>(mut self, p: P) -> Self {
+        self.directories.push(p.into());
+        self
+    }
+
+    /// Add a settings document given as a string, split in `default` and
+    /// per-environment tables like a settings file.
+    pub fn with_inline(self, content: &str, format: FileFormat) -> Self {
+        self.add_source(File::from_str(content, format))
+    }
+
+    /// Do not search the filesystem for settings, secrets and `.env` files.
+    pub fn skip_discovery(mut self) -> Self {
+        self.skip_discovery = true;
+        self
+    }
+
+    /// See `Hydroconf::with_base_config`.
+    pub fn base_config(mut self, base: Config) -> Self {
+        self.base_config = Some(base);
+        self
+    }
+
+    pub fn required<I, S>(mut self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let keys = keys.into_iter().map(Into::into).collect();
+        self.settings = self.settings.set_required(keys);
+        self
+    }
+
+    pub fn unknown_keys(mut self, u: UnknownKeys) -> Self {
+        self.settings = self.settings.set_unknown_keys(u);
+        self
+    }
+
+    pub fn require_env(mut self, r: bool) -> Self {
+        self.settings = self.settings.set_require_env(r);
+        self
+    }
+
+    pub fn secret_keys<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let patterns = patterns.into_iter().map(Into::into).collect();
+        self.settings = self.settings.set_secret_keys(patterns);
+        self
+    }
+
+    pub fn allowed_envs<I, S>(mut self, envs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let envs = envs.into_iter().map(Into::into).collect();
+        self.settings = self.settings.set_allowed_envs(envs);
+        self
+    }
+
+    pub fn warn_shadowed(mut self, w: bool) -> Self {
+        self.settings = self.settings.set_warn_shadowed(w);
+        self
+    }
+
+    pub fn strict_interpolation(mut self, s: bool) -> Self {
+        self.settings = self.settings.set_strict_interpolation(s);
+        self
+    }
+
+    pub fn path_keys<I, S>(mut self, patterns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let patterns = patterns.into_iter().map(Into::into).collect();
+        self.settings = self.settings.set_path_keys(patterns);
+        self
+    }
+
+    pub fn list_merge(mut self, l: ListMerge) -> Self {
+        self.settings = self.settings.set_list_merge(l);
+        self
+    }
+
+    pub fn list_merge_for_key(mut self, key: &str, l: ListMerge) -> Self {
+        self.settings = self.settings.set_list_merge_for_key(key.into(), l);
+        self
+    }
+
+    pub fn shallow_merge(mut self, s: bool) -> Self {
+        self.settings = self.settings.set_shallow_merge(s);
+        self
+    }
+
+    pub fn layer_order<I: IntoIterator<Item = Layer>>(
+        mut self,
+        order: I,
+    ) -> Self {
+        self.settings =
+            self.settings.set_layer_order(order.into_iter().collect());
+        self
+    }
+
+    pub fn flat_files(mut self, f: bool) -> Self {
+        self.settings = self.settings.set_flat_files(f);
+        self
+    }
+
+    pub fn tenant<S: Into<String>>(mut self, t: S) -> Self {
+        self.settings = self.settings.set_tenant(t.into());
+        self
+    }
+
+    /// See `Hydroconf::add_validator`.
+    pub fn validator<V: Into<Validator>>(mut self, key: &str, v: V) -> Self {
+        self.validators.push((key.to_string(), v.into()));
+        self
+    }
+
+    pub fn build(self) -> Hydroconf {
+        let mut hydro = Hydroconf::new(self.settings);
+        if let Some(base) = self.base_config {
+            hydro = hydro.with_base_config(base);
+        }
+        for source in self.sources {
+            hydro.add_boxed_source(source);
+        }
+        for directory in self.directories {
+            hydro.add_directory(directory);
+        }
+        for directory in self.secrets_directories {
+            hydro.add_secrets_directory(directory);
+        }
+        for (key, validator) in self.validators {
+            hydro.add_validator(&key, validator);
+        }
+        hydro.skip_discovery(self.skip_discovery);
+        hydro
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder_settings() {
+        let builder = HydroconfBuilder::new(HydroSettings::default())
+            .root_path("/srv/app")
+            .env("production")
+            .envvar_prefix("MYAPP")
+            .envvar_nested_sep("___")
+            .trim_values(true);
+        assert_eq!(
+            builder.settings,
+            HydroSettings::default()
+                .set_root_path("/srv/app".into())
+                .set_env("production".into())
+                .set_envvar_prefix("MYAPP".into())
+                .set_envvar_nested_sep("___".into())
+                .set_trim_values(true),
+        );
+    }
+}
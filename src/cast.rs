@@ -0,0 +1,118 @@
+//! The Dynaconf tokens giving a type to the values of the `.env` files and
+//! of the environment variables, which are otherwise strings: `@int 42`,
+//! `@float 0.5`, `@bool true`, `@json [1, 2]`, `@base64 SGVsbG8=` and
+//! `@str @int` (a string, even if it starts with a token).
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::{DecodeError, Engine};
+use config::Value;
+use serde_json::Value as JsonValue;
+
+use crate::env::FromVar;
+use crate::utils::from_json;
+
+/// The value of `raw` typed by its token, or `None` if it has none.
+pub fn cast(raw: &str) -> Result<Option<Value>, String> {
+    let (token, rest) = match raw.split_once(char::is_whitespace) {
+        Some((token, rest)) => (token, rest.trim()),
+        None => (raw, ""),
+    };
+    let invalid = || format!("invalid {} value {:?}", token, rest);
+    Ok(Some(match token {
+        "@int" => Value::from(rest.parse::<i64>().map_err(|_| invalid())?),
+        "@float" => Value::from(rest.parse::<f64>().map_err(|_| invalid())?),
+        "@bool" => Value::from(
+            <bool as FromVar>::parse(rest.into()).ok_or_else(invalid)?,
+        ),
+        "@json" => from_json(
+            serde_json::from_str(rest)
+                .map_err(|e| format!("{}: {}", invalid(), e))?,
+        ),
+        BASE64_TOKEN => from_json(decode_base64(rest)?),
+        "@str" => Value::from(rest),
+        _ => return Ok(None),
+    }))
+}
+
+/// The token of the base64 values, which are also decoded in the settings
+/// files.
+pub const BASE64_TOKEN: &str = "@base64";
+
+/// The value of `s` if it has a `@base64` token.
+pub fn base64_value(s: &str) -> Option<&str> {
+    s.strip_prefix(BASE64_TOKEN)?
+        .strip_prefix(char::is_whitespace)
+        .map(str::trim)
+}
+
+/// Decode base64 `data` (whitespace is ignored, e.g. the line breaks of a
+/// PEM) into a string or, if it's not UTF-8, into the list of its bytes.
+pub fn decode_base64(data: &str) -> Result<JsonValue, String> {
+    let data: String = data.split_whitespace().collect();
+    // The data is usually a secret, so the error only tells where it's
+    // invalid, unlike the one of `decode()`.
+    let bytes = BASE64.decode(&data).map_err(|e| {
+        let reason = match e {
+            DecodeError::InvalidByte(offset, _)
+            | DecodeError::InvalidLastSymbol(offset, _) => {
+                format!("invalid character at offset {}", offset)
+            }
+            DecodeError::InvalidLength(_) => "invalid length".into(),
+            DecodeError::InvalidPadding => "invalid padding".into(),
+        };
+        format!("invalid {} value: {}", BASE64_TOKEN, reason)
+    })?;
+    Ok(match String::from_utf8(bytes) {
+        Ok(s) => s.into(),
+        Err(e) => e.into_bytes().into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    use crate::utils::to_json;
+
+    fn json(raw: &str) -> serde_json::Value {
+        to_json(cast(raw).unwrap().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_cast() {
+        assert_eq!(json("@int 42"), json!(42));
+        assert_eq!(json("@int  -1 "), json!(-1));
+        assert_eq!(json("@float 0.5"), json!(0.5));
+        assert_eq!(json("@bool true"), json!(true));
+        assert_eq!(json("@bool off"), json!(false));
+        assert_eq!(
+            json(r#"@json {"hosts": ["a", "b"], "port": 1}"#),
+            json!({"hosts": ["a", "b"], "port": 1})
+        );
+        assert_eq!(json("@base64 SGVsbG8="), json!("Hello"));
+        assert_eq!(json("@base64 SGVs\n bG8="), json!("Hello"));
+        assert_eq!(json("@base64 /w=="), json!([255]));
+        assert_eq!(json("@str @int 42"), json!("@int 42"));
+        assert!(cast("42").unwrap().is_none());
+        assert!(cast("@keyring:app/pg").unwrap().is_none());
+        assert!(cast("me@example.com").unwrap().is_none());
+
+        assert_eq!(
+            cast("@int forty-two").unwrap_err(),
+            "invalid @int value \"forty-two\""
+        );
+        assert_eq!(cast("@bool").unwrap_err(), "invalid @bool value \"\"");
+        assert!(cast("@json [1,").is_err());
+        assert_eq!(
+            cast("@base64 SGV!sbG8=").unwrap_err(),
+            "invalid @base64 value: invalid character at offset 3"
+        );
+        assert_eq!(
+            cast("@base64 SGVsbG8").unwrap_err(),
+            "invalid @base64 value: invalid padding"
+        );
+        assert_eq!(base64_value("@base64 SGVsbG8= "), Some("SGVsbG8="));
+        assert_eq!(base64_value("@base64x"), None);
+    }
+}
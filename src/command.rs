@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use config::{ConfigError, Source, Value};
+
+use crate::env;
+use crate::formats::Format;
+
+/// A source reading the settings from the standard output of a command, e.g.
+/// `sops -d settings.enc.toml` or `op inject -i settings.tpl.toml`. Like a
+/// settings file, the output must be split in `default` and
+/// per-environment tables.
+///
+/// The command is run directly, without a shell, and is killed if it runs
+/// longer than the timeout (30 seconds by default).
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandSource {
+    pub command: Vec<String>,
+    pub format: Format,
+    pub timeout: Duration,
+}
+
+impl CommandSource {
+    /// `command` is the program followed by its arguments.
+    pub fn new(command: Vec<String>) -> Self {
+        Self {
+            command,
+            format: Format::Toml,
+            timeout: Duration::from_secs(30),
+        }
+    }
+
+    /// Read the settings from the `COMMAND_*_FOR_HYDRO` environment
+    /// variables. Return `None` if `COMMAND_FOR_HYDRO` is not set. Its
+    /// arguments are split on whitespace, quoting is not supported.
+    pub fn from_env() -> Option<Self> {
+        let hydro_suffix = "_FOR_HYDRO";
+        let command: String = env::get_var("COMMAND", hydro_suffix)?;
+        let mut source =
+            Self::new(command.split_whitespace().map(Into::into).collect());
+        if let Some(format) = env::get_var("COMMAND_FORMAT", hydro_suffix) {
+            source.format = format;
+        }
+        if let Some(timeout) = env::get_var("COMMAND_TIMEOUT", hydro_suffix) {
+            source.timeout = timeout;
+        }
+        Some(source)
+    }
+
+    pub fn set_format(mut self, f: Format) -> Self {
+        self.format = f;
+        self
+    }
+
+    pub fn set_timeout(mut self, t: Duration) -> Self {
+        self.timeout = t;
+        self
+    }
+
+    /// The command line, for error messages and `Origin`s.
+    pub fn display(&self) -> String {
+        self.command.join(" ")
+    }
+
+    fn error(&self, message: String) -> ConfigError {
+        ConfigError::Message(format!(
+            "command `{}` {}",
+            self.display(),
+            message
+        ))
+    }
+
+    fn run(&self) -> Result<String, ConfigError> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| self.error("is empty".into()))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| self.error(format!("could not be run: {}", e)))?;
+        // Drain the pipes while waiting, so that a large output can't fill
+        // them and block the command.
+        let drain = |pipe: Option<Box<dyn Read + Send>>| {
+            thread::spawn(move || {
+                let mut buf = Vec::new();
+                if let Some(mut pipe) = pipe {
+                    let _ = pipe.read_to_end(&mut buf);
+                }
+                buf
+            })
+        };
+        let stdout = drain(child.stdout.take().map(|p| Box::new(p) as _));
+        let stderr = drain(child.stderr.take().map(|p| Box::new(p) as _));
+
+        let deadline = Instant::now() + self.timeout;
+        let status = loop {
+            let status = child
+                .try_wait()
+                .map_err(|e| self.error(format!("failed: {}", e)))?;
+            if let Some(status) = status {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(
+                    self.error(format!("timed out after {:?}", self.timeout))
+                );
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+        if !status.success() {
+            return Err(self.error(format!(
+                "failed ({}): {}",
+                status,
+                String::from_utf8_lossy(&stderr).trim()
+            )));
+        }
+        String::from_utf8(stdout)
+            .map_err(|_| self.error("printed invalid UTF-8".into()))
+    }
+}
+
+impl Source for CommandSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let output = self.run()?;
+        self.format.parse(&output)?.collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str) -> CommandSource {
+        CommandSource::new(vec!["sh".into(), "-c".into(), script.into()])
+    }
+
+    #[test]
+    fn test_collect() {
+        let m =
+            sh("printf '[default]\\nport = 1\\n[production]\\nport = 2\\n'")
+                .collect()
+                .unwrap();
+        let table = m["production"].clone().into_table().unwrap();
+        assert_eq!(table["port"].clone().into_int().unwrap(), 2);
+
+        let m = sh(r#"echo '{"default": {"debug": true}}'"#)
+            .set_format(Format::Json)
+            .collect()
+            .unwrap();
+        let table = m["default"].clone().into_table().unwrap();
+        assert!(table["debug"].clone().into_bool().unwrap());
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = sh("echo 'bad passphrase' >&2; exit 3")
+            .collect()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("bad passphrase"), "{}", err);
+
+        let start = Instant::now();
+        let err = sh("sleep 5")
+            .set_timeout(Duration::from_millis(100))
+            .collect()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("timed out"), "{}", err);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert!(CommandSource::new(vec![]).collect().is_err());
+        let missing = vec!["hydroconf-missing-command".into()];
+        assert!(CommandSource::new(missing).collect().is_err());
+        assert!(sh("echo 'not toml ['").collect().is_err());
+    }
+
+    #[test]
+    fn test_hydrate() {
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_command(sh("printf '[default]\\nport = 1\\n'"))
+                .set_env("production".into())
+                .set_envvar_prefix("COMMANDTEST".into()),
+        );
+        hydro.skip_discovery(true);
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("port").unwrap(), 1);
+        assert_eq!(
+            hydro.origins("port").last().unwrap().to_string(),
+            "secrets (command sh -c printf '[default]\\nport = 1\\n')"
+        );
+    }
+}
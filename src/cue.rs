@@ -0,0 +1,116 @@
+//! Evaluation of the CUE settings files, such as `settings.cue`, whose
+//! constraints (e.g. `port: int & >1024`) are checked when they're loaded.
+
+use std::path::Path;
+use std::process::Command;
+
+use config::ConfigError;
+
+use crate::utils::pipe_through;
+
+/// Export a CUE file to JSON with the `cue` binary. When it's read from a
+/// local `file`, it's evaluated in its directory (e.g. with its module).
+pub fn export(text: &str, file: Option<&Path>) -> Result<String, ConfigError> {
+    export_with("cue", text, file)
+}
+
+fn export_with(
+    program: &str,
+    text: &str,
+    file: Option<&Path>,
+) -> Result<String, ConfigError> {
+    let mut command = Command::new(program);
+    command.args(["export", "--out", "json"]);
+    match file {
+        Some(path) => command.arg(path),
+        None => command.arg("-"),
+    };
+    pipe_through(&mut command, text).map_err(|stderr| ConfigError::FileParse {
+        uri: None,
+        cause: format!("CUE evaluation failed: {}", errors(&stderr)).into(),
+    })
+}
+
+/// Join the errors printed by `cue`, each made of its path and message on
+/// one line and its positions on the indented lines after it, e.g.
+///
+/// ```text
+/// default.port: invalid value 80 (out of bound >1024):
+///     ./settings.cue:2:8
+/// ```
+///
+/// into `default.port: invalid value 80 (out of bound >1024) at
+/// ./settings.cue:2:8`.
+fn errors(stderr: &str) -> String {
+    let mut errors: Vec<String> = Vec::new();
+    for line in stderr.lines().filter(|l| !l.trim().is_empty()) {
+        let indented = line.starts_with(char::is_whitespace);
+        match errors.last_mut() {
+            Some(error) if indented => {
+                let sep = if error.contains(" at ") { ", " } else { " at " };
+                error.push_str(sep);
+                error.push_str(line.trim());
+            }
+            _ => errors.push(line.trim().trim_end_matches(':').to_string()),
+        }
+    }
+    errors.join("; ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_errors() {
+        let stderr = "\
+default.port: invalid value 80 (out of bound >1024):
+    ./settings.cue:2:8
+    ./settings.cue:5:10
+production.host: incomplete value string
+";
+        assert_eq!(
+            errors(stderr),
+            "default.port: invalid value 80 (out of bound >1024) at \
+             ./settings.cue:2:8, ./settings.cue:5:10; production.host: \
+             incomplete value string"
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        // A fake `cue` printing its arguments and its input.
+        let dir = TempDir::new("cue");
+        let program = dir.join("cue");
+        fs::write(&program, "#!/bin/sh\necho \"$@\"\ncat\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        let program = program.to_str().unwrap();
+
+        let out = export_with(program, "{}", None).unwrap();
+        assert_eq!(out, "export --out json -\n{}");
+        let file = Path::new("config/settings.cue");
+        let out = export_with(program, "", Some(file)).unwrap();
+        assert_eq!(out, "export --out json config/settings.cue\n");
+
+        fs::write(
+            dir.join("cue"),
+            "#!/bin/sh\necho 'default.port: invalid value 80 (out of bound \
+             >1024):\n    ./settings.cue:2:8' >&2\nexit 1\n",
+        )
+        .unwrap();
+        let err = export_with(program, "{}", None).unwrap_err();
+        assert!(matches!(err, ConfigError::FileParse { uri: None, .. }));
+        assert!(
+            err.to_string().contains(
+                "default.port: invalid value 80 (out of bound >1024) at \
+                 ./settings.cue:2:8"
+            ),
+            "{}",
+            err
+        );
+    }
+}
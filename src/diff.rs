@@ -0,0 +1,131 @@
+use std::collections::{btree_map, BTreeMap, HashMap};
+
+use config::Value;
+
+use crate::utils::{flatten_table, to_json};
+
+type Table = HashMap<String, Value>;
+
+/// How the value of a key changed between two versions of the settings.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Added(Value),
+    Removed(Value),
+    Modified { old: Value, new: Value },
+}
+
+/// The keys whose values changed between two versions of the settings, by
+/// dotted path (e.g. `pg.port`). Only the leaves of the nested tables are
+/// listed; arrays are leaves.
+#[derive(Debug, Clone, Default)]
+pub struct Diff(BTreeMap<String, Change>);
+
+impl Diff {
+    pub fn new(old: &Table, new: &Table) -> Self {
+        let mut old: BTreeMap<_, _> = flatten_table(old).into_iter().collect();
+        let mut changes = BTreeMap::new();
+        for (key, new) in flatten_table(new) {
+            let change = match old.remove(&key) {
+                Some(old) if same_value(&old, &new) => continue,
+                Some(old) => Change::Modified { old, new },
+                None => Change::Added(new),
+            };
+            changes.insert(key, change);
+        }
+        for (key, old) in old {
+            changes.insert(key, Change::Removed(old));
+        }
+        Self(changes)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Change> {
+        self.0.get(key)
+    }
+
+    /// Whether `key`, or one of the keys nested under it, changed: e.g.
+    /// `pg` when `pg.port` changed.
+    pub fn contains(&self, key: &str) -> bool {
+        let nested = format!("{}.", key);
+        self.0
+            .keys()
+            .any(|k| k == key || k.starts_with(nested.as_str()))
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<'_, String, Change> {
+        self.0.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diff {
+    type Item = (&'a String, &'a Change);
+    type IntoIter = btree_map::Iter<'a, String, Change>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Whether `a` and `b` are the same value, ignoring where they were read
+/// from.
+pub(crate) fn same_value(a: &Value, b: &Value) -> bool {
+    match (to_json(a.clone()), to_json(b.clone())) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, Value)]) -> Table {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_diff() {
+        let pg = |host: &str, port: i64| {
+            Value::from(table(&[
+                ("host", Value::from(host)),
+                ("port", Value::from(port)),
+            ]))
+        };
+        let old = table(&[
+            ("pg", pg("a", 1)),
+            ("name", Value::from("app")),
+            ("debug", Value::from(true)),
+        ]);
+        let new = table(&[
+            ("pg", pg("a", 2)),
+            ("name", Value::new(Some(&"other".to_string()), "app")),
+            ("region", Value::from("eu")),
+        ]);
+        let diff = Diff::new(&old, &new);
+        let keys: Vec<_> = diff.iter().map(|(k, _)| k.as_str()).collect();
+        assert_eq!(keys, ["debug", "pg.port", "region"]);
+        assert!(matches!(diff.get("debug"), Some(Change::Removed(_))));
+        assert!(matches!(diff.get("region"), Some(Change::Added(_))));
+        match diff.get("pg.port") {
+            Some(Change::Modified { old, new }) => {
+                assert_eq!(old.clone().into_int().unwrap(), 1);
+                assert_eq!(new.clone().into_int().unwrap(), 2);
+            }
+            change => panic!("unexpected change: {:?}", change),
+        }
+        assert!(diff.contains("pg"));
+        assert!(!diff.contains("pg.host"));
+        assert!(!diff.contains("p"));
+        assert!(Diff::new(&new, &new).is_empty());
+    }
+}
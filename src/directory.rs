@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use config::{ConfigError, Source, Value};
+
+use crate::utils::{key_tree, path_to_string, read_text};
+
+/// A source reading a directory of key files, like the volumes of
+/// Kubernetes ConfigMaps and Secrets: each file name is a key and the file
+/// content its value. Names are split on the nested separator, so that
+/// `pg__port` is read as `pg.port`.
+///
+/// Hidden files are ignored (Kubernetes keeps its bookkeeping in `..data`),
+/// as well as a missing directory. Values are read as strings, without
+/// their trailing newline.
+#[derive(Debug, Clone)]
+pub struct DirectorySource {
+    path: PathBuf,
+    nested_sep: String,
+    section: String,
+}
+
+impl DirectorySource {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            nested_sep: "__".into(),
+            section: "default".into(),
+        }
+    }
+
+    pub fn set_nested_sep(mut self, sep: String) -> Self {
+        self.nested_sep = sep;
+        self
+    }
+
+    /// The table where the values are placed, `default` unless set.
+    pub fn set_section(mut self, section: String) -> Self {
+        self.section = section;
+        self
+    }
+
+    fn read_error(&self, e: io::Error) -> ConfigError {
+        ConfigError::FileParse {
+            uri: path_to_string(self.path.clone()),
+            cause: e.into(),
+        }
+    }
+}
+
+impl Source for DirectorySource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let entries = match fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Ok(HashMap::new())
+            }
+            Err(e) => return Err(self.read_error(e)),
+        };
+        let mut kvs = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| self.read_error(e))?.path();
+            let name = match path.file_name().and_then(|n| n.to_str()) {
+                Some(name) if !name.starts_with('.') => name.to_lowercase(),
+                _ => continue,
+            };
+            // `is_file` follows the symlinks used by Kubernetes.
+            if !path.is_file() {
+                continue;
+            }
+            let mut text = read_text(&path, "utf-8")?;
+            if text.ends_with('\n') {
+                text.pop();
+                if text.ends_with('\r') {
+                    text.pop();
+                }
+            }
+            let sep = &self.nested_sep;
+            kvs.push((format!("{}{}{}", self.section, sep, name), text));
+        }
+        Ok(key_tree(kvs, &self.nested_sep))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::get_data_path;
+
+    #[test]
+    fn test_collect() {
+        let dir = get_data_path("28");
+
+        let m = DirectorySource::new(dir.clone())
+            .set_section("production".into())
+            .collect()
+            .unwrap();
+        let table = m["production"].clone().into_table().unwrap();
+        assert_eq!(table.len(), 2);
+        assert!(table["debug"].clone().into_bool().unwrap());
+        let pg = table["pg"].clone().into_table().unwrap();
+        assert_eq!(pg["port"].clone().into_int().unwrap(), 5432);
+        assert_eq!(pg["password"].clone().into_str().unwrap(), "a password");
+
+        let m = DirectorySource::new(dir.clone())
+            .set_nested_sep("_".into())
+            .collect()
+            .unwrap();
+        let table = m["default"].clone().into_table().unwrap();
+        assert!(table["pg"].clone().into_table().is_ok());
+
+        let m = DirectorySource::new(dir.join("missing")).collect().unwrap();
+        assert!(m.is_empty());
+    }
+}
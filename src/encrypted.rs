@@ -0,0 +1,159 @@
+//! The values encrypted one by one, such as `ENC[AES256,...]`: the base64
+//! of a 12-byte nonce followed by the AES-256-GCM ciphertext of the value.
+//! They are decrypted with the base64 key in `DECRYPT_KEY_FOR_HYDRO`.
+
+use config::ConfigError;
+use serde_json::Value as JsonValue;
+
+use crate::utils::walk_strings;
+
+pub const PREFIX: &str = "ENC[AES256,";
+
+/// The environment variable holding the key.
+pub const KEY_VAR: &str = "DECRYPT_KEY_FOR_HYDRO";
+
+/// The ciphertext of an encrypted value.
+fn ciphertext(value: &str) -> Option<&str> {
+    value.strip_prefix(PREFIX)?.strip_suffix(']')
+}
+
+/// Decrypt every encrypted string in `json`. Return whether there was any.
+pub fn decrypt_values(json: &mut JsonValue) -> Result<bool, ConfigError> {
+    let mut key = None;
+    let mut decrypted = false;
+    walk_strings(json, "", &mut |path, value| {
+        let ciphertext = match value.as_str().and_then(ciphertext) {
+            Some(ciphertext) => ciphertext.to_string(),
+            None => return Ok(()),
+        };
+        if key.is_none() {
+            key = Some(std::env::var(KEY_VAR).map_err(|_| {
+                ConfigError::Message(format!(
+                    "{} is encrypted, set {} to decrypt it",
+                    path, KEY_VAR
+                ))
+            })?);
+        }
+        let plaintext = decrypt(
+            key.as_deref().unwrap_or_default(),
+            &ciphertext,
+        )
+        .map_err(|e| {
+            ConfigError::Message(format!("could not decrypt {}: {}", path, e))
+        })?;
+        *value = plaintext.into();
+        decrypted = true;
+        Ok(())
+    })?;
+    Ok(decrypted)
+}
+
+#[cfg(feature = "encryption")]
+mod aes {
+    use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+    use aes_gcm::{Aes256Gcm, Key, Nonce};
+    use base64::engine::general_purpose::STANDARD as BASE64;
+    use base64::Engine;
+
+    use super::{KEY_VAR, PREFIX};
+
+    const NONCE_LEN: usize = 12;
+
+    fn cipher(key: &str) -> Result<Aes256Gcm, String> {
+        let key = BASE64
+            .decode(key.trim())
+            .map_err(|e| format!("invalid {}: {}", KEY_VAR, e))?;
+        if key.len() != 32 {
+            return Err(format!(
+                "invalid {}: expected 32 bytes, got {}",
+                KEY_VAR,
+                key.len()
+            ));
+        }
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)))
+    }
+
+    pub fn decrypt(key: &str, ciphertext: &str) -> Result<String, String> {
+        let data = BASE64
+            .decode(ciphertext.trim())
+            .map_err(|e| format!("invalid base64: {}", e))?;
+        if data.len() < NONCE_LEN {
+            return Err("the ciphertext is too short".into());
+        }
+        let (nonce, data) = data.split_at(NONCE_LEN);
+        let plaintext =
+            cipher(key)?
+                .decrypt(Nonce::from_slice(nonce), data)
+                .map_err(|_| "wrong key or corrupted value".to_string())?;
+        String::from_utf8(plaintext).map_err(|e| e.to_string())
+    }
+
+    /// A new random key, in the format of `DECRYPT_KEY_FOR_HYDRO`.
+    pub fn generate_key() -> String {
+        BASE64.encode(Aes256Gcm::generate_key(OsRng))
+    }
+
+    /// Encrypt `plaintext` with `key`, a base64 key such as the ones of
+    /// `generate_key()`, into an `ENC[AES256,...]` value.
+    pub fn encrypt_value(
+        key: &str,
+        plaintext: &str,
+    ) -> Result<String, String> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = cipher(key)?
+            .encrypt(&nonce, plaintext.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Ok(format!("{}{}]", PREFIX, BASE64.encode(data)))
+    }
+}
+
+#[cfg(feature = "encryption")]
+pub use aes::{encrypt_value, generate_key};
+
+#[cfg(feature = "encryption")]
+use aes::decrypt;
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(_key: &str, _ciphertext: &str) -> Result<String, String> {
+    Err("hydroconf was built without the `encryption` feature".into())
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_encrypt_value() {
+        let key = generate_key();
+        let value = encrypt_value(&key, "a password").unwrap();
+        assert!(value.starts_with(PREFIX));
+        assert_ne!(value, encrypt_value(&key, "a password").unwrap());
+        assert_eq!(
+            decrypt(&key, ciphertext(&value).unwrap()).unwrap(),
+            "a password"
+        );
+        assert_eq!(
+            decrypt(&generate_key(), ciphertext(&value).unwrap()).unwrap_err(),
+            "wrong key or corrupted value"
+        );
+        assert!(encrypt_value("c2hvcnQ=", "x")
+            .unwrap_err()
+            .contains("expected 32 bytes, got 5"));
+    }
+
+    #[test]
+    fn test_decrypt_values() {
+        let mut json =
+            json!({"pg": {"host": "db", "password": "ENC[AES256,x]"}});
+        std::env::remove_var(KEY_VAR);
+        assert_eq!(
+            decrypt_values(&mut json).unwrap_err().to_string(),
+            "pg.password is encrypted, set DECRYPT_KEY_FOR_HYDRO to decrypt it"
+        );
+        let mut json = json!({"pg": {"host": "db"}});
+        assert!(!decrypt_values(&mut json).unwrap());
+    }
+}
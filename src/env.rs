@@ -1,4 +1,10 @@
 use std::path::PathBuf;
+use std::time::Duration;
+
+use config::ConfigError;
+
+use crate::formats::Format;
+use crate::units::parse_duration;
 
 pub fn get_var<'a, T>(key: &'a str, suffix: &'a str) -> Option<T>
 where
@@ -18,6 +24,21 @@ where
     get_var(key, suffix).unwrap_or(default)
 }
 
+/// Fail if the variable is set to a value that `get_var` can't parse, and
+/// would silently replace with the default.
+pub fn check_var<T>(key: &str, suffix: &str) -> Result<(), ConfigError>
+where
+    T: FromVar,
+{
+    let full_key = format!("{}{}", key, suffix);
+    match std::env::var(&full_key) {
+        Ok(v) if T::parse(v.clone()).is_none() => Err(ConfigError::Message(
+            format!("invalid value {:?} for {}", v, full_key),
+        )),
+        _ => Ok(()),
+    }
+}
+
 pub trait FromVar {
     fn parse(var: String) -> Option<Self>
     where
@@ -35,3 +56,44 @@ impl FromVar for String {
         Some(var)
     }
 }
+
+/// A comma-separated list, e.g. `pg.password,*.token`.
+impl FromVar for Vec<String> {
+    fn parse(var: String) -> Option<Self> {
+        Some(
+            var.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(String::from)
+                .collect(),
+        )
+    }
+}
+
+impl FromVar for bool {
+    fn parse(var: String) -> Option<Self> {
+        match var.trim().to_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Some(true),
+            "0" | "false" | "no" | "off" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl FromVar for usize {
+    fn parse(var: String) -> Option<Self> {
+        var.trim().parse().ok()
+    }
+}
+
+impl FromVar for Duration {
+    fn parse(var: String) -> Option<Self> {
+        parse_duration(&var).ok()
+    }
+}
+
+impl FromVar for Format {
+    fn parse(var: String) -> Option<Self> {
+        Format::from_extension(&var.trim().to_lowercase())
+    }
+}
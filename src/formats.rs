@@ -0,0 +1,717 @@
+//! The formats of the settings files.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use config::{Config, ConfigError, File, FileFormat, Source, Value};
+use dotenv_parser::parse_dotenv;
+#[cfg(any(
+    feature = "json5",
+    feature = "hcl",
+    feature = "xml",
+    feature = "dhall",
+    feature = "nestedtext",
+    feature = "msgpack",
+    feature = "cbor"
+))]
+use serde_json::Value as JsonValue;
+
+use crate::age_file;
+use crate::cue;
+use crate::jsonnet;
+#[cfg(feature = "nestedtext")]
+use crate::nestedtext;
+use crate::properties;
+use crate::settings::HydroSettings;
+use crate::sops;
+use crate::utils::{flatten_table, key_tree};
+#[cfg(feature = "xml")]
+use crate::xml::{self, XmlAttributes};
+use crate::yaml;
+
+/// The format of a settings file, usually deduced from its extension.
+///
+/// The formats other than TOML, JSON, YAML, INI and Java properties are
+/// enabled by the feature of the same name.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+    Ini,
+    Properties,
+    Env,
+    Jsonnet,
+    Cue,
+    #[cfg(feature = "hjson")]
+    Hjson,
+    #[cfg(feature = "json5")]
+    Json5,
+    #[cfg(feature = "hcl")]
+    Hcl,
+    #[cfg(feature = "xml")]
+    Xml(XmlAttributes),
+    #[cfg(feature = "dhall")]
+    Dhall,
+    #[cfg(feature = "nestedtext")]
+    NestedText,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "cbor")]
+    Cbor,
+}
+
+impl Format {
+    /// The extensions of the settings files that are discovered, in order of
+    /// preference.
+    pub const EXTENSIONS: &'static [&'static str] = &[
+        "toml",
+        "json",
+        "yaml",
+        "ini",
+        "properties",
+        "env",
+        "jsonnet",
+        "cue",
+        #[cfg(feature = "hjson")]
+        "hjson",
+        #[cfg(feature = "json5")]
+        "json5",
+        #[cfg(feature = "hcl")]
+        "hcl",
+        #[cfg(feature = "xml")]
+        "xml",
+        #[cfg(feature = "dhall")]
+        "dhall",
+        #[cfg(feature = "nestedtext")]
+        "nt",
+        #[cfg(feature = "msgpack")]
+        "msgpack",
+        #[cfg(feature = "cbor")]
+        "cbor",
+    ];
+
+    /// The format named by a file extension, e.g. `toml` or `yml`.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            "ini" => Some(Format::Ini),
+            "properties" => Some(Format::Properties),
+            "env" => Some(Format::Env),
+            "jsonnet" => Some(Format::Jsonnet),
+            "cue" => Some(Format::Cue),
+            #[cfg(feature = "hjson")]
+            "hjson" => Some(Format::Hjson),
+            #[cfg(feature = "json5")]
+            "json5" => Some(Format::Json5),
+            #[cfg(feature = "hcl")]
+            "hcl" => Some(Format::Hcl),
+            #[cfg(feature = "xml")]
+            "xml" => Some(Format::Xml(XmlAttributes::default())),
+            #[cfg(feature = "dhall")]
+            "dhall" => Some(Format::Dhall),
+            #[cfg(feature = "nestedtext")]
+            "nt" => Some(Format::NestedText),
+            #[cfg(feature = "msgpack")]
+            "msgpack" => Some(Format::MessagePack),
+            #[cfg(feature = "cbor")]
+            "cbor" => Some(Format::Cbor),
+            _ => None,
+        }
+    }
+
+    /// Parse a settings document into a source.
+    pub fn parse(
+        self,
+        text: &str,
+    ) -> Result<Box<dyn Source + Send + Sync>, ConfigError> {
+        let format = match self {
+            Format::Toml => FileFormat::Toml,
+            Format::Json => FileFormat::Json,
+            Format::Yaml => return Ok(Box::new(yaml::merge_documents(text)?)),
+            // Like in TOML, `[production.pg]` is the table `pg` in
+            // `production`.
+            Format::Ini => {
+                let flat = File::from_str(text, FileFormat::Ini).collect()?;
+                let kvs = flatten_table(&flat)
+                    .into_iter()
+                    .map(|(key, value)| Ok((key, value.into_str()?)))
+                    .collect::<Result<Vec<_>, ConfigError>>()?;
+                return tree_source(kvs, ".");
+            }
+            Format::Properties => {
+                return tree_source(properties::parse(text)?, ".")
+            }
+            // In the `default` table.
+            Format::Env => return env_file(text, "default", "__"),
+            // Without its external variables and with its relative imports
+            // resolved from the working directory.
+            Format::Jsonnet => {
+                let json = jsonnet::evaluate(text, None, &[])?;
+                return Ok(Box::new(File::from_str(&json, FileFormat::Json)));
+            }
+            Format::Cue => {
+                let json = cue::export(text, None)?;
+                return Ok(Box::new(File::from_str(&json, FileFormat::Json)));
+            }
+            #[cfg(feature = "hjson")]
+            Format::Hjson => FileFormat::Hjson,
+            #[cfg(feature = "json5")]
+            Format::Json5 => {
+                return json_source(
+                    json5::from_str(text).map_err(parse_error)?,
+                )
+            }
+            // A block is a table, nested in a table per label, e.g.
+            // `server "web" { ... }` is the table `server.web`.
+            #[cfg(feature = "hcl")]
+            Format::Hcl => {
+                return json_source(hcl::from_str(text).map_err(parse_error)?)
+            }
+            #[cfg(feature = "xml")]
+            Format::Xml(attributes) => {
+                return json_source(xml::parse(text, attributes)?)
+            }
+            // The program is evaluated, and its relative imports are
+            // resolved from the working directory.
+            #[cfg(feature = "dhall")]
+            Format::Dhall => {
+                let json = serde_dhall::from_str(text).parse();
+                return json_source(json.map_err(parse_error)?);
+            }
+            #[cfg(feature = "nestedtext")]
+            Format::NestedText => {
+                return json_source(nestedtext::parse(text)?)
+            }
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => return self.parse_bytes(text.as_bytes()),
+            #[cfg(feature = "cbor")]
+            Format::Cbor => return self.parse_bytes(text.as_bytes()),
+        };
+        Ok(Box::new(File::from_str(text, format)))
+    }
+
+    /// Whether the documents are binary, like MessagePack and CBOR, rather
+    /// than text.
+    pub fn is_binary(self) -> bool {
+        #[cfg(feature = "msgpack")]
+        if self == Format::MessagePack {
+            return true;
+        }
+        #[cfg(feature = "cbor")]
+        if self == Format::Cbor {
+            return true;
+        }
+        false
+    }
+
+    /// Parse a settings document that may be binary into a source. The
+    /// documents of the text formats must be valid UTF-8.
+    pub fn parse_bytes(
+        self,
+        bytes: &[u8],
+    ) -> Result<Box<dyn Source + Send + Sync>, ConfigError> {
+        match self {
+            #[cfg(feature = "msgpack")]
+            Format::MessagePack => {
+                json_source(rmp_serde::from_slice(bytes).map_err(parse_error)?)
+            }
+            #[cfg(feature = "cbor")]
+            Format::Cbor => {
+                json_source(ciborium::from_reader(bytes).map_err(parse_error)?)
+            }
+            _ => self.parse(std::str::from_utf8(bytes).map_err(parse_error)?),
+        }
+    }
+
+    /// Parse a settings document read from `path`, with the options of
+    /// `settings`. Unlike with `parse`, local Dhall, Jsonnet and CUE files
+    /// are evaluated in their directory (e.g. for their relative imports),
+    /// the Jsonnet programs get their external variables, and the `.env`
+    /// files are read in the section of the current environment.
+    pub(crate) fn parse_file(
+        self,
+        text: &str,
+        path: &Path,
+        settings: &HydroSettings,
+    ) -> Result<Box<dyn Source + Send + Sync>, ConfigError> {
+        // Encrypted files can only be evaluated once decrypted.
+        let local = path.is_file()
+            && !age_file::is_encrypted(path)
+            && !sops::is_encrypted(path, text);
+        match self {
+            Format::Env => {
+                let sep = &settings.envvar_nested_sep;
+                env_file(text, &settings.current_section(sep), sep)
+            }
+            Format::Jsonnet => {
+                let ext_vars = jsonnet::ext_vars(
+                    settings.current_env(),
+                    &settings.envvar_prefix,
+                );
+                let file = if local { Some(path) } else { None };
+                let json = jsonnet::evaluate(text, file, &ext_vars)?;
+                Ok(Box::new(File::from_str(&json, FileFormat::Json)))
+            }
+            Format::Cue if local => {
+                let json = cue::export(text, Some(path))?;
+                Ok(Box::new(File::from_str(&json, FileFormat::Json)))
+            }
+            #[cfg(feature = "xml")]
+            Format::Xml(_) => Format::Xml(settings.xml_attributes).parse(text),
+            #[cfg(feature = "dhall")]
+            Format::Dhall if local => {
+                let json = serde_dhall::from_file(path).parse();
+                json_source(json.map_err(parse_error)?)
+            }
+            _ => self.parse(text),
+        }
+    }
+}
+
+impl From<FileFormat> for Format {
+    fn from(format: FileFormat) -> Self {
+        match format {
+            FileFormat::Toml => Format::Toml,
+            FileFormat::Json => Format::Json,
+            FileFormat::Yaml => Format::Yaml,
+            FileFormat::Ini => Format::Ini,
+            #[cfg(feature = "hjson")]
+            FileFormat::Hjson => Format::Hjson,
+        }
+    }
+}
+
+/// A parser of the settings documents of a custom format, returning their
+/// top-level table (e.g. with the `default` and `production` tables).
+pub type Parser =
+    dyn Fn(&str) -> Result<HashMap<String, Value>, ConfigError> + Send + Sync;
+
+/// The parsers of the custom formats, by file extension, e.g.
+///
+/// ```rust
+/// use std::collections::HashMap;
+/// use hydroconf::{FormatRegistry, Value};
+///
+/// let formats = FormatRegistry::new().register("conf", |text| {
+///     let mut table: HashMap<String, Value> = HashMap::new();
+///     for line in text.lines().filter(|l| !l.trim().is_empty()) {
+///         let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+///         table.insert(key.to_string(), value.trim().into());
+///     }
+///     Ok(HashMap::from([("default".to_string(), table.into())]))
+/// });
+/// assert_eq!(formats.extensions().collect::<Vec<_>>(), ["conf"]);
+/// ```
+///
+/// The registered extensions are discovered after the built-in ones (e.g.
+/// `settings.conf` after `settings.toml`), and their parsers win over the
+/// built-in ones when loading a file.
+#[derive(Clone, Default)]
+pub struct FormatRegistry {
+    parsers: Vec<(String, Arc<Parser>)>,
+}
+
+impl FormatRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the parser of the files with extension `ext` (without the
+    /// dot), replacing the one already registered for it.
+    pub fn register<F>(mut self, ext: &str, parser: F) -> Self
+    where
+        F: Fn(&str) -> Result<HashMap<String, Value>, ConfigError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        let ext = ext.trim_start_matches('.');
+        self.parsers.retain(|(e, _)| e != ext);
+        self.parsers.push((ext.to_string(), Arc::new(parser)));
+        self
+    }
+
+    /// The registered extensions, in order of registration.
+    pub fn extensions(&self) -> impl Iterator<Item = &str> {
+        self.parsers.iter().map(|(ext, _)| ext.as_str())
+    }
+
+    /// The parser registered for the extension `ext`, if any.
+    pub(crate) fn get(&self, ext: &str) -> Option<&Parser> {
+        self.parsers
+            .iter()
+            .find(|(e, _)| e == ext)
+            .map(|(_, parser)| parser.as_ref())
+    }
+}
+
+impl fmt::Debug for FormatRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.extensions()).finish()
+    }
+}
+
+// Parsers can't be compared, so registries are equal when they share them.
+impl PartialEq for FormatRegistry {
+    fn eq(&self, other: &Self) -> bool {
+        self.parsers.len() == other.parsers.len()
+            && self.parsers.iter().zip(&other.parsers).all(
+                |((ext, parser), (other_ext, other_parser))| {
+                    ext == other_ext && Arc::ptr_eq(parser, other_parser)
+                },
+            )
+    }
+}
+
+/// A source for the table returned by the parser of a custom format.
+pub(crate) fn table_source(
+    table: HashMap<String, Value>,
+) -> Box<dyn Source + Send + Sync> {
+    let mut config = Config::default();
+    config.cache = table.into();
+    Box::new(config)
+}
+
+/// A source for a dotenv document, whose keys (e.g. `PG__HOST`) are split
+/// on `sep` and lowercased, in the table `section`.
+fn env_file(
+    text: &str,
+    section: &str,
+    sep: &str,
+) -> Result<Box<dyn Source + Send + Sync>, ConfigError> {
+    let vars = parse_dotenv(text)
+        .map_err(|cause| ConfigError::FileParse { uri: None, cause })?;
+    let kvs = vars.into_iter().map(|(key, value)| {
+        (format!("{}{}{}", section, sep, key.to_lowercase()), value)
+    });
+    tree_source(kvs, sep)
+}
+
+/// A source for `(key, value)` pairs, whose keys are split on `sep`.
+fn tree_source<K, I>(
+    kvs: I,
+    sep: &str,
+) -> Result<Box<dyn Source + Send + Sync>, ConfigError>
+where
+    K: AsRef<str>,
+    I: IntoIterator<Item = (K, String)>,
+{
+    let mut config = Config::default();
+    config.cache = key_tree(kvs, sep).into();
+    Ok(Box::new(config))
+}
+
+/// A source for a document that Hydroconf parses itself, loaded as JSON.
+#[cfg(any(
+    feature = "json5",
+    feature = "hcl",
+    feature = "xml",
+    feature = "dhall",
+    feature = "nestedtext",
+    feature = "msgpack",
+    feature = "cbor"
+))]
+fn json_source(
+    json: JsonValue,
+) -> Result<Box<dyn Source + Send + Sync>, ConfigError> {
+    Ok(Box::new(File::from_str(
+        &json.to_string(),
+        FileFormat::Json,
+    )))
+}
+
+fn parse_error<E>(e: E) -> ConfigError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    ConfigError::FileParse {
+        uri: None,
+        cause: Box::new(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{get_data_path, TempDir};
+
+    #[test]
+    fn test_from_extension() {
+        assert_eq!(Format::from_extension("toml"), Some(Format::Toml));
+        assert_eq!(Format::from_extension("yml"), Some(Format::Yaml));
+        assert_eq!(Format::from_extension("txt"), None);
+        for ext in Format::EXTENSIONS {
+            assert!(Format::from_extension(ext).is_some(), "{}", ext);
+        }
+    }
+
+    #[test]
+    fn test_parse_ini() {
+        let text = "\
+[default]
+pg.port = 5432
+[default.pg.replica]
+host = replica-0
+[production.pg]
+host = db-0
+";
+        let m = Format::Ini.parse(text).unwrap().collect().unwrap();
+        let pg = m["default"].clone().into_table().unwrap()["pg"]
+            .clone()
+            .into_table()
+            .unwrap();
+        assert_eq!(pg["port"].clone().into_int().unwrap(), 5432);
+        let replica = pg["replica"].clone().into_table().unwrap();
+        assert_eq!(replica["host"].clone().into_str().unwrap(), "replica-0");
+        let pg = m["production"].clone().into_table().unwrap()["pg"]
+            .clone()
+            .into_table()
+            .unwrap();
+        assert_eq!(pg["host"].clone().into_str().unwrap(), "db-0");
+    }
+
+    #[test]
+    fn test_parse_properties() {
+        let text = "default.pg.port = 5432\nproduction.pg.host = db-0\n";
+        let m = Format::Properties.parse(text).unwrap().collect().unwrap();
+        let pg = m["default"].clone().into_table().unwrap()["pg"]
+            .clone()
+            .into_table()
+            .unwrap();
+        assert_eq!(pg["port"].clone().into_int().unwrap(), 5432);
+        let pg = m["production"].clone().into_table().unwrap()["pg"]
+            .clone()
+            .into_table()
+            .unwrap();
+        assert_eq!(pg["host"].clone().into_str().unwrap(), "db-0");
+    }
+
+    #[test]
+    fn test_env_file() {
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(get_data_path("23"))
+                .set_env("production".into())
+                .set_envvar_prefix("ENVFILETEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_str("pg.host").unwrap(), "db-0");
+        assert_eq!(hydro.get_int("pg.port").unwrap(), 5432);
+        assert!(!hydro.get_bool("debug").unwrap());
+
+        let m = Format::Env.parse("PG__HOST=a").unwrap().collect().unwrap();
+        let default = m["default"].clone().into_table().unwrap();
+        assert!(default.contains_key("pg"));
+    }
+
+    /// `section.key value` lines.
+    fn parse_conf(text: &str) -> Result<HashMap<String, Value>, ConfigError> {
+        let kvs = text
+            .lines()
+            .map(|line| {
+                let (key, value) = line.split_once(' ').ok_or_else(|| {
+                    ConfigError::FileParse {
+                        uri: None,
+                        cause: format!("no value for {}", line).into(),
+                    }
+                })?;
+                Ok((key, value.to_string()))
+            })
+            .collect::<Result<Vec<_>, ConfigError>>()?;
+        Ok(key_tree(kvs, "."))
+    }
+
+    #[test]
+    fn test_format_registry() {
+        let formats = FormatRegistry::new()
+            .register("conf", parse_conf)
+            .register(".ini", parse_conf);
+        assert_eq!(formats.extensions().collect::<Vec<_>>(), ["conf", "ini"]);
+        assert_eq!(formats, formats.clone());
+        assert_ne!(
+            formats,
+            FormatRegistry::new().register("conf", parse_conf)
+        );
+        assert_eq!(format!("{:?}", formats), r#"["conf", "ini"]"#);
+
+        let dir = TempDir::new("registry");
+        std::fs::write(
+            dir.join("settings.conf"),
+            "default.host localhost\nproduction.host db-0\n",
+        )
+        .unwrap();
+        // Parsed as `.conf`, not as INI.
+        std::fs::write(dir.join("secrets.ini"), "default.password hunter2")
+            .unwrap();
+        let settings = HydroSettings::default()
+            .set_root_path(dir.to_path_buf())
+            .set_env("production".into())
+            .set_envvar_prefix("REGISTRYTEST".into())
+            .set_secrets_file(dir.join("secrets.ini"))
+            .set_format_registry(formats);
+        let sources = crate::FileSources::from_root_with_formats(
+            dir.to_path_buf(),
+            "production",
+            &settings.format_registry,
+        );
+        assert_eq!(sources.settings, Some(dir.join("settings.conf")));
+        let mut hydro = crate::Hydroconf::new(settings);
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_str("host").unwrap(), "db-0");
+        assert_eq!(hydro.get_str("password").unwrap(), "hunter2");
+
+        std::fs::write(dir.join("settings.conf"), "default.host\n").unwrap();
+        let err = hydro.hydrate_in_place().unwrap_err();
+        assert!(matches!(err, ConfigError::FileParse { uri: Some(_), .. }));
+    }
+
+    #[cfg(feature = "json5")]
+    #[test]
+    fn test_parse_json5() {
+        let text = "\
+// The defaults.
+{
+  default: {
+    host: 'localhost',
+    ports: [8080, 8081,],
+    ratio: .5,
+  },
+}
+";
+        let m = Format::Json5.parse(text).unwrap().collect().unwrap();
+        let table = m["default"].clone().into_table().unwrap();
+        assert_eq!(table["host"].clone().into_str().unwrap(), "localhost");
+        assert_eq!(table["ports"].clone().into_array().unwrap().len(), 2);
+        assert_eq!(table["ratio"].clone().into_float().unwrap(), 0.5);
+
+        let err = Format::Json5.parse("{default: ").err().unwrap();
+        assert!(matches!(err, ConfigError::FileParse { uri: None, .. }));
+    }
+
+    #[cfg(feature = "hcl")]
+    #[test]
+    fn test_parse_hcl() {
+        let text = r#"
+default {
+  debug = false
+  server "web" {
+    port  = 8080
+    hosts = ["a", "b"]
+  }
+}
+
+production {
+  debug = true
+}
+"#;
+        let m = Format::Hcl.parse(text).unwrap().collect().unwrap();
+        let default = m["default"].clone().into_table().unwrap();
+        assert!(!default["debug"].clone().into_bool().unwrap());
+        let web = default["server"].clone().into_table().unwrap()["web"]
+            .clone()
+            .into_table()
+            .unwrap();
+        assert_eq!(web["port"].clone().into_int().unwrap(), 8080);
+        assert_eq!(web["hosts"].clone().into_array().unwrap().len(), 2);
+        let production = m["production"].clone().into_table().unwrap();
+        assert!(production["debug"].clone().into_bool().unwrap());
+
+        let err = Format::Hcl.parse("default {").err().unwrap();
+        assert!(matches!(err, ConfigError::FileParse { uri: None, .. }));
+    }
+
+    #[cfg(feature = "json5")]
+    #[test]
+    fn test_hydrate_json5() {
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(get_data_path("24"))
+                .set_env("production".into())
+                .set_envvar_prefix("JSON5TEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("port").unwrap(), 2);
+    }
+
+    #[cfg(feature = "dhall")]
+    #[test]
+    fn test_parse_dhall() {
+        let text = "\
+let Pg = { host : Text, port : Natural }
+let pg : Pg = { host = \"localhost\", port = 5432 }
+in  { default = { pg, replicas = [] : List Pg } }
+";
+        let m = Format::Dhall.parse(text).unwrap().collect().unwrap();
+        let default = m["default"].clone().into_table().unwrap();
+        let pg = default["pg"].clone().into_table().unwrap();
+        assert_eq!(pg["port"].clone().into_int().unwrap(), 5432);
+        assert!(default["replicas"].clone().into_array().unwrap().is_empty());
+
+        // Ill-typed.
+        let err = Format::Dhall.parse("{ default = 1 + \"a\" }").err();
+        assert!(matches!(err, Some(ConfigError::FileParse { .. })));
+    }
+
+    #[cfg(feature = "dhall")]
+    #[test]
+    fn test_hydrate_dhall() {
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(get_data_path("25"))
+                .set_envvar_prefix("DHALLTEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("pg.port").unwrap(), 5432);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_hydrate_msgpack() {
+        let settings = serde_json::json!({
+            "default": {"pg": {"port": 5432}, "hosts": ["a", "b"]},
+            "production": {"pg": {"port": 6432}},
+        });
+        let bytes = rmp_serde::to_vec(&settings).unwrap();
+        let m = Format::MessagePack.parse_bytes(&bytes).unwrap();
+        let default = m.collect().unwrap()["default"].clone();
+        let hosts = default.into_table().unwrap()["hosts"].clone();
+        assert_eq!(hosts.into_array().unwrap().len(), 2);
+        let err = Format::MessagePack.parse_bytes(&[0xc1]).err().unwrap();
+        assert!(matches!(err, ConfigError::FileParse { uri: None, .. }));
+
+        let dir = TempDir::new("msgpack");
+        std::fs::write(dir.join("settings.msgpack"), &bytes).unwrap();
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(dir.to_path_buf())
+                .set_env("production".into())
+                .set_envvar_prefix("MSGPACKTEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("pg.port").unwrap(), 6432);
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_parse_cbor() {
+        let settings = serde_json::json!({
+            "default": {"pg": {"host": "localhost", "port": 5432}},
+        });
+        let mut bytes = Vec::new();
+        ciborium::into_writer(&settings, &mut bytes).unwrap();
+        let m = Format::Cbor.parse_bytes(&bytes).unwrap().collect().unwrap();
+        let pg = m["default"].clone().into_table().unwrap()["pg"]
+            .clone()
+            .into_table()
+            .unwrap();
+        assert_eq!(pg["host"].clone().into_str().unwrap(), "localhost");
+        assert_eq!(pg["port"].clone().into_int().unwrap(), 5432);
+        let err = Format::Cbor.parse_bytes(&[0xff]).err().unwrap();
+        assert!(matches!(err, ConfigError::FileParse { uri: None, .. }));
+    }
+}
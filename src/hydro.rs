@@ -1,22 +1,260 @@
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
-pub use config::{Config, ConfigError, Environment, File, Value};
+pub use config::{
+    Config, ConfigError, Environment, File, FileFormat, Source, Value,
+};
 use dotenv_parser::parse_dotenv;
+#[cfg(feature = "watch")]
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 
-use crate::settings::HydroSettings;
-use crate::sources::FileSources;
-use crate::utils::path_to_string;
+use crate::age_file;
+use crate::builder::HydroconfBuilder;
+use crate::cast;
+use crate::diff::{same_value, Diff};
+use crate::directory::DirectorySource;
+use crate::encrypted;
+use crate::formats::{table_source, Format};
+use crate::include::{expand_glob, included_files, is_glob, INCLUDE_KEY};
+use crate::interpolate;
+use crate::merge;
+use crate::origin::Origin;
+use crate::pipeline::{Pipeline, Stage};
+use crate::remote::read_remote;
+#[cfg(feature = "consul")]
+use crate::remote::ConsulSource;
+#[cfg(feature = "doppler")]
+use crate::remote::DopplerSource;
+#[cfg(feature = "onepassword")]
+use crate::remote::OnePasswordSource;
+#[cfg(feature = "vault")]
+use crate::remote::VaultSource;
+#[cfg(feature = "git")]
+use crate::remote::{cache_dir, checkout};
+use crate::settings::{self, HydroSettings, Layer, ListMerge, UnknownKeys};
+use crate::sops;
+use crate::sources::{
+    file_format, find_all_settings, find_fragments, find_settings,
+    platform_config_dir, walk_to_root, FileSources, SYSTEM_CONFIG_DIR,
+};
+use crate::template;
+use crate::tokens;
+use crate::units::{HumanBytes, HumanDuration};
+use crate::utils::{
+    env_vars, flatten_table, from_json, is_url, path_to_string, read_bytes,
+    read_stdin, read_text, to_json, walk_strings,
+};
+use crate::validate::{Invariants, Validator};
+#[cfg(feature = "watch")]
+use crate::watch::{Callbacks, FileCache, WatchedSource, Watcher};
 
 type Table = HashMap<String, Value>;
 
+/// The key of an environment table naming the environment it inherits
+/// from, e.g. `_inherit = "production"` in `[staging]`.
+const INHERIT_KEY: &str = "_inherit";
+
+/// The environment table merged after the current one, whatever it is.
+const GLOBAL_ENV: &str = "global";
+
+/// The key of an environment table holding the overrides of each tenant,
+/// e.g. `[production.tenants.acme]`.
+const TENANTS_KEY: &str = "tenants";
+
+/// Where Docker mounts the secrets of a service.
+const DOCKER_SECRETS_DIR: &str = "/run/secrets";
+
+/// The settings file path that stands for the standard input.
+pub const STDIN_PATH: &str = "-";
+
+/// Key fragments that usually denote a secret value.
+pub const SECRET_PATTERNS: &[&str] = &["password", "token", "secret"];
+
+/// Look up an environment table in the loaded files, ignoring the case of its
+/// name. If a namespace is given, the table is searched inside the namespace
+/// table. Return the full path of the table along with it.
+fn env_table(
+    config: &Config,
+    namespace: Option<&str>,
+    name: &str,
+) -> Option<(String, Table)> {
+    let mut root = config.cache.clone().into_table().ok()?;
+    let mut prefix = String::new();
+    if let Some(namespace) = namespace {
+        root = root.remove(namespace)?.into_table().ok()?;
+        prefix = format!("{}.", namespace);
+    }
+    root.into_iter()
+        .find(|(key, _)| key.to_lowercase() == name.to_lowercase())
+        .and_then(|(key, value)| {
+            Some((prefix + &key, value.into_table().ok()?))
+        })
+}
+
+/// The path giving the format of a settings file: the format of
+/// `settings.toml.age` or `settings.toml.j2` is the one of `settings.toml`.
+fn format_path(path: &Path) -> PathBuf {
+    if age_file::is_encrypted(path) || template::is_template(path) {
+        path.with_extension("")
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// The value of an override, typed by its `@int`, `@float`, `@bool`,
+/// `@json` or `@str` token, or `value` itself if it has none.
+fn cast_value(
+    key: &str,
+    value: Value,
+    origin: &Origin,
+) -> Result<Value, ConfigError> {
+    let cast = cast::cast(&value.clone().into_str()?).map_err(|e| {
+        ConfigError::Message(format!("{} in {} ({})", e, key, origin))
+    })?;
+    Ok(cast.unwrap_or(value))
+}
+
+/// Whether the segments of `key` match the ones of `pattern`, in which a `*`
+/// stands for one or more segments. Keys are matched case-insensitively.
+fn key_matches(pattern: &[&str], key: &[&str]) -> bool {
+    match (pattern.split_first(), key.split_first()) {
+        (None, None) => true,
+        (Some((&"*", rest)), Some((_, key_rest))) => {
+            key_matches(rest, key_rest) || key_matches(pattern, key_rest)
+        }
+        (Some((segment, rest)), Some((key_segment, key_rest))) => {
+            segment.eq_ignore_ascii_case(key_segment)
+                && key_matches(rest, key_rest)
+        }
+        _ => false,
+    }
+}
+
+/// The error listing the reasons why the settings are invalid.
+fn invalid(errors: Vec<String>) -> ConfigError {
+    ConfigError::Message(format!("invalid settings: {}", errors.join("; ")))
+}
+
+/// Where the warnings go, see `Hydroconf::on_warning`.
+#[derive(Clone)]
+struct Warnings(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl Warnings {
+    fn warn(&self, message: &str) {
+        (self.0)(message)
+    }
+}
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Self(Arc::new(|message| eprintln!("hydroconf: {}", message)))
+    }
+}
+
+impl fmt::Debug for Warnings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Warnings")
+    }
+}
+
+/// Whether the dotted `key` is set in `table`.
+fn has_key(table: &Value, key: &str) -> bool {
+    let mut value = table.clone();
+    for segment in key.split('.') {
+        value = match value
+            .into_table()
+            .ok()
+            .and_then(|mut t| t.remove(segment))
+        {
+            Some(value) => value,
+            None => return false,
+        };
+    }
+    true
+}
+
+/// Read a binary settings file (e.g. MessagePack), which must be a local,
+/// unencrypted file: stdin, the URLs and the age files are read as text.
+fn read_binary(path: &Path) -> Result<Vec<u8>, ConfigError> {
+    if path == Path::new(STDIN_PATH)
+        || is_url(path)
+        || age_file::is_encrypted(path)
+    {
+        return Err(ConfigError::Message(format!(
+            "{} can't be read: binary settings must be local, unencrypted \
+             files",
+            path.display()
+        )));
+    }
+    read_bytes(path)
+}
+
+/// Add the path of a settings file to its parse errors.
+fn with_uri(path: &Path, e: ConfigError) -> ConfigError {
+    match e {
+        ConfigError::FileParse { uri: None, cause } => {
+            ConfigError::FileParse {
+                uri: path_to_string(path.to_path_buf()),
+                cause,
+            }
+        }
+        e => e,
+    }
+}
+
+/// The keys of `old` and `new` whose values differ, ignoring where they were
+/// read from.
+fn changed_keys(old: &Table, new: &Table) -> BTreeSet<String> {
+    old.keys()
+        .chain(new.keys())
+        .filter(|&key| match (old.get(key), new.get(key)) {
+            (Some(a), Some(b)) => !same_value(a, b),
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+/// A source registered with `Hydroconf::add_source()` and co.
+#[derive(Debug, Clone)]
+enum ExtraSource {
+    Source(Box<dyn Source + Send + Sync>),
+    /// A directory of key files, read in the section of the environment
+    /// being hydrated, which can change after it's registered.
+    Directory(PathBuf),
+}
+
 #[derive(Debug, Clone)]
 pub struct Hydroconf {
     config: Config,
     orig_config: Config,
+    settings_config: Config,
+    secrets_config: Config,
     hydro_settings: HydroSettings,
     sources: FileSources,
+    loaded_keys: Vec<(Origin, BTreeSet<String>)>,
+    extra_sources: Vec<(Origin, ExtraSource)>,
+    skip_discovery: bool,
+    base_config: Option<Config>,
+    origins: BTreeMap<String, Vec<Origin>>,
+    // The values given to `set_default` and `set`, to apply them again when
+    // refreshing.
+    defaults: Vec<(String, Value)>,
+    overrides: Vec<(String, Value)>,
+    validators: Vec<(String, Validator)>,
+    invariants: Invariants,
+    warnings: Warnings,
+    pipeline: Pipeline,
+    #[cfg(feature = "watch")]
+    callbacks: Callbacks,
+    #[cfg(feature = "watch")]
+    file_cache: Option<FileCache>,
+    #[cfg(feature = "watch")]
+    watched_sources: Vec<Arc<dyn WatchedSource + Send + Sync>>,
 }
 
 impl Default for Hydroconf {
@@ -30,66 +268,1054 @@ impl Hydroconf {
         Self {
             config: Config::default(),
             orig_config: Config::default(),
+            settings_config: Config::default(),
+            secrets_config: Config::default(),
             hydro_settings,
             sources: FileSources::default(),
+            loaded_keys: Vec::new(),
+            extra_sources: Vec::new(),
+            skip_discovery: false,
+            base_config: None,
+            origins: BTreeMap::new(),
+            defaults: Vec::new(),
+            overrides: Vec::new(),
+            validators: Vec::new(),
+            invariants: Invariants::default(),
+            warnings: Warnings::default(),
+            pipeline: Pipeline::default(),
+            #[cfg(feature = "watch")]
+            callbacks: Callbacks::default(),
+            #[cfg(feature = "watch")]
+            file_cache: None,
+            #[cfg(feature = "watch")]
+            watched_sources: Vec::new(),
         }
     }
 
-    pub fn hydrate<'de, T: Deserialize<'de>>(
+    /// Use `base` as the lowest layer of the configuration: everything that
+    /// Hydroconf loads afterwards (files, `.env`, environment variables) is
+    /// merged on top of it.
+    pub fn with_base_config(mut self, base: Config) -> Self {
+        self.base_config = Some(base);
+        self
+    }
+
+    pub fn builder() -> HydroconfBuilder {
+        HydroconfBuilder::default()
+    }
+
+    /// Register an additional source, layered on top of the discovered
+    /// settings and secrets files. Like those files, it must be split in
+    /// `default` and per-environment tables.
+    pub fn add_source<T>(&mut self, source: T) -> &mut Self
+    where
+        T: Source + Send + Sync + 'static,
+    {
+        self.add_boxed_source(Box::new(source))
+    }
+
+    /// Register an additional source of secrets, e.g. a `VaultSource`. Its
+    /// values are treated like the ones of the secrets file.
+    pub fn add_secrets_source<T>(&mut self, source: T) -> &mut Self
+    where
+        T: Source + Send + Sync + 'static,
+    {
+        let name = format!("source #{}", self.extra_sources.len() + 1);
+        self.extra_sources.push((
+            Origin::SecretSource(name),
+            ExtraSource::Source(Box::new(source)),
+        ));
+        self
+    }
+
+    /// Register a directory of key files, e.g. a Kubernetes ConfigMap or
+    /// Secret volume (see `DirectorySource`). Its values are placed in the
+    /// current environment, on top of the discovered settings files.
+    pub fn add_directory<P: Into<PathBuf>>(&mut self, path: P) -> &mut Self {
+        let path = path.into();
+        let name = format!("directory {}", path.display());
+        self.extra_sources
+            .push((Origin::Source(name), ExtraSource::Directory(path)));
+        self
+    }
+
+    /// Like `add_directory`, but the values are treated like the ones of the
+    /// secrets file.
+    pub fn add_secrets_directory<P: Into<PathBuf>>(
+        &mut self,
+        path: P,
+    ) -> &mut Self {
+        let path = path.into();
+        let name = format!("directory {}", path.display());
+        self.extra_sources
+            .push((Origin::SecretSource(name), ExtraSource::Directory(path)));
+        self
+    }
+
+    fn directory_source(&self, path: PathBuf) -> DirectorySource {
+        DirectorySource::new(path)
+            .set_nested_sep(self.hydro_settings.envvar_nested_sep.clone())
+            .set_section(
+                self.hydro_settings
+                    .current_section(&self.hydro_settings.envvar_nested_sep),
+            )
+    }
+
+    pub(crate) fn add_boxed_source(
+        &mut self,
+        source: Box<dyn Source + Send + Sync>,
+    ) -> &mut Self {
+        let name = format!("source #{}", self.extra_sources.len() + 1);
+        self.extra_sources
+            .push((Origin::Source(name), ExtraSource::Source(source)));
+        self
+    }
+
+    /// If `skip` is true, no file is discovered on the filesystem: only the
+    /// registered sources and the environment variables are used.
+    pub fn skip_discovery(&mut self, skip: bool) -> &mut Self {
+        self.skip_discovery = skip;
+        self
+    }
+
+    pub fn hydrate<'de, T: Deserialize<'de> + 'static>(
         mut self,
     ) -> Result<T, ConfigError> {
-        self.discover_sources();
-        self.load_settings()?;
-        self.merge_settings()?;
-        self.override_from_dotenv()?;
-        self.override_from_env()?;
+        self.hydrate_in_place()?;
         self.try_into()
     }
 
-    pub fn discover_sources(&mut self) {
-        self.sources = self
-            .root_path()
-            .map(|p| {
-                FileSources::from_root(p, self.hydro_settings.env.as_str())
+    /// Like `hydrate()`, also merging the overrides of `tenant`, e.g.
+    /// `[production.tenants.acme]` after `[production]` for `"acme"`.
+    pub fn hydrate_for_tenant<'de, T: Deserialize<'de> + 'static>(
+        mut self,
+        tenant: &str,
+    ) -> Result<T, ConfigError> {
+        self.hydro_settings.tenant = Some(tenant.into());
+        self.hydrate()
+    }
+
+    /// Run all the hydration steps without deserializing the result, so that
+    /// the merged configuration can still be inspected (e.g. with
+    /// `explain()`).
+    pub fn hydrate_in_place(&mut self) -> Result<&mut Self, ConfigError> {
+        for stage in self.pipeline.stages(&self.hydro_settings)? {
+            stage.run(self)?;
+        }
+        Ok(self)
+    }
+
+    /// The names of the hydration stages, in order, e.g. `load_settings`,
+    /// `merge_settings`, `override_from_dotenv` and `override_from_env`.
+    pub fn stage_names(&self) -> Result<Vec<String>, ConfigError> {
+        let stages = self.pipeline.stages(&self.hydro_settings)?;
+        Ok(stages.iter().map(|s| s.name().to_string()).collect())
+    }
+
+    /// Run `stage` before the stage named `name` when hydrating. The stages
+    /// that depend on the settings, such as the layers, are fixed once the
+    /// pipeline is changed.
+    pub fn insert_stage_before<S: Stage + 'static>(
+        &mut self,
+        name: &str,
+        stage: S,
+    ) -> Result<&mut Self, ConfigError> {
+        let settings = &self.hydro_settings;
+        self.pipeline
+            .insert(settings, name, false, Arc::new(stage))?;
+        Ok(self)
+    }
+
+    /// Like `insert_stage_before`, but after the stage named `name`.
+    pub fn insert_stage_after<S: Stage + 'static>(
+        &mut self,
+        name: &str,
+        stage: S,
+    ) -> Result<&mut Self, ConfigError> {
+        let settings = &self.hydro_settings;
+        self.pipeline
+            .insert(settings, name, true, Arc::new(stage))?;
+        Ok(self)
+    }
+
+    pub fn replace_stage<S: Stage + 'static>(
+        &mut self,
+        name: &str,
+        stage: S,
+    ) -> Result<&mut Self, ConfigError> {
+        let settings = &self.hydro_settings;
+        self.pipeline.replace(settings, name, Arc::new(stage))?;
+        Ok(self)
+    }
+
+    pub fn remove_stage(
+        &mut self,
+        name: &str,
+    ) -> Result<&mut Self, ConfigError> {
+        self.pipeline.remove(&self.hydro_settings, name)?;
+        Ok(self)
+    }
+
+    /// Fail if the keys declared with `HydroSettings::set_secret_keys()` are
+    /// set in the settings files, in any environment, or in the `.env`
+    /// files.
+    pub fn check_secret_keys(&self) -> Result<&Self, ConfigError> {
+        let patterns = &self.hydro_settings.secret_keys;
+        if patterns.is_empty() {
+            return Ok(self);
+        }
+        let is_secret = |key: &str| {
+            let key: Vec<_> = key.split('.').collect();
+            patterns.iter().any(|pattern| {
+                let pattern: Vec<_> = pattern.split('.').collect();
+                key_matches(&pattern, &key)
             })
-            .unwrap_or_else(|| FileSources::default());
+        };
+        let namespace = self
+            .hydro_settings
+            .namespace
+            .as_ref()
+            .map(|namespace| format!("{}.", namespace));
+        let mut leaked = BTreeMap::<&str, Vec<String>>::new();
+        for (origin, keys) in &self.loaded_keys {
+            if !matches!(origin, Origin::Settings(_)) {
+                continue;
+            }
+            for key in keys {
+                let key = match namespace {
+                    Some(ref namespace) => match key.strip_prefix(namespace) {
+                        Some(key) => key,
+                        None => continue,
+                    },
+                    None => key.as_str(),
+                };
+                // The first segment is the environment table.
+                match key.split_once('.') {
+                    Some((_, key)) if is_secret(key) => {
+                        leaked.entry(key).or_default().push(origin.to_string())
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (key, origins) in &self.origins {
+            for origin in origins {
+                if matches!(origin, Origin::Dotenv(_)) && is_secret(key) {
+                    leaked.entry(key).or_default().push(origin.to_string());
+                }
+            }
+        }
+        if leaked.is_empty() {
+            return Ok(self);
+        }
+        let leaked: Vec<_> = leaked
+            .into_iter()
+            .map(|(key, mut origins)| {
+                origins.dedup();
+                format!("{} (from {})", key, origins.join(", "))
+            })
+            .collect();
+        Err(ConfigError::Message(format!(
+            "secret settings outside of the secrets: {}",
+            leaked.join("; ")
+        )))
+    }
+
+    /// Fail if a `*_FOR_HYDRO` variable has an invalid value, or unless the
+    /// environment is one of the environments given to
+    /// `HydroSettings::set_allowed_envs()`.
+    pub fn check_env(&self) -> Result<&Self, ConfigError> {
+        settings::check_vars()?;
+        let allowed = &self.hydro_settings.allowed_envs;
+        if allowed.is_empty() {
+            return Ok(self);
+        }
+        match self
+            .hydro_settings
+            .envs()
+            .into_iter()
+            .find(|env| !allowed.iter().any(|a| a == env))
+        {
+            Some(env) => Err(ConfigError::Message(format!(
+                "unknown environment {} (expected one of {})",
+                env,
+                allowed.join(", ")
+            ))),
+            None => Ok(self),
+        }
+    }
+
+    /// Fail unless all the required keys are set, listing the missing ones
+    /// along with the environments that define them.
+    pub fn check_required(&self) -> Result<&Self, ConfigError> {
+        let root = match self.hydro_settings.namespace {
+            Some(ref namespace) => self.orig_config.get::<Table>(namespace),
+            None => self.orig_config.cache.clone().into_table(),
+        }
+        .unwrap_or_default();
+        let mut required = self.hydro_settings.required.clone();
+        if let Some(Ok(declared)) = root
+            .get("required")
+            .map(|v| v.clone().try_into::<Vec<String>>())
+        {
+            required.extend(declared);
+        }
+        let mut seen = BTreeSet::new();
+        let missing: Vec<String> = required
+            .into_iter()
+            .filter(|key| seen.insert(key.clone()))
+            .filter(|key| self.config.get::<Value>(key).is_err())
+            .map(|key| {
+                let envs: BTreeSet<&str> = root
+                    .iter()
+                    .filter(|(_, table)| has_key(table, &key))
+                    .map(|(env, _)| env.as_str())
+                    .collect();
+                if envs.is_empty() {
+                    format!("{} (not set in any environment)", key)
+                } else {
+                    let envs: Vec<_> = envs.into_iter().collect();
+                    format!("{} (set in {})", key, envs.join(", "))
+                }
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok(self);
+        }
+        Err(ConfigError::Message(format!(
+            "missing required settings: {}",
+            missing.join("; ")
+        )))
+    }
+
+    /// Check the value of `key` with `validator` after each hydration, before
+    /// the settings are deserialized. See `Validator` for the built-in ones.
+    pub fn add_validator<V>(&mut self, key: &str, validator: V) -> &mut Self
+    where
+        V: Into<Validator>,
+    {
+        self.validators.push((key.to_string(), validator.into()));
+        self
+    }
+
+    /// Run the validators, and report all the invalid keys along with the
+    /// layer their values come from.
+    pub fn validate(&self) -> Result<&Self, ConfigError> {
+        let errors: Vec<String> = self
+            .validators
+            .iter()
+            .filter_map(|(key, validator)| {
+                let value = self.config.get::<Value>(key).ok();
+                let reason = validator.check(value.as_ref()).err()?;
+                Some(match self.origins(key).last() {
+                    Some(origin) => {
+                        format!("{} {} (from {})", key, reason, origin)
+                    }
+                    None => format!("{} {}", key, reason),
+                })
+            })
+            .collect();
+        if errors.is_empty() {
+            return Ok(self);
+        }
+        Err(invalid(errors))
+    }
+
+    /// Check the deserialized settings with `f` (e.g. that `tls.key` is set
+    /// whenever `tls.cert` is), which returns all the errors it found.
+    /// Hydrating the settings as another type than `T` is an error.
+    pub fn add_invariant<T, F>(&mut self, f: F) -> &mut Self
+    where
+        T: 'static,
+        F: Fn(&T) -> Result<(), Vec<String>> + Send + Sync + 'static,
+    {
+        self.invariants.push(f);
+        self
+    }
+
+    /// Clone or update the configured git repository, and use it as the
+    /// root path.
+    #[cfg(feature = "git")]
+    pub fn checkout_git_repo(&mut self) -> Result<&mut Self, ConfigError> {
+        if let Some(ref url) = self.hydro_settings.git_url {
+            let reference = self.hydro_settings.git_ref.as_deref();
+            let dir = checkout(url, reference, &cache_dir())?;
+            self.hydro_settings.root_path = Some(dir);
+            self.hydro_settings.root_path_by_env.clear();
+        }
+        Ok(self)
+    }
+
+    pub fn discover_sources(&mut self) {
+        if self.skip_discovery {
+            self.sources = FileSources::default();
+            return;
+        }
+        let mut candidates = Vec::new();
+        for root_path in self.root_paths() {
+            let mut walk = walk_to_root(root_path);
+            if let Some(depth) = self.hydro_settings.max_walk_depth {
+                walk.truncate(depth + 1);
+            }
+            for dir in walk {
+                if !candidates.contains(&dir) {
+                    candidates.push(dir);
+                }
+            }
+        }
+        let walk_len = candidates.len();
+        // The platform directory is searched last, if there's nothing in
+        // the project.
+        candidates.extend(
+            self.hydro_settings
+                .app_name
+                .as_deref()
+                .and_then(platform_config_dir),
+        );
+        self.sources = FileSources::from_dirs(
+            candidates.clone(),
+            self.hydro_settings.env.as_str(),
+            &self.hydro_settings.format_registry,
+            &self.hydro_settings.settings_dirs,
+        );
+        let mut matched_settings = Vec::new();
+        if let Some(ref settings_file) = self.hydro_settings.settings_file {
+            let pattern = settings_file.to_string_lossy();
+            if !is_url(settings_file) && is_glob(&pattern) {
+                matched_settings = expand_glob(Path::new(""), &pattern);
+            }
+            // If the pattern matches nothing, loading it fails as for a
+            // missing file.
+            let settings_file = if matched_settings.is_empty() {
+                settings_file.clone()
+            } else {
+                matched_settings.remove(0)
+            };
+            self.sources.fragments = if is_url(&settings_file)
+                || settings_file == Path::new(STDIN_PATH)
+            {
+                Vec::new()
+            } else {
+                find_fragments(
+                    &settings_file,
+                    &self.hydro_settings.format_registry,
+                )
+            };
+            self.sources.settings = Some(settings_file);
+        }
+        if self.hydro_settings.cascade_settings {
+            let mut parents = find_all_settings(
+                &candidates[..walk_len],
+                &self.hydro_settings.format_registry,
+                &self.hydro_settings.settings_dirs,
+            );
+            parents.retain(|parent| {
+                self.sources.settings.as_ref() != Some(parent)
+            });
+            // The closest settings file wins.
+            parents.reverse();
+            self.sources.parent_settings = parents;
+        }
+        // The other files matched by the settings file pattern come first.
+        matched_settings.extend(self.hydro_settings.settings_files.clone());
+        self.sources.extra_settings = matched_settings;
+        if let Some(ref secrets_file) = self.hydro_settings.secrets_file {
+            self.sources.secrets = Some(secrets_file.clone());
+        }
+        // There's no system-wide directory on Windows.
+        if let Some(app) =
+            self.hydro_settings.app_name.as_ref().filter(|_| cfg!(unix))
+        {
+            let dir = Path::new(SYSTEM_CONFIG_DIR).join(app);
+            self.sources.system =
+                find_settings(&dir, &self.hydro_settings.format_registry)
+                    .filter(|system| {
+                        self.sources.settings.as_ref() != Some(system)
+                    });
+        }
     }
 
     pub fn load_settings(&mut self) -> Result<&mut Self, ConfigError> {
-        if let Some(ref settings_path) = self.sources.settings {
-            self.orig_config.merge(File::from(settings_path.clone()))?;
+        let sources = self.sources.clone();
+        for path in sources
+            .system
+            .into_iter()
+            .chain(sources.parent_settings)
+            .chain(sources.settings)
+            .chain(sources.extra_settings)
+            .chain(sources.fragments)
+        {
+            self.load_file(path.clone(), Origin::Settings(path))?;
+        }
+        #[cfg(feature = "consul")]
+        if let Some(ref prefix) = self.hydro_settings.consul_prefix {
+            let source = ConsulSource::from_env(prefix);
+            let name = format!("consul {}/{}", source.addr(), prefix);
+            self.load_source(Box::new(source), Origin::Source(name))?;
+        }
+        #[cfg(feature = "vault")]
+        let load_secrets_file = match self.hydro_settings.vault.clone() {
+            Some(vault) => {
+                let name = format!("vault {}", vault.addr);
+                let source =
+                    VaultSource::new(vault, self.hydro_settings.current_env());
+                self.load_source(
+                    Box::new(source),
+                    Origin::SecretSource(name),
+                )?;
+                false
+            }
+            None => true,
+        };
+        #[cfg(not(feature = "vault"))]
+        let load_secrets_file = true;
+        if self.hydro_settings.docker_secrets {
+            self.load_secrets_directory(PathBuf::from(DOCKER_SECRETS_DIR))?;
+        }
+        if let Some(path) = self.hydro_settings.credentials_directory.clone() {
+            self.load_secrets_directory(path)?;
+        }
+        if let Some(secrets_path) = self.sources.secrets.clone() {
+            if load_secrets_file {
+                self.load_file(
+                    secrets_path.clone(),
+                    Origin::Secrets(secrets_path),
+                )?;
+            }
+        }
+        #[cfg(feature = "doppler")]
+        if let Some(source) = DopplerSource::from_env() {
+            let name = format!("doppler {}", source.api_host());
+            let source = source
+                .set_nested_sep(self.hydro_settings.envvar_nested_sep.clone())
+                .set_section(
+                    self.hydro_settings.current_section(
+                        &self.hydro_settings.envvar_nested_sep,
+                    ),
+                );
+            self.load_source(Box::new(source), Origin::SecretSource(name))?;
         }
-        if let Some(ref secrets_path) = self.sources.secrets {
-            self.orig_config.merge(File::from(secrets_path.clone()))?;
+        #[cfg(feature = "onepassword")]
+        if !self.hydro_settings.onepassword_refs.is_empty() {
+            let mut source =
+                OnePasswordSource::from_env().ok_or_else(|| {
+                    ConfigError::Message(
+                    "OP_CONNECT_HOST and OP_CONNECT_TOKEN are required to \
+                     resolve the 1Password references"
+                        .into(),
+                )
+                })?;
+            for (key, reference) in &self.hydro_settings.onepassword_refs {
+                source = source.add_ref(key.clone(), reference.clone());
+            }
+            let name = format!("1password {}", source.host());
+            let source =
+                source.set_section(self.hydro_settings.current_section("."));
+            self.load_source(Box::new(source), Origin::SecretSource(name))?;
+        }
+        if let Some(source) = self.hydro_settings.command.clone() {
+            let name = format!("command {}", source.display());
+            self.load_source(Box::new(source), Origin::SecretSource(name))?;
+        }
+        for (origin, source) in self.extra_sources.clone() {
+            let source = match source {
+                ExtraSource::Source(source) => source,
+                ExtraSource::Directory(path) => {
+                    Box::new(self.directory_source(path))
+                }
+            };
+            self.load_source(source, origin)?;
         }
 
         Ok(self)
     }
 
+    fn load_file(
+        &mut self,
+        path: PathBuf,
+        origin: Origin,
+    ) -> Result<(), ConfigError> {
+        self.load_included_file(path, origin, &[])
+    }
+
+    /// Load a settings file, then the files it includes, if any. `parents`
+    /// are the files including it.
+    fn load_included_file(
+        &mut self,
+        path: PathBuf,
+        origin: Origin,
+        parents: &[PathBuf],
+    ) -> Result<(), ConfigError> {
+        if parents.contains(&path) {
+            let chain: Vec<_> = parents
+                .iter()
+                .chain([&path])
+                .map(|p| p.display().to_string())
+                .collect();
+            return Err(ConfigError::Message(format!(
+                "circular {} {}",
+                INCLUDE_KEY,
+                chain.join(" -> ")
+            )));
+        }
+        let mut table = self.file_table(&path, origin.is_secret())?;
+        let include = table.remove(INCLUDE_KEY);
+        // A `settings.env` file is already in the section of the current
+        // environment.
+        if self.hydro_settings.flat_files
+            && self.file_format(&path) != Some(Format::Env)
+        {
+            let section = self.hydro_settings.current_section(".");
+            for name in section.rsplit('.') {
+                table = Table::from([(name.to_string(), table.into())]);
+            }
+        }
+        self.load_source(table_source(table), origin.clone())
+            .map_err(|e| with_uri(&path, e))?;
+        if let Some(include) = include {
+            let parents = [parents, std::slice::from_ref(&path)].concat();
+            for included in included_files(&path, include)? {
+                let origin = match origin {
+                    Origin::Secrets(_) => Origin::Secrets(included.clone()),
+                    _ => Origin::Settings(included.clone()),
+                };
+                self.sources.included.push(included.clone());
+                self.load_included_file(included, origin, &parents)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The top-level table of a settings file. With the `watch` feature, a
+    /// local file is only read the first time: its table is then taken from
+    /// the cache.
+    fn file_table(
+        &self,
+        path: &Path,
+        secret: bool,
+    ) -> Result<Table, ConfigError> {
+        #[cfg(feature = "watch")]
+        if let Some(ref cache) = self.file_cache {
+            if path != Path::new(STDIN_PATH) && !is_url(path) {
+                let cached = cache.lock().unwrap().get(path).cloned();
+                if let Some(table) = cached {
+                    return Ok(table);
+                }
+                let table = self.read_table(path, secret)?;
+                cache.lock().unwrap().insert(path.into(), table.clone());
+                return Ok(table);
+            }
+        }
+        self.read_table(path, secret)
+    }
+
+    /// The built-in format of a settings file, if any.
+    fn file_format(&self, path: &Path) -> Option<Format> {
+        if path == Path::new(STDIN_PATH) {
+            Some(self.hydro_settings.stdin_format)
+        } else {
+            file_format(&format_path(path))
+        }
+    }
+
+    fn read_table(
+        &self,
+        path: &Path,
+        secret: bool,
+    ) -> Result<Table, ConfigError> {
+        self.file_source(path, secret)?
+            .collect()
+            .map_err(|e| with_uri(path, e))
+    }
+
+    /// Parse a settings file, in the format of its extension.
+    fn file_source(
+        &self,
+        path: &Path,
+        secret: bool,
+    ) -> Result<Box<dyn Source + Send + Sync>, ConfigError> {
+        let (custom, format) = if path == Path::new(STDIN_PATH) {
+            (None, Some(self.hydro_settings.stdin_format))
+        } else {
+            let format_path = format_path(path);
+            let ext = format_path.extension().and_then(|e| e.to_str());
+            let registry = &self.hydro_settings.format_registry;
+            (ext.and_then(|e| registry.get(e)), file_format(&format_path))
+        };
+        let with_uri = |e| with_uri(path, e);
+        Ok(match (custom, format) {
+            // The parsers of the custom formats win over the built-in ones.
+            (Some(parser), _) => {
+                let text = self.read_file(path, None, secret)?;
+                table_source(parser(&text).map_err(with_uri)?)
+            }
+            (_, Some(format)) if format.is_binary() => {
+                format.parse_bytes(&read_binary(path)?).map_err(with_uri)?
+            }
+            (_, Some(format)) => {
+                let text = self.read_file(path, Some(format), secret)?;
+                format
+                    .parse_file(&text, path, &self.hydro_settings)
+                    .map_err(with_uri)?
+            }
+            (_, None) => Box::new(File::from(path.to_path_buf())),
+        })
+    }
+
+    /// Read a settings file, or stdin if `path` is `-`. It's fetched if it's
+    /// a URL (without caching it if it's a `secret` file), and decrypted if
+    /// it's encrypted.
+    fn read_file(
+        &self,
+        path: &Path,
+        format: Option<Format>,
+        secret: bool,
+    ) -> Result<String, ConfigError> {
+        let mut text = if path == Path::new(STDIN_PATH) {
+            read_stdin()?
+        } else if age_file::is_encrypted(path) {
+            let bytes = if is_url(path) {
+                read_remote(&path.to_string_lossy(), secret)?.into_bytes()
+            } else {
+                read_bytes(path)?
+            };
+            #[cfg(feature = "age")]
+            let identity = self.hydro_settings.age_identity.as_deref();
+            #[cfg(not(feature = "age"))]
+            let identity = None;
+            age_file::decrypt(&bytes, identity)?
+        } else if is_url(path) {
+            read_remote(&path.to_string_lossy(), secret)?
+        } else {
+            read_text(path, &self.hydro_settings.encoding)?
+        };
+        if sops::is_encrypted(path, &text) {
+            text = sops::decrypt(&text, format)?;
+        }
+        if template::is_template(path) {
+            text = template::render(&text, &self.template_context()?)
+                .map_err(|e| {
+                    ConfigError::Message(format!(
+                        "could not render {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+        }
+        Ok(text)
+    }
+
+    /// The variables of the templates: `env`, the environment variables,
+    /// `hydro_env`, the current environment, and `settings`, the tables of
+    /// the files and sources loaded before.
+    fn template_context(&self) -> Result<serde_json::Value, ConfigError> {
+        let env: serde_json::Map<_, _> =
+            env_vars().map(|(k, v)| (k, v.into())).collect();
+        Ok(serde_json::json!({
+            "env": env,
+            "hydro_env": self.hydro_settings.current_env(),
+            "settings": to_json(self.orig_config.cache.clone())?,
+        }))
+    }
+
+    fn load_secrets_directory(
+        &mut self,
+        path: PathBuf,
+    ) -> Result<(), ConfigError> {
+        let name = format!("directory {}", path.display());
+        let source = self.directory_source(path);
+        self.load_source(Box::new(source), Origin::SecretSource(name))
+    }
+
+    fn load_source(
+        &mut self,
+        source: Box<dyn Source + Send + Sync>,
+        origin: Origin,
+    ) -> Result<(), ConfigError> {
+        let mut cache = Value::from(Table::new());
+        source.collect_to(&mut cache)?;
+        cache = self.expand_values(cache, &origin)?;
+        let mut config = Config::default();
+        config.cache = cache;
+        let keys = flatten_table(&config.cache.clone().into_table()?)
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        if origin.is_secret() {
+            self.secrets_config.merge(config.clone())?;
+        } else {
+            self.settings_config.merge(config.clone())?;
+        }
+        self.loaded_keys.push((origin, keys));
+        self.orig_config.merge(config)?;
+        Ok(())
+    }
+
+    /// Expand the `${VAR}` references to the environment variables in the
+    /// string values of a settings file, then decode the `@base64` values of
+    /// any source.
+    fn expand_values(
+        &self,
+        value: Value,
+        origin: &Origin,
+    ) -> Result<Value, ConfigError> {
+        let strict = self.hydro_settings.strict_interpolation;
+        let expand_env =
+            matches!(origin, Origin::Settings(_) | Origin::Secrets(_));
+        let mut json = to_json(value.clone())?;
+        let mut expanded_any = false;
+        walk_strings(&mut json, "", &mut |path, value| {
+            let error = |e| {
+                ConfigError::Message(format!("{} in {} ({})", e, path, origin))
+            };
+            if let Some(s) =
+                value.as_str().filter(|s| expand_env && s.contains('$'))
+            {
+                expanded_any = true;
+                *value =
+                    interpolate::expand_env(s, strict).map_err(error)?.into();
+            }
+            if let Some(data) = value.as_str().and_then(cast::base64_value) {
+                expanded_any = true;
+                *value = cast::decode_base64(data).map_err(error)?;
+            }
+            Ok(())
+        })?;
+        // Going through JSON drops the file names of the values, which
+        // are only kept if there was nothing to expand.
+        Ok(if expanded_any { from_json(json) } else { value })
+    }
+
     pub fn merge_settings(&mut self) -> Result<&mut Self, ConfigError> {
-        for &name in &["default", self.hydro_settings.env.as_str()] {
-            let table_value: Option<Table> = self.orig_config.get(name).ok();
-            if let Some(value) = table_value {
-                let mut new_config = Config::default();
-                new_config.cache = value.into();
-                self.config.merge(new_config)?;
+        // A `Config` that was never mutated has no table at its root.
+        if let Some(Ok(base)) = self
+            .base_config
+            .as_ref()
+            .map(|base| base.cache.clone().into_table())
+        {
+            self.record_origins(&base, Origin::Base);
+            let mut base_config = Config::default();
+            base_config.cache = base.into();
+            self.config.merge(base_config)?;
+        }
+        let namespace = self.hydro_settings.namespace.clone();
+        let namespace = namespace.as_deref();
+        if self.hydro_settings.require_env {
+            for env in self.hydro_settings.envs() {
+                if env_table(&self.orig_config, namespace, env).is_some() {
+                    continue;
+                }
+                let table = match namespace {
+                    Some(namespace) => format!("{}.{}", namespace, env),
+                    None => env.to_string(),
+                };
+                return Err(ConfigError::Message(format!(
+                    "the settings have no [{}] table for the environment {}",
+                    table, env
+                )));
             }
         }
+        for (table_name, mut value) in self.env_tables(&self.orig_config)? {
+            let mut lower = self.table();
+            let settings = &self.hydro_settings;
+            let strategy = merge::Strategy {
+                lists: &|key| settings.list_merge_of(key),
+                shallow: settings.shallow_merge,
+            };
+            if merge::apply_markers(&mut lower, &mut value, &strategy)
+                .map_err(|e| {
+                    ConfigError::Message(format!("{} in [{}]", e, table_name))
+                })?
+            {
+                self.rebuild_config(lower)?;
+            }
+            for (key, _) in flatten_table(&value) {
+                let raw_key = format!("{}.{}", table_name, key);
+                if let Some(origin) = self.loaded_origin(&raw_key) {
+                    self.record_origin(&key, origin);
+                }
+            }
+            let mut new_config = Config::default();
+            new_config.cache = value.into();
+            self.config.merge(new_config)?;
+        }
 
         Ok(self)
     }
 
-    pub fn override_from_dotenv(&mut self) -> Result<&mut Self, ConfigError> {
-        for dotenv_path in &self.sources.dotenv {
-            let source = std::fs::read_to_string(dotenv_path.clone())
-                .map_err(|e| ConfigError::FileParse {
-                    uri: path_to_string(dotenv_path.clone()),
-                    cause: e.into(),
+    /// The names of the environment tables to merge, from the lowest to the
+    /// highest priority: `default`, then each of the environments preceded
+    /// by the ones it inherits from with `_inherit`, and `global`.
+    fn env_layers(&self) -> Result<Vec<String>, ConfigError> {
+        let mut layers = vec!["default".to_string()];
+        for env in self.hydro_settings.envs() {
+            for name in self.inheritance_chain(env)? {
+                if !layers.contains(&name) {
+                    layers.push(name);
+                }
+            }
+        }
+        layers.push(GLOBAL_ENV.into());
+        Ok(layers)
+    }
+
+    /// The tables of `config` to merge, in the order of `env_layers()`, along
+    /// with their full paths. If a tenant is set, each table is followed by
+    /// the one of the tenant in its `tenants` table, e.g.
+    /// `production.tenants.acme` after `production`.
+    fn env_tables(
+        &self,
+        config: &Config,
+    ) -> Result<Vec<(String, Table)>, ConfigError> {
+        let namespace = self.hydro_settings.namespace.as_deref();
+        let tenant = self.hydro_settings.tenant.as_deref();
+        let mut tables = Vec::new();
+        for name in self.env_layers()? {
+            let (table_name, mut table) =
+                match env_table(config, namespace, &name) {
+                    Some(found) => found,
+                    None => continue,
+                };
+            table.remove(INHERIT_KEY);
+            let tenants = match tenant {
+                Some(_) => table.remove(TENANTS_KEY),
+                None => None,
+            };
+            tables.push((table_name.clone(), table));
+            let (tenant, tenants) = match (tenant, tenants) {
+                (Some(tenant), Some(tenants)) => (tenant, tenants),
+                _ => continue,
+            };
+            let tenants = tenants.into_table().map_err(|_| {
+                ConfigError::Message(format!(
+                    "{} must be a table in [{}]",
+                    TENANTS_KEY, table_name
+                ))
+            })?;
+            let found = tenants
+                .into_iter()
+                .find(|(key, _)| key.to_lowercase() == tenant.to_lowercase());
+            if let Some((key, value)) = found {
+                let tenant_name =
+                    format!("{}.{}.{}", table_name, TENANTS_KEY, key);
+                let value = value.into_table().map_err(|_| {
+                    ConfigError::Message(format!(
+                        "{} must be a table",
+                        tenant_name
+                    ))
                 })?;
+                tables.push((tenant_name, value));
+            }
+        }
+        Ok(tables)
+    }
+
+    /// The environments `env` inherits from with `_inherit`, from the most
+    /// distant one, followed by `env`.
+    fn inheritance_chain(
+        &self,
+        env: &str,
+    ) -> Result<Vec<String>, ConfigError> {
+        let namespace = self.hydro_settings.namespace.as_deref();
+        let mut chain = vec![env.to_string()];
+        while let Some((table_name, table)) =
+            env_table(&self.orig_config, namespace, &chain[chain.len() - 1])
+        {
+            let parent = match table.get(INHERIT_KEY) {
+                Some(parent) => parent.clone().into_str().map_err(|_| {
+                    ConfigError::Message(format!(
+                        "{} must be the name of an environment in [{}]",
+                        INHERIT_KEY, table_name
+                    ))
+                })?,
+                None => break,
+            };
+            if parent.eq_ignore_ascii_case("default") {
+                break;
+            }
+            if chain.iter().any(|name| name.eq_ignore_ascii_case(&parent)) {
+                chain.push(parent);
+                return Err(ConfigError::Message(format!(
+                    "circular environment inheritance {}",
+                    chain.join(" -> ")
+                )));
+            }
+            if env_table(&self.orig_config, namespace, &parent).is_none() {
+                return Err(ConfigError::Message(format!(
+                    "[{}] inherits from {}, which has no table",
+                    table_name, parent
+                )));
+            }
+            chain.push(parent);
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// The last loaded file or source that defined `raw_key`.
+    fn loaded_origin(&self, raw_key: &str) -> Option<Origin> {
+        self.loaded_keys
+            .iter()
+            .rev()
+            .find(|(_, keys)| keys.contains(raw_key))
+            .map(|(origin, _)| origin.clone())
+    }
+
+    fn record_origin(&mut self, key: &str, origin: Origin) {
+        self.origins
+            .entry(key.to_string())
+            .or_default()
+            .push(origin);
+    }
+
+    fn record_origins(&mut self, table: &Table, origin: Origin) {
+        for (key, _) in flatten_table(table) {
+            self.record_origin(&key, origin.clone());
+        }
+    }
+
+    /// The path of an overridden key, where the numeric segments index the
+    /// arrays: `servers.0.host` is `servers[0].host` if `servers` is an
+    /// array, so that only this element is overridden. The index must be
+    /// one of an element of the array.
+    fn override_path(&self, key: &str) -> Result<String, ConfigError> {
+        let mut path = String::new();
+        for segment in key.split('.') {
+            let array = segment
+                .parse::<usize>()
+                .ok()
+                .filter(|_| !path.is_empty())
+                .and_then(|index| {
+                    Some((index, self.config.get_array(&path).ok()?))
+                });
+            match array {
+                Some((index, items)) if index < items.len() => {
+                    path = format!("{}[{}]", path, index);
+                }
+                Some((index, items)) => {
+                    return Err(ConfigError::Message(format!(
+                        "index {} of {} is out of range ({} has {} items)",
+                        index,
+                        key,
+                        path,
+                        items.len()
+                    )))
+                }
+                None if path.is_empty() => path.push_str(segment),
+                None => path = format!("{}.{}", path, segment),
+            }
+        }
+        Ok(path)
+    }
+
+    pub fn override_from_dotenv(&mut self) -> Result<&mut Self, ConfigError> {
+        for dotenv_path in &self.sources.dotenv.clone() {
+            let source =
+                read_text(dotenv_path, &self.hydro_settings.encoding)?;
             let map =
                 parse_dotenv(&source).map_err(|e| ConfigError::FileParse {
                     uri: path_to_string(dotenv_path.clone()),
-                    cause: e.into(),
+                    cause: e,
                 })?;
 
             for (key, val) in map.iter() {
@@ -106,7 +1332,13 @@ impl Hydroconf {
                 }
                 let sep = self.hydro_settings.envvar_nested_sep.clone();
                 key = key.replace(&sep, ".");
-                self.config.set::<String>(&key, val.into())?;
+                let path = self.override_path(&key)?;
+                let origin = Origin::Dotenv(dotenv_path.clone());
+                let value =
+                    cast_value(&key, Value::from(val.as_str()), &origin)?;
+                let value = self.merge_value(&key, value, &origin)?;
+                self.config.set(&path, value)?;
+                self.record_origin(&key, origin);
             }
         }
 
@@ -114,34 +1346,707 @@ impl Hydroconf {
     }
 
     pub fn override_from_env(&mut self) -> Result<&mut Self, ConfigError> {
-        self.config.merge(
-            Environment::with_prefix(
-                self.hydro_settings.envvar_prefix.as_str(),
-            )
-            .separator(self.hydro_settings.envvar_nested_sep.as_str()),
-        )?;
+        // Like `config::Environment`, which panics on the variables that
+        // aren't valid Unicode instead of skipping them.
+        let prefix =
+            format!("{}_", self.hydro_settings.envvar_prefix).to_lowercase();
+        let sep = self.hydro_settings.envvar_nested_sep.to_lowercase();
+        let vars: Vec<_> = env_vars().collect();
+        for (name, val) in &vars {
+            let key = match name.to_lowercase().strip_prefix(&prefix) {
+                Some(key) if !sep.is_empty() => key.replace(&sep, "."),
+                Some(key) => key.to_string(),
+                None => continue,
+            };
+            self.set_env_value(key, Value::from(val.as_str()))?;
+        }
+
+        let mut matched = Vec::new();
+        for (name, val) in &vars {
+            for (re, template) in &self.hydro_settings.env_regex_map {
+                if let Some(caps) = re.captures(name) {
+                    let mut key = String::new();
+                    caps.expand(template, &mut key);
+                    matched.push((key.to_lowercase(), val.as_str()));
+                }
+            }
+        }
+        for (key, val) in matched {
+            self.set_env_value(key, Value::from(val))?;
+        }
 
         Ok(self)
     }
 
+    /// Set the value of an environment variable at `key`.
+    fn set_env_value(
+        &mut self,
+        key: String,
+        value: Value,
+    ) -> Result<(), ConfigError> {
+        let (key, value) = self.env_file_value(key, value)?;
+        let value = cast_value(&key, value, &Origin::Environment)?;
+        let value = self.merge_value(&key, value, &Origin::Environment)?;
+        // Set as an override, like the `.env` values, so that the last
+        // layer applied wins.
+        let path = self.override_path(&key)?;
+        self.config.set(&path, value)?;
+        self.record_origin(&key, Origin::Environment);
+        Ok(())
+    }
+
+    /// The value of an override merged with the current value of `key` if
+    /// it has a `@merge` token.
+    fn merge_value(
+        &self,
+        key: &str,
+        value: Value,
+        origin: &Origin,
+    ) -> Result<Value, ConfigError> {
+        let below = self.config.get::<Value>(key).ok();
+        let merged =
+            merge::merge_token(&value, below.as_ref()).map_err(|e| {
+                ConfigError::Message(format!("{} in {} ({})", e, key, origin))
+            })?;
+        Ok(merged.unwrap_or(value))
+    }
+
+    /// Follow the `_FILE` convention of the Docker images: the value of
+    /// `HYDRO_PG__PASSWORD_FILE` is the path of the file holding the value of
+    /// `pg.password`, if the settings have a `pg.password` key but no
+    /// `pg.password_file` one. Otherwise, it's the value of
+    /// `pg.password_file`, e.g. `HYDRO_LOG_FILE` sets `log_file`.
+    fn env_file_value(
+        &self,
+        key: String,
+        value: Value,
+    ) -> Result<(String, Value), ConfigError> {
+        let target = match key.strip_suffix("_file") {
+            Some(target)
+                if self.config.get::<Value>(target).is_ok()
+                    && self.config.get::<Value>(&key).is_err() =>
+            {
+                target
+            }
+            _ => return Ok((key, value)),
+        };
+        let path = value.into_str()?;
+        let content =
+            tokens::read_value_file(Path::new(&path)).map_err(|e| {
+                ConfigError::Message(format!(
+                    "could not read {} from {}: {}",
+                    target, path, e
+                ))
+            })?;
+        Ok((target.to_string(), Value::from(content)))
+    }
+
+    /// Apply the `.env` files and the environment variables over the merged
+    /// files, then the files again if they come after them in the order of
+    /// `HydroSettings::set_layer_order()`.
+    pub fn apply_layers(&mut self) -> Result<&mut Self, ConfigError> {
+        for layer in self.hydro_settings.override_layers()? {
+            match layer {
+                Layer::Settings => self.reapply_settings()?,
+                Layer::Secrets => self.reapply_secrets()?,
+                Layer::Dotenv => self.override_from_dotenv()?,
+                Layer::Env => self.override_from_env()?,
+            };
+        }
+        Ok(self)
+    }
+
+    /// Apply the `default` and current environment values of the secrets
+    /// files again, so that they take precedence over `.env` files and
+    /// environment variables.
+    pub fn reapply_secrets(&mut self) -> Result<&mut Self, ConfigError> {
+        self.reapply_files(true)
+    }
+
+    /// Like `reapply_secrets`, for the settings files and the added sources.
+    pub fn reapply_settings(&mut self) -> Result<&mut Self, ConfigError> {
+        self.reapply_files(false)
+    }
+
+    fn reapply_files(
+        &mut self,
+        secret: bool,
+    ) -> Result<&mut Self, ConfigError> {
+        let config = if secret {
+            &self.secrets_config
+        } else {
+            &self.settings_config
+        };
+        let config = config.clone();
+        for (table_name, mut value) in self.env_tables(&config)? {
+            // The tables can't be replaced anymore, but the lists are
+            // still appended to the current ones, which already have
+            // the items of the secrets.
+            let settings = &self.hydro_settings;
+            let lists = |key: &str| match settings.list_merge_of(key) {
+                ListMerge::Replace => ListMerge::Replace,
+                _ => ListMerge::Unique,
+            };
+            let strategy = merge::Strategy {
+                lists: &lists,
+                shallow: false,
+            };
+            merge::apply_markers(&mut self.table(), &mut value, &strategy)
+                .map_err(|e| {
+                    ConfigError::Message(format!("{} in [{}]", e, table_name))
+                })?;
+            for (key, value) in flatten_table(&value) {
+                let raw_key = format!("{}.{}", table_name, key);
+                self.config.set(&key, value)?;
+                let origin = self
+                    .loaded_keys
+                    .iter()
+                    .rev()
+                    .find(|(origin, keys)| {
+                        origin.is_secret() == secret && keys.contains(&raw_key)
+                    })
+                    .map(|(origin, _)| origin.clone());
+                if let Some(origin) = origin {
+                    self.record_origin(&key, origin);
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Replace the `@file:<path>` values with the content of the files,
+    /// without their trailing newlines.
+    pub fn resolve_files(&mut self) -> Result<&mut Self, ConfigError> {
+        let mut json = to_json(self.config.cache.clone())?;
+        if tokens::resolve_files(&mut json)? {
+            self.replace_config(json)?;
+        }
+        Ok(self)
+    }
+
+    /// Replace the `@uuid` and `@random:N` values with new values, generated
+    /// again at every hydration.
+    pub fn generate_values(&mut self) -> Result<&mut Self, ConfigError> {
+        let mut json = to_json(self.config.cache.clone())?;
+        if tokens::generate_values(&mut json)? {
+            self.replace_config(json)?;
+        }
+        Ok(self)
+    }
+
+    /// Decrypt the `ENC[AES256,...]` values with the key in
+    /// `DECRYPT_KEY_FOR_HYDRO`.
+    pub fn decrypt_values(&mut self) -> Result<&mut Self, ConfigError> {
+        let mut json = to_json(self.config.cache.clone())?;
+        if encrypted::decrypt_values(&mut json)? {
+            self.replace_config(json)?;
+        }
+        Ok(self)
+    }
+
+    /// Replace the `${dotted.key}` references in the values with the values
+    /// of the keys, and render the `@format` values, once all the layers are
+    /// merged.
+    pub fn resolve_references(&mut self) -> Result<&mut Self, ConfigError> {
+        let json = to_json(self.config.cache.clone())?;
+        let resolvable = |key: &str| self.holds_references(key);
+        let resolved = interpolate::resolve_keys(&json, &resolvable)
+            .map_err(ConfigError::Message)?;
+        if resolved != json {
+            self.replace_config(resolved)?;
+        }
+        Ok(self)
+    }
+
+    /// Whether the value of `key` can reference other keys: only the values
+    /// of the settings files and sources can, while the ones of the secrets,
+    /// the `.env` files and the environment variables are taken as they are.
+    /// The origin of an item of a list is the one of the list.
+    fn holds_references(&self, key: &str) -> bool {
+        let mut key = key;
+        loop {
+            if let Some(origin) = self.origins(key).last() {
+                return matches!(
+                    origin,
+                    Origin::Default
+                        | Origin::Base
+                        | Origin::Settings(_)
+                        | Origin::Source(_)
+                        | Origin::Override
+                );
+            }
+            match key.rsplit_once('.') {
+                Some((parent, _)) => key = parent,
+                None => return true,
+            }
+        }
+    }
+
+    /// Replace the `@keyring:<service>/<user>` values with the passwords
+    /// stored in the platform credential store.
+    #[cfg(feature = "keyring")]
+    pub fn resolve_keyring(&mut self) -> Result<&mut Self, ConfigError> {
+        let mut json = to_json(self.config.cache.clone())?;
+        if tokens::resolve_keyring(&mut json, tokens::keyring_lookup)? {
+            self.replace_config(json)?;
+        }
+        Ok(self)
+    }
+
+    /// Resolve the relative paths at the keys declared with
+    /// `HydroSettings::set_path_keys()` against the directory of the file
+    /// that set them. The values of the other layers are left as they are.
+    pub fn resolve_paths(&mut self) -> Result<&mut Self, ConfigError> {
+        let patterns = &self.hydro_settings.path_keys;
+        if patterns.is_empty() {
+            return Ok(self);
+        }
+        let patterns: Vec<Vec<&str>> =
+            patterns.iter().map(|p| p.split('.').collect()).collect();
+        let mut resolved = Vec::new();
+        for (key, value) in flatten_table(&self.table()) {
+            let segments: Vec<_> = key.split('.').collect();
+            if !patterns.iter().any(|p| key_matches(p, &segments)) {
+                continue;
+            }
+            let file = match self.origins(&key).last() {
+                Some(
+                    Origin::Settings(path)
+                    | Origin::Secrets(path)
+                    | Origin::Dotenv(path),
+                ) if !path.to_string_lossy().contains("://") => path,
+                _ => continue,
+            };
+            let dir = file.parent().unwrap_or_else(|| Path::new(""));
+            let dir = if dir.is_absolute() {
+                dir.to_path_buf()
+            } else {
+                std::env::current_dir()
+                    .map_err(|e| ConfigError::Foreign(Box::new(e)))?
+                    .join(dir)
+            };
+            let resolve = |value: Value| -> Result<Value, ConfigError> {
+                let path = PathBuf::from(value.clone().into_str()?);
+                Ok(if path.is_relative() && !path.as_os_str().is_empty() {
+                    dir.join(path).to_string_lossy().into_owned().into()
+                } else {
+                    value
+                })
+            };
+            let value = match value.clone().into_array() {
+                Ok(items) => items
+                    .into_iter()
+                    .map(resolve)
+                    .collect::<Result<Vec<_>, _>>()?
+                    .into(),
+                Err(_) => resolve(value)?,
+            };
+            resolved.push((key, value));
+        }
+        for (key, value) in resolved {
+            self.config.set(&key, value)?;
+        }
+        Ok(self)
+    }
+
+    /// Strip surrounding whitespace (e.g. the trailing newline of a value
+    /// read from a file) from every string value in the merged configuration.
+    pub fn trim_values(&mut self) -> Result<&mut Self, ConfigError> {
+        let mut json = to_json(self.config.cache.clone())?;
+        walk_strings(&mut json, "", &mut |_, value| {
+            if let Some(s) = value.as_str() {
+                *value = s.trim().into();
+            }
+            Ok(())
+        })?;
+        self.replace_config(json)?;
+        Ok(self)
+    }
+
+    /// Replace the layers merged so far with `table`, e.g. once merge
+    /// markers have changed them. The values given to `set_default` and
+    /// `set` are applied again, so that they keep their priority over the
+    /// layers merged afterwards.
+    fn rebuild_config(&mut self, table: Table) -> Result<(), ConfigError> {
+        let mut lower_config = Config::default();
+        lower_config.cache = table.into();
+        self.config = Config::default();
+        self.config.merge(lower_config)?;
+        for (key, value) in &self.defaults {
+            self.config.set_default(key, value.clone())?;
+        }
+        for (key, value) in &self.overrides {
+            self.config.set(key, value.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Update the merged configuration to `json`, a transformed copy of it.
+    /// Only the values that changed are set, on top of the layers, so the
+    /// others keep their origin and the `set()` overrides keep winning.
+    fn replace_config(
+        &mut self,
+        json: serde_json::Value,
+    ) -> Result<(), ConfigError> {
+        let old: HashMap<String, Value> =
+            flatten_table(&self.table()).into_iter().collect();
+        let new = from_json(json).into_table().unwrap_or_default();
+        for (key, value) in flatten_table(&new) {
+            match old.get(&key) {
+                Some(previous) if same_value(previous, &value) => {}
+                _ => {
+                    self.config.set(&key, value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the keys loaded from the settings (non-secret) files whose
+    /// name matches one of `patterns`, e.g. `default.pg.password`.
+    /// Matching is a case-insensitive substring test on the last key segment.
+    pub fn audit_secret_leakage(&self, patterns: &[&str]) -> Vec<String> {
+        let patterns: Vec<String> =
+            patterns.iter().map(|p| p.to_lowercase()).collect();
+        let mut leaked = BTreeSet::new();
+        for (origin, keys) in &self.loaded_keys {
+            if let Origin::Settings(_) = origin {
+                for key in keys {
+                    let name = key.rsplit('.').next().unwrap_or(key);
+                    let name = name.to_lowercase();
+                    if patterns.iter().any(|p| name.contains(p.as_str())) {
+                        leaked.insert(key.clone());
+                    }
+                }
+            }
+        }
+        leaked.into_iter().collect()
+    }
+
+    /// The layers that set `key`, from the lowest to the highest priority.
+    /// The last one is the layer whose value is in effect.
+    pub fn origins(&self, key: &str) -> &[Origin] {
+        self.origins.get(key).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// The keys set by several layers: the layers whose values are
+    /// overridden, from the lowest to the highest priority, and the one
+    /// whose value is in effect. The values given to `set_default()` and
+    /// those of an environment table overriding the `default` table of the
+    /// same file aren't listed.
+    pub fn shadowed(&self) -> Vec<(String, Vec<Origin>, Origin)> {
+        let mut shadowed = Vec::new();
+        for (key, origins) in &self.origins {
+            let mut origins: Vec<_> = origins
+                .iter()
+                .filter(|origin| **origin != Origin::Default)
+                .cloned()
+                .collect();
+            origins.dedup();
+            if let Some(effective) = origins.pop() {
+                if !origins.is_empty() {
+                    shadowed.push((key.clone(), origins, effective));
+                }
+            }
+        }
+        shadowed
+    }
+
+    pub(crate) fn warn_shadowed(&self) {
+        for (key, overridden, effective) in self.shadowed() {
+            let overridden: Vec<_> =
+                overridden.iter().map(Origin::to_string).collect();
+            self.warnings.warn(&format!(
+                "{} from {} is overridden by {}",
+                key,
+                overridden.join(", "),
+                effective
+            ));
+        }
+    }
+
+    /// A human-readable report listing each key of the merged configuration,
+    /// its final value and the layer it comes from. Values from the secrets
+    /// layer, or whose name looks like a secret, are masked.
+    pub fn explain(&self) -> String {
+        let table = self.table();
+        let mut rows: Vec<(String, String, String)> = flatten_table(&table)
+            .into_iter()
+            .map(|(key, value)| {
+                let origin = self.origins(&key).last();
+                let name =
+                    key.rsplit('.').next().unwrap_or(&key).to_lowercase();
+                let masked = origin.is_some_and(Origin::is_secret)
+                    || SECRET_PATTERNS.iter().any(|p| name.contains(p));
+                let value = if masked {
+                    "********".to_string()
+                } else {
+                    to_json(value)
+                        .map(|json| json.to_string())
+                        .unwrap_or_default()
+                };
+                let origin = origin
+                    .map(|o| o.to_string())
+                    .unwrap_or_else(|| "unknown".to_string());
+                (key, value, origin)
+            })
+            .collect();
+        rows.sort();
+
+        let header =
+            ("KEY".to_string(), "VALUE".to_string(), "ORIGIN".to_string());
+        let key_width =
+            rows.iter().map(|r| r.0.len()).max().unwrap_or(0).max(3);
+        let value_width =
+            rows.iter().map(|r| r.1.len()).max().unwrap_or(0).max(5);
+        std::iter::once(header)
+            .chain(rows)
+            .map(|(key, value, origin)| {
+                format!(
+                    "{:kw$}  {:vw$}  {}\n",
+                    key,
+                    value,
+                    origin,
+                    kw = key_width,
+                    vw = value_width,
+                )
+            })
+            .collect()
+    }
+
+    /// Hydrate the settings, and hydrate them again whenever one of the
+    /// discovered files changes. See `Watcher`.
+    #[cfg(feature = "watch")]
+    pub fn watch<T>(self) -> Result<Watcher<T>, ConfigError>
+    where
+        T: DeserializeOwned + Send + Sync + 'static,
+    {
+        Watcher::new(self)
+    }
+
+    /// Call `f` with the previous and the new settings whenever a `Watcher`
+    /// reloads them, e.g. to resize a pool or to change the log level. The
+    /// callbacks taking another type than the watched settings are never
+    /// called.
+    #[cfg(feature = "watch")]
+    pub fn on_change<T, F>(&mut self, f: F) -> &mut Self
+    where
+        T: 'static,
+        F: Fn(&T, &T) + Send + Sync + 'static,
+    {
+        self.callbacks.push(f);
+        self
+    }
+
+    /// Register an additional source, like `add_source`, whose changes make
+    /// a `Watcher` reload the settings, e.g. a `ConsulSource` or an
+    /// `EtcdSource`.
+    #[cfg(feature = "watch")]
+    pub fn add_watched_source<T>(&mut self, source: T) -> &mut Self
+    where
+        T: WatchedSource + Clone + Send + Sync + 'static,
+    {
+        self.watched_sources.push(Arc::new(source.clone()));
+        self.add_source(source)
+    }
+
+    /// The sources whose changes are watched, including the Consul prefix
+    /// of the settings.
+    #[cfg(feature = "watch")]
+    pub(crate) fn watched_sources(
+        &self,
+    ) -> Vec<Arc<dyn WatchedSource + Send + Sync>> {
+        #[allow(unused_mut)]
+        let mut sources = self.watched_sources.clone();
+        #[cfg(feature = "consul")]
+        if let Some(ref prefix) = self.hydro_settings.consul_prefix {
+            sources.push(Arc::new(ConsulSource::from_env(prefix)));
+        }
+        sources
+    }
+
+    /// Call `f` with the keys that changed whenever a `Watcher` reloads the
+    /// settings, e.g. to reconnect to the database only when `pg` changed.
+    /// It's not called when nothing changed.
+    #[cfg(feature = "watch")]
+    pub fn on_diff<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&Diff) + Send + Sync + 'static,
+    {
+        self.callbacks.push_diff(f);
+        self
+    }
+
+    #[cfg(feature = "watch")]
+    pub(crate) fn callbacks(&self) -> &Callbacks {
+        &self.callbacks
+    }
+
+    /// Read the local files once, and take their values from `cache` for the
+    /// next hydrations, until it's cleared.
+    #[cfg(feature = "watch")]
+    pub(crate) fn set_file_cache(&mut self, cache: FileCache) {
+        self.file_cache = Some(cache);
+    }
+
+    /// The local files the settings were read from: the system-wide and
+    /// project settings files and their fragments, the secrets, the files
+    /// they include and the `.env` files.
+    #[cfg(feature = "watch")]
+    pub(crate) fn files(&self) -> Vec<PathBuf> {
+        let sources = &self.sources;
+        sources
+            .system
+            .iter()
+            .chain(&sources.parent_settings)
+            .chain(&sources.settings)
+            .chain(&sources.extra_settings)
+            .chain(&sources.fragments)
+            .chain(&sources.secrets)
+            .chain(&sources.included)
+            .chain(&sources.dotenv)
+            .filter(|p| *p != Path::new(STDIN_PATH) && !is_url(p))
+            .cloned()
+            .collect()
+    }
+
     pub fn root_path(&self) -> Option<PathBuf> {
-        self.hydro_settings
-            .root_path
-            .clone()
-            .or_else(|| std::env::current_exe().ok())
+        self.root_paths().into_iter().next()
+    }
+
+    /// The paths the search for the settings files starts from, in order:
+    /// the root path of the current environment or the root path, or if
+    /// there's none, the working directory followed by the executable.
+    pub fn root_paths(&self) -> Vec<PathBuf> {
+        let root_path = self
+            .hydro_settings
+            .root_path_by_env
+            .get(self.hydro_settings.current_env())
+            .or(self.hydro_settings.root_path.as_ref());
+        match root_path {
+            Some(root_path) => vec![root_path.clone()],
+            None => std::env::current_dir()
+                .into_iter()
+                .chain(std::env::current_exe())
+                .collect(),
+        }
+    }
+
+    pub fn try_into<'de, T: Deserialize<'de> + 'static>(
+        self,
+    ) -> Result<T, ConfigError> {
+        let invariants = self.invariants.clone();
+        let settings = self.deserialize()?;
+        let errors = invariants.check(&settings)?;
+        if errors.is_empty() {
+            Ok(settings)
+        } else {
+            Err(invalid(errors))
+        }
+    }
+
+    fn deserialize<'de, T: Deserialize<'de>>(self) -> Result<T, ConfigError> {
+        let unknown_keys = self.hydro_settings.unknown_keys;
+        if unknown_keys == UnknownKeys::Allow {
+            return self.config.try_into();
+        }
+        let mut unknown = Vec::new();
+        let value = serde_ignored::deserialize(self.config, |path| {
+            unknown.push(path.to_string())
+        })?;
+        unknown.sort();
+        if unknown_keys == UnknownKeys::Deny && !unknown.is_empty() {
+            return Err(ConfigError::Message(format!(
+                "unknown settings: {}",
+                unknown.join(", ")
+            )));
+        }
+        for key in unknown {
+            self.warnings
+                .warn(&format!("unknown setting {}, ignored", key));
+        }
+        Ok(value)
+    }
+
+    /// Call `f` with the warnings of the hydration (e.g. the unknown keys,
+    /// see `HydroSettings::set_unknown_keys`) instead of printing them on
+    /// the standard error, e.g. to log them.
+    pub fn on_warning<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&str) + Send + Sync + 'static,
+    {
+        self.warnings = Warnings(Arc::new(f));
+        self
+    }
+
+    /// Read all the sources again and rebuild the merged configuration in
+    /// place, keeping the values given to `set` and `set_default`. Return
+    /// the top-level keys whose values changed, were added or were removed.
+    pub fn refresh(&mut self) -> Result<BTreeSet<String>, ConfigError> {
+        let (old, new) = self.reload()?;
+        Ok(changed_keys(&old, &new))
+    }
+
+    /// Like `refresh`, but return every key that changed, by dotted path,
+    /// along with its previous and new values.
+    pub fn refresh_diff(&mut self) -> Result<Diff, ConfigError> {
+        let (old, new) = self.reload()?;
+        Ok(Diff::new(&old, &new))
+    }
+
+    /// Rebuild the merged configuration, and return the previous and the new
+    /// one.
+    fn reload(&mut self) -> Result<(Table, Table), ConfigError> {
+        let old = self.table();
+        self.clear();
+        self.hydrate_in_place()?;
+        self.apply_sets()?;
+        Ok((old, self.table()))
+    }
+
+    /// Forget the loaded values, but not the sources.
+    fn clear(&mut self) {
+        self.config = Config::default();
+        self.orig_config = Config::default();
+        self.settings_config = Config::default();
+        self.secrets_config = Config::default();
+        self.loaded_keys.clear();
+        self.origins.clear();
     }
 
-    pub fn try_into<'de, T: Deserialize<'de>>(self) -> Result<T, ConfigError> {
-        self.config.try_into()
+    /// Apply the values given to `set_default` and `set` again.
+    fn apply_sets(&mut self) -> Result<(), ConfigError> {
+        for (key, value) in std::mem::take(&mut self.defaults) {
+            self.set_default(&key, value)?;
+        }
+        for (key, value) in std::mem::take(&mut self.overrides) {
+            self.set(&key, value)?;
+        }
+        Ok(())
     }
 
-    //pub fn refresh(&mut self) -> Result<&mut Self, ConfigError> {
-    //self.orig_config.refresh()?;
-    //self.config.cache = Value::new(None, Table::new());
-    //self.merge()?;
-    //self.override_from_env()?;
-    //Ok(self)
-    //}
+    /// A copy for the environment `env` (e.g. `production`), with the same
+    /// sources, settings and values given to `set`, to hydrate on its own.
+    /// Unlike `ENV_FOR_HYDRO`, this doesn't touch the process environment,
+    /// so that the same sources can be hydrated for several environments,
+    /// e.g. in tests.
+    pub fn for_env(&self, env: &str) -> Result<Self, ConfigError> {
+        self.clone().with_env(env)
+    }
+
+    /// Like `for_env`, without copying.
+    pub fn with_env(mut self, env: &str) -> Result<Self, ConfigError> {
+        self.hydro_settings = self.hydro_settings.set_env(env.into());
+        self.clear();
+        self.apply_sets()?;
+        Ok(self)
+    }
+
+    /// The merged configuration.
+    pub(crate) fn table(&self) -> Table {
+        self.config.cache.clone().into_table().unwrap_or_default()
+    }
 
     pub fn set_default<T>(
         &mut self,
@@ -151,7 +2056,13 @@ impl Hydroconf {
     where
         T: Into<Value>,
     {
-        self.config.set_default(key, value)?;
+        let value = value.into();
+        self.config.set_default(key, value.clone())?;
+        self.defaults.push((key.to_string(), value));
+        self.origins
+            .entry(key.to_string())
+            .or_default()
+            .insert(0, Origin::Default);
         Ok(self)
     }
 
@@ -163,7 +2074,10 @@ impl Hydroconf {
     where
         T: Into<Value>,
     {
-        self.config.set(key, value)?;
+        let value = value.into();
+        self.config.set(key, value.clone())?;
+        self.overrides.push((key.to_string(), value));
+        self.record_origin(key, Origin::Override);
         Ok(self)
     }
 
@@ -190,6 +2104,18 @@ impl Hydroconf {
         self.get(key).and_then(Value::into_bool)
     }
 
+    /// Get a duration written as `"30s"`, `"1m30s"`, etc., or as a number of
+    /// seconds.
+    pub fn get_duration(&self, key: &str) -> Result<Duration, ConfigError> {
+        self.get::<HumanDuration>(key).map(|d| d.0)
+    }
+
+    /// Get a size written as `"10MB"`, `"4KiB"`, etc., or as a number of
+    /// bytes.
+    pub fn get_bytes(&self, key: &str) -> Result<u64, ConfigError> {
+        self.get::<HumanBytes>(key).map(|b| b.0)
+    }
+
     pub fn get_table(
         &self,
         key: &str,
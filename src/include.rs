@@ -0,0 +1,196 @@
+//! The `include` key of the settings files, naming other files to load
+//! after them: `include = ["logging.toml", "db/*.toml"]`.
+
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+use config::{ConfigError, Value};
+use regex::Regex;
+
+use crate::utils::is_url;
+
+pub const INCLUDE_KEY: &str = "include";
+
+/// The files included by `including`, relative to its directory, in order.
+/// The patterns with `*` or `?` are expanded to the matching files, sorted,
+/// while the other paths are kept even if they don't exist.
+pub fn included_files(
+    including: &Path,
+    include: Value,
+) -> Result<Vec<PathBuf>, ConfigError> {
+    let error = || {
+        ConfigError::Message(format!(
+            "{} must be a path or a list of paths in {}",
+            INCLUDE_KEY,
+            including.display()
+        ))
+    };
+    let patterns = match include.clone().into_array() {
+        Ok(items) => items
+            .into_iter()
+            .map(|item| item.into_str().map_err(|_| error()))
+            .collect::<Result<Vec<_>, _>>()?,
+        Err(_) => vec![include.into_str().map_err(|_| error())?],
+    };
+    let dir = including.parent().unwrap_or_else(|| Path::new(""));
+    let mut files = Vec::new();
+    for pattern in patterns {
+        if !is_glob(&pattern) {
+            files.push(dir.join(pattern));
+        } else if is_url(including) {
+            return Err(ConfigError::Message(format!(
+                "the {} pattern {} can't be expanded in {}",
+                INCLUDE_KEY,
+                pattern,
+                including.display()
+            )));
+        } else {
+            files.extend(expand_glob(dir, &pattern));
+        }
+    }
+    Ok(files)
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?'])
+}
+
+/// The files matching `pattern`, relative to `dir`, whose components can
+/// hold `*` (any characters) and `?` (one character), or be `**` (any
+/// number of directories).
+pub fn expand_glob(dir: &Path, pattern: &str) -> Vec<PathBuf> {
+    let mut paths = vec![dir.to_path_buf()];
+    for component in Path::new(pattern).components() {
+        let name = match component {
+            Component::Normal(name) => name.to_string_lossy(),
+            component => {
+                for path in paths.iter_mut() {
+                    path.push(component);
+                }
+                continue;
+            }
+        };
+        if !is_glob(&name) {
+            for path in paths.iter_mut() {
+                path.push(name.as_ref());
+            }
+            continue;
+        }
+        if name == "**" {
+            paths = paths.iter().flat_map(|path| subdirs(path)).collect();
+            continue;
+        }
+        let regex = glob_regex(&name);
+        paths = paths
+            .iter()
+            .flat_map(|path| {
+                let mut matches: Vec<_> = read_dir(path)
+                    .filter(|file_name| {
+                        let file_name = file_name.to_string_lossy();
+                        // As in a shell, `*` doesn't match the hidden files.
+                        (!file_name.starts_with('.') || name.starts_with('.'))
+                            && regex.is_match(&file_name)
+                    })
+                    .map(|file_name| path.join(file_name))
+                    .collect();
+                matches.sort();
+                matches
+            })
+            .collect();
+    }
+    paths.retain(|path| path.is_file());
+    paths
+}
+
+/// The names of the entries of `dir`, which is the working directory if it's
+/// empty.
+fn read_dir(dir: &Path) -> impl Iterator<Item = OsString> {
+    let dir = if dir.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        dir
+    };
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name())
+}
+
+/// `dir` followed by all its subdirectories, recursively and sorted, except
+/// the hidden ones. The symbolic links aren't followed, so that a loop can't
+/// be walked forever.
+fn subdirs(dir: &Path) -> Vec<PathBuf> {
+    let mut children: Vec<_> = read_dir(dir)
+        .filter(|file_name| !file_name.to_string_lossy().starts_with('.'))
+        .map(|file_name| dir.join(file_name))
+        .filter(|path| {
+            std::fs::symlink_metadata(path)
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false)
+        })
+        .collect();
+    children.sort();
+    let mut dirs = vec![dir.to_path_buf()];
+    for child in children {
+        dirs.extend(subdirs(&child));
+    }
+    dirs
+}
+
+fn glob_regex(pattern: &str) -> Regex {
+    let mut regex = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    Regex::new(&regex).expect("an escaped glob is a valid regex")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::get_data_path;
+    use std::collections::HashMap;
+
+    type Table = HashMap<String, Value>;
+
+    #[test]
+    fn test_included_files() {
+        let dir = get_data_path("29");
+        let settings = dir.join("settings.toml");
+        let include = Value::from(vec!["logging.toml", "db/*.toml"]);
+        assert_eq!(
+            included_files(&settings, include).unwrap(),
+            vec![
+                dir.join("logging.toml"),
+                dir.join("db/a.toml"),
+                dir.join("db/b.toml"),
+            ]
+        );
+        assert_eq!(
+            included_files(&settings, Value::from("d?/*.yaml")).unwrap(),
+            vec![dir.join("db/d.yaml")]
+        );
+        assert_eq!(
+            included_files(&settings, Value::from("**/?.toml")).unwrap(),
+            vec![dir.join("db/a.toml"), dir.join("db/b.toml")]
+        );
+        assert!(included_files(&settings, Value::from("none/*.toml"))
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            included_files(&settings, Value::from(Table::new()))
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "include must be a path or a list of paths in {}",
+                settings.display()
+            )
+        );
+    }
+}
@@ -0,0 +1,459 @@
+//! The `${NAME}` references in the values of the settings: `${VAR}` is an
+//! environment variable, expanded when the files are loaded, and
+//! `${dotted.key}` is another key, resolved once all the layers are merged,
+//! like the `@format` values.
+
+use serde_json::Value as JsonValue;
+
+/// Replace the `${NAME}` and `${NAME:-default}` references in `text` with
+/// the value returned by `resolve(name, default)`, or keep them as they are
+/// if it returns `None`. `$${` is a literal `${`, which is only unescaped if
+/// `unescape`, so that a later expansion skips it too. A `${` with no `}`
+/// is an error if `strict`, and is kept as it is otherwise.
+pub fn expand<F>(
+    text: &str,
+    unescape: bool,
+    strict: bool,
+    mut resolve: F,
+) -> Result<String, String>
+where
+    F: FnMut(&str, Option<&str>) -> Result<Option<String>, String>,
+{
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(i) = rest.find('$') {
+        expanded.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            expanded.push_str(if unescape { "${" } else { "$${" });
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = match after.find('}') {
+                Some(end) => end,
+                None if strict => {
+                    return Err(format!("unclosed ${{ in {:?}", text))
+                }
+                None => break,
+            };
+            let (name, default) = split_reference(&after[..end]);
+            match resolve(name, default)? {
+                Some(value) => expanded.push_str(&value),
+                // The reference, from `$` to `}`.
+                None => expanded.push_str(&rest[..end + 3]),
+            }
+            rest = &after[end + 1..];
+        } else {
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// The name and the default of the reference `NAME:-default`.
+fn split_reference(reference: &str) -> (&str, Option<&str>) {
+    match reference.split_once(":-") {
+        Some((name, default)) => (name.trim(), Some(default)),
+        None => (reference.trim(), None),
+    }
+}
+
+/// Whether the reference `name` is a key of the settings, e.g.
+/// `redis.host`, rather than an environment variable.
+fn is_key(name: &str) -> bool {
+    name.contains('.')
+}
+
+/// Expand the environment variables referenced in `text`, keeping the
+/// references to the keys. The default is used if the variable is unset or
+/// empty; an unset variable with no default is an error if `strict`, and is
+/// kept as it is otherwise.
+pub fn expand_env(text: &str, strict: bool) -> Result<String, String> {
+    expand(text, false, strict, |name, default| {
+        if is_key(name) {
+            return Ok(None);
+        }
+        match (std::env::var(name), default) {
+            (Ok(value), Some(default)) if value.is_empty() => {
+                Ok(Some(default.to_string()))
+            }
+            (Ok(value), _) => Ok(Some(value)),
+            (Err(_), Some(default)) => Ok(Some(default.to_string())),
+            (Err(_), None) if strict => {
+                Err(format!("undefined environment variable {}", name))
+            }
+            (Err(_), None) => Ok(None),
+        }
+    })
+}
+
+/// Whether the string value at a key may hold references, e.g. not if it
+/// comes from an environment variable or a secret, which are taken as they
+/// are.
+pub type Resolvable<'a> = &'a dyn Fn(&str) -> bool;
+
+/// Resolve the `${dotted.key}` references in the string values of `root`,
+/// the merged settings, at the keys that are `resolvable`. A value that is a
+/// single reference takes the value of the key, whatever its kind; in a
+/// longer string, the key must hold a string, a number or a boolean.
+pub fn resolve_keys(
+    root: &JsonValue,
+    resolvable: Resolvable,
+) -> Result<JsonValue, String> {
+    resolve_value(root, resolvable, root, "", &mut Vec::new())
+}
+
+fn resolve_value(
+    root: &JsonValue,
+    resolvable: Resolvable,
+    value: &JsonValue,
+    path: &str,
+    stack: &mut Vec<String>,
+) -> Result<JsonValue, String> {
+    let child = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    Ok(match value {
+        // The errors name the key whose value is being resolved, not the
+        // keys it references.
+        JsonValue::String(s) if !needs_resolving(s) || !resolvable(path) => {
+            value.clone()
+        }
+        JsonValue::String(s) if stack.is_empty() => {
+            stack.push(path.to_string());
+            let resolved = resolve_string(root, resolvable, s, stack);
+            stack.pop();
+            resolved.map_err(|e| format!("{} in {}", e, path))?
+        }
+        JsonValue::String(s) => resolve_string(root, resolvable, s, stack)?,
+        JsonValue::Array(items) => JsonValue::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let path = child(&i.to_string());
+                    resolve_value(root, resolvable, item, &path, stack)
+                })
+                .collect::<Result<_, _>>()?,
+        ),
+        JsonValue::Object(map) => JsonValue::Object(
+            map.iter()
+                .map(|(key, item)| {
+                    let item = resolve_value(
+                        root,
+                        resolvable,
+                        item,
+                        &child(key),
+                        stack,
+                    )?;
+                    Ok((key.clone(), item))
+                })
+                .collect::<Result<_, String>>()?,
+        ),
+        other => other.clone(),
+    })
+}
+
+/// The prefix of the values rendered from the merged settings, as in
+/// Dynaconf: `@format {this.pg.host}:{this.pg.port}`.
+const FORMAT_PREFIX: &str = "@format ";
+
+fn needs_resolving(text: &str) -> bool {
+    text.contains('$') || text.starts_with(FORMAT_PREFIX)
+}
+
+fn resolve_string(
+    root: &JsonValue,
+    resolvable: Resolvable,
+    text: &str,
+    stack: &mut Vec<String>,
+) -> Result<JsonValue, String> {
+    if let Some(template) = text.strip_prefix(FORMAT_PREFIX) {
+        return render_format(root, resolvable, template, stack)
+            .map(JsonValue::String);
+    }
+    let single = text
+        .strip_prefix("${")
+        .and_then(|rest| rest.strip_suffix('}'))
+        .filter(|reference| !reference.contains(['$', '{', '}']));
+    if let Some((name, default)) = single.map(split_reference) {
+        if is_key(name) {
+            return lookup(root, resolvable, name, default, stack);
+        }
+    }
+    // A malformed reference isn't one.
+    expand(text, true, false, |name, default| {
+        if !is_key(name) {
+            return Ok(None);
+        }
+        match lookup(root, resolvable, name, default, stack)? {
+            JsonValue::String(s) => Ok(Some(s)),
+            JsonValue::Array(_) | JsonValue::Object(_) => {
+                Err(format!("{} can't be interpolated in a string", name))
+            }
+            other => Ok(Some(other.to_string())),
+        }
+    })
+    .map(JsonValue::String)
+}
+
+/// Render the `@format` `template`, where `{this.pg.host}` is the value of
+/// a key, `{env[HOME]}` the one of an environment variable, and `{{` and
+/// `}}` are literal braces.
+fn render_format(
+    root: &JsonValue,
+    resolvable: Resolvable,
+    template: &str,
+    stack: &mut Vec<String>,
+) -> Result<String, String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        rendered.push_str(&rest[..i]);
+        rest = &rest[i..];
+        if let Some(after) =
+            rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}"))
+        {
+            rendered.push_str(&rest[..1]);
+            rest = after;
+            continue;
+        }
+        let end = match rest.strip_prefix('{').and_then(|r| r.find('}')) {
+            Some(end) => end + 1,
+            None => {
+                return Err(format!("unbalanced braces in {:?}", template))
+            }
+        };
+        let field = rest[1..end].trim();
+        if let Some(key) = field.strip_prefix("this.") {
+            match lookup(root, resolvable, key, None, stack)? {
+                JsonValue::String(s) => rendered.push_str(&s),
+                JsonValue::Array(_) | JsonValue::Object(_) => {
+                    return Err(format!("{} can't be formatted", key))
+                }
+                other => rendered.push_str(&other.to_string()),
+            }
+        } else if let Some(var) = field
+            .strip_prefix("env[")
+            .and_then(|var| var.strip_suffix(']'))
+        {
+            let var = var.trim_matches(|c| c == '\'' || c == '"');
+            let value = std::env::var(var).map_err(|_| {
+                format!("undefined environment variable {}", var)
+            })?;
+            rendered.push_str(&value);
+        } else {
+            return Err(format!("unknown @format field {{{}}}", field));
+        }
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// The resolved value of the key `name`.
+fn lookup(
+    root: &JsonValue,
+    resolvable: Resolvable,
+    name: &str,
+    default: Option<&str>,
+    stack: &mut Vec<String>,
+) -> Result<JsonValue, String> {
+    if stack.iter().any(|key| key == name) {
+        stack.push(name.to_string());
+        return Err(format!("circular reference {}", stack.join(" -> ")));
+    }
+    let value = name
+        .split('.')
+        .try_fold(root, |value, segment| match value {
+            JsonValue::Object(map) => map.get(segment),
+            JsonValue::Array(items) => {
+                segment.parse::<usize>().ok().and_then(|i| items.get(i))
+            }
+            _ => None,
+        });
+    match (value, default) {
+        (Some(value), _) => {
+            stack.push(name.to_string());
+            let value = resolve_value(root, resolvable, value, name, stack);
+            stack.pop();
+            value
+        }
+        (None, Some(default)) => Ok(JsonValue::String(default.to_string())),
+        (None, None) => Err(format!("undefined key {}", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_expand() {
+        let resolve = |name: &str, default: Option<&str>| {
+            Ok(match name {
+                "USER" => Some("app".to_string()),
+                "KEEP" => None,
+                _ => default.map(String::from),
+            })
+        };
+        assert_eq!(
+            expand(
+                "postgres://${USER}@db:${PORT:-5432}/x",
+                true,
+                true,
+                resolve
+            )
+            .unwrap(),
+            "postgres://app@db:5432/x"
+        );
+        assert_eq!(
+            expand("${KEEP} $${USER} $5 $", true, true, resolve).unwrap(),
+            "${KEEP} ${USER} $5 $"
+        );
+        assert_eq!(
+            expand("$${USER} ${USER}", false, true, resolve).unwrap(),
+            "$${USER} app"
+        );
+        assert_eq!(
+            expand("a${USER}b${USER}", true, true, resolve).unwrap(),
+            "aappbapp"
+        );
+        assert_eq!(
+            expand("${USER", true, true, resolve).unwrap_err(),
+            "unclosed ${ in \"${USER\""
+        );
+        assert_eq!(
+            expand("${USER}:pa${ss", true, false, resolve).unwrap(),
+            "app:pa${ss"
+        );
+    }
+
+    #[test]
+    fn test_expand_env() {
+        std::env::set_var("INTERPOLATETEST_USER", "app");
+        std::env::set_var("INTERPOLATETEST_EMPTY", "");
+        assert_eq!(
+            expand_env("${INTERPOLATETEST_USER}@db", true).unwrap(),
+            "app@db"
+        );
+        assert_eq!(
+            expand_env("${INTERPOLATETEST_EMPTY:-x}", true).unwrap(),
+            "x"
+        );
+        assert_eq!(
+            expand_env("${INTERPOLATETEST_UNSET:-5432}", true).unwrap(),
+            "5432"
+        );
+        assert_eq!(
+            expand_env("a${INTERPOLATETEST_UNSET}b", false).unwrap(),
+            "a${INTERPOLATETEST_UNSET}b"
+        );
+        assert_eq!(
+            expand_env("${INTERPOLATETEST_UNSET}", true).unwrap_err(),
+            "undefined environment variable INTERPOLATETEST_UNSET"
+        );
+        assert_eq!(
+            expand_env("${redis.host} $${INTERPOLATETEST_USER}", true)
+                .unwrap(),
+            "${redis.host} $${INTERPOLATETEST_USER}"
+        );
+        std::env::remove_var("INTERPOLATETEST_USER");
+        std::env::remove_var("INTERPOLATETEST_EMPTY");
+    }
+
+    #[test]
+    fn test_resolve_keys() {
+        let settings = json!({
+            "redis": {"host": "localhost", "port": 6379},
+            "redis_url": "redis://${redis.host}:${redis.port}/0",
+            "cache": {"url": "${redis_url}", "port": "${redis.port}"},
+            "servers": [{"host": "a"}],
+            "first": "${servers.0.host}",
+            "backup": "${backup.host:-none} $${redis.host}",
+        });
+        assert_eq!(
+            resolve_keys(&settings, &|_| true).unwrap(),
+            json!({
+                "redis": {"host": "localhost", "port": 6379},
+                "redis_url": "redis://localhost:6379/0",
+                // Not a key.
+                "cache": {"url": "${redis_url}", "port": 6379},
+                "servers": [{"host": "a"}],
+                "first": "a",
+                "backup": "none ${redis.host}",
+            })
+        );
+
+        let chained = json!({
+            "a": {"url": "${b.url}/x"},
+            "b": {"url": "${c.host}:1"},
+            "c": {"host": "h"},
+        });
+        assert_eq!(
+            resolve_keys(&chained, &|_| true).unwrap()["a"]["url"],
+            "h:1/x"
+        );
+
+        std::env::set_var("INTERPOLATETEST_SCHEME", "postgres");
+        let formatted = json!({
+            "pg": {"host": "db", "port": 5432, "name": "@format {this.app}"},
+            "app": "shop",
+            "url": "@format {env[INTERPOLATETEST_SCHEME]}://{this.pg.host}:\
+                    {this.pg.port}/{this.pg.name} {{x}}",
+            "plain": "{this.app}",
+        });
+        let resolved = resolve_keys(&formatted, &|_| true).unwrap();
+        std::env::remove_var("INTERPOLATETEST_SCHEME");
+        assert_eq!(resolved["url"], "postgres://db:5432/shop {x}");
+        assert_eq!(resolved["plain"], "{this.app}");
+
+        let literal = json!({
+            "p": {"a": "ab${cd", "b": "$${p.a}"},
+            "c": "x${p.a}",
+        });
+        assert_eq!(
+            resolve_keys(&literal, &|key| key == "c").unwrap(),
+            json!({
+                "p": {"a": "ab${cd", "b": "$${p.a}"},
+                "c": "xab${cd",
+            })
+        );
+
+        let err = |settings| resolve_keys(&settings, &|_| true).unwrap_err();
+        assert_eq!(
+            err(json!({"a": {"b": "${a.c}"}, "x": "${a.b}"})),
+            "undefined key a.c in a.b"
+        );
+        assert_eq!(
+            err(json!({"a": {"b": "${a.c}", "c": "${a.b}"}})),
+            "circular reference a.b -> a.c -> a.b in a.b"
+        );
+        assert_eq!(
+            err(json!({"a": {"b": "${a.b}"}})),
+            "circular reference a.b -> a.b in a.b"
+        );
+        assert_eq!(
+            err(json!({"a": {"b": "@format {this.a.b}"}})),
+            "circular reference a.b -> a.b in a.b"
+        );
+        assert_eq!(
+            err(json!({"x": "@format {that.a}"})),
+            "unknown @format field {that.a} in x"
+        );
+        assert_eq!(
+            err(json!({"x": "@format {this.a"})),
+            "unbalanced braces in \"{this.a\" in x"
+        );
+        assert_eq!(
+            err(json!({"a": {"b": [1]}, "x": "list ${a.b}"})),
+            "a.b can't be interpolated in a string in x"
+        );
+    }
+}
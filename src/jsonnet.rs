@@ -0,0 +1,110 @@
+//! Evaluation of the Jsonnet settings files, such as `settings.jsonnet`.
+
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+use config::ConfigError;
+
+use crate::utils::pipe_through;
+
+/// The external variables of a Jsonnet program, as `--ext-str` arguments:
+/// `std.extVar("env")` is the current environment, and each environment
+/// variable with the given prefix (e.g. `HYDRO_REGION`) is available under
+/// its own name.
+pub fn ext_vars(env: &str, envvar_prefix: &str) -> Vec<String> {
+    let prefix = format!("{}_", envvar_prefix);
+    let mut args = vec!["--ext-str".to_string(), format!("env={}", env)];
+    for (name, _) in env::vars_os() {
+        if let Some(name) = name.to_str().filter(|n| n.starts_with(&prefix)) {
+            // Without a value, `jsonnet` reads it from its environment, so
+            // it doesn't show up in the arguments.
+            args.extend(["--ext-str".to_string(), name.to_string()]);
+        }
+    }
+    args
+}
+
+/// Evaluate a Jsonnet program with the `jsonnet` binary, and return the
+/// resulting JSON. When it's read from a local `file`, the relative imports
+/// are resolved from its directory, otherwise from the working directory.
+pub fn evaluate(
+    text: &str,
+    file: Option<&Path>,
+    ext_vars: &[String],
+) -> Result<String, ConfigError> {
+    evaluate_with("jsonnet", text, file, ext_vars)
+}
+
+fn evaluate_with(
+    program: &str,
+    text: &str,
+    file: Option<&Path>,
+    ext_vars: &[String],
+) -> Result<String, ConfigError> {
+    let mut command = Command::new(program);
+    command.args(ext_vars);
+    match file {
+        Some(path) => command.arg(path),
+        None => command.arg("-"),
+    };
+    pipe_through(&mut command, text).map_err(|message| {
+        ConfigError::FileParse {
+            uri: None,
+            cause: format!("could not evaluate with jsonnet: {}", message)
+                .into(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_ext_vars() {
+        env::set_var("JSONNETTEST_REGION", "eu");
+        assert_eq!(
+            ext_vars("production", "JSONNETTEST"),
+            vec![
+                "--ext-str",
+                "env=production",
+                "--ext-str",
+                "JSONNETTEST_REGION"
+            ],
+        );
+        env::remove_var("JSONNETTEST_REGION");
+    }
+
+    #[test]
+    fn test_evaluate() {
+        // A fake `jsonnet` printing its arguments and its input.
+        let dir = TempDir::new("jsonnet");
+        let program = dir.join("jsonnet");
+        fs::write(&program, "#!/bin/sh\necho \"$@\"\ncat\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        let program = program.to_str().unwrap();
+
+        let args = ["--ext-str".to_string(), "env=production".to_string()];
+        let out = evaluate_with(program, "{}", None, &args).unwrap();
+        assert_eq!(out, "--ext-str env=production -\n{}");
+        let file = Path::new("config/settings.jsonnet");
+        let out = evaluate_with(program, "", Some(file), &[]).unwrap();
+        assert_eq!(out, "config/settings.jsonnet\n");
+
+        fs::write(
+            dir.join("jsonnet"),
+            "#!/bin/sh\necho 'RUNTIME ERROR: Undefined external variable: \
+             region' >&2\nexit 1\n",
+        )
+        .unwrap();
+        let err = evaluate_with(program, "{}", None, &[])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("Undefined external variable"), "{}", err);
+    }
+}
@@ -119,13 +119,57 @@
 //!
 //! * `ROOT_PATH_FOR_HYDRO`: specifies the location from which Hydroconf should
 //!   start searching configuration files. By default, Hydroconf will start from
-//!   the directory that contains your executable;
+//!   the working directory, and then from the directory that contains your
+//!   executable;
 //! * `SETTINGS_FILE_FOR_HYDRO`: exact location of the main settings file;
+//!   with the `http` feature, it can also be an `http://` or `https://` URL.
+//!   The last downloaded copy is cached in the cache directory of the user
+//!   (e.g. `~/.cache/hydroconf`), only readable by them, and it's used if
+//!   the file didn't change (according to its ETag) or if the server can't
+//!   be reached. The secrets files are never cached. With the `s3` feature, it can be an `s3://bucket/key`
+//!   URL, read with the credentials given by the standard `AWS_*` variables.
+//!   If it's `-`, the settings are read from stdin, e.g. to pipe the output
+//!   of a templating tool, in the format given by `STDIN_FORMAT_FOR_HYDRO`
+//!   (by default `toml`). If it's a pattern such as `settings.*.toml` or
+//!   `conf/**/*.yaml`, where `*` and `?` match any characters and `**` any
+//!   number of directories, all the matching files are merged, in
+//!   lexicographic order;
+//! * `SETTINGS_FILES_FOR_HYDRO`: a comma-separated list of settings files
+//!   loaded after the main one, in order, e.g. a service-specific file after
+//!   a shared base. They can be URLs as well, and more can be added with
+//!   `HydroSettings::add_settings_file()`;
+//! * `SETTINGS_DIRS_FOR_HYDRO`: a comma-separated list of the subfolders in
+//!   which the settings and secrets files are searched at each level of the
+//!   directory tree, in order, e.g. `.,conf,deploy/config`, where `.` is the
+//!   directory itself. By default it's `.,config`;
+//! * `MAX_WALK_DEPTH_FOR_HYDRO`: the number of parent directories of the root
+//!   path in which the settings files are searched, e.g. `0` to only search
+//!   the root path. By default there's no limit;
+//! * `CASCADE_SETTINGS_FOR_HYDRO`: if `true`, the search doesn't stop at the
+//!   first settings file: all the ones found while walking up the directory
+//!   tree are merged, from the farthest one, so that the closest one wins,
+//!   e.g. the `settings.toml` of a package over the one of its monorepo. The
+//!   walk still stops at the root of the project (see below). By default
+//!   it's `false`;
+//! * `APP_NAME_FOR_HYDRO`: the name of the application. If the project has no
+//!   settings files, they're searched in its directory in the configuration
+//!   directory of the platform: `$XDG_CONFIG_HOME/<app>` (by default
+//!   `~/.config/<app>`) on Linux, `~/Library/Application Support/<app>` on
+//!   macOS and `%APPDATA%\<app>` on Windows. On Unix, the system-wide
+//!   settings file `/etc/<app>/settings.toml` (or any other supported format)
+//!   is also loaded first, beneath the settings of the project. By default
+//!   there's none;
 //! * `SECRETS_FILE_FOR_HYDRO`: exact location of the file containing secrets;
+//!   it can be a URL as well;
 //! * `ENV_FOR_HYDRO`: the environment to load after loading the `default` one
 //!   (e.g. `development`, `testing`, `staging`, `production`, etc.). By default,
 //!   Hydroconf will load the `development` environment, unless otherwise
-//!   specified.
+//!   specified. The value is trimmed and matched case-insensitively against the
+//!   environment tables, so ` Production ` selects `[production]`. It can
+//!   also be a comma-separated list such as `eu,production`, whose tables
+//!   (and `.env.<env>` files) are merged in this order after `default`. The
+//!   last one is the environment of the options that take a single one,
+//!   such as the root path by environment.
 //! * `ENVVAR_PREFIX_FOR_HYDRO`: the prefix of the environement variables holding
 //!   your configuration -- see group number 2. above. By default it's `HYDRO`
 //!   (note that you don't have to include the `_` separator, as that is added
@@ -134,9 +178,139 @@
 //!   holding your configuration that signals a nesting point. By default it's `__`
 //!   (double underscore), so if you set `HYDRO_REDIS__HOST=localhost`, Hydroconf
 //!   will match it with the nested field `redis.host` in your configuration.
+//!   A number indexes an array: `HYDRO_SERVERS__0__HOST=a.example.com`
+//!   overrides the host of the first element of `servers`, and leaves the
+//!   other elements alone. An index past the end of the array is an error.
+//! * `TRIM_VALUES_FOR_HYDRO`: if `true`, surrounding whitespace (such as the
+//!   trailing newline of a value read from a file) is stripped from all string
+//!   values after merging. By default it's `false`;
+//! * `SECRETS_WIN_OVER_ENV_FOR_HYDRO`: if `true`, the values from the secrets
+//!   file are applied again after the `.env` files and the environment
+//!   variables, so that they can't be overridden by them. By default it's
+//!   `false`;
+//! * `NAMESPACE_FOR_HYDRO`: the name of the top-level table in which the
+//!   `default` and environment tables are looked up. This lets several
+//!   applications share the same configuration files, with sections such as
+//!   `[app1.default]` and `[app2.production]`. By default there's no
+//!   namespace;
+//! * `DOCKER_SECRETS_FOR_HYDRO`: if true, the Docker secrets mounted in
+//!   `/run/secrets` are loaded like the secrets file, with their names split
+//!   on `ENVVAR_NESTED_SEP_FOR_HYDRO` (e.g. `pg__password`). By default it's
+//!   `false`;
+//! * `COMMAND_FOR_HYDRO`: a command (e.g. `sops -d settings.enc.toml`)
+//!   whose output is loaded like the secrets file, after it. The output is
+//!   read as `COMMAND_FORMAT_FOR_HYDRO` (by default `toml`), and the command
+//!   is killed after `COMMAND_TIMEOUT_FOR_HYDRO` (by default `30s`). The
+//!   arguments are split on whitespace;
+//! * `GIT_URL_FOR_HYDRO` and `GIT_REF_FOR_HYDRO`: with the `git` feature,
+//!   the repository at this URL is cloned (or fetched, if it was already
+//!   cloned) in the cache directory of the user, the given branch, tag or
+//!   commit is checked out, and the settings files are discovered in it. It
+//!   replaces the root path. By default the default branch of the
+//!   repository is used;
+//! * `XML_ATTRIBUTES_FOR_HYDRO`: with the `xml` feature, how the attributes
+//!   of the elements of `settings.xml` are read: `keys` (the default) reads
+//!   `<pg port="5432"/>` as `pg.port`, like a child element, `prefixed`
+//!   reads it as `pg._port`, and `ignore` skips the attributes;
+//! * `UNKNOWN_KEYS_FOR_HYDRO`: what to do with the settings that the
+//!   configuration struct doesn't have, e.g. a misspelled `pg.prot`: `allow`
+//!   (the default) ignores them, `warn` reports them with the callback given
+//!   to `Hydroconf::on_warning()` (by default on the standard error), and
+//!   `deny` makes the deserialization fail;
+//! * `REQUIRE_ENV_FOR_HYDRO`: if `true`, the hydration fails when none of
+//!   the settings files has a table for the environment (e.g. `[production]`
+//!   when `ENV_FOR_HYDRO=production`), instead of silently using only the
+//!   `default` table. By default it's `false`;
+//! * `SECRET_KEYS_FOR_HYDRO`: a comma-separated list of the keys that must
+//!   only be set by the secrets file or the secret sources, e.g.
+//!   `*.password,api_token`, where `*` stands for one or more segments. The
+//!   hydration fails if any of them is set in the settings files, in any
+//!   environment, or in the `.env` files. By default there's none;
+//! * `ALLOWED_ENVS_FOR_HYDRO`: a comma-separated list of the valid
+//!   environments, e.g. `development,staging,production`. The hydration
+//!   fails if `ENV_FOR_HYDRO` isn't one of them, so that a typo such as
+//!   `prodcution` is caught at startup. By default any environment is
+//!   allowed;
+//! * `WARN_SHADOWED_FOR_HYDRO`: if `true`, the keys whose values are
+//!   overridden by a later layer (e.g. a value of the settings file
+//!   overridden by an environment variable) are reported at the end of the
+//!   hydration, with the callback given to `Hydroconf::on_warning()`. They
+//!   are also returned by `Hydroconf::shadowed()`. By default it's `false`;
+//! * `STRICT_INTERPOLATION_FOR_HYDRO`: if `true`, the hydration fails when a
+//!   value of the settings files references an unset environment variable
+//!   with no default (see below), or holds a `${` with no `}`. By default
+//!   they're kept as they are;
+//! * `PATH_KEYS_FOR_HYDRO`: a comma-separated list of the keys holding
+//!   paths, e.g. `tls.cert,*.dir`, where `*` stands for one or more
+//!   segments. Their relative values are resolved against the directory of
+//!   the settings, secrets or `.env` file that set them, instead of the
+//!   working directory. By default there's none;
+//! * `LIST_MERGE_FOR_HYDRO`: how the lists of an environment table are
+//!   combined with the ones of the tables merged before, e.g. `default`:
+//!   `replace` (the default), `append`, `prepend` or `unique` (appending the
+//!   items that aren't in the list yet). It's set for a single key with
+//!   `HydroSettings::set_list_merge_for_key()`;
+//! * `SHALLOW_MERGE_FOR_HYDRO`: if `true`, the tables of an environment table
+//!   (e.g. `[production.pg]`) replace the ones merged before instead of being
+//!   merged recursively. By default it's `false`;
+//! * `LAYER_ORDER_FOR_HYDRO`: the order in which the layers are applied, the
+//!   last one winning: by default `settings,secrets,dotenv,env`. For the
+//!   files to win over the environment, it can be e.g.
+//!   `dotenv,env,settings,secrets`. All four layers must be listed;
+//! * `FLAT_FILES_FOR_HYDRO`: if `true`, the settings and secrets files have no
+//!   `default` and environment tables: they're taken as they are, as the
+//!   values of the current environment, e.g. `pg.host = "localhost"`. The
+//!   files are still discovered, and overridden by the `.env` files and the
+//!   environment variables. By default it's `false`;
+//! * `TENANT_FOR_HYDRO`: the name of a tenant whose overrides are merged
+//!   after each environment table, from its `tenants` table: with `acme`,
+//!   `[production.tenants.acme]` is merged after `[production]`, and
+//!   `[default.tenants.acme]` after `[default]`. It's also set by
+//!   `Hydroconf::hydrate_for_tenant()`. By default there's no tenant, and
+//!   the `tenants` tables are kept as settings.
+//!
+//! The hydration fails if one of these variables has a value that can't be
+//! parsed, e.g. `UNKNOWN_KEYS_FOR_HYDRO=dney`, instead of using the default.
+//!
+//! The string values of the settings and secrets files can reference
+//! environment variables, which are expanded when the files are loaded:
+//! `url = "postgres://${PG_USER}@db:${PG_PORT:-5432}"`. The default after
+//! `:-` is used if the variable is unset or empty, and `$${` is a literal
+//! `${`.
+//!
+//! A reference with a dot is another key instead, resolved once all the
+//! layers are merged, so that they can all change it:
+//! `redis_url = "redis://${redis.host}:${redis.port}"`. A value made of a
+//! single reference, such as `port = "${redis.port}"`, takes the value of
+//! the key as it is, even if it's a number or a table. Referencing an
+//! undefined key with no default, or a key that references back the first
+//! one, makes the hydration fail. Only the values of the settings files and
+//! sources are resolved: the ones of the secrets, the `.env` files and the
+//! environment variables are taken as they are, even if they hold a `$`.
+//!
+//! The values starting with `@format` are rendered at the same time, as in
+//! Dynaconf: in `"@format {this.pg.host}:{this.pg.port}"`, `{this.pg.host}`
+//! is the value of `pg.host`, `{env[HOME]}` would be the one of the `HOME`
+//! environment variable, and `{{` and `}}` are literal braces.
+//!
+//! The values of the `.env` files and of the environment variables are
+//! strings, unless they start with a type token, as in Dynaconf:
+//! `HYDRO_PG__PORT="@int 5432"`, `@float 0.5`, `@bool true` or
+//! `@json ["a", "b"]`. `@str` keeps the rest of the value as a string, even
+//! if it starts with a token.
+//!
+//! `@base64` decodes the rest of the value, in the settings files and the
+//! other sources too, so that certificates and keys can be passed around on
+//! a single line, e.g. `HYDRO_TLS__CERT="@base64 LS0tLS1CRUdJTi..."`. The
+//! decoded value is a string or, if it's not UTF-8, the list of its bytes
+//! (a `Vec<u8>`).
+//!
+//! Services started by systemd with `LoadCredential=` get their credentials
+//! in the directory given by `CREDENTIALS_DIRECTORY`. If it's set, the
+//! credentials are loaded like the Docker secrets.
 //!
 //! # Hydroconf initialization
-//! You can create a new Hydroconf struct in two ways.
+//! You can create a new Hydroconf struct in three ways.
 //!
 //! The first one is to use the `Hydroconf::default()` method, which will use the
 //! default settings. The default constructor will attempt to load the settings
@@ -156,12 +330,32 @@
 //! Note that `HydroSettings::default()` will still try to load the settings from
 //! the environment before you overwrite them.
 //!
+//! Finally, `Hydroconf::builder()` gathers all the settings and the additional
+//! sources in a single builder:
+//!
+//! ```rust
+//! # use hydroconf::{FileFormat, Hydroconf};
+//!
+//! let hydro = Hydroconf::builder()
+//!     .envvar_prefix("MYAPP")
+//!     .env("staging")
+//!     .with_inline("[default]\nworkers = 4", FileFormat::Toml)
+//!     .build();
+//! ```
+//!
+//! The same sources can then be hydrated for other environments, e.g. in the
+//! tests or in a process serving several tenants, without changing
+//! `ENV_FOR_HYDRO`: `hydro.for_env("production")?` returns a copy for the
+//! `production` environment.
+//!
 //! # The hydration process
 //! ## 1. Configuration loading
 //! When you call `Hydroconf::hydrate()`, Hydroconf starts looking for your
 //! configuration files and if it finds them, it loads them. The search starts from
 //! `HydroSettings.root_path`; if the root path is not defined, Hydroconf will use
-//! `std::env::current_exe()`. From this path, Hydroconf generates all the possible
+//! `std::env::current_dir()`, followed by `std::env::current_exe()` (e.g. for
+//! a binary installed in `/usr/local/bin` but run from the directory of its
+//! settings). From this path, Hydroconf generates all the possible
 //! candidates by walking up the directory tree, also searching in the `config`
 //! subfolder at each level. For example, if the root path is
 //! `/home/user/www/api-server/dist`, Hydroconf will try the following paths, in
@@ -181,9 +375,123 @@
 //! 12. `/`
 //!
 //! In each directory, Hydroconf will search for the files
-//! `settings.{toml,json,yaml,ini,hjson}` and
-//! `.secrets.{toml,json,yaml,ini,hjson}`. As soon as one of those (or both) are
-//! found, the search stops and Hydroconf won't search the remaining upper levels.
+//! `settings.{toml,json,yaml,ini,properties,env,jsonnet,cue,hjson}` and
+//! `.secrets.{toml,json,yaml,ini,properties,env,jsonnet,cue,hjson}`. As soon
+//! as one of those (or both) are found, the search stops and Hydroconf won't
+//! search the remaining upper levels.
+//!
+//! The walk also stops at the root of the project: the first directory
+//! holding a `.git` directory, a `Cargo.toml` file or a `.hydroconf-root`
+//! file is the last one searched, so that the settings of an unrelated parent
+//! directory (e.g. `/home/config`) are never loaded. It can also be limited
+//! to a number of parent directories with `MAX_WALK_DEPTH_FOR_HYDRO`. If
+//! nothing is found and an application name is set, the configuration
+//! directory of the platform (e.g. `~/.config/<app>`) is searched last. With
+//! an application name, the system-wide settings file
+//! `/etc/<app>/settings.toml`, if any, is loaded too, before the settings
+//! file that was found, so that the latter wins.
+//!
+//! The subfolders searched at each level can be changed with
+//! `SETTINGS_DIRS_FOR_HYDRO` (see below), e.g. for a project keeping its
+//! settings in `conf` or `deploy/config`.
+//!
+//! The files of the `settings.d` directory next to the settings file (e.g.
+//! `config/settings.d/10-logging.toml`), if any, are then merged in
+//! lexicographic order, so that packaging tools and operators can drop in
+//! overrides without editing the settings file. They can be in any of the
+//! formats below, and the other files (e.g. a `README.md`) are ignored.
+//!
+//! A settings or secrets file can also be split into several ones with a
+//! top-level `include` key:
+//!
+//! ```toml
+//! include = ["logging.toml", "db/*.toml"]
+//!
+//! [default]
+//! pg.host = "localhost"
+//! ```
+//!
+//! The paths are relative to the including file, and the ones with `*`, `?`
+//! or `**` match any number of files, in lexicographic order. The included files
+//! are merged right after the including one, so their values win, and they
+//! can include other files as well.
+//!
+//! * A YAML file can hold several documents separated by `---`, e.g. a base
+//!   and its overrides: they're merged in order.
+//! * In an INI file, the section names and the keys are split on dots like
+//!   in TOML: `[production.pg]` is the table `pg` in `production`.
+//! * In a Java `.properties` file, the dotted keys are nested paths:
+//!   `production.pg.port=5432` is `pg.port` in `production`.
+//! * A `settings.env` file has the syntax of the `.env` files, but its
+//!   variables are the settings of the current environment, split on
+//!   `ENVVAR_NESTED_SEP_FOR_HYDRO` and without a prefix: `PG__HOST=db-0` is
+//!   `pg.host`.
+//! * A `.jsonnet` program is evaluated with the `jsonnet` binary, and the
+//!   resulting JSON is loaded. The current environment is the external
+//!   variable `env` (`std.extVar("env")`), and the environment variables
+//!   with the prefix (see `ENVVAR_PREFIX_FOR_HYDRO`) are external variables
+//!   as well, e.g. `std.extVar("HYDRO_REGION")`.
+//! * A `.cue` file is exported to JSON with the `cue` binary, so that its
+//!   constraints (e.g. `port: int & >1024`) are checked. The failures are
+//!   reported with the path of the invalid value, e.g. `default.port`.
+//! * HJSON can be turned off with `default-features = false`.
+//! * The `json5` feature adds `.json5` files (JSON with comments, trailing
+//!   commas and unquoted keys).
+//! * The `hcl` feature adds `.hcl` files, where a block such as
+//!   `server "web" { ... }` is the table `server.web`.
+//! * The `xml` feature adds `.xml` files, where the children of the root
+//!   element are the tables and the repeated elements are arrays.
+//! * The `dhall` feature adds `.dhall` files, which are type-checked and
+//!   evaluated when the settings are loaded: the relative imports are
+//!   resolved from the directory of the file.
+//! * The `nestedtext` feature adds [NestedText](https://nestedtext.org)
+//!   `.nt` files, made of indented `key: value` lines, `- item` lists and
+//!   `> text` multiline strings, with no quoting nor escaping.
+//! * The `msgpack` and `cbor` features add the binary `.msgpack` and `.cbor`
+//!   files, e.g. generated by other programs, with the same `default` and
+//!   environment tables. They're read from local, unencrypted files only.
+//! * Other formats can be added with a `FormatRegistry`, which maps an
+//!   extension (e.g. `conf`) to a parser returning the top-level table:
+//!   `settings.conf` and `.secrets.conf` are then searched after the
+//!   built-in extensions. A registered parser also wins over the built-in
+//!   one for the same extension.
+//!
+//! Files encrypted with [SOPS](https://github.com/getsops/sops), either named
+//! like `.secrets.enc.toml` or holding SOPS metadata, are decrypted when they
+//! are loaded, with the `sops` binary and its usual keys (age, KMS or PGP).
+//! Only the encrypted version has to be kept on disk.
+//!
+//! With the `age` feature, the files encrypted with
+//! [age](https://age-encryption.org) and named like `.secrets.toml.age` are
+//! decrypted in memory, with the identity file at `AGE_IDENTITY_FOR_HYDRO` or
+//! the key in `AGE_KEY_FOR_HYDRO` (`AGE-SECRET-KEY-1...`). They are then
+//! loaded like the plain files.
+//!
+//! With the `encryption` feature, single values can be encrypted instead of
+//! whole files, in any settings file, `.env` file or environment variable:
+//!
+//! ```toml
+//! [production]
+//! pg.password = "ENC[AES256,yH5Rq0Tb9r8...]"
+//! ```
+//!
+//! They are decrypted once the settings are merged, with the base64 key in
+//! `DECRYPT_KEY_FOR_HYDRO`. `hydroconf::generate_key()` creates a key and
+//! `hydroconf::encrypt_value(&key, "a password")` encrypts a value.
+//!
+//! With the `minijinja` feature, the files named like `settings.toml.j2` are
+//! rendered as [MiniJinja](https://docs.rs/minijinja) templates before being
+//! loaded like `settings.toml`. The templates can use `env`, the environment
+//! variables, `hydro_env`, the current environment, and `settings`, the
+//! settings loaded from the previous files:
+//!
+//! ```toml
+//! [default]
+//! workers = {{ env.WORKERS | default(4) }}
+//! {% if hydro_env == "production" %}log_level = "warn"{% endif %}
+//! ```
+//!
+//! An undefined variable is an error.
 //!
 //! ## 2. Merging
 //! In this step, Hydroconf merges the values from the different environments
@@ -193,6 +501,55 @@
 //! Hydroconf (`ENV_FOR_HYDRO`, or "development" if not specified) exists and in
 //! that case it selects those values and merges them with the existing ones.
 //!
+//! An environment can start from the values of another one, which it names
+//! with `_inherit`, instead of `default` only:
+//!
+//! ```toml
+//! [production]
+//! pg.host = "db.prod"
+//! pg.pool_size = 20
+//!
+//! [staging]
+//! _inherit = "production"
+//! pg.host = "db.staging"
+//! ```
+//!
+//! With `ENV_FOR_HYDRO=staging`, the tables `default`, `production` and
+//! `staging` are merged in this order. The chains can be longer, but not
+//! circular.
+//!
+//! Finally, as with Dynaconf, the `global` table is merged last, whatever the
+//! environment, for the values that must be the same in all of them. They
+//! can still be overridden by the `.env` files and the environment
+//! variables.
+//!
+//! The tables are merged recursively, while the other values, lists
+//! included, replace the existing ones. The lists can be appended or
+//! prepended instead with `LIST_MERGE_FOR_HYDRO` (see above), for all the
+//! keys or some of them. As with Dynaconf, this can also be changed in the
+//! settings files:
+//!
+//! ```toml
+//! [default]
+//! hosts = ["a", "b"]
+//! cache = { ttl = 60, size = 100 }
+//!
+//! [production]
+//! # Appended: ["a", "b", "c"].
+//! hosts = ["dynaconf_merge", "c"]
+//! # Replaced: { ttl = 5 }.
+//! cache = { dynaconf_merge = false, ttl = 5 }
+//! ```
+//!
+//! `_replace = true` is the same as `dynaconf_merge = false`, and it's the
+//! way back with `SHALLOW_MERGE_FOR_HYDRO=true` (see above): there a table
+//! with `_replace = false` is still merged recursively.
+//!
+//! The `@merge` token does the same in the `.env` files and the environment
+//! variables, e.g. `HYDRO_HOSTS="@merge c,d"`, `HYDRO_PG="@merge port=5433"`
+//! or `HYDRO_HOSTS='@merge ["c"]'`. The items already in a list are not
+//! appended again.
+//!
 //! ## 3. `.env` file overrides
 //! In this step Hydroconf starts from the root path (the same one from step 1),
 //! and walks the filesystem upward in search of an `.env` file. If it finds
@@ -203,10 +560,301 @@
 //! you defined with the Hydro prefix (`HYDRO_` by default, as explained in the
 //! [previous section](#environment-variables)).
 //!
+//! By default environment variables override everything, secrets included.
+//! This is convenient, but it also means that a stray `HYDRO_PG__PASSWORD` in
+//! a shell can silently replace the password stored in your secrets file. If
+//! you'd rather treat the secrets file as the source of truth, set
+//! `SECRETS_WIN_OVER_ENV_FOR_HYDRO=true`: the trade-off is that secrets can no
+//! longer be rotated or patched through the environment.
+//!
+//! More generally, the order of the settings files, secrets, `.env` files
+//! and environment variables can be changed with `LAYER_ORDER_FOR_HYDRO` or
+//! `HydroconfBuilder::layer_order()`, e.g. for the files to win over the
+//! environment variables in some deployments. The values given to
+//! `Hydroconf::set()` always win.
+//!
 //! ## 5. Deserialization
 //! Finally, Hydroconf tries to deserialize the configuration into the return
 //! type you specify, which should be your configuration struct.
 //!
+//! ## Custom stages
+//! The steps above are the stages of a pipeline, named like the methods of
+//! `Hydroconf` they call: `Hydroconf::stage_names()` lists them. A stage can
+//! be inserted, replaced or removed, e.g. to load the values of a company
+//! vault between the `.env` files and the environment variables:
+//!
+//! ```rust
+//! # use hydroconf::{ConfigError, FnStage, Hydroconf};
+//! let mut hydro = Hydroconf::builder().skip_discovery().build();
+//! hydro.insert_stage_after(
+//!     "override_from_dotenv",
+//!     FnStage::new("company_vault", |hydro: &mut Hydroconf| {
+//!         hydro.set("pg.password", "from the vault")?;
+//!         Ok(())
+//!     }),
+//! )?;
+//! hydro.hydrate_in_place()?;
+//! assert_eq!(hydro.get_str("pg.password")?, "from the vault");
+//! # Ok::<(), ConfigError>(())
+//! ```
+//!
+//! Implementing the `Stage` trait does the same for a type. The stages that
+//! depend on the settings, such as the layers, are fixed once the pipeline is
+//! changed.
+//!
+//! # Directories of key files
+//! Kubernetes mounts ConfigMaps and Secrets as directories with one file per
+//! key. Register them with `Hydroconf::add_directory()`: each file name is a
+//! key, split on `ENVVAR_NESTED_SEP_FOR_HYDRO` (e.g. `pg__port`), and the
+//! file content is its value. They are loaded after the settings files, in
+//! the current environment, so they override both `default` and the
+//! environment table. Use `Hydroconf::add_secrets_directory()` for
+//! directories of secrets.
+//!
+//! # Remote sources
+//! Additional sources can be registered with `Hydroconf::add_source()` (or
+//! `Hydroconf::add_secrets_source()` for secrets). Like the settings files,
+//! they must be split in `default` and per-environment tables. Hydroconf
+//! ships the following ones, each behind a cargo feature:
+//!
+//! * `vault`: `VaultSource` reads the secrets from a HashiCorp Vault KV v2
+//!   engine, at `<mount>/data/<path>/default` and `<mount>/data/<path>/<env>`.
+//!   If `VAULT_PATH_FOR_HYDRO` is set (or `HydroSettings::set_vault()` is
+//!   called), Vault replaces the secrets file. The other variables are
+//!   `VAULT_ADDR_FOR_HYDRO`, `VAULT_MOUNT_FOR_HYDRO` (by default `secret`),
+//!   and either `VAULT_TOKEN_FOR_HYDRO` or `VAULT_ROLE_ID_FOR_HYDRO` and
+//!   `VAULT_SECRET_ID_FOR_HYDRO` for AppRole authentication.
+//! * `doppler`: `DopplerSource` reads the secrets of a Doppler config. If
+//!   `DOPPLER_TOKEN` is set to a service token, they're loaded in the
+//!   current environment after the secrets file, with their names split on
+//!   `ENVVAR_NESTED_SEP_FOR_HYDRO` (e.g. `PG__PASSWORD`).
+//! * `etcd`: `EtcdSource` reads the settings under a key prefix in etcd v3,
+//!   e.g. `/myapp/default/pg/port` and `/myapp/production/pg/host`.
+//! * `consul`: `ConsulSource` reads the settings under a key prefix in the
+//!   Consul KV store, with the same layout. If `CONSUL_PREFIX_FOR_HYDRO` is
+//!   set (or `HydroSettings::set_consul_prefix()` is called), it's loaded
+//!   right after the settings file, from the agent at `CONSUL_HTTP_ADDR`.
+//! * `onepassword`: `OnePasswordSource` resolves 1Password secret references
+//!   (e.g. `op://Production/Postgres/password`) through a 1Password Connect
+//!   server. The references added with `HydroSettings::add_onepassword_ref()`
+//!   are resolved into their keys after the secrets file, from the server at
+//!   `OP_CONNECT_HOST` with the token `OP_CONNECT_TOKEN`.
+//! * `redis`: `RedisSource` reads the settings from the fields of a Redis
+//!   hash, or from the keys under a prefix, split on `:`: e.g.
+//!   `production:pg:host`.
+//! * `spring`: `SpringConfigSource` reads the settings of a profile from a
+//!   Spring Cloud Config Server, to share them with JVM services.
+//! * `sqlite`, `postgres` and `mysql`: `SqlSource` reads the settings from
+//!   the rows of a table such as `config(key, value, env)`, selecting the
+//!   `default` rows and the ones of the current environment.
+//! * `zookeeper`: `ZooKeeperSource` reads the settings from the leaves of a
+//!   ZooKeeper subtree, e.g. `/myapp/production/pg/host`.
+//!
+//! # OS keyring
+//! With the `keyring` feature, the values of the form
+//! `@keyring:<service>/<user>`, e.g. `pg.password = "@keyring:myapp/pg"`, are
+//! replaced with the password stored for that service and user in the
+//! platform credential store (macOS Keychain, Windows Credential Manager or
+//! Secret Service). They are resolved after all the overrides, so they can
+//! also be set in `.env` files and environment variables.
+//!
+//! # Secrets in files
+//! The values of the form `@file:<path>`, e.g.
+//! `pg.password = "@file:/run/secrets/pg_password"`, are replaced with the
+//! content of the file, without its trailing newlines, after all the
+//! overrides. As with the Docker images, an environment variable ending with
+//! `_FILE` holds the path of the file of the value instead:
+//! `HYDRO_PG__PASSWORD_FILE=/run/secrets/pg_password` sets `pg.password`, if
+//! the settings have a `pg.password` key but no `pg.password_file` one.
+//! Otherwise the variable is a plain value: `HYDRO_LOG_FILE` sets `log_file`.
+//!
+//! # Generated values
+//! The value `@uuid` is replaced with a random UUID (version 4), and
+//! `@random:32` with 32 random letters and digits, e.g. for an instance
+//! identifier or a development secret key:
+//!
+//! ```toml
+//! [default]
+//! worker.id = "@uuid"
+//! worker.name = "worker-${worker.id}"
+//!
+//! [development]
+//! secret_key = "@random:50"
+//! ```
+//!
+//! They're generated again at every hydration, before the key references
+//! are resolved, so the values referencing them see the same value.
+//!
+//! # Durations and sizes
+//! Values like `timeout = "1m30s"` or `max_body = "10MB"` can be read with
+//! `Hydroconf::get_duration()` and `Hydroconf::get_bytes()`. To deserialize them
+//! directly into your configuration struct, annotate the fields with the
+//! helpers exported by Hydroconf:
+//!
+//! ```rust
+//! # use std::time::Duration;
+//! # use serde::Deserialize;
+//! #[derive(Debug, Deserialize)]
+//! struct HttpConfig {
+//!     #[serde(deserialize_with = "hydroconf::deserialize_duration")]
+//!     timeout: Duration,
+//!     #[serde(deserialize_with = "hydroconf::deserialize_bytes")]
+//!     max_body: u64,
+//! }
+//! ```
+//!
+//! Durations accept the units `ns`, `us`, `ms`, `s`, `m`, `h`, `d` and `w`,
+//! which can be combined (`1h30m`). Sizes accept decimal (`KB`, `MB`, `GB`,
+//! `TB`) and binary (`KiB`, `MiB`, `GiB`, `TiB`) units. Plain numbers are
+//! interpreted as seconds and bytes respectively.
+//!
+//! # Validation
+//! Validators check the merged settings at the end of the hydration, before
+//! they're deserialized, so that the errors name the offending keys and the
+//! layers their values come from, instead of a serde error:
+//!
+//! ```rust
+//! # use hydroconf::{Hydroconf, Validator};
+//! # use regex::Regex;
+//! let mut hydro = Hydroconf::default();
+//! hydro
+//!     .add_validator("pg.host", Validator::must_exist())
+//!     .add_validator("pg.password", Validator::non_empty())
+//!     .add_validator("log", Validator::one_of(["debug", "info", "warn"]))
+//!     .add_validator("pg.port", Validator::range(1..=65535))
+//!     .add_validator("pool.ratio", Validator::float_range(0.0..=1.0))
+//!     .add_validator("region", Validator::regex(Regex::new("^[a-z]+-[0-9]$")?))
+//!     .add_validator("workers", |v: &hydroconf::Value| {
+//!         match v.clone().into_int() {
+//!             Ok(n) if n % 2 == 0 => Ok(()),
+//!             _ => Err("must be an even number".to_string()),
+//!         }
+//!     });
+//! # Ok::<(), regex::Error>(())
+//! ```
+//!
+//! All the invalid keys are reported at once.
+//!
+//! The invariants spanning several fields are checked on the deserialized
+//! settings instead, by the functions given to `Hydroconf::add_invariant()`,
+//! which return all the errors they found:
+//!
+//! ```rust,ignore
+//! hydro.add_invariant(|config: &Config| match config.tls {
+//!     Some(ref tls) if tls.cert.is_some() && tls.key.is_none() => {
+//!         Err(vec!["tls.cert requires tls.key".to_string()])
+//!     }
+//!     _ => Ok(()),
+//! });
+//! ```
+//!
+//! Hydrating the settings as another type than the one an invariant checks
+//! is an error, rather than skipping the invariant.
+//!
+//! With the `derive` feature, the checks of single fields can be declared
+//! on the struct itself: the settings of a type deriving `HydrateValidate`
+//! are checked after their deserialization, along with the invariants.
+//!
+//! ```rust,ignore
+//! #[derive(Deserialize, HydrateValidate)]
+//! struct Config {
+//!     #[hydro(non_empty)]
+//!     name: String,
+//!     #[hydro(range(min = 1, max = 65535))]
+//!     port: u16,
+//!     #[hydro(nested)]
+//!     pg: Postgres,
+//! }
+//! ```
+//!
+//! `range` accepts either bound, and skips the `Option` fields set to `None`,
+//! while `non_empty` rejects them; `nested` checks a field whose type derives
+//! `HydrateValidate` too. The automatic checks don't apply to generic
+//! structs, which can be checked with `hydro.add_invariant(Config::validate)`.
+//!
+//! The keys that must be set can also be declared with
+//! `HydroSettings::set_required()`, or in a `required` array at the top of
+//! the settings files:
+//!
+//! ```toml
+//! required = ["pg.host", "pg.password"]
+//!
+//! [default]
+//! ...
+//! ```
+//!
+//! The hydration then fails with the list of all the missing keys, along
+//! with the environments that do define them.
+//!
+//! With the `schema` feature, `settings_file_schema::<T>()` returns the JSON
+//! Schema of the settings files of a configuration struct deriving
+//! `schemars::JsonSchema`, for the autocompletion in editors and the
+//! validation of the files in CI. `json_schema::<T>()` returns the schema of
+//! the hydrated settings.
+//!
+//! # Hot reloading
+//! `Hydroconf::refresh()` reads all the sources again and rebuilds the merged
+//! configuration in place, keeping the values given to `set()` and
+//! `set_default()`. It returns the top-level keys that changed, while
+//! `Hydroconf::refresh_diff()` returns a `Diff` of every changed key, with
+//! its previous and new values.
+//!
+//! With the `watch` feature, `Hydroconf::watch()` hydrates the settings and
+//! returns a `Watcher`, which hydrates them again whenever one of the
+//! discovered files (settings, secrets or `.env`) changes:
+//!
+//! ```rust,ignore
+//! let watcher = Hydroconf::default().watch::<Config>()?;
+//! // Always the latest settings, without locking.
+//! let port = watcher.get().port;
+//! // A `HydratedHandle`, cheap to clone into each request handler.
+//! let settings = watcher.handle();
+//! ```
+//!
+//! Bursts of writes to the files make a single reload, once no file changed
+//! for `Watcher::debounce(delay)` (50ms by default). If the new files can't
+//! be loaded (e.g. a half-written file), the previous settings are kept. To
+//! react to the reloads, register callbacks with `Hydroconf::on_change()`
+//! before watching:
+//!
+//! ```rust,ignore
+//! let mut hydro = Hydroconf::default();
+//! hydro.on_change(|old: &Config, new: &Config| {
+//!     if old.log_level != new.log_level {
+//!         set_log_level(&new.log_level);
+//!     }
+//! });
+//! let watcher = hydro.watch::<Config>()?;
+//! ```
+//!
+//! To only react to some of the keys, register callbacks with
+//! `Hydroconf::on_diff()` instead, which are given the `Diff` of each reload.
+//!
+//! The remote sources (e.g. Consul, Vault or a settings file at a URL) can
+//! also be fetched again periodically with `Watcher::refresh_every(ttl)`:
+//! the local files are cached between the refreshes, and only read again when
+//! they change.
+//!
+//! The sources whose servers notify their changes are reloaded as soon as
+//! they change instead: the Consul prefix of the settings, and the sources
+//! registered with `Hydroconf::add_watched_source()` (e.g. a `ConsulSource`
+//! or an `EtcdSource`, or any source implementing `WatchedSource`).
+//!
+//! With the `stream` feature, async applications can consume the reloads
+//! from `Watcher::stream()` instead, which yields the new settings (or the
+//! error that made a reload fail) on each reload:
+//!
+//! ```rust,ignore
+//! let mut configs = Box::pin(watcher.stream());
+//! while let Some(config) = configs.next().await {
+//!     apply(config?);
+//! }
+//! ```
+//!
+//! On Unix, the `sighup` feature adds `Watcher::reload_on_sighup()`, which
+//! also reloads the settings when the process receives SIGHUP, like most
+//! daemons do.
+//!
 //! # Best practices
 //! In order to keep your configuration simple, secure and effective, Hydroconf
 //! makes it easy for you to follow these best practices:
@@ -224,12 +872,94 @@
 //!    are not in the secret file, define the environment variables `HYDRO_*`
 //!    (or use a custom prefix and define `ENVVAR_PREFIX_FOR_HYDRO`).
 
+mod age_file;
+mod builder;
+mod cast;
+mod command;
+mod cue;
+mod diff;
+mod directory;
+mod encrypted;
 mod env;
+mod formats;
 mod hydro;
+mod include;
+mod interpolate;
+mod jsonnet;
+mod merge;
+#[cfg(feature = "nestedtext")]
+mod nestedtext;
+mod origin;
+mod pipeline;
+mod properties;
+mod remote;
+#[cfg(feature = "schema")]
+mod schema;
 mod settings;
+mod sops;
 mod sources;
+mod template;
+#[cfg(test)]
+mod testing;
+mod tokens;
+mod units;
 mod utils;
+mod validate;
+#[cfg(feature = "watch")]
+mod watch;
+#[cfg(feature = "xml")]
+mod xml;
+mod yaml;
 
-pub use hydro::{Config, ConfigError, Environment, File, Hydroconf};
-pub use settings::HydroSettings;
+pub use builder::HydroconfBuilder;
+pub use command::CommandSource;
+pub use diff::{Change, Diff};
+pub use directory::DirectorySource;
+#[cfg(feature = "encryption")]
+pub use encrypted::{encrypt_value, generate_key};
+pub use formats::{Format, FormatRegistry, Parser};
+pub use hydro::{
+    Config, ConfigError, Environment, File, FileFormat, Hydroconf, Source,
+    Value, SECRET_PATTERNS, STDIN_PATH,
+};
+#[cfg(feature = "derive")]
+pub use hydroconf_derive::HydrateValidate;
+pub use origin::Origin;
+pub use pipeline::{FnStage, Stage};
+#[cfg(feature = "consul")]
+pub use remote::ConsulSource;
+#[cfg(feature = "doppler")]
+pub use remote::DopplerSource;
+#[cfg(feature = "etcd")]
+pub use remote::EtcdSource;
+#[cfg(feature = "onepassword")]
+pub use remote::OnePasswordSource;
+#[cfg(feature = "redis")]
+pub use remote::RedisSource;
+#[cfg(feature = "spring")]
+pub use remote::SpringConfigSource;
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "mysql"))]
+pub use remote::SqlSource;
+#[cfg(feature = "zookeeper")]
+pub use remote::ZooKeeperSource;
+#[cfg(feature = "vault")]
+pub use remote::{VaultSettings, VaultSource};
+#[cfg(feature = "schema")]
+pub use schema::{json_schema, settings_file_schema};
+pub use settings::{HydroSettings, Layer, ListMerge, UnknownKeys};
 pub use sources::FileSources;
+pub use units::{
+    deserialize_bytes, deserialize_duration, parse_bytes, parse_duration,
+};
+pub use validate::{HydrateValidate, Validator};
+#[cfg(feature = "watch")]
+pub use watch::{Changes, HydratedHandle, WatchedSource, Watcher};
+#[cfg(feature = "xml")]
+pub use xml::XmlAttributes;
+
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::validate::{IsEmpty, Registration};
+    pub use inventory;
+}
@@ -0,0 +1,432 @@
+//! The Dynaconf markers controlling how the value of a key is merged with
+//! the one of the layer below, e.g. the `default` environment:
+//!
+//! * `dynaconf_merge = false` or `_replace = true` in a table: the table
+//!   replaces the one below instead of being merged with it
+//!   (`dynaconf_merge = true` or `_replace = false` merges them);
+//! * `["dynaconf_merge", "c"]`: the items are appended to the list below;
+//! * `"@merge ..."`: the JSON array or table, or the comma-separated items
+//!   (`@merge c,d` or `@merge port=1,host=db`), are merged with the value
+//!   below.
+//!
+//! The appended items that are already in the list are skipped. The lists
+//! with no marker are combined with the list below by their `ListMerge`
+//! strategy, and the tables are merged unless the merge is shallow.
+
+use std::collections::HashMap;
+
+use config::Value;
+use serde_json::Value as JsonValue;
+
+use crate::settings::ListMerge;
+use crate::utils::{from_json, to_json};
+
+type Table = HashMap<String, Value>;
+
+/// The key of the tables marked to be merged or replaced, and the first
+/// item of the lists to append.
+pub const MERGE_KEY: &str = "dynaconf_merge";
+
+/// The key of the tables marked to replace the ones below (`true`) or to
+/// be merged with them (`false`).
+pub const REPLACE_KEY: &str = "_replace";
+
+/// The token of the values merged with the value below.
+pub const MERGE_TOKEN: &str = "@merge";
+
+/// How the values with no marker are merged with the ones below.
+pub struct Strategy<'a> {
+    /// The strategy of the list at a key.
+    pub lists: &'a dyn Fn(&str) -> ListMerge,
+    /// Whether the tables of an environment table replace the ones below,
+    /// instead of being merged recursively.
+    pub shallow: bool,
+}
+
+/// Apply the markers of `upper`, an environment table about to be merged
+/// with `lower`: the appended lists and the `@merge` values are replaced
+/// with the merged values, the markers are removed and the replaced tables
+/// are removed from `lower`. The values with no marker follow `strategy`.
+/// Return whether `lower` changed.
+pub fn apply_markers(
+    lower: &mut Table,
+    upper: &mut Table,
+    strategy: &Strategy,
+) -> Result<bool, String> {
+    apply_table_markers(lower, upper, "", false, strategy)
+}
+
+/// Like `apply_markers`, for the table at `path`, which replaces `lower`
+/// unless it's marked otherwise if `replace`.
+fn apply_table_markers(
+    lower: &mut Table,
+    upper: &mut Table,
+    path: &str,
+    replace: bool,
+    strategy: &Strategy,
+) -> Result<bool, String> {
+    let mut replace = replace;
+    for (marker, replaces_if) in [(MERGE_KEY, false), (REPLACE_KEY, true)] {
+        if let Some(value) = upper.remove(marker) {
+            replace = value.into_bool().map_err(|_| {
+                format!("{} must be a boolean in {}", marker, display(path))
+            })? == replaces_if;
+        }
+    }
+    let mut changed = false;
+    if replace && !lower.is_empty() {
+        lower.clear();
+        changed = true;
+    }
+    for (key, value) in upper.iter_mut() {
+        let key_path = join(path, key);
+        let below = lower.get(key).cloned();
+        if let Ok(mut table) = value.clone().into_table() {
+            let mut below_table = below
+                .and_then(|below| below.into_table().ok())
+                .unwrap_or_default();
+            let replace = strategy.shallow && path.is_empty();
+            if apply_table_markers(
+                &mut below_table,
+                &mut table,
+                &key_path,
+                replace,
+                strategy,
+            )? {
+                lower.insert(key.clone(), below_table.into());
+                changed = true;
+            }
+            *value = table.into();
+        } else if let Some(merged) = merge_value(value, below.as_ref())
+            .map_err(|e| format!("{} in {}", e, key_path))?
+        {
+            *value = merged;
+        } else if let (Ok(items), Some(Ok(below_items))) = (
+            value.clone().into_array(),
+            below.map(|below| below.into_array()),
+        ) {
+            let list_merge = (strategy.lists)(&key_path);
+            *value = combine(below_items, items, list_merge)?.into();
+        }
+    }
+    Ok(changed)
+}
+
+/// The value of an override, `raw`, merged with the current value if it
+/// has a `@merge` token, e.g. `@merge c,d`.
+pub fn merge_token(
+    raw: &Value,
+    below: Option<&Value>,
+) -> Result<Option<Value>, String> {
+    match raw.clone().into_str() {
+        Ok(s) if is_merge_token(&s) => merge_value(raw, below),
+        _ => Ok(None),
+    }
+}
+
+/// `value` merged with `below` if it's marked, `None` otherwise.
+fn merge_value(
+    value: &Value,
+    below: Option<&Value>,
+) -> Result<Option<Value>, String> {
+    let json = to_json(value.clone()).map_err(|e| e.to_string())?;
+    let upper = match json {
+        JsonValue::Array(ref items)
+            if items.first().and_then(JsonValue::as_str)
+                == Some(MERGE_KEY) =>
+        {
+            let items = match value.clone().into_array() {
+                Ok(items) => items,
+                Err(_) => return Ok(None),
+            };
+            items.into_iter().skip(1).collect::<Vec<_>>().into()
+        }
+        JsonValue::String(ref s) if is_merge_token(s) => {
+            from_json(parse_token(s[MERGE_TOKEN.len()..].trim())?)
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(match below {
+        Some(below) => merge(below.clone(), upper)?,
+        None => upper,
+    }))
+}
+
+/// `upper` merged with `lower`: the new items of the lists are appended
+/// and the tables merged recursively.
+fn merge(lower: Value, upper: Value) -> Result<Value, String> {
+    if let (Ok(items), Ok(new_items)) =
+        (lower.clone().into_array(), upper.clone().into_array())
+    {
+        return Ok(combine(items, new_items, ListMerge::Unique)?.into());
+    }
+    if let (Ok(mut table), Ok(new_table)) =
+        (lower.into_table(), upper.clone().into_table())
+    {
+        for (key, value) in new_table {
+            let value = match table.remove(&key) {
+                Some(below) => merge(below, value)?,
+                None => value,
+            };
+            table.insert(key, value);
+        }
+        return Ok(table.into());
+    }
+    Ok(upper)
+}
+
+/// The list `upper` combined with the list below it, `lower`.
+pub fn combine(
+    lower: Vec<Value>,
+    upper: Vec<Value>,
+    strategy: ListMerge,
+) -> Result<Vec<Value>, String> {
+    Ok(match strategy {
+        ListMerge::Replace => upper,
+        ListMerge::Append => lower.into_iter().chain(upper).collect(),
+        ListMerge::Prepend => upper.into_iter().chain(lower).collect(),
+        ListMerge::Unique => {
+            let mut items = lower;
+            let mut seen = Vec::new();
+            for item in &items {
+                seen.push(to_json(item.clone()).map_err(|e| e.to_string())?);
+            }
+            for item in upper {
+                let json = to_json(item.clone()).map_err(|e| e.to_string())?;
+                if !seen.contains(&json) {
+                    seen.push(json);
+                    items.push(item);
+                }
+            }
+            items
+        }
+    })
+}
+
+fn is_merge_token(s: &str) -> bool {
+    s == MERGE_TOKEN
+        || s.strip_prefix(MERGE_TOKEN)
+            .is_some_and(|rest| rest.starts_with(char::is_whitespace))
+}
+
+/// The value of a `@merge` token: a JSON array or table, `key=value`
+/// pairs or items, separated by commas.
+fn parse_token(raw: &str) -> Result<JsonValue, String> {
+    if raw.starts_with('[') || raw.starts_with('{') {
+        return serde_json::from_str(raw).map_err(|e| {
+            format!("invalid {} value {:?}: {}", MERGE_TOKEN, raw, e)
+        });
+    }
+    let items: Vec<&str> = raw
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect();
+    if !items.is_empty() && items.iter().all(|item| item.contains('=')) {
+        Ok(JsonValue::Object(
+            items
+                .iter()
+                .filter_map(|item| item.split_once('='))
+                .map(|(k, v)| (k.trim().to_string(), parse_item(v.trim())))
+                .collect(),
+        ))
+    } else {
+        Ok(JsonValue::Array(
+            items.into_iter().map(parse_item).collect(),
+        ))
+    }
+}
+
+/// A number, boolean or JSON value, or else a string.
+fn parse_item(raw: &str) -> JsonValue {
+    serde_json::from_str(raw).unwrap_or_else(|_| raw.into())
+}
+
+fn join(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn display(path: &str) -> &str {
+    if path.is_empty() {
+        "the environment table"
+    } else {
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn table(json: JsonValue) -> Table {
+        from_json(json).into_table().unwrap()
+    }
+
+    fn json(table: Table) -> JsonValue {
+        to_json(table.into()).unwrap()
+    }
+
+    const DEEP: Strategy = Strategy {
+        lists: &|_| ListMerge::Replace,
+        shallow: false,
+    };
+
+    #[test]
+    fn test_apply_markers() {
+        let mut lower = table(json!({
+            "hosts": ["a", "b"],
+            "pg": {"host": "localhost", "port": 5432},
+            "cache": {"ttl": 60, "size": 100},
+            "plugins": ["x"],
+        }));
+        let mut upper = table(json!({
+            "hosts": ["dynaconf_merge", "b", "c"],
+            "pg": {"dynaconf_merge": true, "host": "db"},
+            "cache": {"dynaconf_merge": false, "ttl": 5},
+            "plugins": "@merge y,z",
+            "extra": ["dynaconf_merge", 1],
+        }));
+        assert!(apply_markers(&mut lower, &mut upper, &DEEP).unwrap());
+        assert_eq!(
+            json(upper),
+            json!({
+                "hosts": ["a", "b", "c"],
+                "pg": {"host": "db"},
+                "cache": {"ttl": 5},
+                "plugins": ["x", "y", "z"],
+                "extra": [1],
+            })
+        );
+        assert_eq!(json(lower.clone())["cache"], json!({}));
+
+        let mut upper = table(json!({"dynaconf_merge": false, "a": 1}));
+        assert!(apply_markers(&mut lower, &mut upper, &DEEP).unwrap());
+        assert!(lower.is_empty());
+
+        let mut upper = table(json!({"pg": {"dynaconf_merge": [1]}}));
+        assert_eq!(
+            apply_markers(&mut Table::new(), &mut upper, &DEEP).unwrap_err(),
+            "dynaconf_merge must be a boolean in pg"
+        );
+    }
+
+    #[test]
+    fn test_shallow_merge() {
+        let lower = table(json!({
+            "pg": {"host": "localhost", "port": 5432, "tls": {"ca": "a"}},
+            "cache": {"ttl": 60, "size": 100},
+            "redis": {"host": "localhost"},
+        }));
+        let upper = table(json!({
+            "pg": {"host": "db", "tls": {"_replace": true, "cert": "c"}},
+            "cache": {"_replace": false, "ttl": 5},
+            "redis": {"port": 6380},
+        }));
+
+        let (mut deep_lower, mut deep_upper) = (lower.clone(), upper.clone());
+        assert!(
+            apply_markers(&mut deep_lower, &mut deep_upper, &DEEP).unwrap()
+        );
+        assert_eq!(json(deep_lower.clone())["pg"]["tls"], json!({}));
+        assert_eq!(json(deep_lower)["pg"]["port"], json!(5432));
+        assert_eq!(json(deep_upper)["cache"], json!({"ttl": 5}));
+
+        let shallow = Strategy {
+            lists: &|_| ListMerge::Replace,
+            shallow: true,
+        };
+        let (mut lower, mut upper) = (lower, upper);
+        assert!(apply_markers(&mut lower, &mut upper, &shallow).unwrap());
+        assert_eq!(
+            json(lower),
+            json!({
+                "pg": {},
+                "cache": {"ttl": 60, "size": 100},
+                "redis": {},
+            })
+        );
+        assert_eq!(
+            json(upper)["pg"],
+            json!({"host": "db", "tls": {"cert": "c"}})
+        );
+
+        let mut upper = table(json!({"_replace": "yes please"}));
+        assert_eq!(
+            apply_markers(&mut Table::new(), &mut upper, &DEEP).unwrap_err(),
+            "_replace must be a boolean in the environment table"
+        );
+    }
+
+    #[test]
+    fn test_list_strategies() {
+        let lower = table(json!({
+            "hosts": ["a", "b"],
+            "pg": {"replicas": ["r1"]},
+            "ports": [1],
+            "tags": ["x"],
+        }));
+        let upper = table(json!({
+            "hosts": ["b", "c"],
+            "pg": {"replicas": ["r2"]},
+            "ports": [2],
+            "tags": ["dynaconf_merge", "y"],
+        }));
+        let lists = |strategy: ListMerge| {
+            let (mut lower, mut upper) = (lower.clone(), upper.clone());
+            let strategy_of = move |key: &str| match key {
+                "ports" => ListMerge::Replace,
+                "pg.replicas" => ListMerge::Prepend,
+                _ => strategy,
+            };
+            let strategy = Strategy {
+                lists: &strategy_of,
+                shallow: false,
+            };
+            apply_markers(&mut lower, &mut upper, &strategy).unwrap();
+            json(upper)
+        };
+        let append = lists(ListMerge::Append);
+        assert_eq!(append["hosts"], json!(["a", "b", "b", "c"]));
+        assert_eq!(append["pg"]["replicas"], json!(["r2", "r1"]));
+        assert_eq!(append["ports"], json!([2]));
+        assert_eq!(append["tags"], json!(["x", "y"]));
+        assert_eq!(lists(ListMerge::Unique)["hosts"], json!(["a", "b", "c"]));
+        assert_eq!(
+            lists(ListMerge::Prepend)["hosts"],
+            json!(["b", "c", "a", "b"])
+        );
+        assert_eq!(lists(ListMerge::Replace)["hosts"], json!(["b", "c"]));
+    }
+
+    #[test]
+    fn test_merge_token() {
+        let merged = |raw: &str, below: JsonValue| {
+            to_json(
+                merge_token(&Value::from(raw), Some(&from_json(below)))
+                    .unwrap()
+                    .unwrap(),
+            )
+            .unwrap()
+        };
+        assert_eq!(merged("@merge [3, 1]", json!([1, 2])), json!([1, 2, 3]));
+        assert_eq!(merged("@merge 3,4", json!([1])), json!([1, 3, 4]));
+        assert_eq!(
+            merged("@merge port=1,ssl=true", json!({"host": "db"})),
+            json!({"host": "db", "port": 1, "ssl": true})
+        );
+        assert_eq!(
+            merged(r#"@merge {"pg": {"port": 1}}"#, json!({"pg": {"a": 0}})),
+            json!({"pg": {"a": 0, "port": 1}})
+        );
+        assert!(merge_token(&Value::from("@merged"), None)
+            .unwrap()
+            .is_none());
+        assert!(merge_token(&Value::from(1), None).unwrap().is_none());
+        assert!(merge_token(&Value::from("@merge [1,"), None).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+//! Support for the [NestedText](https://nestedtext.org) settings files, such
+//! as
+//!
+//! ```text
+//! default:
+//!   pg:
+//!     host: localhost
+//!     port: 5432
+//!   hosts:
+//!     - a.example.com
+//!     - b.example.com
+//!   motd:
+//!     > Welcome!
+//!     > Have a nice day.
+//! ```
+//!
+//! All the values are strings, which are converted when they're read.
+
+use config::ConfigError;
+use serde_json::{Map, Value as JsonValue};
+
+#[derive(Debug)]
+enum Kind {
+    /// `- value`, or `-` followed by an indented value.
+    ListItem(Option<String>),
+    /// `key: value`, or `key:` followed by an indented value.
+    DictItem(String, Option<String>),
+    /// `: key`, a line of a multiline key.
+    KeyItem(String),
+    /// `> text`, a line of a multiline string.
+    StringItem(String),
+    /// `[a, b]` or `{a: 1}`.
+    Inline(String),
+}
+
+#[derive(Debug)]
+struct Line {
+    number: usize,
+    indent: usize,
+    kind: Kind,
+}
+
+/// Convert a NestedText document into a tree of strings.
+pub fn parse(text: &str) -> Result<JsonValue, ConfigError> {
+    let lines = lines(text)?;
+    if lines.is_empty() {
+        return Ok(JsonValue::Object(Map::new()));
+    }
+    let mut pos = 0;
+    let value = value(&lines, &mut pos, lines[0].indent)?;
+    match lines.get(pos) {
+        Some(line) => Err(error(line.number, "invalid indentation")),
+        None => Ok(value),
+    }
+}
+
+fn error(number: usize, message: &str) -> ConfigError {
+    ConfigError::FileParse {
+        uri: None,
+        cause: format!("line {}: {}", number, message).into(),
+    }
+}
+
+fn lines(text: &str) -> Result<Vec<Line>, ConfigError> {
+    let mut lines = Vec::new();
+    for (i, raw) in text.lines().enumerate() {
+        let number = i + 1;
+        let content = raw.trim_start_matches(' ');
+        if content.trim().is_empty() || content.starts_with('#') {
+            continue;
+        }
+        if content.starts_with('\t') {
+            return Err(error(number, "tabs can't be used for indentation"));
+        }
+        let indent = raw.len() - content.len();
+        let kind = if let Some(rest) = tag(content, '-') {
+            Kind::ListItem(rest)
+        } else if let Some(rest) = tag(content, '>') {
+            Kind::StringItem(rest.unwrap_or_default())
+        } else if let Some(rest) = tag(content, ':') {
+            Kind::KeyItem(rest.unwrap_or_default())
+        } else if content.starts_with(['[', '{']) {
+            Kind::Inline(content.trim_end().to_string())
+        } else {
+            let (key, value) = split_key(content)
+                .ok_or_else(|| error(number, "unrecognized line"))?;
+            Kind::DictItem(key, value)
+        };
+        lines.push(Line {
+            number,
+            indent,
+            kind,
+        });
+    }
+    Ok(lines)
+}
+
+/// The rest of a line starting with `tag`, followed by a space or nothing.
+fn tag(content: &str, tag: char) -> Option<Option<String>> {
+    let rest = content.strip_prefix(tag)?;
+    if rest.is_empty() {
+        Some(None)
+    } else {
+        rest.strip_prefix(' ').map(|rest| Some(rest.to_string()))
+    }
+}
+
+/// Split `key: value` or `key:` on the first `: ` or the final `:`.
+fn split_key(content: &str) -> Option<(String, Option<String>)> {
+    if let Some(i) = content.find(": ") {
+        let value = content[i + 2..].to_string();
+        return Some((content[..i].trim_end().to_string(), Some(value)));
+    }
+    let key = content.strip_suffix(':')?;
+    Some((key.trim_end().to_string(), None))
+}
+
+fn value(
+    lines: &[Line],
+    pos: &mut usize,
+    indent: usize,
+) -> Result<JsonValue, ConfigError> {
+    let first = &lines[*pos];
+    if first.indent != indent {
+        return Err(error(first.number, "invalid indentation"));
+    }
+    match first.kind {
+        Kind::ListItem(_) => {
+            let mut list = Vec::new();
+            while let Some(Line {
+                indent: i,
+                kind: Kind::ListItem(item),
+                ..
+            }) = lines.get(*pos)
+            {
+                if *i != indent {
+                    break;
+                }
+                *pos += 1;
+                list.push(item_value(lines, pos, indent, item)?);
+            }
+            Ok(JsonValue::Array(list))
+        }
+        Kind::DictItem(..) | Kind::KeyItem(_) => {
+            let mut dict = Map::new();
+            while let Some(line) =
+                lines.get(*pos).filter(|l| l.indent == indent)
+            {
+                let (key, value) = match &line.kind {
+                    Kind::DictItem(key, item) => {
+                        *pos += 1;
+                        (key.clone(), item_value(lines, pos, indent, item)?)
+                    }
+                    Kind::KeyItem(_) => {
+                        let mut parts = Vec::new();
+                        while let Some(Line {
+                            indent: i,
+                            kind: Kind::KeyItem(part),
+                            ..
+                        }) = lines.get(*pos)
+                        {
+                            if *i != indent {
+                                break;
+                            }
+                            parts.push(part.as_str());
+                            *pos += 1;
+                        }
+                        let value =
+                            match lines.get(*pos) {
+                                Some(next) if next.indent > indent => {
+                                    value(lines, pos, next.indent)?
+                                }
+                                _ => return Err(error(
+                                    line.number,
+                                    "a multiline key needs an indented value",
+                                )),
+                            };
+                        (parts.join("\n"), value)
+                    }
+                    _ => return Err(error(line.number, "expected a key")),
+                };
+                if dict.insert(key, value).is_some() {
+                    return Err(error(line.number, "duplicate key"));
+                }
+            }
+            Ok(JsonValue::Object(dict))
+        }
+        Kind::StringItem(_) => {
+            let mut parts = Vec::new();
+            while let Some(Line {
+                indent: i,
+                kind: Kind::StringItem(part),
+                ..
+            }) = lines.get(*pos)
+            {
+                if *i != indent {
+                    break;
+                }
+                parts.push(part.as_str());
+                *pos += 1;
+            }
+            Ok(parts.join("\n").into())
+        }
+        Kind::Inline(ref text) => {
+            *pos += 1;
+            let chars: Vec<char> = text.chars().collect();
+            let mut end = 0;
+            inline(&chars, &mut end)
+                .filter(|_| end == chars.len())
+                .ok_or_else(|| error(first.number, "invalid inline value"))
+        }
+    }
+}
+
+/// The value of a list or dict item: on the same line, indented on the next
+/// lines, or an empty string.
+fn item_value(
+    lines: &[Line],
+    pos: &mut usize,
+    indent: usize,
+    item: &Option<String>,
+) -> Result<JsonValue, ConfigError> {
+    let nested = lines.get(*pos).filter(|next| next.indent > indent);
+    match (item, nested) {
+        (Some(_), Some(next)) => {
+            Err(error(next.number, "invalid indentation"))
+        }
+        (Some(item), None) => Ok(item.clone().into()),
+        (None, Some(next)) => value(lines, pos, next.indent),
+        (None, None) => Ok("".into()),
+    }
+}
+
+/// Parse an inline list or dict, whose strings can't hold `[]{},:`.
+fn inline(chars: &[char], pos: &mut usize) -> Option<JsonValue> {
+    let close = match chars.get(*pos)? {
+        '[' => ']',
+        '{' => '}',
+        _ => return None,
+    };
+    *pos += 1;
+    let mut list = Vec::new();
+    let mut dict = Map::new();
+    loop {
+        if close == ']' {
+            list.push(inline_item(chars, pos)?);
+        } else {
+            let key = inline_string(chars, pos);
+            // `{}` is the empty dict.
+            if key.is_empty()
+                && dict.is_empty()
+                && chars.get(*pos) == Some(&'}')
+            {
+                *pos += 1;
+                return Some(JsonValue::Object(dict));
+            }
+            if chars.get(*pos) != Some(&':') {
+                return None;
+            }
+            *pos += 1;
+            dict.insert(key, inline_item(chars, pos)?);
+        }
+        match chars.get(*pos)? {
+            ',' => *pos += 1,
+            &c if c == close => break,
+            _ => return None,
+        }
+    }
+    *pos += 1;
+    if close == '}' {
+        return Some(JsonValue::Object(dict));
+    }
+    // `[]` is the empty list, not a list holding an empty string.
+    if list == [JsonValue::from("")] {
+        list.clear();
+    }
+    Some(JsonValue::Array(list))
+}
+
+/// A list item or a dict value: an inline list or dict, or a string.
+fn inline_item(chars: &[char], pos: &mut usize) -> Option<JsonValue> {
+    let s = inline_string(chars, pos);
+    if !s.is_empty() || !matches!(chars.get(*pos), Some('[') | Some('{')) {
+        return Some(s.into());
+    }
+    let value = inline(chars, pos)?;
+    // Nothing but spaces after a nested list or dict.
+    Some(value).filter(|_| inline_string(chars, pos).is_empty())
+}
+
+/// Read a string up to the next delimiter, and return it trimmed.
+fn inline_string(chars: &[char], pos: &mut usize) -> String {
+    let start = *pos;
+    while chars
+        .get(*pos)
+        .is_some_and(|c| !matches!(c, '[' | ']' | '{' | '}' | ',' | ':'))
+    {
+        *pos += 1;
+    }
+    chars[start..*pos]
+        .iter()
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        let text = "\
+# The defaults.
+default:
+  pg:
+    host: localhost
+    port: 5432
+  hosts:
+    - a.example.com
+    -
+      - nested
+    - [b, c]
+  empty:
+  motd:
+    > Welcome!
+    >
+    > Have a nice day.
+  : multiline
+  : key
+    value: x
+  inline: {a: 1, b: [2, 3], c: {}}
+production:
+  url: http://example.com: no split
+  empty list: []
+";
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({
+                "default": {
+                    "pg": {"host": "localhost", "port": "5432"},
+                    "hosts": ["a.example.com", ["nested"], "[b, c]"],
+                    "empty": "",
+                    "motd": "Welcome!\n\nHave a nice day.",
+                    "multiline\nkey": {"value": "x"},
+                    "inline": "{a: 1, b: [2, 3], c: {}}",
+                },
+                "production": {
+                    "url": "http://example.com: no split",
+                    "empty list": "[]",
+                },
+            })
+        );
+        assert_eq!(
+            parse("[a, {b: c}, [], [ ]]").unwrap(),
+            json!(["a", {"b": "c"}, [], []])
+        );
+        assert_eq!(
+            parse("{a: 1, b: [2, 3]}").unwrap()["b"],
+            json!(["2", "3"])
+        );
+        assert_eq!(parse("# Nothing\n").unwrap(), json!({}));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        for text in &[
+            "a: 1\n  b: 2\n",
+            "a: 1\n a: 2\n",
+            "a: 1\n- b\n",
+            "a: 1\na: 2\n",
+            "just text\n",
+            "\ta: 1\n",
+            "[a, b\n",
+            "{a}\n",
+            ": key\na: 1\n",
+        ] {
+            assert!(
+                matches!(
+                    parse(text),
+                    Err(ConfigError::FileParse { uri: None, .. })
+                ),
+                "{:?}",
+                text
+            );
+        }
+    }
+}
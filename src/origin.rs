@@ -0,0 +1,39 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// The layer a configuration value was loaded from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Origin {
+    Default,
+    Base,
+    Settings(PathBuf),
+    Secrets(PathBuf),
+    Source(String),
+    SecretSource(String),
+    Dotenv(PathBuf),
+    Environment,
+    Override,
+}
+
+impl Origin {
+    /// Whether the value comes from the secrets layer.
+    pub fn is_secret(&self) -> bool {
+        matches!(self, Origin::Secrets(_) | Origin::SecretSource(_))
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Origin::Default => write!(f, "default"),
+            Origin::Base => write!(f, "base config"),
+            Origin::Settings(p) => write!(f, "settings ({})", p.display()),
+            Origin::Secrets(p) => write!(f, "secrets ({})", p.display()),
+            Origin::Source(name) => write!(f, "{}", name),
+            Origin::SecretSource(name) => write!(f, "secrets ({})", name),
+            Origin::Dotenv(p) => write!(f, "dotenv ({})", p.display()),
+            Origin::Environment => write!(f, "environment"),
+            Origin::Override => write!(f, "override"),
+        }
+    }
+}
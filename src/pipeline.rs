@@ -0,0 +1,206 @@
+//! The stages of `Hydroconf::hydrate()`, which can be reordered, replaced
+//! or extended with custom ones.
+
+use std::fmt;
+use std::sync::Arc;
+
+use config::ConfigError;
+
+use crate::hydro::Hydroconf;
+use crate::settings::{HydroSettings, Layer};
+
+/// A step of the hydration, e.g. `merge_settings`.
+pub trait Stage: Send + Sync {
+    /// The name of the stage, unique in the pipeline.
+    fn name(&self) -> &str;
+
+    fn run(&self, hydro: &mut Hydroconf) -> Result<(), ConfigError>;
+}
+
+/// A stage running a closure.
+pub struct FnStage<F> {
+    name: String,
+    f: F,
+}
+
+impl<F> FnStage<F>
+where
+    F: Fn(&mut Hydroconf) -> Result<(), ConfigError> + Send + Sync,
+{
+    pub fn new<S: Into<String>>(name: S, f: F) -> Self {
+        Self {
+            name: name.into(),
+            f,
+        }
+    }
+}
+
+impl<F> Stage for FnStage<F>
+where
+    F: Fn(&mut Hydroconf) -> Result<(), ConfigError> + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn run(&self, hydro: &mut Hydroconf) -> Result<(), ConfigError> {
+        (self.f)(hydro)
+    }
+}
+
+type Stages = Vec<Arc<dyn Stage>>;
+
+/// The stages of a `Hydroconf`. Until they're changed, they're the default
+/// ones, which depend on the settings (e.g. the order of the layers).
+#[derive(Clone, Default)]
+pub(crate) struct Pipeline(Option<Stages>);
+
+impl Pipeline {
+    pub fn stages(
+        &self,
+        settings: &HydroSettings,
+    ) -> Result<Stages, ConfigError> {
+        match self.0 {
+            Some(ref stages) => Ok(stages.clone()),
+            None => default_stages(settings),
+        }
+    }
+
+    /// Insert `stage` before or after the stage named `name`.
+    pub fn insert(
+        &mut self,
+        settings: &HydroSettings,
+        name: &str,
+        after: bool,
+        stage: Arc<dyn Stage>,
+    ) -> Result<(), ConfigError> {
+        let mut stages = self.stages(settings)?;
+        let index = position(&stages, name)?;
+        check_unique(&stages, stage.name())?;
+        stages.insert(index + after as usize, stage);
+        self.0 = Some(stages);
+        Ok(())
+    }
+
+    pub fn replace(
+        &mut self,
+        settings: &HydroSettings,
+        name: &str,
+        stage: Arc<dyn Stage>,
+    ) -> Result<(), ConfigError> {
+        let mut stages = self.stages(settings)?;
+        let index = position(&stages, name)?;
+        if stage.name() != name {
+            check_unique(&stages, stage.name())?;
+        }
+        stages[index] = stage;
+        self.0 = Some(stages);
+        Ok(())
+    }
+
+    pub fn remove(
+        &mut self,
+        settings: &HydroSettings,
+        name: &str,
+    ) -> Result<(), ConfigError> {
+        let mut stages = self.stages(settings)?;
+        stages.remove(position(&stages, name)?);
+        self.0 = Some(stages);
+        Ok(())
+    }
+}
+
+fn position(stages: &Stages, name: &str) -> Result<usize, ConfigError> {
+    stages.iter().position(|s| s.name() == name).ok_or_else(|| {
+        ConfigError::Message(format!(
+            "there's no stage named {} in the pipeline",
+            name
+        ))
+    })
+}
+
+fn check_unique(stages: &Stages, name: &str) -> Result<(), ConfigError> {
+    if stages.iter().any(|s| s.name() == name) {
+        return Err(ConfigError::Message(format!(
+            "there's already a stage named {} in the pipeline",
+            name
+        )));
+    }
+    Ok(())
+}
+
+impl fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ref stages) => f
+                .debug_list()
+                .entries(stages.iter().map(|s| s.name()))
+                .finish(),
+            None => f.write_str("Pipeline"),
+        }
+    }
+}
+
+fn stage<F>(name: &str, f: F) -> Arc<dyn Stage>
+where
+    F: Fn(&mut Hydroconf) -> Result<(), ConfigError> + Send + Sync + 'static,
+{
+    Arc::new(FnStage::new(name, f))
+}
+
+/// The stages of `Hydroconf::hydrate()`, named like the methods they call.
+fn default_stages(settings: &HydroSettings) -> Result<Stages, ConfigError> {
+    let mut stages = vec![stage("check_env", |h| h.check_env().map(drop))];
+    #[cfg(feature = "git")]
+    stages.push(stage("checkout_git_repo", |h| {
+        h.checkout_git_repo().map(drop)
+    }));
+    stages.extend([
+        stage("discover_sources", |h| {
+            h.discover_sources();
+            Ok(())
+        }),
+        stage("load_settings", |h| h.load_settings().map(drop)),
+        stage("merge_settings", |h| h.merge_settings().map(drop)),
+    ]);
+    for layer in settings.override_layers()? {
+        stages.push(match layer {
+            Layer::Settings => {
+                stage("reapply_settings", |h| h.reapply_settings().map(drop))
+            }
+            Layer::Secrets => {
+                stage("reapply_secrets", |h| h.reapply_secrets().map(drop))
+            }
+            Layer::Dotenv => stage("override_from_dotenv", |h| {
+                h.override_from_dotenv().map(drop)
+            }),
+            Layer::Env => {
+                stage("override_from_env", |h| h.override_from_env().map(drop))
+            }
+        });
+    }
+    stages.extend([
+        stage("resolve_files", |h| h.resolve_files().map(drop)),
+        stage("generate_values", |h| h.generate_values().map(drop)),
+        stage("decrypt_values", |h| h.decrypt_values().map(drop)),
+        stage("resolve_references", |h| h.resolve_references().map(drop)),
+    ]);
+    #[cfg(feature = "keyring")]
+    stages.push(stage("resolve_keyring", |h| h.resolve_keyring().map(drop)));
+    if settings.trim_values {
+        stages.push(stage("trim_values", |h| h.trim_values().map(drop)));
+    }
+    stages.extend([
+        stage("resolve_paths", |h| h.resolve_paths().map(drop)),
+        stage("check_secret_keys", |h| h.check_secret_keys().map(drop)),
+        stage("check_required", |h| h.check_required().map(drop)),
+        stage("validate", |h| h.validate().map(drop)),
+    ]);
+    if settings.warn_shadowed {
+        stages.push(stage("warn_shadowed", |h| {
+            h.warn_shadowed();
+            Ok(())
+        }));
+    }
+    Ok(stages)
+}
@@ -0,0 +1,127 @@
+//! Support for the Java `.properties` files, where the dotted keys are
+//! nested paths, e.g. `production.pg.port=5432`.
+
+use config::ConfigError;
+
+/// Parse the `(key, value)` pairs of a `.properties` document: `key=value`,
+/// `key: value` or `key value` lines, with `#` and `!` comments, lines
+/// continued by a trailing backslash and the usual escapes.
+pub fn parse(text: &str) -> Result<Vec<(String, String)>, ConfigError> {
+    let mut pairs = Vec::new();
+    let mut lines = text.lines().enumerate();
+    while let Some((n, line)) = lines.next() {
+        let mut logical = line.trim_start().to_string();
+        if logical.is_empty() || logical.starts_with(['#', '!']) {
+            continue;
+        }
+        while ends_with_continuation(&logical) {
+            logical.pop();
+            match lines.next() {
+                Some((_, next)) => logical.push_str(next.trim_start()),
+                None => break,
+            }
+        }
+        let (key, value) = split_pair(&logical);
+        let error = |message: &str| {
+            ConfigError::Message(format!(
+                "invalid .properties line {}: {}",
+                n + 1,
+                message
+            ))
+        };
+        pairs.push((
+            unescape(key).map_err(error)?,
+            unescape(value).map_err(error)?,
+        ));
+    }
+    Ok(pairs)
+}
+
+/// Whether a line ends with an odd number of backslashes.
+fn ends_with_continuation(line: &str) -> bool {
+    line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+/// Split a line on the first unescaped `=`, `:` or whitespace.
+fn split_pair(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '=' || c == ':' || c.is_whitespace() {
+            let mut value = line[i + c.len_utf8()..].trim_start();
+            // `key = value`: the separator is the `=`, not the space.
+            if c.is_whitespace() {
+                if let Some(rest) = value.strip_prefix(['=', ':']) {
+                    value = rest.trim_start();
+                }
+            }
+            return (&line[..i], value);
+        }
+    }
+    (line, "")
+}
+
+fn unescape(s: &str) -> Result<String, &'static str> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let code = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 4)
+                    .ok_or("invalid \\u escape")?;
+                out.push(char::from_u32(code).ok_or("invalid \\u escape")?);
+            }
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r"
+# The defaults.
+! Also a comment.
+default.pg.host = localhost
+default.pg.port:5432
+default.name Hydro\u00e9conf
+default.path=C:\\app\\data
+default.hosts = a,\
+                b
+production.key\=with\:separators = x y
+production.empty
+";
+        assert_eq!(
+            parse(text).unwrap(),
+            vec![
+                ("default.pg.host".into(), "localhost".into()),
+                ("default.pg.port".into(), "5432".into()),
+                ("default.name".into(), "Hydro\u{e9}conf".into()),
+                ("default.path".into(), "C:\\app\\data".into()),
+                ("default.hosts".into(), "a,b".into()),
+                ("production.key=with:separators".into(), "x y".into()),
+                ("production.empty".into(), "".into()),
+            ]
+        );
+        assert!(parse("default.x = \\u00zz").is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use config::{ConfigError, Source, Value};
+
+use super::http::{agent, send_json};
+#[cfg(feature = "watch")]
+use super::http::{request_error, watch_agent};
+use crate::utils::key_tree;
+#[cfg(feature = "watch")]
+use crate::watch::{Changes, WatchedSource};
+
+/// How long a blocking query waits for a change.
+#[cfg(feature = "watch")]
+const WAIT: &str = "5m";
+
+/// A source reading the settings stored under a key prefix in the Consul KV
+/// store.
+///
+/// The first path segment after the prefix is the section, the following
+/// ones are the nested keys: with the prefix `myapp`, the key
+/// `myapp/production/pg/port` is read as `pg.port` in the `production`
+/// section. Values are read as strings.
+#[derive(Clone)]
+pub struct ConsulSource {
+    addr: String,
+    prefix: String,
+    token: Option<String>,
+}
+
+impl ConsulSource {
+    pub fn new(addr: String, prefix: &str) -> Self {
+        Self {
+            addr,
+            prefix: prefix.trim_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    /// Connect to the agent given by the standard `CONSUL_HTTP_ADDR` and
+    /// `CONSUL_HTTP_TOKEN` environment variables.
+    pub fn from_env(prefix: &str) -> Self {
+        let addr = env::var("CONSUL_HTTP_ADDR")
+            .unwrap_or_else(|_| "127.0.0.1:8500".into());
+        let addr = if addr.contains("://") {
+            addr
+        } else {
+            format!("http://{}", addr)
+        };
+        let source = Self::new(addr, prefix);
+        match env::var("CONSUL_HTTP_TOKEN") {
+            Ok(token) => source.set_token(token),
+            Err(_) => source,
+        }
+    }
+
+    pub fn set_token(mut self, t: String) -> Self {
+        self.token = Some(t);
+        self
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    fn url(&self) -> String {
+        format!(
+            "{}/v1/kv/{}/?recurse=true",
+            self.addr.trim_end_matches('/'),
+            self.prefix,
+        )
+    }
+
+    fn get(&self, agent: ureq::Agent, url: &str) -> ureq::Request {
+        let request = agent.get(url);
+        match self.token {
+            Some(ref token) => request.set("X-Consul-Token", token),
+            None => request,
+        }
+    }
+
+    /// Wait until the index of the keys under the prefix is not `index`
+    /// anymore (a blocking query), and return the new one.
+    #[cfg(feature = "watch")]
+    fn wait_index(&self, index: u64) -> Result<u64, ConfigError> {
+        let url = format!("{}&index={}&wait={}", self.url(), index, WAIT);
+        let response = match self.get(watch_agent(), &url).call() {
+            // There's an index even when there are no keys.
+            Ok(response) | Err(ureq::Error::Status(404, response)) => response,
+            Err(e) => return Err(request_error(&url, e)),
+        };
+        response
+            .header("X-Consul-Index")
+            .and_then(|i| i.parse().ok())
+            .ok_or_else(|| {
+                ConfigError::Message(format!("no X-Consul-Index from {}", url))
+            })
+    }
+}
+
+// Keep the token out of the logs.
+impl fmt::Debug for ConsulSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ConsulSource")
+            .field("addr", &self.addr)
+            .field("prefix", &self.prefix)
+            .field("token", &self.token.as_ref().map(|_| "********"))
+            .finish()
+    }
+}
+
+impl Source for ConsulSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let url = self.url();
+        let response =
+            send_json(self.get(agent(), &url), None)?.unwrap_or_default();
+        let mut kvs = Vec::new();
+        for entry in response.as_array().into_iter().flatten() {
+            // Folders have no value.
+            let (key, value) =
+                match (entry["Key"].as_str(), entry["Value"].as_str()) {
+                    (Some(key), Some(value)) => (key, value),
+                    _ => continue,
+                };
+            let value = BASE64
+                .decode(value)
+                .ok()
+                .and_then(|v| String::from_utf8(v).ok())
+                .ok_or_else(|| {
+                    ConfigError::Message(format!(
+                        "invalid value of {} from {}",
+                        key, url
+                    ))
+                })?;
+            if let Some(key) = key.strip_prefix(self.prefix.as_str()) {
+                kvs.push((key.to_string(), value));
+            }
+        }
+        Ok(key_tree(kvs, "/"))
+    }
+}
+
+/// The changes are notified by blocking queries, which return as soon as
+/// the index of the keys under the prefix changes.
+#[cfg(feature = "watch")]
+impl WatchedSource for ConsulSource {
+    fn changes(&self) -> Changes {
+        let source = self.clone();
+        // The first query returns the current index right away. The index
+        // is kept across the errors, so that no change is missed.
+        let mut index = 0;
+        Box::new(std::iter::from_fn(move || loop {
+            let new = match source.wait_index(index) {
+                Ok(new) => new,
+                Err(e) => return Some(Err(e)),
+            };
+            let changed = index != 0 && new != index;
+            // The index can go backwards, e.g. after a snapshot restore.
+            index = if new < index { 0 } else { new };
+            if changed {
+                return Some(Ok(()));
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::testing::{json, serve};
+    use serde_json::json;
+
+    fn consul_server() -> String {
+        let (addr, _) = serve(|req| {
+            if req.header("X-Consul-Token") != Some("tok") {
+                return json(403, json!("ACL not found"));
+            }
+            if !req.path.starts_with("/v1/kv/myapp/?recurse=true") {
+                return json(404, json!(null));
+            }
+            let kv = |k: &str, v: Option<&str>| {
+                let v = v.map(|v| BASE64.encode(v));
+                json!({"Key": k, "Value": v})
+            };
+            json(
+                200,
+                json!([
+                    kv("myapp/", None),
+                    kv("myapp/default/pg/port", Some("5432")),
+                    kv("myapp/default/pg/host", Some("localhost")),
+                    kv("myapp/production/pg/host", Some("db-0")),
+                ]),
+            )
+        });
+        addr
+    }
+
+    #[test]
+    fn test_collect() {
+        let source = ConsulSource::new(consul_server(), "/myapp/")
+            .set_token("tok".into());
+        let m = source.collect().unwrap();
+        let pg = |section: &str| {
+            let table = m[section].clone().into_table().unwrap();
+            table["pg"].clone().into_table().unwrap()
+        };
+        assert_eq!(pg("default")["port"].clone().into_int().unwrap(), 5432);
+        assert_eq!(
+            pg("production")["host"].clone().into_str().unwrap(),
+            "db-0"
+        );
+
+        let source =
+            ConsulSource::new(source.addr, "other").set_token("tok".into());
+        assert!(source.collect().unwrap().is_empty());
+        let source = ConsulSource::new(source.addr, "myapp");
+        assert!(source.collect().is_err());
+    }
+
+    #[test]
+    fn test_hydrate() {
+        env::set_var("CONSUL_HTTP_ADDR", consul_server());
+        env::set_var("CONSUL_HTTP_TOKEN", "tok");
+        env::set_var("CONSULTEST_PG__PORT", "6543");
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_env("production".into())
+                .set_envvar_prefix("CONSULTEST".into())
+                .set_consul_prefix("myapp".into()),
+        );
+        hydro.skip_discovery(true);
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_str("pg.host").unwrap(), "db-0");
+        assert_eq!(hydro.get_int("pg.port").unwrap(), 6543);
+        env::remove_var("CONSUL_HTTP_ADDR");
+        env::remove_var("CONSUL_HTTP_TOKEN");
+        env::remove_var("CONSULTEST_PG__PORT");
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn test_changes() {
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        // The index changes on every other query.
+        let queries = AtomicU64::new(0);
+        let (addr, log) = serve(move |_| {
+            let index = 10 + queries.fetch_add(1, Ordering::SeqCst) / 2;
+            let (status, mut headers, body) = json(200, json!([]));
+            headers.push(("X-Consul-Index".into(), index.to_string()));
+            (status, headers, body)
+        });
+        let mut changes = ConsulSource::new(addr, "myapp")
+            .set_token("tok".into())
+            .changes();
+        assert!(changes.next().unwrap().is_ok());
+        let paths: Vec<_> =
+            log.lock().unwrap().iter().map(|r| r.path.clone()).collect();
+        assert_eq!(
+            paths,
+            [
+                "/v1/kv/myapp/?recurse=true&index=0&wait=5m",
+                "/v1/kv/myapp/?recurse=true&index=10&wait=5m",
+                "/v1/kv/myapp/?recurse=true&index=10&wait=5m",
+            ]
+        );
+        assert_eq!(
+            log.lock().unwrap()[0].header("X-Consul-Token"),
+            Some("tok")
+        );
+
+        let (addr, _) = serve(|_| json(500, json!(null)));
+        let mut changes = ConsulSource::new(addr, "myapp").changes();
+        assert!(changes.next().unwrap().is_err());
+    }
+}
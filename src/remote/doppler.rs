@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+
+use config::{ConfigError, Source, Value};
+
+use super::http::{agent, send_json};
+use crate::utils::key_tree;
+
+/// A source reading the secrets of a Doppler config, with a service token.
+///
+/// Doppler secrets are named like environment variables, so their names are
+/// lowercased and split on the nested separator: `PG__PASSWORD` is read as
+/// `pg.password`. They are placed in the `default` table unless another
+/// section is set. The `DOPPLER_*` metadata is ignored.
+#[derive(Clone)]
+pub struct DopplerSource {
+    api_host: String,
+    token: String,
+    nested_sep: String,
+    section: String,
+}
+
+impl DopplerSource {
+    pub fn new(token: String) -> Self {
+        Self {
+            api_host: "https://api.doppler.com".into(),
+            token,
+            nested_sep: "__".into(),
+            section: "default".into(),
+        }
+    }
+
+    /// Authenticate with the standard `DOPPLER_TOKEN` environment variable,
+    /// and use `DOPPLER_API_HOST` if it's set. Return `None` if there's no
+    /// token.
+    pub fn from_env() -> Option<Self> {
+        let token =
+            env::var("DOPPLER_TOKEN").ok().filter(|t| !t.is_empty())?;
+        let source = Self::new(token);
+        match env::var("DOPPLER_API_HOST") {
+            Ok(host) => Some(source.set_api_host(host)),
+            Err(_) => Some(source),
+        }
+    }
+
+    pub fn set_api_host(mut self, host: String) -> Self {
+        self.api_host = host;
+        self
+    }
+
+    pub fn set_nested_sep(mut self, sep: String) -> Self {
+        self.nested_sep = sep;
+        self
+    }
+
+    /// The table where the secrets are placed, `default` unless set.
+    pub fn set_section(mut self, section: String) -> Self {
+        self.section = section;
+        self
+    }
+
+    pub fn api_host(&self) -> &str {
+        &self.api_host
+    }
+}
+
+// Keep the token out of the logs.
+impl fmt::Debug for DopplerSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DopplerSource")
+            .field("api_host", &self.api_host)
+            .field("token", &"********")
+            .field("nested_sep", &self.nested_sep)
+            .field("section", &self.section)
+            .finish()
+    }
+}
+
+impl Source for DopplerSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let url = format!(
+            "{}/v3/configs/config/secrets/download?format=json",
+            self.api_host.trim_end_matches('/'),
+        );
+        let request = agent()
+            .get(&url)
+            .set("Authorization", &format!("Bearer {}", self.token));
+        let response = send_json(request, None)?.ok_or_else(|| {
+            ConfigError::Message(format!("{} was not found", url))
+        })?;
+        let sep = &self.nested_sep;
+        let mut kvs = Vec::new();
+        for (name, value) in response.as_object().into_iter().flatten() {
+            if name.starts_with("DOPPLER_") {
+                continue;
+            }
+            let value =
+                value.as_str().map_or_else(|| value.to_string(), Into::into);
+            let key =
+                format!("{}{}{}", self.section, sep, name.to_lowercase());
+            kvs.push((key, value));
+        }
+        Ok(key_tree(kvs, sep))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::testing::{json, serve};
+    use serde_json::json;
+
+    #[test]
+    fn test_collect() {
+        let (url, log) = serve(|req| match req.header("Authorization") {
+            Some("Bearer dp.st.prd.xxxx") => json(
+                200,
+                json!({
+                    "DOPPLER_CONFIG": "prd",
+                    "DOPPLER_PROJECT": "myapp",
+                    "PG__PASSWORD": "a password",
+                    "PG__PORT": "5432",
+                }),
+            ),
+            _ => json(401, json!({"messages": ["Invalid Auth token"]})),
+        });
+        let source = DopplerSource::new("dp.st.prd.xxxx".into())
+            .set_api_host(url.clone())
+            .set_section("production".into());
+        let m = source.collect().unwrap();
+        let table = m["production"].clone().into_table().unwrap();
+        assert_eq!(table.len(), 1);
+        let pg = table["pg"].clone().into_table().unwrap();
+        assert_eq!(pg["password"].clone().into_str().unwrap(), "a password");
+        assert_eq!(pg["port"].clone().into_int().unwrap(), 5432);
+        assert_eq!(
+            log.lock().unwrap()[0].path,
+            "/v3/configs/config/secrets/download?format=json"
+        );
+        assert!(!format!("{:?}", source).contains("xxxx"));
+
+        let source =
+            DopplerSource::new("dp.st.dev.yyyy".into()).set_api_host(url);
+        assert!(source.collect().is_err());
+    }
+}
@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+#[cfg(feature = "watch")]
+use std::io::{self, BufRead, BufReader, Read};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use config::{ConfigError, Source, Value};
+use serde_json::{json, Value as JsonValue};
+
+use super::http::{agent, send_json};
+#[cfg(feature = "watch")]
+use super::http::{request_error, watch_agent};
+use crate::utils::key_tree;
+#[cfg(feature = "watch")]
+use crate::watch::{Changes, WatchedSource};
+
+/// A source reading the settings stored under a key prefix in etcd v3,
+/// through its JSON gateway.
+///
+/// The first path segment after the prefix is the section, the following
+/// ones are the nested keys: with the prefix `/myapp`, the key
+/// `/myapp/production/pg/port` is read as `pg.port` in the `production`
+/// section. Values are read as strings.
+#[derive(Debug, Clone)]
+pub struct EtcdSource {
+    endpoint: String,
+    prefix: String,
+}
+
+impl EtcdSource {
+    pub fn new(endpoint: String, prefix: String) -> Self {
+        Self {
+            endpoint,
+            prefix: format!("{}/", prefix.trim_end_matches('/')),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.endpoint.trim_end_matches('/'), path)
+    }
+
+    /// The range of the keys under the prefix.
+    fn keys(&self) -> JsonValue {
+        json!({
+            "key": BASE64.encode(&self.prefix),
+            "range_end": BASE64.encode(prefix_end(self.prefix.as_bytes())),
+        })
+    }
+
+    fn range(&self) -> Result<Vec<(String, String)>, ConfigError> {
+        let url = self.url("/v3/kv/range");
+        let body = self.keys();
+        let response =
+            send_json(agent().post(&url), Some(&body))?.unwrap_or_default();
+        let kvs = match response["kvs"].as_array() {
+            Some(kvs) => kvs,
+            None => return Ok(Vec::new()),
+        };
+        kvs.iter()
+            .map(|kv| {
+                Ok((decode(&url, &kv["key"])?, decode(&url, &kv["value"])?))
+            })
+            .collect()
+    }
+}
+
+/// The end of the range of the keys starting with `prefix`.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    while let Some(last) = end.pop() {
+        if last < 0xff {
+            end.push(last + 1);
+            return end;
+        }
+    }
+    // All the keys.
+    vec![0]
+}
+
+fn decode(url: &str, value: &JsonValue) -> Result<String, ConfigError> {
+    let invalid =
+        || ConfigError::Message(format!("invalid key or value from {}", url));
+    let bytes = BASE64
+        .decode(value.as_str().unwrap_or_default())
+        .map_err(|_| invalid())?;
+    String::from_utf8(bytes).map_err(|_| invalid())
+}
+
+impl Source for EtcdSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let kvs = self.range()?;
+        let kvs = kvs
+            .into_iter()
+            .map(|(k, v)| (k[self.prefix.len()..].to_string(), v));
+        Ok(key_tree(kvs, "/"))
+    }
+}
+
+/// The changes are notified by a watch stream of the keys under the
+/// prefix.
+#[cfg(feature = "watch")]
+impl WatchedSource for EtcdSource {
+    fn changes(&self) -> Changes {
+        Box::new(EtcdChanges {
+            source: self.clone(),
+            stream: None,
+            revision: None,
+        })
+    }
+}
+
+#[cfg(feature = "watch")]
+type Stream = io::Lines<BufReader<Box<dyn Read + Send + Sync>>>;
+
+/// The events of a watch stream, each line of which is a JSON response.
+#[cfg(feature = "watch")]
+struct EtcdChanges {
+    source: EtcdSource,
+    stream: Option<Stream>,
+    // The last revision seen, to resume the stream from there after
+    // reconnecting.
+    revision: Option<i64>,
+}
+
+#[cfg(feature = "watch")]
+impl EtcdChanges {
+    fn connect(&self) -> Result<Stream, ConfigError> {
+        let url = self.source.url("/v3/watch");
+        let mut request = self.source.keys();
+        request["progress_notify"] = json!(true);
+        if let Some(revision) = self.revision {
+            request["start_revision"] = json!((revision + 1).to_string());
+        }
+        let body = json!({ "create_request": request });
+        let response = watch_agent()
+            .post(&url)
+            .send_string(&body.to_string())
+            .map_err(|e| request_error(&url, e))?;
+        Ok(BufReader::new(response.into_reader()).lines())
+    }
+
+    /// Read a line of the stream, keeping its revision: return whether it's
+    /// a change to the keys.
+    fn read(&mut self, line: &str) -> Result<bool, ConfigError> {
+        let url = self.source.url("/v3/watch");
+        let response: JsonValue = serde_json::from_str(line).map_err(|e| {
+            ConfigError::Message(format!("invalid JSON from {}: {}", url, e))
+        })?;
+        let result = match response.get("result") {
+            Some(result) => result,
+            None => {
+                return Err(ConfigError::Message(format!(
+                    "watch of {} failed: {}",
+                    url, response["error"]
+                )))
+            }
+        };
+        if let Some(revision) = result["header"]["revision"]
+            .as_str()
+            .and_then(|r| r.parse().ok())
+        {
+            self.revision = Some(revision);
+        }
+        if result["canceled"].as_bool() == Some(true) {
+            // E.g. the start revision was compacted: start from the current
+            // one, and reload in case a change was missed.
+            self.revision = None;
+            return Ok(true);
+        }
+        Ok(result["events"].as_array().is_some_and(|e| !e.is_empty()))
+    }
+}
+
+#[cfg(feature = "watch")]
+impl Iterator for EtcdChanges {
+    type Item = Result<(), ConfigError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let stream = match self.stream {
+                Some(ref mut stream) => stream,
+                None => match self.connect() {
+                    Ok(stream) => self.stream.insert(stream),
+                    Err(e) => return Some(Err(e)),
+                },
+            };
+            let line = match stream.next() {
+                Some(Ok(line)) => line,
+                end => {
+                    self.stream = None;
+                    let reason = match end {
+                        Some(Err(e)) => e.to_string(),
+                        _ => "closed".to_string(),
+                    };
+                    return Some(Err(ConfigError::Message(format!(
+                        "watch stream of {}: {}",
+                        self.source.url("/v3/watch"),
+                        reason
+                    ))));
+                }
+            };
+            match self.read(&line) {
+                Ok(true) => return Some(Ok(())),
+                Ok(false) => continue,
+                Err(e) => {
+                    self.stream = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::testing::{json, serve};
+
+    fn etcd_server() -> String {
+        let (addr, _) = serve(|req| {
+            let body: JsonValue = serde_json::from_str(&req.body).unwrap();
+            if req.path != "/v3/kv/range"
+                || body["key"] != BASE64.encode("/myapp/")
+                || body["range_end"] != BASE64.encode("/myapp0")
+            {
+                return json(200, json!({"count": "0"}));
+            }
+            let kv = |k: &str, v: &str| {
+                let (k, v) = (BASE64.encode(k), BASE64.encode(v));
+                json!({"key": k, "value": v})
+            };
+            json(
+                200,
+                json!({"kvs": [
+                    kv("/myapp/default/pg/port", "5432"),
+                    kv("/myapp/default/pg/host", "localhost"),
+                    kv("/myapp/production/pg/host", "db-0"),
+                    kv("/myapp/orphan", "ignored"),
+                ]}),
+            )
+        });
+        addr
+    }
+
+    #[test]
+    fn test_collect() {
+        let source = EtcdSource::new(etcd_server(), "/myapp".into());
+        let m = source.collect().unwrap();
+        assert_eq!(m.len(), 2);
+        let pg = |section: &str| {
+            let table = m[section].clone().into_table().unwrap();
+            table["pg"].clone().into_table().unwrap()
+        };
+        assert_eq!(pg("default")["port"].clone().into_int().unwrap(), 5432);
+        assert_eq!(
+            pg("default")["host"].clone().into_str().unwrap(),
+            "localhost"
+        );
+        assert_eq!(
+            pg("production")["host"].clone().into_str().unwrap(),
+            "db-0"
+        );
+
+        let source = EtcdSource::new(etcd_server(), "/other/".into());
+        assert!(source.collect().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_hydrate() {
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_env("production".into())
+                .set_envvar_prefix("ETCDTEST".into()),
+        );
+        hydro
+            .skip_discovery(true)
+            .add_source(EtcdSource::new(etcd_server(), "/myapp".into()));
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_str("pg.host").unwrap(), "db-0");
+        assert_eq!(hydro.get_int("pg.port").unwrap(), 5432);
+    }
+
+    #[test]
+    fn test_prefix_end() {
+        assert_eq!(prefix_end(b"/app/"), b"/app0".to_vec());
+        assert_eq!(prefix_end(b"a\xff"), b"b".to_vec());
+        assert_eq!(prefix_end(b"\xff"), vec![0]);
+    }
+
+    #[cfg(feature = "watch")]
+    #[test]
+    fn test_changes() {
+        let (addr, log) = serve(|req| {
+            let body: JsonValue = serde_json::from_str(&req.body).unwrap();
+            let request = &body["create_request"];
+            assert_eq!(req.path, "/v3/watch");
+            assert_eq!(request["key"], BASE64.encode("/myapp/"));
+            let result = |revision: i64, events: JsonValue| {
+                let header = json!({"revision": revision.to_string()});
+                json!({"result": {"header": header, "events": events}})
+                    .to_string()
+            };
+            let lines = match request["start_revision"].as_str() {
+                None => vec![
+                    result(5, json!(null)),
+                    result(6, json!([{"type": "PUT"}])),
+                ],
+                Some(_) => vec![result(8, json!([{"type": "DELETE"}]))],
+            };
+            (200, Vec::new(), lines.join("\n").into_bytes())
+        });
+        let mut changes = EtcdSource::new(addr, "/myapp".into()).changes();
+        assert!(changes.next().unwrap().is_ok());
+        // The stream ends, and resumes after the last revision seen.
+        assert!(changes.next().unwrap().is_err());
+        assert!(changes.next().unwrap().is_ok());
+        let log = log.lock().unwrap();
+        let body: JsonValue = serde_json::from_str(&log[1].body).unwrap();
+        assert_eq!(body["create_request"]["start_revision"], "7");
+    }
+}
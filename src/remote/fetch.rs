@@ -0,0 +1,194 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use config::ConfigError;
+
+use super::http::{agent, request_error};
+use super::private_dir;
+
+/// Fetch a settings file over HTTP(S). The last copy is cached in
+/// `cache_dir`, if any, along with its ETag, so that unchanged files are not
+/// downloaded again and the cached copy is used if the server can't be
+/// reached. An error response from the server is not hidden by the cache.
+pub fn fetch_text(
+    url: &str,
+    cache_dir: Option<&Path>,
+) -> Result<String, ConfigError> {
+    // The cache is best-effort: without a private directory, the file is
+    // fetched every time.
+    let cache_dir = match cache_dir.filter(|dir| private_dir(dir).is_ok()) {
+        Some(dir) => dir,
+        None => {
+            return agent()
+                .get(url)
+                .call()
+                .map_err(|e| request_error(url, e))?
+                .into_string()
+                .map_err(|e| request_error(url, e.into()))
+        }
+    };
+    let name: String = url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let body_path = cache_dir.join(&name);
+    let etag_path = cache_dir.join(name + ".etag");
+    let cached = fs::read_to_string(&body_path).ok();
+
+    let mut request = agent().get(url);
+    if cached.is_some() {
+        if let Ok(etag) = fs::read_to_string(&etag_path) {
+            request = request.set("If-None-Match", &etag);
+        }
+    }
+    let error = match request.call() {
+        Ok(response) if response.status() == 304 => {
+            if let Some(text) = cached {
+                return Ok(text);
+            }
+            ConfigError::Message(format!(
+                "{} returned 304 but no copy is cached",
+                url
+            ))
+        }
+        Ok(response) => {
+            let etag = response.header("ETag").map(String::from);
+            let text = response
+                .into_string()
+                .map_err(|e| request_error(url, e.into()))?;
+            // Failing to write the cache is not an error.
+            let _ = write_private(&body_path, &text);
+            let _ = match etag {
+                Some(etag) => write_private(&etag_path, &etag),
+                None => fs::remove_file(&etag_path),
+            };
+            return Ok(text);
+        }
+        Err(e @ ureq::Error::Transport(_)) => request_error(url, e),
+        Err(e) => return Err(request_error(url, e)),
+    };
+    cached.ok_or(error)
+}
+
+/// Write a file only readable by the current user.
+fn write_private(path: &Path, contents: &str) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::fs::{OpenOptions, Permissions};
+        use std::io::Write;
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.set_permissions(Permissions::from_mode(0o600))?;
+        file.write_all(contents.as_bytes())
+    }
+    #[cfg(not(unix))]
+    fs::write(path, contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::testing::serve;
+    use crate::testing::TempDir;
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_etag_cache() {
+        // 0: up, 1: unreachable, 2: the file is gone.
+        let state = Arc::new(AtomicU8::new(0));
+        let server_state = state.clone();
+        let (url, log) = serve(move |req| {
+            match server_state.load(Ordering::SeqCst) {
+                1 => return (0, vec![], vec![]),
+                2 => return (404, vec![], vec![]),
+                _ => {}
+            }
+            if req.header("If-None-Match") == Some("\"v1\"") {
+                return (304, vec![], vec![]);
+            }
+            let etag = ("ETag".to_string(), "\"v1\"".to_string());
+            (200, vec![etag], b"[default]\nport = 1\n".to_vec())
+        });
+        let url = format!("{}/myapp.toml", url);
+        let dir = TempDir::new("etag");
+
+        let text = fetch_text(&url, Some(&dir)).unwrap();
+        assert_eq!(text, "[default]\nport = 1\n");
+        assert_eq!(fetch_text(&url, Some(&dir)).unwrap(), text);
+        {
+            let log = log.lock().unwrap();
+            assert_eq!(log[0].header("If-None-Match"), None);
+            assert_eq!(log[1].header("If-None-Match"), Some("\"v1\""));
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = |path: &Path| {
+                fs::metadata(path).unwrap().permissions().mode() & 0o777
+            };
+            assert_eq!(mode(&dir), 0o700);
+            for entry in fs::read_dir(&dir).unwrap() {
+                assert_eq!(mode(&entry.unwrap().path()), 0o600);
+            }
+        }
+
+        state.store(1, Ordering::SeqCst);
+        assert_eq!(fetch_text(&url, Some(&dir)).unwrap(), text);
+        // Only a network failure falls back to the cached copy.
+        state.store(2, Ordering::SeqCst);
+        assert!(fetch_text(&url, Some(&dir)).is_err());
+        state.store(1, Ordering::SeqCst);
+        let _ = fs::remove_dir_all(&dir);
+        assert!(fetch_text(&url, Some(&dir)).is_err());
+    }
+
+    #[test]
+    fn test_secrets_file_url() {
+        let (url, _) = serve(|_| {
+            let body = "[default]\npassword = 'a password'\n";
+            (200, vec![], body.as_bytes().to_vec())
+        });
+        let url = format!("{}/secrets.toml", url);
+        let root = TempDir::new("secrets-url");
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(root.to_path_buf())
+                .set_secrets_file(url.clone().into())
+                .set_envvar_prefix("SECRETSURLTEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_str("password").unwrap(), "a password");
+        // The secrets are never written to the cache.
+        let name: String = url
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        assert!(!crate::remote::cache_dir().join(name).exists());
+    }
+
+    #[test]
+    fn test_settings_file_url() {
+        let (url, _) = serve(|_| {
+            let body = "[default]\nport = 1\n[production]\nport = 2\n";
+            (200, vec![], body.as_bytes().to_vec())
+        });
+        let root = TempDir::new("url");
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(root.to_path_buf())
+                .set_settings_file(format!("{}/myapp.toml", url).into())
+                .set_env("production".into())
+                .set_envvar_prefix("URLTEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("port").unwrap(), 2);
+    }
+}
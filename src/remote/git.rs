@@ -0,0 +1,146 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use config::ConfigError;
+
+use super::private_dir;
+
+/// Clone the repository at `url` in `cache_dir` (or fetch it, if it was
+/// already cloned) and check out `reference`, a branch, tag or commit. The
+/// default branch of the remote is used if `reference` is `None`.
+///
+/// If the remote can't be reached, `reference` is checked out as it was
+/// last fetched.
+pub fn checkout(
+    url: &str,
+    reference: Option<&str>,
+    cache_dir: &Path,
+) -> Result<PathBuf, ConfigError> {
+    let name: String = url
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let dir = cache_dir.join("git").join(name);
+    private_dir(&dir)?;
+    if dir.join(".git").exists() {
+        // If the remote can't be reached, `reference` is checked out from
+        // the objects fetched before.
+        let _ = git(
+            &dir,
+            &["fetch", "--quiet", "--force", "--tags", "--prune", "origin"],
+        );
+    } else {
+        git(&dir, &["clone", "--quiet", "--no-checkout", "--", url, "."])?;
+    }
+    // Prefer the remote branch over a stale local one.
+    let remote_ref = format!("origin/{}", reference.unwrap_or("HEAD"));
+    let verify = ["rev-parse", "--verify", "--quiet", &remote_ref];
+    let rev = match reference {
+        Some(reference) if git(&dir, &verify).is_err() => reference,
+        _ => &remote_ref,
+    };
+    git(&dir, &["checkout", "--quiet", "--force", "--detach", rev])?;
+    Ok(dir)
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<(), ConfigError> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| {
+            ConfigError::Message(format!("could not run git: {}", e))
+        })?;
+    if output.status.success() {
+        return Ok(());
+    }
+    Err(ConfigError::Message(format!(
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    fn commit(repo: &Path, content: &str, tag: Option<&str>) {
+        fs::write(repo.join("settings.toml"), content).unwrap();
+        let author = [
+            "-c",
+            "user.name=Hydroconf",
+            "-c",
+            "user.email=hydroconf@example.com",
+        ];
+        git(repo, &["add", "settings.toml"]).unwrap();
+        let mut args = author.to_vec();
+        args.extend(&["commit", "--quiet", "-m", "update"]);
+        git(repo, &args).unwrap();
+        if let Some(tag) = tag {
+            git(repo, &["tag", tag]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_checkout() {
+        let tmp = TempDir::new("git");
+        let repo = tmp.join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        git(&repo, &["init", "--quiet", "--initial-branch=main"]).unwrap();
+        commit(&repo, "[default]\nport = 1\n", Some("v1"));
+        let url = repo.to_str().unwrap();
+        let cache = tmp.join("cache");
+        let read = |dir: PathBuf| {
+            fs::read_to_string(dir.join("settings.toml")).unwrap()
+        };
+
+        let dir = checkout(url, None, &cache).unwrap();
+        assert_eq!(read(dir), "[default]\nport = 1\n");
+
+        commit(&repo, "[default]\nport = 2\n", None);
+        let dir = checkout(url, None, &cache).unwrap();
+        assert_eq!(read(dir), "[default]\nport = 2\n");
+        let dir = checkout(url, Some("v1"), &cache).unwrap();
+        assert_eq!(read(dir), "[default]\nport = 1\n");
+        let dir = checkout(url, Some("main"), &cache).unwrap();
+        assert_eq!(read(dir), "[default]\nport = 2\n");
+        assert!(checkout(url, Some("missing"), &cache).is_err());
+
+        // The references last fetched are used if the remote is gone.
+        fs::remove_dir_all(&repo).unwrap();
+        let dir = checkout(url, Some("main"), &cache).unwrap();
+        assert_eq!(read(dir), "[default]\nport = 2\n");
+        let dir = checkout(url, Some("v1"), &cache).unwrap();
+        assert_eq!(read(dir), "[default]\nport = 1\n");
+        assert!(checkout(url, Some("missing"), &cache).is_err());
+    }
+
+    #[test]
+    fn test_checkout_option_url() {
+        let tmp = TempDir::new("git-option");
+        let url = "--upload-pack=touch pwned";
+        let error = checkout(url, None, &tmp).unwrap_err().to_string();
+        // The URL is a repository, not an option.
+        assert!(error.contains(&format!("'{}'", url)), "{}", error);
+    }
+
+    #[test]
+    fn test_hydrate_from_repo() {
+        let repo = TempDir::new("git-hydrate");
+        git(&repo, &["init", "--quiet", "--initial-branch=main"]).unwrap();
+        commit(&repo, "[default]\nport = 1\n[production]\nport = 2\n", None);
+
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_git_repo(repo.to_str().unwrap().into(), None)
+                .set_env("production".into())
+                .set_envvar_prefix("GITTEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("port").unwrap(), 2);
+    }
+}
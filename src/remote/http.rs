@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use config::ConfigError;
+#[cfg(any(
+    feature = "vault",
+    feature = "etcd",
+    feature = "consul",
+    feature = "spring",
+    feature = "doppler",
+    feature = "onepassword"
+))]
+use serde_json::Value as JsonValue;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a read can block while watching a remote source: longer than
+/// the Consul blocking queries and the etcd progress notifications.
+#[cfg(all(feature = "watch", any(feature = "consul", feature = "etcd")))]
+const WATCH_TIMEOUT: Duration = Duration::from_secs(11 * 60);
+
+pub fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(TIMEOUT).build()
+}
+
+/// An agent for the requests that block until the remote settings change.
+#[cfg(all(feature = "watch", any(feature = "consul", feature = "etcd")))]
+pub fn watch_agent() -> ureq::Agent {
+    ureq::AgentBuilder::new()
+        .timeout_connect(TIMEOUT)
+        .timeout_read(WATCH_TIMEOUT)
+        .build()
+}
+
+pub fn request_error(url: &str, e: ureq::Error) -> ConfigError {
+    ConfigError::Message(format!("request to {} failed: {}", url, e))
+}
+
+#[cfg(any(
+    feature = "vault",
+    feature = "etcd",
+    feature = "consul",
+    feature = "spring",
+    feature = "doppler",
+    feature = "onepassword"
+))]
+fn parse_json(url: &str, body: &str) -> Result<JsonValue, ConfigError> {
+    serde_json::from_str(body).map_err(|e| {
+        ConfigError::Message(format!("invalid JSON from {}: {}", url, e))
+    })
+}
+
+/// Send `request` and parse the JSON response body. A 404 response is
+/// returned as `None`.
+#[cfg(any(
+    feature = "vault",
+    feature = "etcd",
+    feature = "consul",
+    feature = "spring",
+    feature = "doppler",
+    feature = "onepassword"
+))]
+pub fn send_json(
+    request: ureq::Request,
+    body: Option<&JsonValue>,
+) -> Result<Option<JsonValue>, ConfigError> {
+    let url = request.url().to_string();
+    let response = match body {
+        Some(body) => request.send_string(&body.to_string()),
+        None => request.call(),
+    };
+    match response {
+        Ok(response) => {
+            let body = response
+                .into_string()
+                .map_err(|e| request_error(&url, e.into()))?;
+            parse_json(&url, &body).map(Some)
+        }
+        Err(ureq::Error::Status(404, _)) => Ok(None),
+        Err(e) => Err(request_error(&url, e)),
+    }
+}
@@ -0,0 +1,145 @@
+//! Sources that load the configuration from remote services. Each of them is
+//! behind its own cargo feature.
+
+#[cfg(any(feature = "http", feature = "git"))]
+use std::path::{Path, PathBuf};
+
+use config::ConfigError;
+
+#[cfg(feature = "consul")]
+mod consul;
+#[cfg(feature = "doppler")]
+mod doppler;
+#[cfg(feature = "etcd")]
+mod etcd;
+#[cfg(feature = "http")]
+mod fetch;
+#[cfg(feature = "git")]
+mod git;
+#[cfg(feature = "http")]
+mod http;
+#[cfg(feature = "onepassword")]
+mod onepassword;
+#[cfg(feature = "redis")]
+mod redis;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "spring")]
+mod spring;
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "mysql"))]
+mod sql;
+#[cfg(feature = "vault")]
+mod vault;
+#[cfg(feature = "zookeeper")]
+mod zookeeper;
+
+#[cfg(feature = "redis")]
+pub use self::redis::RedisSource;
+#[cfg(feature = "consul")]
+pub use consul::ConsulSource;
+#[cfg(feature = "doppler")]
+pub use doppler::DopplerSource;
+#[cfg(feature = "etcd")]
+pub use etcd::EtcdSource;
+#[cfg(feature = "git")]
+pub(crate) use git::checkout;
+#[cfg(feature = "onepassword")]
+pub use onepassword::OnePasswordSource;
+#[cfg(feature = "spring")]
+pub use spring::SpringConfigSource;
+#[cfg(any(feature = "sqlite", feature = "postgres", feature = "mysql"))]
+pub use sql::SqlSource;
+#[cfg(feature = "vault")]
+pub use vault::{VaultSettings, VaultSource};
+#[cfg(feature = "zookeeper")]
+pub use zookeeper::ZooKeeperSource;
+
+/// The directory where the remote settings files and repositories are
+/// cached: `hydroconf` in the cache directory of the user, or else in the
+/// temporary directory, with the id of the user.
+#[cfg(any(feature = "http", feature = "git"))]
+pub(crate) fn cache_dir() -> PathBuf {
+    if let Some(dir) = crate::sources::platform_cache_dir("hydroconf") {
+        return dir;
+    }
+    #[cfg(unix)]
+    return std::env::temp_dir().join(format!("hydroconf-{}", user_id()));
+    #[cfg(not(unix))]
+    std::env::temp_dir().join("hydroconf")
+}
+
+#[cfg(all(unix, any(feature = "http", feature = "git")))]
+fn user_id() -> u32 {
+    // SAFETY: `geteuid` has no preconditions and can't fail.
+    unsafe { libc::geteuid() }
+}
+
+/// Create `dir` if needed, only accessible by the current user. Fail if it
+/// belongs to another user, who could have put files in it.
+#[cfg(any(feature = "http", feature = "git"))]
+pub(crate) fn private_dir(dir: &Path) -> Result<(), ConfigError> {
+    let error = |e: std::io::Error| {
+        ConfigError::Message(format!(
+            "could not create {}: {}",
+            dir.display(),
+            e
+        ))
+    };
+    #[cfg(unix)]
+    {
+        use std::fs::{DirBuilder, Permissions};
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+        DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .map_err(error)?;
+        let metadata = std::fs::symlink_metadata(dir).map_err(error)?;
+        if !metadata.is_dir() || metadata.uid() != user_id() {
+            return Err(ConfigError::Message(format!(
+                "{} is not a directory of the current user",
+                dir.display()
+            )));
+        }
+        if metadata.mode() & 0o077 != 0 {
+            std::fs::set_permissions(dir, Permissions::from_mode(0o700))
+                .map_err(error)?;
+        }
+    }
+    #[cfg(not(unix))]
+    std::fs::create_dir_all(dir).map_err(error)?;
+    Ok(())
+}
+
+/// Fetch a settings file given as an `http://`, `https://` or `s3://` URL.
+/// The secrets files (`secret`) are never cached on disk.
+pub(crate) fn read_remote(
+    url: &str,
+    secret: bool,
+) -> Result<String, ConfigError> {
+    #[cfg(feature = "s3")]
+    if url.starts_with("s3://") {
+        return s3::read_object(url);
+    }
+    #[cfg(feature = "http")]
+    if !url.starts_with("s3://") {
+        let cache_dir = cache_dir();
+        let cache_dir = Some(cache_dir.as_path()).filter(|_| !secret);
+        return fetch::fetch_text(url, cache_dir);
+    }
+    let _ = secret;
+    let feature = if url.starts_with("s3://") {
+        "s3"
+    } else {
+        "http"
+    };
+    Err(ConfigError::Message(format!(
+        "{} can't be fetched: hydroconf was built without the `{}` feature",
+        url, feature
+    )))
+}
+
+#[cfg(test)]
+#[allow(dead_code)]
+pub(crate) mod testing;
@@ -0,0 +1,291 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::env;
+use std::fmt::{self, Write};
+
+use config::{ConfigError, Source, Value};
+use serde_json::Value as JsonValue;
+
+use super::http::{agent, send_json};
+use crate::utils::key_tree;
+
+/// A source resolving 1Password secret references, such as
+/// `op://Production/Postgres/password`, through a 1Password Connect server.
+///
+/// Each reference is mapped to a configuration key (e.g. `pg.password`) in
+/// the `default` table, unless another section is set. A reference is
+/// `op://<vault>/<item>/<field>` or `op://<vault>/<item>/<section>/<field>`,
+/// where vaults and items are given by name or ID, and fields by label or
+/// ID.
+#[derive(Clone)]
+pub struct OnePasswordSource {
+    host: String,
+    token: String,
+    refs: Vec<(String, String)>,
+    section: String,
+}
+
+impl OnePasswordSource {
+    pub fn new(host: String, token: String) -> Self {
+        Self {
+            host,
+            token,
+            refs: Vec::new(),
+            section: "default".into(),
+        }
+    }
+
+    /// Connect to the server given by the standard `OP_CONNECT_HOST` and
+    /// `OP_CONNECT_TOKEN` environment variables. Return `None` if either is
+    /// missing.
+    pub fn from_env() -> Option<Self> {
+        let var = |name| env::var(name).ok().filter(|v| !v.is_empty());
+        Some(Self::new(var("OP_CONNECT_HOST")?, var("OP_CONNECT_TOKEN")?))
+    }
+
+    /// Resolve `reference` into `key`.
+    pub fn add_ref(mut self, key: String, reference: String) -> Self {
+        self.refs.push((key, reference));
+        self
+    }
+
+    /// The table where the secrets are placed, `default` unless set.
+    pub fn set_section(mut self, section: String) -> Self {
+        self.section = section;
+        self
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn get(&self, path: &str) -> Result<Option<JsonValue>, ConfigError> {
+        let url = format!("{}/v1/{}", self.host.trim_end_matches('/'), path);
+        let request = agent()
+            .get(&url)
+            .set("Authorization", &format!("Bearer {}", self.token));
+        send_json(request, None)
+    }
+
+    /// Find the ID of a vault or an item by name, or check that `name` is
+    /// an ID. `path` is the collection, e.g. `vaults`.
+    fn find_id(
+        &self,
+        path: &str,
+        attribute: &str,
+        name: &str,
+    ) -> Result<String, ConfigError> {
+        let filter = format!("{} eq \"{}\"", attribute, name);
+        let query = format!("{}?filter={}", path, query_encode(&filter));
+        let found = self.get(&query)?.unwrap_or_default();
+        if let Some(id) = found[0]["id"].as_str() {
+            return Ok(id.into());
+        }
+        match self.get(&format!("{}/{}", path, name))? {
+            Some(_) => Ok(name.into()),
+            None => Err(ConfigError::Message(format!(
+                "1Password {} {} was not found",
+                attribute, name
+            ))),
+        }
+    }
+
+    fn fetch_item(
+        &self,
+        vault: &str,
+        item: &str,
+    ) -> Result<JsonValue, ConfigError> {
+        let vault_id = self.find_id("vaults", "name", vault)?;
+        let items = format!("vaults/{}/items", vault_id);
+        let item_id = self.find_id(&items, "title", item)?;
+        self.get(&format!("{}/{}", items, item_id))?.ok_or_else(|| {
+            ConfigError::Message(format!(
+                "1Password item {} was not found",
+                item
+            ))
+        })
+    }
+}
+
+/// Split a secret reference into its vault, item, section and field.
+fn parse_ref(
+    reference: &str,
+) -> Result<(&str, &str, Option<&str>, &str), ConfigError> {
+    let parts: Vec<&str> = reference
+        .strip_prefix("op://")
+        .map(|r| r.split('/').collect())
+        .unwrap_or_default();
+    match parts[..] {
+        [vault, item, field] => Ok((vault, item, None, field)),
+        [vault, item, section, field] => {
+            Ok((vault, item, Some(section), field))
+        }
+        _ => Err(ConfigError::Message(format!(
+            "invalid 1Password reference {}",
+            reference
+        ))),
+    }
+}
+
+/// Find the value of a field of an item by label or ID.
+fn field_value(
+    item: &JsonValue,
+    section: Option<&str>,
+    field: &str,
+) -> Option<String> {
+    let sections = item["sections"].as_array();
+    let section_id = match section {
+        Some(section) => Some(
+            sections
+                .into_iter()
+                .flatten()
+                .find(|s| s["label"] == section || s["id"] == section)?["id"]
+                .as_str()?,
+        ),
+        None => None,
+    };
+    item["fields"]
+        .as_array()?
+        .iter()
+        .filter(|f| {
+            section_id.is_none() || f["section"]["id"].as_str() == section_id
+        })
+        .find(|f| f["label"] == field || f["id"] == field)?["value"]
+        .as_str()
+        .map(Into::into)
+}
+
+fn query_encode(s: &str) -> String {
+    s.bytes().fold(String::new(), |mut out, b| {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' => out.push(b as char),
+            b'-' | b'.' | b'_' | b'~' => out.push(b as char),
+            _ => {
+                let _ = write!(out, "%{:02X}", b);
+            }
+        }
+        out
+    })
+}
+
+// Keep the token out of the logs.
+impl fmt::Debug for OnePasswordSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OnePasswordSource")
+            .field("host", &self.host)
+            .field("token", &"********")
+            .field("refs", &self.refs)
+            .field("section", &self.section)
+            .finish()
+    }
+}
+
+impl Source for OnePasswordSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let mut items: HashMap<(&str, &str), JsonValue> = HashMap::new();
+        let mut kvs = Vec::new();
+        for (key, reference) in &self.refs {
+            let (vault, item, section, field) = parse_ref(reference)?;
+            let fetched = match items.entry((vault, item)) {
+                Entry::Occupied(e) => e.into_mut(),
+                Entry::Vacant(e) => e.insert(self.fetch_item(vault, item)?),
+            };
+            let value =
+                field_value(fetched, section, field).ok_or_else(|| {
+                    ConfigError::Message(format!(
+                        "1Password field {} was not found",
+                        reference
+                    ))
+                })?;
+            kvs.push((format!("{}.{}", self.section, key), value));
+        }
+        Ok(key_tree(kvs, "."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::testing::{json, serve};
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_ref() {
+        assert_eq!(
+            parse_ref("op://Prod/Postgres/password").unwrap(),
+            ("Prod", "Postgres", None, "password")
+        );
+        assert_eq!(
+            parse_ref("op://Prod/Postgres/admin/password").unwrap(),
+            ("Prod", "Postgres", Some("admin"), "password")
+        );
+        assert!(parse_ref("op://Prod/Postgres").is_err());
+        assert!(parse_ref("Prod/Postgres/password").is_err());
+    }
+
+    #[test]
+    fn test_collect() {
+        let (url, log) = serve(|req| match req.path.as_str() {
+            "/v1/vaults?filter=name%20eq%20%22Prod%22" => {
+                json(200, json!([{"id": "v1", "name": "Prod"}]))
+            }
+            "/v1/vaults/v1/items?filter=title%20eq%20%22Postgres%22" => {
+                json(200, json!([{"id": "i1", "title": "Postgres"}]))
+            }
+            "/v1/vaults/v1/items/i1" => json(
+                200,
+                json!({
+                    "id": "i1",
+                    "sections": [{"id": "s1", "label": "admin"}],
+                    "fields": [
+                        {"id": "username", "label": "username",
+                         "value": "app"},
+                        {"id": "password", "label": "password",
+                         "value": "app password"},
+                        {"id": "f3", "label": "password",
+                         "section": {"id": "s1"}, "value": "admin password"},
+                    ],
+                }),
+            ),
+            path if path.contains("?filter=") => json(200, json!([])),
+            _ => json(404, json!({"status": 404})),
+        });
+        let source = OnePasswordSource::new(url.clone(), "token".into())
+            .add_ref("pg.user".into(), "op://Prod/Postgres/username".into())
+            .add_ref(
+                "pg.password".into(),
+                "op://Prod/Postgres/password".into(),
+            )
+            .add_ref(
+                "pg.admin_password".into(),
+                "op://Prod/Postgres/admin/password".into(),
+            )
+            .set_section("production".into());
+        let m = source.collect().unwrap();
+        let table = m["production"].clone().into_table().unwrap();
+        let pg = table["pg"].clone().into_table().unwrap();
+        assert_eq!(pg["user"].clone().into_str().unwrap(), "app");
+        assert_eq!(pg["password"].clone().into_str().unwrap(), "app password");
+        assert_eq!(
+            pg["admin_password"].clone().into_str().unwrap(),
+            "admin password"
+        );
+        // The item is fetched once.
+        assert_eq!(log.lock().unwrap().len(), 3);
+        assert_eq!(
+            log.lock().unwrap()[0].header("Authorization"),
+            Some("Bearer token")
+        );
+        assert!(!format!("{:?}", source).contains("\"token\""));
+
+        let source = OnePasswordSource::new(url.clone(), "token".into())
+            .add_ref("pg.host".into(), "op://Prod/Postgres/host".into());
+        assert!(source.collect().is_err());
+        let source = OnePasswordSource::new(url, "token".into())
+            .add_ref("x".into(), "op://Staging/Postgres/password".into());
+        assert!(source.collect().is_err());
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use config::{ConfigError, Source, Value};
+use redis::Commands;
+
+use crate::utils::key_tree;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone)]
+enum Layout {
+    Hash(String),
+    Prefix(String),
+}
+
+/// A source reading the settings from Redis, either from the fields of a
+/// hash or from the keys under a prefix.
+///
+/// Field and key names are split on `:`. The first segment is the section,
+/// the following ones are the nested keys: the hash field
+/// `production:pg:port`, or the key `myapp:production:pg:port` with the
+/// prefix `myapp`, is read as `pg.port` in the `production` section. Values
+/// are read as strings.
+#[derive(Debug, Clone)]
+pub struct RedisSource {
+    url: String,
+    layout: Layout,
+}
+
+impl RedisSource {
+    /// Read the fields of the hash stored at `key`. `url` is a Redis URL
+    /// such as `redis://:password@localhost:6379/0`.
+    pub fn hash(url: &str, key: &str) -> Self {
+        Self {
+            url: url.into(),
+            layout: Layout::Hash(key.into()),
+        }
+    }
+
+    /// Read the string keys starting with `<prefix>:`.
+    pub fn prefix(url: &str, prefix: &str) -> Self {
+        Self {
+            url: url.into(),
+            layout: Layout::Prefix(format!(
+                "{}:",
+                prefix.trim_end_matches(':')
+            )),
+        }
+    }
+
+    fn read(&self) -> redis::RedisResult<Vec<(String, String)>> {
+        let client = redis::Client::open(self.url.as_str())?;
+        let mut con = client.get_connection_with_timeout(TIMEOUT)?;
+        match self.layout {
+            Layout::Hash(ref key) => {
+                let fields: HashMap<String, String> = con.hgetall(key)?;
+                Ok(fields.into_iter().collect())
+            }
+            Layout::Prefix(ref prefix) => {
+                let pattern = format!("{}*", prefix);
+                let keys = con
+                    .scan_match::<_, String>(pattern)?
+                    .collect::<Result<Vec<_>, _>>()?;
+                if keys.is_empty() {
+                    return Ok(Vec::new());
+                }
+                let values: Vec<Option<String>> =
+                    redis::cmd("MGET").arg(&keys).query(&mut con)?;
+                Ok(keys
+                    .into_iter()
+                    .zip(values)
+                    .filter_map(|(k, v)| {
+                        Some((k[prefix.len()..].to_string(), v?))
+                    })
+                    .collect())
+            }
+        }
+    }
+}
+
+impl Source for RedisSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let kvs = self.read().map_err(|e| {
+            let what = match self.layout {
+                Layout::Hash(ref key) => key,
+                Layout::Prefix(ref prefix) => prefix,
+            };
+            ConfigError::Message(format!(
+                "could not read {} from Redis: {}",
+                what, e
+            ))
+        })?;
+        Ok(key_tree(kvs, ":"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Reply to the RESP commands sent by the client with the data of a
+    /// fixed database.
+    fn redis_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut writer = stream;
+                while let Some(command) = read_command(&mut reader) {
+                    let reply = respond(&command);
+                    if writer.write_all(reply.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        format!("redis://{}", addr)
+    }
+
+    fn read_command(reader: &mut impl BufRead) -> Option<Vec<String>> {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let n: usize = line.trim().strip_prefix('*')?.parse().ok()?;
+        let mut args = Vec::new();
+        for _ in 0..n {
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            line.clear();
+            reader.read_line(&mut line).ok()?;
+            args.push(line.trim_end().to_string());
+        }
+        Some(args)
+    }
+
+    fn bulk(items: &[&str]) -> String {
+        let mut reply = format!("*{}\r\n", items.len());
+        for item in items {
+            reply += &format!("${}\r\n{}\r\n", item.len(), item);
+        }
+        reply
+    }
+
+    fn respond(command: &[String]) -> String {
+        let keys = [
+            ("myapp:default:pg:port", "5432"),
+            ("myapp:default:pg:host", "localhost"),
+            ("myapp:production:pg:host", "db-0"),
+        ];
+        match command[0].to_uppercase().as_str() {
+            "HGETALL" if command[1] == "myapp" => bulk(&[
+                "default:pg:port",
+                "5432",
+                "production:pg:host",
+                "db-0",
+            ]),
+            "HGETALL" => bulk(&[]),
+            "SCAN" => {
+                let prefix = command[3].trim_end_matches('*');
+                let matching: Vec<&str> = keys
+                    .iter()
+                    .map(|(k, _)| *k)
+                    .filter(|k| k.starts_with(prefix))
+                    .collect();
+                format!("*2\r\n$1\r\n0\r\n{}", bulk(&matching))
+            }
+            "MGET" => {
+                let values: Vec<&str> = command[1..]
+                    .iter()
+                    .map(|k| keys.iter().find(|(key, _)| key == k).unwrap().1)
+                    .collect();
+                bulk(&values)
+            }
+            _ => "+OK\r\n".into(),
+        }
+    }
+
+    fn pg(m: &HashMap<String, Value>, section: &str, key: &str) -> String {
+        let table = m[section].clone().into_table().unwrap();
+        let pg = table["pg"].clone().into_table().unwrap();
+        pg[key].clone().into_str().unwrap()
+    }
+
+    #[test]
+    fn test_hash() {
+        let url = redis_server();
+        let m = RedisSource::hash(&url, "myapp").collect().unwrap();
+        assert_eq!(pg(&m, "default", "port"), "5432");
+        assert_eq!(pg(&m, "production", "host"), "db-0");
+        let m = RedisSource::hash(&url, "other").collect().unwrap();
+        assert!(m.is_empty());
+    }
+
+    #[test]
+    fn test_prefix() {
+        let url = redis_server();
+        let m = RedisSource::prefix(&url, "myapp").collect().unwrap();
+        assert_eq!(pg(&m, "default", "port"), "5432");
+        assert_eq!(pg(&m, "default", "host"), "localhost");
+        assert_eq!(pg(&m, "production", "host"), "db-0");
+        let m = RedisSource::prefix(&url, "other:").collect().unwrap();
+        assert!(m.is_empty());
+    }
+
+    #[test]
+    fn test_connection_error() {
+        let source = RedisSource::hash("redis://127.0.0.1:1", "myapp");
+        assert!(source.collect().is_err());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use config::{ConfigError, Source, Value};
+use serde_json::{Map, Value as JsonValue};
+
+use super::http::{agent, send_json};
+use crate::utils::from_json;
+
+/// A source reading the settings from a Spring Cloud Config Server, at
+/// `<server>/<app>/<profile>[/<label>]`.
+///
+/// The server merges the property sources of the profile itself, so all
+/// the values are placed in the table of the Hydroconf environment. By
+/// default the profile is the environment name.
+#[derive(Clone)]
+pub struct SpringConfigSource {
+    server: String,
+    app: String,
+    env: String,
+    profile: Option<String>,
+    label: Option<String>,
+    credentials: Option<(String, String)>,
+}
+
+impl SpringConfigSource {
+    pub fn new(server: &str, app: &str, env: &str) -> Self {
+        Self {
+            server: server.into(),
+            app: app.into(),
+            env: env.into(),
+            profile: None,
+            label: None,
+            credentials: None,
+        }
+    }
+
+    /// Request `profile` (e.g. `prod`) instead of the environment name.
+    pub fn set_profile(mut self, profile: String) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    /// The branch, tag or commit of the config repository.
+    pub fn set_label(mut self, label: String) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    /// Authenticate with HTTP basic auth.
+    pub fn set_credentials(mut self, user: String, password: String) -> Self {
+        self.credentials = Some((user, password));
+        self
+    }
+
+    fn url(&self) -> String {
+        let mut url = format!(
+            "{}/{}/{}",
+            self.server.trim_end_matches('/'),
+            self.app,
+            self.profile.as_ref().unwrap_or(&self.env),
+        );
+        if let Some(ref label) = self.label {
+            url = format!("{}/{}", url, label);
+        }
+        url
+    }
+}
+
+// Keep the password out of the logs.
+impl fmt::Debug for SpringConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SpringConfigSource")
+            .field("server", &self.server)
+            .field("app", &self.app)
+            .field("env", &self.env)
+            .field("profile", &self.profile)
+            .field("label", &self.label)
+            .field(
+                "credentials",
+                &self
+                    .credentials
+                    .as_ref()
+                    .map(|(user, _)| (user, "********")),
+            )
+            .finish()
+    }
+}
+
+impl Source for SpringConfigSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let url = self.url();
+        let mut request = agent().get(&url);
+        if let Some((ref user, ref password)) = self.credentials {
+            let token = BASE64.encode(format!("{}:{}", user, password));
+            request =
+                request.set("Authorization", &format!("Basic {}", token));
+        }
+        let response = send_json(request, None)?.ok_or_else(|| {
+            ConfigError::Message(format!("{} was not found", url))
+        })?;
+        let mut table = JsonValue::Object(Map::new());
+        // The property sources are listed from the highest precedence.
+        let sources = response["propertySources"].as_array();
+        for source in sources.into_iter().flatten().rev() {
+            let properties = source["source"].as_object();
+            let len = properties.map_or(0, Map::len);
+            for (key, value) in properties.into_iter().flatten() {
+                insert_property(&mut table, key, value.clone(), len)?;
+            }
+        }
+        let mut m = HashMap::new();
+        m.insert(self.env.clone(), from_json(table));
+        Ok(m)
+    }
+}
+
+/// Insert a value at a Spring property path such as `servers[0].host`.
+///
+/// The properties may come in any order, so the arrays are padded with
+/// nulls, but an index can't reach `max_len`, the number of properties of
+/// the source: a dense array never has more items than that.
+fn insert_property(
+    root: &mut JsonValue,
+    key: &str,
+    value: JsonValue,
+    max_len: usize,
+) -> Result<(), ConfigError> {
+    let mut node = root;
+    for part in key.split('.') {
+        let (name, indexes) = match part.find('[') {
+            Some(i) => (&part[..i], &part[i..]),
+            None => (part, ""),
+        };
+        if !name.is_empty() {
+            if !node.is_object() {
+                *node = JsonValue::Object(Map::new());
+            }
+            node = node
+                .as_object_mut()
+                .unwrap()
+                .entry(name)
+                .or_insert(JsonValue::Null);
+        }
+        for index in indexes.split('[').filter_map(|i| i.strip_suffix(']')) {
+            let index: usize = match index.parse() {
+                Ok(index) => index,
+                Err(_) => continue,
+            };
+            if index >= max_len {
+                return Err(ConfigError::Message(format!(
+                    "index {} of {} is out of range",
+                    index, key
+                )));
+            }
+            if !node.is_array() {
+                *node = JsonValue::Array(Vec::new());
+            }
+            let array = node.as_array_mut().unwrap();
+            if array.len() <= index {
+                array.resize(index + 1, JsonValue::Null);
+            }
+            node = &mut array[index];
+        }
+    }
+    *node = value;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::testing::{json, serve};
+    use serde_json::json;
+
+    #[test]
+    fn test_insert_property() {
+        let mut root = json!({});
+        insert_property(&mut root, "pg.host", json!("db"), 5).unwrap();
+        insert_property(&mut root, "servers[1].port", json!(81), 5).unwrap();
+        insert_property(&mut root, "servers[0].port", json!(80), 5).unwrap();
+        insert_property(&mut root, "matrix[0][1]", json!(true), 5).unwrap();
+        assert_eq!(
+            insert_property(&mut root, "servers[5].port", json!(0), 5)
+                .unwrap_err()
+                .to_string(),
+            "index 5 of servers[5].port is out of range"
+        );
+        let key = "servers[18446744073709551615]";
+        assert!(insert_property(&mut root, key, json!(0), 5).is_err());
+        assert_eq!(
+            root,
+            json!({
+                "pg": {"host": "db"},
+                "servers": [{"port": 80}, {"port": 81}],
+                "matrix": [[null, true]],
+            })
+        );
+    }
+
+    #[test]
+    fn test_collect() {
+        let (server, log) = serve(|req| match req.path.as_str() {
+            "/myapp/prod" | "/myapp/prod/v2" => json(
+                200,
+                json!({
+                    "name": "myapp",
+                    "profiles": ["prod"],
+                    "propertySources": [
+                        {
+                            "name": "myapp-prod.yml",
+                            "source": {"pg.host": "db-0"},
+                        },
+                        {
+                            "name": "myapp.yml",
+                            "source": {
+                                "pg.host": "localhost",
+                                "pg.port": 5432,
+                            },
+                        },
+                    ],
+                }),
+            ),
+            _ => json(404, json!({})),
+        });
+        let source = SpringConfigSource::new(&server, "myapp", "production")
+            .set_profile("prod".into())
+            .set_label("v2".into())
+            .set_credentials("user".into(), "pass".into());
+        let m = source.collect().unwrap();
+        let table = m["production"].clone().into_table().unwrap();
+        let pg = table["pg"].clone().into_table().unwrap();
+        assert_eq!(pg["host"].clone().into_str().unwrap(), "db-0");
+        assert_eq!(pg["port"].clone().into_int().unwrap(), 5432);
+        assert_eq!(
+            log.lock().unwrap()[0].header("Authorization"),
+            Some("Basic dXNlcjpwYXNz")
+        );
+        assert!(!format!("{:?}", source).contains("pass\""));
+
+        let source = SpringConfigSource::new(&server, "myapp", "staging");
+        assert!(source.collect().is_err());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use config::{ConfigError, Source, Value};
+
+use crate::utils::key_tree;
+
+/// A `(key, value, env)` row. Rows with a `NULL` value are skipped.
+type Row = (String, Option<String>, String);
+
+/// A source reading the settings from the rows of a SQL table with a key, a
+/// value and an environment column, such as `config(key, value, env)`.
+///
+/// Only the rows of the `default` environment and of the current one are
+/// selected, and they are placed in the sections of the same names. Keys
+/// are dotted paths (e.g. `pg.port`) and values are read as strings.
+///
+/// The database is given as a URL: `postgres://...` with the `postgres`
+/// feature, `mysql://...` with the `mysql` feature and `sqlite://<path>`
+/// with the `sqlite` feature.
+#[derive(Debug, Clone)]
+pub struct SqlSource {
+    url: String,
+    table: String,
+    env: String,
+    key_column: String,
+    value_column: String,
+    env_column: String,
+}
+
+impl SqlSource {
+    pub fn new(url: &str, table: &str, env: &str) -> Self {
+        Self {
+            url: url.into(),
+            table: table.into(),
+            env: env.into(),
+            key_column: "key".into(),
+            value_column: "value".into(),
+            env_column: "env".into(),
+        }
+    }
+
+    /// Use other column names than `key`, `value` and `env`.
+    pub fn set_columns(mut self, key: &str, value: &str, env: &str) -> Self {
+        self.key_column = key.into();
+        self.value_column = value.into();
+        self.env_column = env.into();
+        self
+    }
+
+    fn error<E: fmt::Display>(&self, e: E) -> ConfigError {
+        ConfigError::Message(format!(
+            "could not read the table {}: {}",
+            self.table, e
+        ))
+    }
+
+    /// The query selecting the rows, with `quote` around the names and the
+    /// given placeholders for the two environments. Names can't be bound as
+    /// parameters, so only plain identifiers are accepted.
+    fn query(
+        &self,
+        quote: char,
+        placeholders: [&str; 2],
+    ) -> Result<String, ConfigError> {
+        let quoted = |name: &str| -> Result<String, ConfigError> {
+            let segments: Vec<&str> = name.split('.').collect();
+            let valid = segments.iter().all(|s| {
+                !s.is_empty()
+                    && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+            });
+            if !valid {
+                return Err(self.error(format!("invalid name {:?}", name)));
+            }
+            Ok(segments
+                .iter()
+                .map(|s| format!("{}{}{}", quote, s, quote))
+                .collect::<Vec<_>>()
+                .join("."))
+        };
+        let env_column = quoted(&self.env_column)?;
+        Ok(format!(
+            "SELECT {}, {}, {} FROM {} WHERE {} IN ({}, {})",
+            quoted(&self.key_column)?,
+            quoted(&self.value_column)?,
+            env_column,
+            quoted(&self.table)?,
+            env_column,
+            placeholders[0],
+            placeholders[1],
+        ))
+    }
+
+    fn read(&self) -> Result<Vec<Row>, ConfigError> {
+        let scheme = self.url.split("://").next().unwrap_or_default();
+        match scheme {
+            #[cfg(feature = "sqlite")]
+            "sqlite" => self.read_sqlite(&self.url["sqlite://".len()..]),
+            #[cfg(feature = "postgres")]
+            "postgres" | "postgresql" => self.read_postgres(),
+            #[cfg(feature = "mysql")]
+            "mysql" => self.read_mysql(),
+            _ => {
+                let feature = match scheme {
+                    "postgresql" => "postgres",
+                    scheme => scheme,
+                };
+                let message =
+                    if ["sqlite", "postgres", "mysql"].contains(&feature) {
+                        format!(
+                            "hydroconf was built without the `{}` feature",
+                            feature
+                        )
+                    } else {
+                        "unsupported database URL".into()
+                    };
+                Err(self.error(message))
+            }
+        }
+    }
+
+    #[cfg(feature = "sqlite")]
+    fn read_sqlite(&self, path: &str) -> Result<Vec<Row>, ConfigError> {
+        use rusqlite::types::ValueRef;
+        use rusqlite::{Connection, OpenFlags};
+
+        let con = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+        )
+        .map_err(|e| self.error(e))?;
+        let query = self.query('"', ["?1", "?2"])?;
+        let mut stmt = con.prepare(&query).map_err(|e| self.error(e))?;
+        // SQLite columns are dynamically typed.
+        let rows = stmt
+            .query_map(["default", &self.env], |row| {
+                let value = match row.get_ref(1)? {
+                    ValueRef::Integer(i) => Some(i.to_string()),
+                    ValueRef::Real(f) => Some(f.to_string()),
+                    ValueRef::Text(t) => {
+                        Some(String::from_utf8_lossy(t).into_owned())
+                    }
+                    ValueRef::Null | ValueRef::Blob(_) => None,
+                };
+                Ok((row.get(0)?, value, row.get(2)?))
+            })
+            .map_err(|e| self.error(e))?;
+        rows.collect::<Result<_, _>>().map_err(|e| self.error(e))
+    }
+
+    #[cfg(feature = "postgres")]
+    fn read_postgres(&self) -> Result<Vec<Row>, ConfigError> {
+        let mut client = postgres::Client::connect(&self.url, postgres::NoTls)
+            .map_err(|e| self.error(e))?;
+        let query = self.query('"', ["$1", "$2"])?;
+        let rows = client
+            .query(query.as_str(), &[&"default", &self.env])
+            .map_err(|e| self.error(e))?;
+        let row = |row: &postgres::Row| {
+            Ok((row.try_get(0)?, row.try_get(1)?, row.try_get(2)?))
+        };
+        rows.iter()
+            .map(row)
+            .collect::<Result<_, postgres::Error>>()
+            .map_err(|e| self.error(e))
+    }
+
+    #[cfg(feature = "mysql")]
+    fn read_mysql(&self) -> Result<Vec<Row>, ConfigError> {
+        use mysql::prelude::Queryable;
+
+        let opts =
+            mysql::Opts::from_url(&self.url).map_err(|e| self.error(e))?;
+        let mut conn = mysql::Conn::new(opts).map_err(|e| self.error(e))?;
+        let query = self.query('`', ["?", "?"])?;
+        conn.exec(query, ("default", &self.env))
+            .map_err(|e| self.error(e))
+    }
+}
+
+impl Source for SqlSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let kvs = self.read()?.into_iter().filter_map(|(key, value, env)| {
+            Some((format!("{}.{}", env, key), value?))
+        });
+        Ok(key_tree(kvs, "."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query() {
+        let source = SqlSource::new("sqlite://db", "app.config", "staging");
+        assert_eq!(
+            source.query('`', ["?", "?"]).unwrap(),
+            "SELECT `key`, `value`, `env` FROM `app`.`config` \
+             WHERE `env` IN (?, ?)"
+        );
+        let source = source.set_columns("name", "value; DROP x", "env");
+        assert!(source.query('"', ["?1", "?2"]).is_err());
+    }
+
+    #[test]
+    fn test_unsupported_url() {
+        let source = SqlSource::new("oracle://db", "config", "staging");
+        let err = source.collect().unwrap_err().to_string();
+        assert!(err.contains("unsupported"), "{}", err);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_collect_sqlite() {
+        let dir = crate::testing::TempDir::new("sql");
+        let path = dir.join("config.db");
+        let con = rusqlite::Connection::open(&path).unwrap();
+        con.execute_batch(
+            "CREATE TABLE config (name TEXT, val, env TEXT);
+             INSERT INTO config VALUES
+                 ('pg.host', 'localhost', 'default'),
+                 ('pg.port', 5432, 'default'),
+                 ('debug', NULL, 'default'),
+                 ('pg.host', 'db-0', 'production'),
+                 ('pg.host', 'db-staging', 'staging');",
+        )
+        .unwrap();
+        drop(con);
+
+        let url = format!("sqlite://{}", path.display());
+        let m = SqlSource::new(&url, "config", "production")
+            .set_columns("name", "val", "env")
+            .collect()
+            .unwrap();
+        assert_eq!(m.len(), 2);
+        let default = m["default"].clone().into_table().unwrap();
+        assert!(!default.contains_key("debug"));
+        let pg = default["pg"].clone().into_table().unwrap();
+        assert_eq!(pg["port"].clone().into_int().unwrap(), 5432);
+        let production = m["production"].clone().into_table().unwrap();
+        let pg = production["pg"].clone().into_table().unwrap();
+        assert_eq!(pg["host"].clone().into_str().unwrap(), "db-0");
+    }
+}
@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use config::{ConfigError, Source, Value};
+use serde_json::{json, Value as JsonValue};
+
+use super::http::{agent, send_json};
+use crate::env;
+use crate::utils::from_json;
+
+/// Connection settings of a HashiCorp Vault KV v2 secrets engine.
+///
+/// The secrets of each environment are read from `<mount>/data/<path>/<env>`,
+/// e.g. `secret/data/myapp/default` and `secret/data/myapp/production`.
+#[derive(Clone, PartialEq)]
+pub struct VaultSettings {
+    pub addr: String,
+    pub mount: String,
+    pub path: String,
+    pub token: Option<String>,
+    pub role_id: Option<String>,
+    pub secret_id: Option<String>,
+}
+
+impl VaultSettings {
+    pub fn new(addr: String, path: String) -> Self {
+        Self {
+            addr,
+            mount: "secret".into(),
+            path,
+            token: None,
+            role_id: None,
+            secret_id: None,
+        }
+    }
+
+    /// Read the settings from the `VAULT_*_FOR_HYDRO` environment variables.
+    /// Return `None` if `VAULT_PATH_FOR_HYDRO` is not set.
+    pub fn from_env() -> Option<Self> {
+        let hydro_suffix = "_FOR_HYDRO";
+        let path: String = env::get_var("VAULT_PATH", hydro_suffix)?;
+        Some(Self {
+            addr: env::get_var_default(
+                "VAULT_ADDR",
+                hydro_suffix,
+                "http://127.0.0.1:8200".into(),
+            ),
+            mount: env::get_var_default(
+                "VAULT_MOUNT",
+                hydro_suffix,
+                "secret".into(),
+            ),
+            path,
+            token: env::get_var("VAULT_TOKEN", hydro_suffix),
+            role_id: env::get_var("VAULT_ROLE_ID", hydro_suffix),
+            secret_id: env::get_var("VAULT_SECRET_ID", hydro_suffix),
+        })
+    }
+
+    pub fn set_mount(mut self, m: String) -> Self {
+        self.mount = m;
+        self
+    }
+
+    pub fn set_token(mut self, t: String) -> Self {
+        self.token = Some(t);
+        self
+    }
+
+    pub fn set_approle(mut self, role_id: String, secret_id: String) -> Self {
+        self.role_id = Some(role_id);
+        self.secret_id = Some(secret_id);
+        self
+    }
+}
+
+// Keep the credentials out of the logs.
+impl fmt::Debug for VaultSettings {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mask = |v: &Option<String>| v.as_ref().map(|_| "********");
+        f.debug_struct("VaultSettings")
+            .field("addr", &self.addr)
+            .field("mount", &self.mount)
+            .field("path", &self.path)
+            .field("token", &mask(&self.token))
+            .field("role_id", &self.role_id)
+            .field("secret_id", &mask(&self.secret_id))
+            .finish()
+    }
+}
+
+/// A source reading the `default` and current environment secrets from
+/// Vault.
+#[derive(Debug, Clone)]
+pub struct VaultSource {
+    settings: VaultSettings,
+    env: String,
+}
+
+impl VaultSource {
+    pub fn new(settings: VaultSettings, env: &str) -> Self {
+        Self {
+            settings,
+            env: env.to_string(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/v1/{}", self.settings.addr.trim_end_matches('/'), path)
+    }
+
+    fn token(&self, agent: &ureq::Agent) -> Result<String, ConfigError> {
+        if let Some(ref token) = self.settings.token {
+            return Ok(token.clone());
+        }
+        let (role_id, secret_id) =
+            match (&self.settings.role_id, &self.settings.secret_id) {
+                (Some(role_id), Some(secret_id)) => (role_id, secret_id),
+                _ => {
+                    return Err(ConfigError::Message(
+                        "Vault requires either a token or an AppRole role_id \
+                         and secret_id"
+                            .into(),
+                    ))
+                }
+            };
+        let url = self.url("auth/approle/login");
+        let body = json!({"role_id": role_id, "secret_id": secret_id});
+        send_json(agent.post(&url), Some(&body))?
+            .as_ref()
+            .and_then(|r| r["auth"]["client_token"].as_str())
+            .map(String::from)
+            .ok_or_else(|| {
+                ConfigError::Message(format!("{}: no client token", url))
+            })
+    }
+}
+
+impl Source for VaultSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let agent = agent();
+        let token = self.token(&agent)?;
+        let mut m = HashMap::new();
+        for name in &["default", self.env.as_str()] {
+            let url = self.url(&format!(
+                "{}/data/{}/{}",
+                self.settings.mount,
+                self.settings.path.trim_matches('/'),
+                name,
+            ));
+            let request = agent.get(&url).set("X-Vault-Token", &token);
+            if let Some(response) = send_json(request, None)? {
+                m.insert(name.to_string(), secret_data(response));
+            }
+        }
+        Ok(m)
+    }
+}
+
+/// Extract the secret from a KV v2 read response.
+fn secret_data(mut response: JsonValue) -> Value {
+    from_json(response["data"]["data"].take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::testing::{json, serve};
+
+    fn vault_server() -> String {
+        let (addr, _) = serve(|req| match req.path.as_str() {
+            "/v1/auth/approle/login" => {
+                let body: JsonValue = serde_json::from_str(&req.body).unwrap();
+                if body["role_id"] == "role" && body["secret_id"] == "s3cr3t" {
+                    json(200, json!({"auth": {"client_token": "approle-tok"}}))
+                } else {
+                    json(400, json!({"errors": ["invalid role"]}))
+                }
+            }
+            _ if req.header("X-Vault-Token") != Some("root")
+                && req.header("X-Vault-Token") != Some("approle-tok") =>
+            {
+                json(403, json!({"errors": ["permission denied"]}))
+            }
+            "/v1/kv/data/myapp/default" => json(
+                200,
+                json!({"data": {"data": {"pg": {"password": "a password"}}}}),
+            ),
+            "/v1/kv/data/myapp/production" => json(
+                200,
+                json!({"data": {"data": {"pg": {"password": "strong"}}}}),
+            ),
+            _ => json(404, json!({"errors": []})),
+        });
+        addr
+    }
+
+    fn password(source: &VaultSource, env: &str) -> Option<String> {
+        let m = source.collect().unwrap();
+        let table = m.get(env)?.clone().into_table().unwrap();
+        let pg = table["pg"].clone().into_table().unwrap();
+        Some(pg["password"].clone().into_str().unwrap())
+    }
+
+    #[test]
+    fn test_token_auth() {
+        let settings = VaultSettings::new(vault_server(), "myapp".into())
+            .set_mount("kv".into())
+            .set_token("root".into());
+        let source = VaultSource::new(settings, "production");
+        assert_eq!(password(&source, "default"), Some("a password".into()));
+        assert_eq!(password(&source, "production"), Some("strong".into()));
+
+        let source = VaultSource::new(source.settings, "staging");
+        assert_eq!(password(&source, "staging"), None);
+    }
+
+    #[test]
+    fn test_approle_auth() {
+        let settings = VaultSettings::new(vault_server(), "myapp".into())
+            .set_mount("kv".into())
+            .set_approle("role".into(), "s3cr3t".into());
+        let source = VaultSource::new(settings.clone(), "production");
+        assert_eq!(password(&source, "production"), Some("strong".into()));
+
+        let settings = settings.set_approle("role".into(), "wrong".into());
+        assert!(VaultSource::new(settings, "production").collect().is_err());
+    }
+
+    #[test]
+    fn test_missing_credentials() {
+        let settings = VaultSettings::new(vault_server(), "myapp".into());
+        assert!(VaultSource::new(settings, "default").collect().is_err());
+    }
+
+    #[test]
+    fn test_replaces_secrets_file() {
+        let settings = VaultSettings::new(vault_server(), "myapp".into())
+            .set_mount("kv".into())
+            .set_token("root".into());
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(
+                    std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+                        .join("tests/data"),
+                )
+                .set_env("production".into())
+                .set_envvar_prefix("VAULTTEST".into())
+                .set_vault(settings),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_str("pg.password").unwrap(), "strong");
+        assert_eq!(hydro.get_str("pg.host").unwrap(), "db-0");
+        assert!(hydro.origins("pg.password")[0].is_secret());
+    }
+
+    #[test]
+    fn test_debug_masks_credentials() {
+        let settings = VaultSettings::new("http://vault".into(), "app".into())
+            .set_token("root".into());
+        assert!(!format!("{:?}", settings).contains("root"));
+    }
+}
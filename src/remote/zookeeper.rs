@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use config::{ConfigError, Source, Value};
+
+use crate::utils::key_tree;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const OP_GET_DATA: i32 = 4;
+const OP_GET_CHILDREN: i32 = 8;
+const OP_CLOSE: i32 = -11;
+const ERR_NO_NODE: i32 = -101;
+
+/// A source reading the settings stored in a ZooKeeper subtree.
+///
+/// The first znode under the root path is the section, the following ones
+/// are the nested keys: with the path `/myapp`, the data of the znode
+/// `/myapp/production/pg/port` is read as `pg.port` in the `production`
+/// section. Only the leaves are read, as strings.
+#[derive(Debug, Clone)]
+pub struct ZooKeeperSource {
+    hosts: String,
+    path: String,
+}
+
+impl ZooKeeperSource {
+    /// `hosts` is a comma-separated list of `host:port` servers, which are
+    /// tried in order.
+    pub fn new(hosts: &str, path: &str) -> Self {
+        Self {
+            hosts: hosts.into(),
+            path: format!("/{}", path.trim_matches('/')),
+        }
+    }
+
+    fn connect(&self) -> io::Result<Session> {
+        let mut last_error = None;
+        for host in self.hosts.split(',').map(str::trim) {
+            let addrs = match host.to_socket_addrs() {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            for addr in addrs {
+                match Session::open(&addr) {
+                    Ok(session) => return Ok(session),
+                    Err(e) => last_error = Some(e),
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "no server given")
+        }))
+    }
+
+    /// Collect the `(relative path, data)` pairs of the leaves under `path`.
+    fn walk(
+        &self,
+        session: &mut Session,
+        path: &str,
+        out: &mut Vec<(String, String)>,
+    ) -> io::Result<()> {
+        let children = match session.get_children(path)? {
+            Some(children) => children,
+            None => return Ok(()),
+        };
+        if children.is_empty() {
+            if let Some(data) = session.get_data(path)? {
+                let data = String::from_utf8(data).map_err(|_| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{} is not valid UTF-8", path),
+                    )
+                })?;
+                out.push((path[self.path.len()..].to_string(), data));
+            }
+            return Ok(());
+        }
+        for child in children {
+            let child_path = if path == "/" {
+                format!("/{}", child)
+            } else {
+                format!("{}/{}", path, child)
+            };
+            self.walk(session, &child_path, out)?;
+        }
+        Ok(())
+    }
+}
+
+impl Source for ZooKeeperSource {
+    fn clone_into_box(&self) -> Box<dyn Source + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+        let read = || {
+            let mut session = self.connect()?;
+            let mut kvs = Vec::new();
+            self.walk(&mut session, &self.path, &mut kvs)?;
+            session.close();
+            Ok(kvs)
+        };
+        let kvs = read().map_err(|e: io::Error| {
+            ConfigError::Message(format!(
+                "could not read {} from ZooKeeper: {}",
+                self.path, e
+            ))
+        })?;
+        Ok(key_tree(kvs, "/"))
+    }
+}
+
+/// A minimal ZooKeeper client session, speaking the jute protocol.
+struct Session {
+    stream: TcpStream,
+    xid: i32,
+}
+
+impl Session {
+    fn open(addr: &std::net::SocketAddr) -> io::Result<Self> {
+        let stream = TcpStream::connect_timeout(addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+        let mut session = Self { stream, xid: 0 };
+        let mut request = Vec::new();
+        put_i32(&mut request, 0); // protocol version
+        request.extend_from_slice(&0i64.to_be_bytes()); // last zxid seen
+        put_i32(&mut request, TIMEOUT.as_millis() as i32);
+        request.extend_from_slice(&0i64.to_be_bytes()); // session id
+        put_bytes(&mut request, &[0; 16]); // password
+        session.send(&request)?;
+        session.receive()?;
+        Ok(session)
+    }
+
+    fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 4);
+        put_i32(&mut frame, payload.len() as i32);
+        frame.extend_from_slice(payload);
+        self.stream.write_all(&frame)
+    }
+
+    fn receive(&mut self) -> io::Result<Vec<u8>> {
+        let mut len = [0; 4];
+        self.stream.read_exact(&mut len)?;
+        let mut payload = vec![0; i32::from_be_bytes(len).max(0) as usize];
+        self.stream.read_exact(&mut payload)?;
+        Ok(payload)
+    }
+
+    /// Send a request for `path` and return the reply body, or `None` if
+    /// the znode does not exist.
+    fn call(&mut self, op: i32, path: &str) -> io::Result<Option<Reader>> {
+        self.xid += 1;
+        let mut request = Vec::new();
+        put_i32(&mut request, self.xid);
+        put_i32(&mut request, op);
+        put_bytes(&mut request, path.as_bytes());
+        request.push(0); // watch
+        self.send(&request)?;
+        loop {
+            let mut reply = Reader(self.receive()?, 0);
+            let xid = reply.i32()?;
+            reply.i64()?; // zxid
+            let err = reply.i32()?;
+            // Skip the notifications and pings.
+            if xid != self.xid {
+                continue;
+            }
+            return match err {
+                0 => Ok(Some(reply)),
+                ERR_NO_NODE => Ok(None),
+                err => {
+                    Err(io::Error::other(format!("error {} on {}", err, path)))
+                }
+            };
+        }
+    }
+
+    fn get_children(&mut self, path: &str) -> io::Result<Option<Vec<String>>> {
+        let mut reply = match self.call(OP_GET_CHILDREN, path)? {
+            Some(reply) => reply,
+            None => return Ok(None),
+        };
+        let count = reply.i32()?.max(0);
+        (0..count)
+            .map(|_| {
+                let child = reply.bytes()?.unwrap_or_default();
+                String::from_utf8(child).map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, "invalid name")
+                })
+            })
+            .collect::<io::Result<_>>()
+            .map(Some)
+    }
+
+    fn get_data(&mut self, path: &str) -> io::Result<Option<Vec<u8>>> {
+        match self.call(OP_GET_DATA, path)? {
+            Some(mut reply) => reply.bytes(),
+            None => Ok(None),
+        }
+    }
+
+    fn close(mut self) {
+        let mut request = Vec::new();
+        put_i32(&mut request, self.xid + 1);
+        put_i32(&mut request, OP_CLOSE);
+        let _ = self.send(&request);
+    }
+}
+
+fn put_i32(buf: &mut Vec<u8>, v: i32) {
+    buf.extend_from_slice(&v.to_be_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    put_i32(buf, bytes.len() as i32);
+    buf.extend_from_slice(bytes);
+}
+
+struct Reader(Vec<u8>, usize);
+
+impl Reader {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.1 + n > self.0.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated reply",
+            ));
+        }
+        self.1 += n;
+        Ok(&self.0[self.1 - n..self.1])
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        let mut b = [0; 4];
+        b.copy_from_slice(self.take(4)?);
+        Ok(i32::from_be_bytes(b))
+    }
+
+    fn i64(&mut self) -> io::Result<i64> {
+        let mut b = [0; 8];
+        b.copy_from_slice(self.take(8)?);
+        Ok(i64::from_be_bytes(b))
+    }
+
+    /// A length-prefixed buffer, `None` if the length is -1.
+    fn bytes(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let len = self.i32()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        Ok(Some(self.take(len as usize)?.to_vec()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const TREE: &[(&str, &str)] = &[
+        ("/myapp", ""),
+        ("/myapp/default", ""),
+        ("/myapp/default/pg", ""),
+        ("/myapp/default/pg/port", "5432"),
+        ("/myapp/default/pg/host", "localhost"),
+        ("/myapp/production", ""),
+        ("/myapp/production/pg", ""),
+        ("/myapp/production/pg/host", "db-0"),
+    ];
+
+    fn read_frame(stream: &mut TcpStream) -> Option<Reader> {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len).ok()?;
+        let mut payload = vec![0; i32::from_be_bytes(len) as usize];
+        stream.read_exact(&mut payload).ok()?;
+        Some(Reader(payload, 0))
+    }
+
+    fn reply(stream: &mut TcpStream, xid: i32, err: i32, body: &[u8]) {
+        let mut payload = Vec::new();
+        put_i32(&mut payload, xid);
+        payload.extend_from_slice(&0i64.to_be_bytes());
+        put_i32(&mut payload, err);
+        payload.extend_from_slice(body);
+        let mut frame = Vec::new();
+        put_bytes(&mut frame, &payload);
+        stream.write_all(&frame).unwrap();
+    }
+
+    /// Serve the znodes of `TREE`.
+    fn zk_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                if read_frame(&mut stream).is_none() {
+                    continue;
+                }
+                let mut connect = Vec::new();
+                put_i32(&mut connect, 0);
+                put_i32(&mut connect, 10_000);
+                connect.extend_from_slice(&1i64.to_be_bytes());
+                put_bytes(&mut connect, &[0; 16]);
+                let mut frame = Vec::new();
+                put_bytes(&mut frame, &connect);
+                stream.write_all(&frame).unwrap();
+
+                while let Some(mut request) = read_frame(&mut stream) {
+                    let xid = request.i32().unwrap();
+                    let op = request.i32().unwrap();
+                    if op == OP_CLOSE {
+                        break;
+                    }
+                    let path = request.bytes().unwrap().unwrap();
+                    let path = String::from_utf8(path).unwrap();
+                    let node = TREE.iter().find(|(p, _)| *p == path);
+                    let (_, data) = match node {
+                        Some(node) => node,
+                        None => {
+                            reply(&mut stream, xid, ERR_NO_NODE, &[]);
+                            continue;
+                        }
+                    };
+                    let mut body = Vec::new();
+                    if op == OP_GET_CHILDREN {
+                        let prefix = format!("{}/", path);
+                        let children: Vec<&str> = TREE
+                            .iter()
+                            .filter_map(|(p, _)| p.strip_prefix(&prefix))
+                            .filter(|p| !p.contains('/'))
+                            .collect();
+                        put_i32(&mut body, children.len() as i32);
+                        for child in children {
+                            put_bytes(&mut body, child.as_bytes());
+                        }
+                    } else {
+                        put_bytes(&mut body, data.as_bytes());
+                        body.extend_from_slice(&[0; 68]); // stat
+                    }
+                    // A watch notification, to be skipped by the client.
+                    reply(&mut stream, -1, 0, &[]);
+                    reply(&mut stream, xid, 0, &body);
+                }
+            }
+        });
+        addr.to_string()
+    }
+
+    #[test]
+    fn test_collect() {
+        let hosts = format!("127.0.0.1:1, {}", zk_server());
+        let m = ZooKeeperSource::new(&hosts, "/myapp/").collect().unwrap();
+        let pg = |section: &str| {
+            let table = m[section].clone().into_table().unwrap();
+            table["pg"].clone().into_table().unwrap()
+        };
+        assert_eq!(pg("default")["port"].clone().into_int().unwrap(), 5432);
+        assert_eq!(
+            pg("default")["host"].clone().into_str().unwrap(),
+            "localhost"
+        );
+        assert_eq!(
+            pg("production")["host"].clone().into_str().unwrap(),
+            "db-0"
+        );
+
+        let source = ZooKeeperSource::new(&hosts, "missing");
+        assert!(source.collect().unwrap().is_empty());
+        let source = ZooKeeperSource::new("127.0.0.1:1", "myapp");
+        assert!(source.collect().is_err());
+    }
+}
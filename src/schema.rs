@@ -0,0 +1,93 @@
+use schemars::{schema_for, JsonSchema};
+use serde_json::{json, Value as JsonValue};
+
+/// The JSON Schema of the settings `T`, once hydrated, e.g. to document
+/// them.
+pub fn json_schema<T: JsonSchema>() -> JsonValue {
+    schema_for!(T).to_value()
+}
+
+/// The JSON Schema of the settings files of `T`: a table per environment
+/// (`default`, `production`, ...), each of them with some of the settings,
+/// and an optional `required` array. Editors use it for autocompletion,
+/// and CI to validate the settings files.
+pub fn settings_file_schema<T: JsonSchema>() -> JsonValue {
+    let mut schema = json_schema::<T>();
+    // Any key can be left to another environment or layer.
+    remove_required(&mut schema);
+    let mut env = match schema {
+        JsonValue::Object(map) => map,
+        other => return other,
+    };
+    let mut file = json!({
+        "type": "object",
+        "properties": {
+            "required": {"type": "array", "items": {"type": "string"}},
+        },
+    });
+    for keyword in ["$schema", "title", "$defs"] {
+        if let Some(value) = env.remove(keyword) {
+            file[keyword] = value;
+        }
+    }
+    file["additionalProperties"] = JsonValue::Object(env);
+    file
+}
+
+/// Remove the `required` keywords of `schema` and its subschemas.
+fn remove_required(schema: &mut JsonValue) {
+    match schema {
+        JsonValue::Object(map) => {
+            // Unlike a property named `required`, the keyword is an array.
+            if map.get("required").is_some_and(JsonValue::is_array) {
+                map.remove("required");
+            }
+            map.values_mut().for_each(remove_required);
+        }
+        JsonValue::Array(items) => items.iter_mut().for_each(remove_required),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Postgres {
+        host: String,
+        port: u16,
+        required: Option<bool>,
+    }
+
+    #[allow(dead_code)]
+    #[derive(JsonSchema)]
+    struct Settings {
+        pg: Postgres,
+        debug: bool,
+    }
+
+    #[test]
+    fn test_json_schema() {
+        let schema = json_schema::<Settings>();
+        assert_eq!(schema["title"], "Settings");
+        assert_eq!(schema["required"], json!(["pg", "debug"]));
+        assert_eq!(schema["properties"]["pg"]["$ref"], "#/$defs/Postgres");
+    }
+
+    #[test]
+    fn test_settings_file_schema() {
+        let schema = settings_file_schema::<Settings>();
+        assert_eq!(schema["title"], "Settings");
+        assert!(schema["$schema"].is_string());
+        assert_eq!(schema["properties"]["required"]["type"], "array");
+        let env = &schema["additionalProperties"];
+        assert_eq!(env["type"], "object");
+        assert!(env.get("required").is_none());
+        assert!(env["properties"]["debug"].is_object());
+        let pg = &schema["$defs"]["Postgres"];
+        assert!(pg.get("required").is_none());
+        assert!(pg["properties"]["required"].is_object());
+    }
+}
@@ -1,16 +1,72 @@
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::time::Duration;
 
-use crate::env;
+use config::ConfigError;
+use regex::Regex;
 
-#[derive(Debug, Clone, PartialEq)]
+use crate::command::CommandSource;
+use crate::env::{self, FromVar};
+use crate::formats::{Format, FormatRegistry};
+use crate::hydro::STDIN_PATH;
+#[cfg(feature = "vault")]
+use crate::remote::VaultSettings;
+use crate::sources::SETTINGS_DIRS;
+#[cfg(feature = "xml")]
+use crate::xml::XmlAttributes;
+
+#[derive(Debug, Clone)]
 pub struct HydroSettings {
     pub root_path: Option<PathBuf>,
+    pub root_path_by_env: HashMap<String, PathBuf>,
     pub settings_file: Option<PathBuf>,
+    pub settings_files: Vec<PathBuf>,
+    pub settings_dirs: Vec<PathBuf>,
+    pub app_name: Option<String>,
+    pub max_walk_depth: Option<usize>,
+    pub cascade_settings: bool,
+    pub stdin_format: Format,
     pub secrets_file: Option<PathBuf>,
     pub env: String,
     pub envvar_prefix: String,
     pub encoding: String,
     pub envvar_nested_sep: String,
+    pub env_regex_map: Vec<(Regex, String)>,
+    pub trim_values: bool,
+    pub secrets_win_over_env: bool,
+    pub namespace: Option<String>,
+    pub docker_secrets: bool,
+    pub credentials_directory: Option<PathBuf>,
+    pub command: Option<CommandSource>,
+    pub format_registry: FormatRegistry,
+    pub required: Vec<String>,
+    pub unknown_keys: UnknownKeys,
+    pub require_env: bool,
+    pub secret_keys: Vec<String>,
+    pub allowed_envs: Vec<String>,
+    pub warn_shadowed: bool,
+    pub strict_interpolation: bool,
+    pub path_keys: Vec<String>,
+    pub list_merge: ListMerge,
+    pub list_merge_by_key: HashMap<String, ListMerge>,
+    pub shallow_merge: bool,
+    pub layer_order: Vec<Layer>,
+    pub flat_files: bool,
+    pub tenant: Option<String>,
+    #[cfg(feature = "age")]
+    pub age_identity: Option<PathBuf>,
+    #[cfg(feature = "consul")]
+    pub consul_prefix: Option<String>,
+    #[cfg(feature = "git")]
+    pub git_url: Option<String>,
+    #[cfg(feature = "git")]
+    pub git_ref: Option<String>,
+    #[cfg(feature = "onepassword")]
+    pub onepassword_refs: Vec<(String, String)>,
+    #[cfg(feature = "vault")]
+    pub vault: Option<VaultSettings>,
+    #[cfg(feature = "xml")]
+    pub xml_attributes: XmlAttributes,
 }
 
 impl Default for HydroSettings {
@@ -18,13 +74,45 @@ impl Default for HydroSettings {
         let hydro_suffix = "_FOR_HYDRO";
         Self {
             root_path: env::get_var("ROOT_PATH", hydro_suffix),
+            root_path_by_env: HashMap::new(),
             settings_file: env::get_var("SETTINGS_FILE", hydro_suffix),
+            settings_files: env::get_var_default(
+                "SETTINGS_FILES",
+                hydro_suffix,
+                Vec::<String>::new(),
+            )
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+            settings_dirs: env::get_var_default(
+                "SETTINGS_DIRS",
+                hydro_suffix,
+                SETTINGS_DIRS
+                    .iter()
+                    .map(|dir| dir.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .into_iter()
+            .map(PathBuf::from)
+            .collect(),
+            app_name: env::get_var("APP_NAME", hydro_suffix),
+            max_walk_depth: env::get_var("MAX_WALK_DEPTH", hydro_suffix),
+            cascade_settings: env::get_var_default(
+                "CASCADE_SETTINGS",
+                hydro_suffix,
+                false,
+            ),
+            stdin_format: env::get_var_default(
+                "STDIN_FORMAT",
+                hydro_suffix,
+                Format::Toml,
+            ),
             secrets_file: env::get_var("SECRETS_FILE", hydro_suffix),
-            env: env::get_var_default(
+            env: normalize_env(&env::get_var_default(
                 "ENV",
                 hydro_suffix,
-                "development".into(),
-            ),
+                "development".to_string(),
+            )),
             envvar_prefix: env::get_var_default(
                 "ENVVAR_PREFIX",
                 hydro_suffix,
@@ -40,31 +128,351 @@ impl Default for HydroSettings {
                 hydro_suffix,
                 "__".into(),
             ),
+            env_regex_map: Vec::new(),
+            trim_values: env::get_var_default(
+                "TRIM_VALUES",
+                hydro_suffix,
+                false,
+            ),
+            secrets_win_over_env: env::get_var_default(
+                "SECRETS_WIN_OVER_ENV",
+                hydro_suffix,
+                false,
+            ),
+            namespace: env::get_var("NAMESPACE", hydro_suffix),
+            docker_secrets: env::get_var_default(
+                "DOCKER_SECRETS",
+                hydro_suffix,
+                false,
+            ),
+            // Set by systemd for the services using `LoadCredential=`.
+            credentials_directory: std::env::var_os("CREDENTIALS_DIRECTORY")
+                .map(PathBuf::from),
+            command: CommandSource::from_env(),
+            format_registry: FormatRegistry::default(),
+            required: Vec::new(),
+            unknown_keys: env::get_var_default(
+                "UNKNOWN_KEYS",
+                hydro_suffix,
+                UnknownKeys::Allow,
+            ),
+            require_env: env::get_var_default(
+                "REQUIRE_ENV",
+                hydro_suffix,
+                false,
+            ),
+            secret_keys: env::get_var_default(
+                "SECRET_KEYS",
+                hydro_suffix,
+                Vec::new(),
+            ),
+            allowed_envs: env::get_var_default(
+                "ALLOWED_ENVS",
+                hydro_suffix,
+                Vec::<String>::new(),
+            )
+            .iter()
+            .map(|e| normalize_env(e))
+            .collect(),
+            warn_shadowed: env::get_var_default(
+                "WARN_SHADOWED",
+                hydro_suffix,
+                false,
+            ),
+            strict_interpolation: env::get_var_default(
+                "STRICT_INTERPOLATION",
+                hydro_suffix,
+                false,
+            ),
+            path_keys: env::get_var_default(
+                "PATH_KEYS",
+                hydro_suffix,
+                Vec::new(),
+            ),
+            list_merge: env::get_var_default(
+                "LIST_MERGE",
+                hydro_suffix,
+                ListMerge::Replace,
+            ),
+            list_merge_by_key: HashMap::new(),
+            shallow_merge: env::get_var_default(
+                "SHALLOW_MERGE",
+                hydro_suffix,
+                false,
+            ),
+            layer_order: env::get_var_default(
+                "LAYER_ORDER",
+                hydro_suffix,
+                Layer::DEFAULT_ORDER.to_vec(),
+            ),
+            flat_files: env::get_var_default(
+                "FLAT_FILES",
+                hydro_suffix,
+                false,
+            ),
+            tenant: env::get_var("TENANT", hydro_suffix),
+            #[cfg(feature = "age")]
+            age_identity: env::get_var("AGE_IDENTITY", hydro_suffix),
+            #[cfg(feature = "consul")]
+            consul_prefix: env::get_var("CONSUL_PREFIX", hydro_suffix),
+            #[cfg(feature = "git")]
+            git_url: env::get_var("GIT_URL", hydro_suffix),
+            #[cfg(feature = "git")]
+            git_ref: env::get_var("GIT_REF", hydro_suffix),
+            #[cfg(feature = "onepassword")]
+            onepassword_refs: Vec::new(),
+            #[cfg(feature = "vault")]
+            vault: VaultSettings::from_env(),
+            #[cfg(feature = "xml")]
+            xml_attributes: env::get_var_default(
+                "XML_ATTRIBUTES",
+                hydro_suffix,
+                XmlAttributes::Keys,
+            ),
         }
     }
 }
 
+/// Fail if one of the `*_FOR_HYDRO` variables read by
+/// `HydroSettings::default()` has a value that can't be parsed, e.g.
+/// `UNKNOWN_KEYS_FOR_HYDRO=dney`.
+pub(crate) fn check_vars() -> Result<(), ConfigError> {
+    let hydro_suffix = "_FOR_HYDRO";
+    for key in &[
+        "CASCADE_SETTINGS",
+        "TRIM_VALUES",
+        "SECRETS_WIN_OVER_ENV",
+        "DOCKER_SECRETS",
+        "REQUIRE_ENV",
+        "WARN_SHADOWED",
+        "STRICT_INTERPOLATION",
+        "SHALLOW_MERGE",
+        "FLAT_FILES",
+    ] {
+        env::check_var::<bool>(key, hydro_suffix)?;
+    }
+    env::check_var::<usize>("MAX_WALK_DEPTH", hydro_suffix)?;
+    env::check_var::<Format>("STDIN_FORMAT", hydro_suffix)?;
+    env::check_var::<Format>("COMMAND_FORMAT", hydro_suffix)?;
+    env::check_var::<Duration>("COMMAND_TIMEOUT", hydro_suffix)?;
+    env::check_var::<UnknownKeys>("UNKNOWN_KEYS", hydro_suffix)?;
+    env::check_var::<ListMerge>("LIST_MERGE", hydro_suffix)?;
+    env::check_var::<Vec<Layer>>("LAYER_ORDER", hydro_suffix)?;
+    #[cfg(feature = "xml")]
+    env::check_var::<XmlAttributes>("XML_ATTRIBUTES", hydro_suffix)?;
+    Ok(())
+}
+
+// `Regex` does not implement `PartialEq`, so patterns are compared by their
+// source string.
+impl PartialEq for HydroSettings {
+    fn eq(&self, other: &Self) -> bool {
+        let Self {
+            root_path,
+            root_path_by_env,
+            settings_file,
+            settings_files,
+            settings_dirs,
+            app_name,
+            max_walk_depth,
+            cascade_settings,
+            stdin_format,
+            secrets_file,
+            env,
+            envvar_prefix,
+            encoding,
+            envvar_nested_sep,
+            env_regex_map,
+            trim_values,
+            secrets_win_over_env,
+            namespace,
+            docker_secrets,
+            credentials_directory,
+            command,
+            format_registry,
+            required,
+            unknown_keys,
+            require_env,
+            secret_keys,
+            allowed_envs,
+            warn_shadowed,
+            strict_interpolation,
+            path_keys,
+            list_merge,
+            list_merge_by_key,
+            shallow_merge,
+            layer_order,
+            flat_files,
+            tenant,
+            #[cfg(feature = "age")]
+            age_identity,
+            #[cfg(feature = "consul")]
+            consul_prefix,
+            #[cfg(feature = "git")]
+            git_url,
+            #[cfg(feature = "git")]
+            git_ref,
+            #[cfg(feature = "onepassword")]
+            onepassword_refs,
+            #[cfg(feature = "vault")]
+            vault,
+            #[cfg(feature = "xml")]
+            xml_attributes,
+        } = self;
+        let eq = *root_path == other.root_path
+            && *root_path_by_env == other.root_path_by_env
+            && *settings_file == other.settings_file
+            && *settings_files == other.settings_files
+            && *settings_dirs == other.settings_dirs
+            && *app_name == other.app_name
+            && *max_walk_depth == other.max_walk_depth
+            && *cascade_settings == other.cascade_settings
+            && *stdin_format == other.stdin_format
+            && *secrets_file == other.secrets_file
+            && *env == other.env
+            && *envvar_prefix == other.envvar_prefix
+            && *encoding == other.encoding
+            && *envvar_nested_sep == other.envvar_nested_sep
+            && env_regex_map.len() == other.env_regex_map.len()
+            && env_regex_map.iter().zip(&other.env_regex_map).all(
+                |((re, tpl), (other_re, other_tpl))| {
+                    re.as_str() == other_re.as_str() && tpl == other_tpl
+                },
+            )
+            && *trim_values == other.trim_values
+            && *secrets_win_over_env == other.secrets_win_over_env
+            && *namespace == other.namespace
+            && *docker_secrets == other.docker_secrets
+            && *credentials_directory == other.credentials_directory
+            && *command == other.command
+            && *format_registry == other.format_registry
+            && *required == other.required
+            && *unknown_keys == other.unknown_keys
+            && *require_env == other.require_env
+            && *secret_keys == other.secret_keys
+            && *allowed_envs == other.allowed_envs
+            && *warn_shadowed == other.warn_shadowed
+            && *strict_interpolation == other.strict_interpolation
+            && *path_keys == other.path_keys
+            && *list_merge == other.list_merge
+            && *list_merge_by_key == other.list_merge_by_key
+            && *shallow_merge == other.shallow_merge
+            && *layer_order == other.layer_order
+            && *flat_files == other.flat_files
+            && *tenant == other.tenant;
+        #[cfg(feature = "age")]
+        let eq = eq && *age_identity == other.age_identity;
+        #[cfg(feature = "consul")]
+        let eq = eq && *consul_prefix == other.consul_prefix;
+        #[cfg(feature = "git")]
+        let eq = eq && *git_url == other.git_url && *git_ref == other.git_ref;
+        #[cfg(feature = "onepassword")]
+        let eq = eq && *onepassword_refs == other.onepassword_refs;
+        #[cfg(feature = "vault")]
+        let eq = eq && *vault == other.vault;
+        #[cfg(feature = "xml")]
+        let eq = eq && *xml_attributes == other.xml_attributes;
+        eq
+    }
+}
+
 impl HydroSettings {
+    /// The section of the current environment for the flat sources, whose
+    /// keys are split on `sep`.
+    pub(crate) fn current_section(&self, sep: &str) -> String {
+        match self.namespace {
+            Some(ref namespace) => {
+                format!("{}{}{}", namespace, sep, self.current_env())
+            }
+            None => self.current_env().to_string(),
+        }
+    }
+
     pub fn set_root_path(mut self, p: PathBuf) -> Self {
         self.root_path = Some(p);
         self
     }
 
+    pub fn set_root_path_for_env(mut self, e: String, p: PathBuf) -> Self {
+        self.root_path_by_env.insert(normalize_env(&e), p);
+        self
+    }
+
     pub fn set_settings_file(mut self, p: PathBuf) -> Self {
         self.settings_file = Some(p);
         self
     }
 
+    /// Load `p` after the settings file, e.g. a service-specific file after
+    /// a base one. The settings files are layered in the order they're
+    /// added.
+    pub fn add_settings_file(mut self, p: PathBuf) -> Self {
+        self.settings_files.push(p);
+        self
+    }
+
+    /// The directories, relative to the root path and each of its parents,
+    /// in which the settings and secrets files are looked up, in order. By
+    /// default they're the directory itself and `config`.
+    pub fn set_settings_dirs(mut self, dirs: Vec<PathBuf>) -> Self {
+        self.settings_dirs = dirs;
+        self
+    }
+
+    /// The name of the application, whose directory in the configuration
+    /// directory of the platform (e.g. `~/.config/<app>` on Linux) is
+    /// searched when the project has no settings files.
+    pub fn set_app_name(mut self, n: String) -> Self {
+        self.app_name = Some(n);
+        self
+    }
+
+    /// Search the settings files in at most `n` parents of the root path.
+    /// With `0`, only the root path itself is searched.
+    pub fn set_max_walk_depth(mut self, n: usize) -> Self {
+        self.max_walk_depth = Some(n);
+        self
+    }
+
+    /// Instead of stopping at the first settings file found while walking
+    /// up from the root path, merge all of them, the closest one winning,
+    /// e.g. the settings of a package over the ones of its monorepo.
+    pub fn set_cascade_settings(mut self, c: bool) -> Self {
+        self.cascade_settings = c;
+        self
+    }
+
+    /// Read the settings document from stdin, in `format`, instead of a
+    /// file. This is the same as setting `SETTINGS_FILE_FOR_HYDRO` to `-`.
+    pub fn set_settings_from_stdin(mut self, format: Format) -> Self {
+        self.settings_file = Some(PathBuf::from(STDIN_PATH));
+        self.stdin_format = format;
+        self
+    }
+
     pub fn set_secrets_file(mut self, p: PathBuf) -> Self {
         self.secrets_file = Some(p);
         self
     }
 
+    /// Set the environment, or the comma-separated environments merged in
+    /// order, e.g. `default,eu,production`.
     pub fn set_env(mut self, e: String) -> Self {
-        self.env = e;
+        self.env = normalize_env(&e);
         self
     }
 
+    /// The environments, in the order their tables are merged.
+    pub fn envs(&self) -> Vec<&str> {
+        self.env.split(',').collect()
+    }
+
+    /// The last of the environments, the one of the settings that depend on
+    /// a single environment, such as `set_root_path_for_env()`.
+    pub fn current_env(&self) -> &str {
+        self.env.rsplit(',').next().unwrap_or_default()
+    }
+
     pub fn set_envvar_prefix(mut self, p: String) -> Self {
         self.envvar_prefix = p;
         self
@@ -79,6 +487,363 @@ impl HydroSettings {
         self.envvar_nested_sep = s;
         self
     }
+
+    /// Capture environment variables whose name matches `re` into the key
+    /// obtained by expanding `template` (e.g. `services.$1.db.host`).
+    pub fn add_env_regex(mut self, re: Regex, template: String) -> Self {
+        self.env_regex_map.push((re, template));
+        self
+    }
+
+    pub fn set_trim_values(mut self, t: bool) -> Self {
+        self.trim_values = t;
+        self
+    }
+
+    pub fn set_secrets_win_over_env(mut self, w: bool) -> Self {
+        self.secrets_win_over_env = w;
+        self
+    }
+
+    pub fn set_namespace(mut self, n: String) -> Self {
+        self.namespace = Some(n);
+        self
+    }
+
+    /// Read the Docker secrets mounted in `/run/secrets`.
+    pub fn set_docker_secrets(mut self, d: bool) -> Self {
+        self.docker_secrets = d;
+        self
+    }
+
+    /// Read the systemd credentials from `p` instead of
+    /// `$CREDENTIALS_DIRECTORY`.
+    pub fn set_credentials_directory(mut self, p: PathBuf) -> Self {
+        self.credentials_directory = Some(p);
+        self
+    }
+
+    /// Load the output of a command as secrets, after the secrets file.
+    pub fn set_command(mut self, c: CommandSource) -> Self {
+        self.command = Some(c);
+        self
+    }
+
+    /// Discover and load the settings files of the custom formats in
+    /// `registry`, e.g. `settings.conf`.
+    pub fn set_format_registry(mut self, registry: FormatRegistry) -> Self {
+        self.format_registry = registry;
+        self
+    }
+
+    /// Fail the hydration unless all the keys in `keys` (e.g. `pg.host`) are
+    /// set. They're checked along with the `required` array of the settings
+    /// files.
+    pub fn set_required(mut self, keys: Vec<String>) -> Self {
+        self.required = keys;
+        self
+    }
+
+    /// What to do with the keys of the settings that the deserialized type
+    /// doesn't have, e.g. a misspelled `pg.prot`.
+    pub fn set_unknown_keys(mut self, u: UnknownKeys) -> Self {
+        self.unknown_keys = u;
+        self
+    }
+
+    /// Fail the hydration if the settings have no table for the current
+    /// environment, e.g. a `[production]` table, instead of only using the
+    /// `default` one.
+    pub fn set_require_env(mut self, r: bool) -> Self {
+        self.require_env = r;
+        self
+    }
+
+    /// Fail the hydration if the keys matching `patterns` are set in the
+    /// settings files or in the `.env` files, which are usually under
+    /// version control, instead of a secrets file or source. A `*` in a
+    /// pattern stands for one or more segments, so that `*.password`
+    /// matches `pg.password` and `services.redis.password`.
+    pub fn set_secret_keys(mut self, patterns: Vec<String>) -> Self {
+        self.secret_keys = patterns;
+        self
+    }
+
+    /// Fail the hydration unless the environment is one of `envs`, so that
+    /// a misspelled `ENV_FOR_HYDRO` doesn't go unnoticed. Any environment is
+    /// allowed if `envs` is empty.
+    pub fn set_allowed_envs(mut self, envs: Vec<String>) -> Self {
+        self.allowed_envs = envs.iter().map(|e| normalize_env(e)).collect();
+        self
+    }
+
+    /// Report the values of the settings files overridden by a later layer
+    /// (e.g. the `.env` files or the environment variables) with
+    /// `Hydroconf::on_warning`, at the end of the hydration.
+    pub fn set_warn_shadowed(mut self, w: bool) -> Self {
+        self.warn_shadowed = w;
+        self
+    }
+
+    /// Fail the hydration if a `${VAR}` reference in the settings files
+    /// names an unset environment variable and has no default, instead of
+    /// replacing it with an empty string.
+    pub fn set_strict_interpolation(mut self, s: bool) -> Self {
+        self.strict_interpolation = s;
+        self
+    }
+
+    /// Resolve the relative paths at the keys matching `patterns` (e.g.
+    /// `tls.cert` or `*.path`) against the directory of the settings file,
+    /// secrets file or `.env` file that set them, so that they don't depend
+    /// on the working directory. A `*` stands for one or more segments.
+    pub fn set_path_keys(mut self, patterns: Vec<String>) -> Self {
+        self.path_keys = patterns;
+        self
+    }
+
+    /// How the lists of an environment table are combined with the ones of
+    /// the tables merged before, e.g. `default`. By default they replace
+    /// them.
+    pub fn set_list_merge(mut self, l: ListMerge) -> Self {
+        self.list_merge = l;
+        self
+    }
+
+    /// How the list at `key` (e.g. `allowed_hosts`) is combined with the
+    /// ones of the tables merged before, instead of `set_list_merge()`.
+    pub fn set_list_merge_for_key(
+        mut self,
+        key: String,
+        l: ListMerge,
+    ) -> Self {
+        self.list_merge_by_key.insert(key, l);
+        self
+    }
+
+    /// Replace the tables of the `default` table (e.g. `[default.pg]`) with
+    /// the ones of the environment tables, instead of merging them
+    /// recursively. A table can still be merged with `_replace = false`.
+    pub fn set_shallow_merge(mut self, s: bool) -> Self {
+        self.shallow_merge = s;
+        self
+    }
+
+    /// The order in which the layers are applied, the last one winning, e.g.
+    /// `[Settings, Dotenv, Env, Secrets]` for the files to win over the
+    /// environment variables. Every layer must be listed once.
+    pub fn set_layer_order(mut self, order: Vec<Layer>) -> Self {
+        self.layer_order = order;
+        self
+    }
+
+    /// Take the settings files as they are, without `default` and
+    /// environment tables: their values are the ones of the current
+    /// environment. This suits the small tools with a single environment.
+    pub fn set_flat_files(mut self, f: bool) -> Self {
+        self.flat_files = f;
+        self
+    }
+
+    /// Merge the overrides of `tenant` after each environment table, e.g.
+    /// `[production.tenants.acme]` after `[production]`.
+    pub fn set_tenant(mut self, t: String) -> Self {
+        self.tenant = Some(t);
+        self
+    }
+
+    /// The layers applied after the settings files are merged, in order,
+    /// with the secrets moved last if they win over the environment.
+    pub(crate) fn override_layers(&self) -> Result<Vec<Layer>, ConfigError> {
+        let mut layers = self.layer_order.clone();
+        let complete = layers.len() == Layer::DEFAULT_ORDER.len()
+            && Layer::DEFAULT_ORDER.iter().all(|l| layers.contains(l));
+        if !complete {
+            return Err(ConfigError::Message(format!(
+                "the layer order must list settings, secrets, dotenv and env \
+                 once, got {:?}",
+                layers
+            )));
+        }
+        if self.secrets_win_over_env {
+            layers.retain(|l| *l != Layer::Secrets);
+            layers.push(Layer::Secrets);
+        }
+        // Merging the files already applied the settings, then the secrets.
+        let merged = match layers.as_slice() {
+            [Layer::Settings, Layer::Secrets, ..] => 2,
+            [Layer::Settings | Layer::Secrets, ..] => 1,
+            _ => 0,
+        };
+        Ok(layers.split_off(merged))
+    }
+
+    /// The strategy of the list at `key`.
+    pub(crate) fn list_merge_of(&self, key: &str) -> ListMerge {
+        self.list_merge_by_key
+            .get(key)
+            .copied()
+            .unwrap_or(self.list_merge)
+    }
+
+    /// Decrypt the age-encrypted files (e.g. `.secrets.toml.age`) with the
+    /// identities in `p`, in addition to `AGE_KEY_FOR_HYDRO`.
+    #[cfg(feature = "age")]
+    pub fn set_age_identity(mut self, p: PathBuf) -> Self {
+        self.age_identity = Some(p);
+        self
+    }
+
+    /// Load the keys under `prefix` in the Consul KV store, after the
+    /// settings file. The agent address and the ACL token are read from
+    /// `CONSUL_HTTP_ADDR` and `CONSUL_HTTP_TOKEN`.
+    #[cfg(feature = "consul")]
+    pub fn set_consul_prefix(mut self, prefix: String) -> Self {
+        self.consul_prefix = Some(prefix);
+        self
+    }
+
+    /// Clone the git repository at `url` and discover the settings files in
+    /// it, at `reference` (a branch, tag or commit) or at the default branch.
+    #[cfg(feature = "git")]
+    pub fn set_git_repo(
+        mut self,
+        url: String,
+        reference: Option<String>,
+    ) -> Self {
+        self.git_url = Some(url);
+        self.git_ref = reference;
+        self
+    }
+
+    /// Resolve the 1Password secret `reference` (e.g.
+    /// `op://Production/Postgres/password`) into `key`, through the Connect
+    /// server at `OP_CONNECT_HOST`.
+    #[cfg(feature = "onepassword")]
+    pub fn add_onepassword_ref(
+        mut self,
+        key: String,
+        reference: String,
+    ) -> Self {
+        self.onepassword_refs.push((key, reference));
+        self
+    }
+
+    /// Read the secrets from Vault instead of the secrets file.
+    #[cfg(feature = "vault")]
+    pub fn set_vault(mut self, v: VaultSettings) -> Self {
+        self.vault = Some(v);
+        self
+    }
+
+    /// How the attributes of the elements of the XML files are read.
+    #[cfg(feature = "xml")]
+    pub fn set_xml_attributes(mut self, a: XmlAttributes) -> Self {
+        self.xml_attributes = a;
+        self
+    }
+}
+
+/// What to do with the keys of the settings that the deserialized type
+/// doesn't have.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum UnknownKeys {
+    /// Ignore them, like serde does.
+    #[default]
+    Allow,
+    /// Report them with `Hydroconf::on_warning`.
+    Warn,
+    /// Fail the deserialization.
+    Deny,
+}
+
+impl FromVar for UnknownKeys {
+    fn parse(var: String) -> Option<Self> {
+        match var.trim().to_lowercase().as_str() {
+            "allow" => Some(UnknownKeys::Allow),
+            "warn" => Some(UnknownKeys::Warn),
+            "deny" => Some(UnknownKeys::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// How a list is combined with the list of the same key in the tables
+/// merged before.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum ListMerge {
+    /// Replace the list, the usual behavior.
+    #[default]
+    Replace,
+    /// Append the items after the ones of the list.
+    Append,
+    /// Insert the items before the ones of the list.
+    Prepend,
+    /// Append the items that aren't in the list yet.
+    Unique,
+}
+
+impl FromVar for ListMerge {
+    fn parse(var: String) -> Option<Self> {
+        match var.trim().to_lowercase().as_str() {
+            "replace" => Some(ListMerge::Replace),
+            "append" => Some(ListMerge::Append),
+            "prepend" => Some(ListMerge::Prepend),
+            "unique" => Some(ListMerge::Unique),
+            _ => None,
+        }
+    }
+}
+
+/// A layer of the settings, in `HydroSettings::set_layer_order()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Layer {
+    /// The settings files and the added sources.
+    Settings,
+    /// The secrets files and sources.
+    Secrets,
+    /// The `.env` files.
+    Dotenv,
+    /// The environment variables.
+    Env,
+}
+
+impl Layer {
+    pub const DEFAULT_ORDER: [Layer; 4] =
+        [Layer::Settings, Layer::Secrets, Layer::Dotenv, Layer::Env];
+}
+
+impl FromVar for Layer {
+    fn parse(var: String) -> Option<Self> {
+        match var.trim().to_lowercase().as_str() {
+            "settings" => Some(Layer::Settings),
+            "secrets" => Some(Layer::Secrets),
+            "dotenv" => Some(Layer::Dotenv),
+            "env" => Some(Layer::Env),
+            _ => None,
+        }
+    }
+}
+
+/// A comma-separated list, e.g. `settings,dotenv,env,secrets`.
+impl FromVar for Vec<Layer> {
+    fn parse(var: String) -> Option<Self> {
+        var.split(',')
+            .map(|l| Layer::parse(l.to_string()))
+            .collect()
+    }
+}
+
+/// Environment names are matched case-insensitively, and deployment tools
+/// sometimes add stray whitespace around them, or around the items of a
+/// comma-separated list.
+fn normalize_env(e: &str) -> String {
+    let envs: Vec<String> = e
+        .split(',')
+        .map(|e| e.trim().to_lowercase())
+        .filter(|e| !e.is_empty())
+        .collect();
+    envs.join(",")
 }
 
 #[cfg(test)]
@@ -92,12 +857,55 @@ mod tests {
             HydroSettings::default(),
             HydroSettings {
                 root_path: None,
+                root_path_by_env: HashMap::new(),
                 settings_file: None,
+                settings_files: Vec::new(),
+                settings_dirs: vec![PathBuf::new(), PathBuf::from("config")],
+                app_name: None,
+                max_walk_depth: None,
+                cascade_settings: false,
+                stdin_format: Format::Toml,
                 secrets_file: None,
                 env: "development".into(),
                 envvar_prefix: "HYDRO".into(),
                 encoding: "utf-8".into(),
                 envvar_nested_sep: "__".into(),
+                env_regex_map: Vec::new(),
+                trim_values: false,
+                secrets_win_over_env: false,
+                namespace: None,
+                docker_secrets: false,
+                credentials_directory: None,
+                command: None,
+                format_registry: FormatRegistry::default(),
+                required: Vec::new(),
+                unknown_keys: UnknownKeys::Allow,
+                require_env: false,
+                secret_keys: Vec::new(),
+                allowed_envs: Vec::new(),
+                warn_shadowed: false,
+                strict_interpolation: false,
+                path_keys: Vec::new(),
+                list_merge: ListMerge::Replace,
+                list_merge_by_key: HashMap::new(),
+                shallow_merge: false,
+                layer_order: Layer::DEFAULT_ORDER.to_vec(),
+                flat_files: false,
+                tenant: None,
+                #[cfg(feature = "age")]
+                age_identity: None,
+                #[cfg(feature = "consul")]
+                consul_prefix: None,
+                #[cfg(feature = "git")]
+                git_url: None,
+                #[cfg(feature = "git")]
+                git_ref: None,
+                #[cfg(feature = "onepassword")]
+                onepassword_refs: Vec::new(),
+                #[cfg(feature = "vault")]
+                vault: None,
+                #[cfg(feature = "xml")]
+                xml_attributes: XmlAttributes::Keys,
             },
         );
     }
@@ -110,12 +918,55 @@ mod tests {
             HydroSettings::default(),
             HydroSettings {
                 root_path: Some("/an/absolute/path".into()),
+                root_path_by_env: HashMap::new(),
                 settings_file: None,
+                settings_files: Vec::new(),
+                settings_dirs: vec![PathBuf::new(), PathBuf::from("config")],
+                app_name: None,
+                max_walk_depth: None,
+                cascade_settings: false,
+                stdin_format: Format::Toml,
                 secrets_file: None,
                 env: "development".into(),
                 envvar_prefix: "HYDRO".into(),
                 encoding: "latin-1".into(),
                 envvar_nested_sep: "__".into(),
+                env_regex_map: Vec::new(),
+                trim_values: false,
+                secrets_win_over_env: false,
+                namespace: None,
+                docker_secrets: false,
+                credentials_directory: None,
+                command: None,
+                format_registry: FormatRegistry::default(),
+                required: Vec::new(),
+                unknown_keys: UnknownKeys::Allow,
+                require_env: false,
+                secret_keys: Vec::new(),
+                allowed_envs: Vec::new(),
+                warn_shadowed: false,
+                strict_interpolation: false,
+                path_keys: Vec::new(),
+                list_merge: ListMerge::Replace,
+                list_merge_by_key: HashMap::new(),
+                shallow_merge: false,
+                layer_order: Layer::DEFAULT_ORDER.to_vec(),
+                flat_files: false,
+                tenant: None,
+                #[cfg(feature = "age")]
+                age_identity: None,
+                #[cfg(feature = "consul")]
+                consul_prefix: None,
+                #[cfg(feature = "git")]
+                git_url: None,
+                #[cfg(feature = "git")]
+                git_ref: None,
+                #[cfg(feature = "onepassword")]
+                onepassword_refs: Vec::new(),
+                #[cfg(feature = "vault")]
+                vault: None,
+                #[cfg(feature = "xml")]
+                xml_attributes: XmlAttributes::Keys,
             },
         );
         remove_var("ENCODING_FOR_HYDRO");
@@ -129,16 +980,82 @@ mod tests {
                 .set_root_path(PathBuf::from("~/test/dir")),
             HydroSettings {
                 root_path: Some(PathBuf::from("~/test/dir")),
+                root_path_by_env: HashMap::new(),
                 settings_file: None,
+                settings_files: Vec::new(),
+                settings_dirs: vec![PathBuf::new(), PathBuf::from("config")],
+                app_name: None,
+                max_walk_depth: None,
+                cascade_settings: false,
+                stdin_format: Format::Toml,
                 secrets_file: None,
                 env: "development".into(),
                 envvar_prefix: "HYDRO".into(),
                 encoding: "utf-8".into(),
                 envvar_nested_sep: "__".into(),
+                env_regex_map: Vec::new(),
+                trim_values: false,
+                secrets_win_over_env: false,
+                namespace: None,
+                docker_secrets: false,
+                credentials_directory: None,
+                command: None,
+                format_registry: FormatRegistry::default(),
+                required: Vec::new(),
+                unknown_keys: UnknownKeys::Allow,
+                require_env: false,
+                secret_keys: Vec::new(),
+                allowed_envs: Vec::new(),
+                warn_shadowed: false,
+                strict_interpolation: false,
+                path_keys: Vec::new(),
+                list_merge: ListMerge::Replace,
+                list_merge_by_key: HashMap::new(),
+                shallow_merge: false,
+                layer_order: Layer::DEFAULT_ORDER.to_vec(),
+                flat_files: false,
+                tenant: None,
+                #[cfg(feature = "age")]
+                age_identity: None,
+                #[cfg(feature = "consul")]
+                consul_prefix: None,
+                #[cfg(feature = "git")]
+                git_url: None,
+                #[cfg(feature = "git")]
+                git_ref: None,
+                #[cfg(feature = "onepassword")]
+                onepassword_refs: Vec::new(),
+                #[cfg(feature = "vault")]
+                vault: None,
+                #[cfg(feature = "xml")]
+                xml_attributes: XmlAttributes::Keys,
             },
         );
     }
 
+    #[test]
+    fn test_set_env_normalization() {
+        assert_eq!(
+            HydroSettings::default()
+                .set_env(" Production \n".into())
+                .env,
+            "production",
+        );
+        assert_eq!(
+            HydroSettings::default()
+                .set_root_path_for_env("STAGING".into(), "/srv".into())
+                .root_path_by_env
+                .get("staging"),
+            Some(&PathBuf::from("/srv")),
+        );
+
+        let settings =
+            HydroSettings::default().set_env(" EU, ,Production ".into());
+        assert_eq!(settings.env, "eu,production");
+        assert_eq!(settings.envs(), ["eu", "production"]);
+        assert_eq!(settings.current_env(), "production");
+    }
+
     #[test]
     fn test_all_builder_methods() {
         assert_eq!(
@@ -149,16 +1066,100 @@ mod tests {
                 .set_env("production".into())
                 .set_envvar_nested_sep("-".into())
                 .set_root_path(PathBuf::from("~/test/dir"))
-                .set_settings_file(PathBuf::from("settings.toml")),
+                .set_root_path_for_env(
+                    "production".into(),
+                    PathBuf::from("/srv/prod"),
+                )
+                .set_settings_file(PathBuf::from("settings.toml"))
+                .set_trim_values(true)
+                .set_secrets_win_over_env(true)
+                .set_namespace("app1".into())
+                .set_docker_secrets(true)
+                .set_credentials_directory(PathBuf::from("/run/creds"))
+                .set_command(CommandSource::new(vec!["sops".into()]))
+                .add_env_regex(
+                    Regex::new("^SVC_(.+)_HOST$").unwrap(),
+                    "services.$1.host".into(),
+                ),
             HydroSettings {
                 root_path: Some(PathBuf::from("~/test/dir")),
+                root_path_by_env: vec![(
+                    "production".to_string(),
+                    PathBuf::from("/srv/prod"),
+                )]
+                .into_iter()
+                .collect(),
                 settings_file: Some(PathBuf::from("settings.toml")),
+                settings_files: Vec::new(),
+                settings_dirs: vec![PathBuf::new(), PathBuf::from("config")],
+                app_name: None,
+                max_walk_depth: None,
+                cascade_settings: false,
+                stdin_format: Format::Toml,
                 secrets_file: Some(PathBuf::from(".secrets.toml")),
                 env: "production".into(),
                 envvar_prefix: "HY_".into(),
                 encoding: "latin-1".into(),
                 envvar_nested_sep: "-".into(),
+                env_regex_map: vec![(
+                    Regex::new("^SVC_(.+)_HOST$").unwrap(),
+                    "services.$1.host".into(),
+                )],
+                trim_values: true,
+                secrets_win_over_env: true,
+                namespace: Some("app1".into()),
+                docker_secrets: true,
+                credentials_directory: Some(PathBuf::from("/run/creds")),
+                command: Some(CommandSource::new(vec!["sops".into()])),
+                format_registry: FormatRegistry::default(),
+                required: Vec::new(),
+                unknown_keys: UnknownKeys::Allow,
+                require_env: false,
+                secret_keys: Vec::new(),
+                allowed_envs: Vec::new(),
+                warn_shadowed: false,
+                strict_interpolation: false,
+                path_keys: Vec::new(),
+                list_merge: ListMerge::Replace,
+                list_merge_by_key: HashMap::new(),
+                shallow_merge: false,
+                layer_order: Layer::DEFAULT_ORDER.to_vec(),
+                flat_files: false,
+                tenant: None,
+                #[cfg(feature = "age")]
+                age_identity: None,
+                #[cfg(feature = "consul")]
+                consul_prefix: None,
+                #[cfg(feature = "git")]
+                git_url: None,
+                #[cfg(feature = "git")]
+                git_ref: None,
+                #[cfg(feature = "onepassword")]
+                onepassword_refs: Vec::new(),
+                #[cfg(feature = "vault")]
+                vault: None,
+                #[cfg(feature = "xml")]
+                xml_attributes: XmlAttributes::Keys,
             },
         );
     }
+
+    #[test]
+    fn test_check_var() {
+        set_var("CHECKVARTEST_FOR_HYDRO", "dney");
+        assert_eq!(
+            env::check_var::<UnknownKeys>("CHECKVARTEST", "_FOR_HYDRO")
+                .unwrap_err()
+                .to_string(),
+            "invalid value \"dney\" for CHECKVARTEST_FOR_HYDRO",
+        );
+        set_var("CHECKVARTEST_FOR_HYDRO", " Deny ");
+        assert!(env::check_var::<UnknownKeys>("CHECKVARTEST", "_FOR_HYDRO")
+            .is_ok());
+        set_var("CHECKVARTEST_FOR_HYDRO", "settings,envs");
+        assert!(env::check_var::<Vec<Layer>>("CHECKVARTEST", "_FOR_HYDRO")
+            .is_err());
+        remove_var("CHECKVARTEST_FOR_HYDRO");
+        assert!(env::check_var::<bool>("CHECKVARTEST", "_FOR_HYDRO").is_ok());
+    }
 }
@@ -0,0 +1,115 @@
+//! Decryption of the settings files encrypted with SOPS.
+
+use std::path::Path;
+use std::process::Command;
+
+use config::ConfigError;
+use regex::Regex;
+
+use crate::formats::Format;
+use crate::utils::pipe_through;
+
+/// Whether a settings file is encrypted with SOPS: either its name has an
+/// `.enc` part (e.g. `.secrets.enc.toml`), or it has SOPS metadata.
+pub fn is_encrypted(path: &Path, text: &str) -> bool {
+    let named = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.split('.').any(|part| part == "enc"));
+    // A top-level `sops` key in YAML, JSON (also used for the formats SOPS
+    // encrypts as binary data, such as TOML) or INI.
+    let metadata =
+        Regex::new(r#"(?m)^sops:|^\s*"sops"\s*:|^\[sops\]"#).unwrap();
+    named || metadata.is_match(text)
+}
+
+/// Decrypt `text` with the `sops` binary, which finds the age, KMS or PGP
+/// keys as usual (e.g. `SOPS_AGE_KEY_FILE`). The documents of the custom
+/// formats, without a `format`, are decrypted as binary data.
+pub fn decrypt(
+    text: &str,
+    format: Option<Format>,
+) -> Result<String, ConfigError> {
+    decrypt_with("sops", text, format)
+}
+
+fn decrypt_with(
+    program: &str,
+    text: &str,
+    format: Option<Format>,
+) -> Result<String, ConfigError> {
+    // SOPS has no TOML support: such files are encrypted as binary data.
+    let file_type = match format {
+        Some(Format::Json) => "json",
+        Some(Format::Yaml) => "yaml",
+        Some(Format::Ini) => "ini",
+        _ => "binary",
+    };
+    let mut command = Command::new(program);
+    command
+        .args(["--decrypt", "--input-type", file_type])
+        .args(["--output-type", file_type, "/dev/stdin"]);
+    pipe_through(&mut command, text).map_err(|message| {
+        ConfigError::Message(format!(
+            "could not decrypt with sops: {}",
+            message
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_is_encrypted() {
+        let plain = "[default]\nport = 1\n";
+        assert!(is_encrypted(Path::new(".secrets.enc.toml"), plain));
+        assert!(!is_encrypted(Path::new(".secrets.toml"), plain));
+        assert!(!is_encrypted(Path::new("encoded.toml"), plain));
+        assert!(is_encrypted(
+            Path::new(".secrets.yaml"),
+            "default:\n  password: ENC[AES256_GCM,data:x]\nsops:\n  version: 3.8.1\n"
+        ));
+        assert!(is_encrypted(
+            Path::new(".secrets.toml"),
+            "{\n\t\"data\": \"ENC[AES256_GCM,data:x]\",\n\t\"sops\": {}\n}"
+        ));
+        assert!(is_encrypted(
+            Path::new(".secrets.ini"),
+            "[default]\npassword = ENC[x]\n[sops]\nversion = 3.8.1\n"
+        ));
+    }
+
+    #[test]
+    fn test_decrypt() {
+        // A fake `sops` printing its arguments and its input.
+        let dir = TempDir::new("sops");
+        let program = dir.join("sops");
+        fs::write(&program, "#!/bin/sh\necho \"$@\"\ncat\n").unwrap();
+        fs::set_permissions(&program, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        let program = program.to_str().unwrap();
+
+        let out = decrypt_with(program, "a: 1\n", Some(Format::Yaml)).unwrap();
+        assert_eq!(
+            out,
+            "--decrypt --input-type yaml --output-type yaml /dev/stdin\na: 1\n"
+        );
+        let out = decrypt_with(program, "{}", Some(Format::Toml)).unwrap();
+        assert!(out.starts_with("--decrypt --input-type binary "));
+
+        fs::write(
+            dir.join("sops"),
+            "#!/bin/sh\necho 'no key' >&2\nexit 128\n",
+        )
+        .unwrap();
+        let err = decrypt_with(program, "{}", Some(Format::Json))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("no key"), "{}", err);
+    }
+}
@@ -1,46 +1,112 @@
 use std::path::{Path, PathBuf};
 
-const SETTINGS_FILE_EXTENSIONS: &[&str] =
-    &["toml", "json", "yaml", "ini", "hjson"];
-const SETTINGS_DIRS: &[&str] = &["", "config"];
+use crate::formats::{Format, FormatRegistry};
+
+/// The directories in which the settings files are looked up by default,
+/// relative to each candidate directory.
+pub const SETTINGS_DIRS: &[&str] = &["", "config"];
+
+/// The files marking the root of a project, where the search for the
+/// settings files stops.
+pub const ROOT_MARKERS: &[&str] = &[".git", "Cargo.toml", ".hydroconf-root"];
+
+/// The directory holding the system-wide settings of the applications, e.g.
+/// `/etc/<app>/settings.toml`.
+pub const SYSTEM_CONFIG_DIR: &str = "/etc";
+
+/// The directory of the settings fragments, next to the settings file.
+pub const FRAGMENTS_DIR: &str = "settings.d";
 
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct FileSources {
+    /// The system-wide settings file, e.g. `/etc/<app>/settings.toml`,
+    /// loaded before all the others.
+    pub system: Option<PathBuf>,
+    /// The settings files of the parent directories of the one holding
+    /// `settings`, from the farthest one, loaded before it when the settings
+    /// are cascaded.
+    pub parent_settings: Vec<PathBuf>,
     pub settings: Option<PathBuf>,
+    /// The settings files loaded after `settings`, in order.
+    pub extra_settings: Vec<PathBuf>,
     pub secrets: Option<PathBuf>,
     pub dotenv: Vec<PathBuf>,
+    pub fragments: Vec<PathBuf>,
+    /// The files included by the settings and secrets files, once they're
+    /// loaded.
+    pub included: Vec<PathBuf>,
 }
 
 impl FileSources {
     pub fn from_root(root_path: PathBuf, env: &str) -> Self {
+        Self::from_root_with_formats(root_path, env, &FormatRegistry::new())
+    }
+
+    /// Like `from_root`, also discovering the settings files with the
+    /// extensions registered in `formats`, after the built-in ones.
+    pub fn from_root_with_formats(
+        root_path: PathBuf,
+        env: &str,
+        formats: &FormatRegistry,
+    ) -> Self {
+        let dirs: Vec<_> = SETTINGS_DIRS.iter().map(PathBuf::from).collect();
+        Self::from_root_in_dirs(root_path, env, formats, &dirs)
+    }
+
+    /// Like `from_root_with_formats`, looking up the settings files in
+    /// `settings_dirs` (relative to each candidate directory) instead of
+    /// `SETTINGS_DIRS`.
+    pub fn from_root_in_dirs(
+        root_path: PathBuf,
+        env: &str,
+        formats: &FormatRegistry,
+        settings_dirs: &[PathBuf],
+    ) -> Self {
+        Self::from_dirs(walk_to_root(root_path), env, formats, settings_dirs)
+    }
+
+    /// Discover the files in `candidates`, in order, stopping at the first
+    /// directory holding any of them.
+    pub fn from_dirs(
+        candidates: Vec<PathBuf>,
+        env: &str,
+        formats: &FormatRegistry,
+        settings_dirs: &[PathBuf],
+    ) -> Self {
         let mut sources = Self {
+            system: None,
+            parent_settings: Vec::new(),
             settings: None,
+            extra_settings: Vec::new(),
             secrets: None,
             dotenv: Vec::new(),
+            fragments: Vec::new(),
+            included: Vec::new(),
         };
         let mut settings_found = false;
-        let candidates = walk_to_root(root_path);
-
         for cand in candidates {
             let dotenv_cand = cand.join(".env");
             if dotenv_cand.exists() {
                 sources.dotenv.push(dotenv_cand);
             }
-            let dotenv_cand = cand.join(format!(".env.{}", env));
-            if dotenv_cand.exists() {
-                sources.dotenv.push(dotenv_cand);
+            for env in env.split(',') {
+                let dotenv_cand = cand.join(format!(".env.{}", env));
+                if dotenv_cand.exists() {
+                    sources.dotenv.push(dotenv_cand);
+                }
             }
-            'outer: for &settings_dir in SETTINGS_DIRS {
+            'outer: for settings_dir in settings_dirs {
                 let dir = cand.join(settings_dir);
-                for &ext in SETTINGS_FILE_EXTENSIONS {
-                    let settings_cand = dir.join(format!("settings.{}", ext));
-                    if settings_cand.exists() {
-                        sources.settings = Some(settings_cand);
+                let custom = formats
+                    .extensions()
+                    .filter(|ext| !Format::EXTENSIONS.contains(ext));
+                for ext in Format::EXTENSIONS.iter().copied().chain(custom) {
+                    if let Some(cand) = find_file(&dir, "settings", ext) {
+                        sources.settings = Some(cand);
                         settings_found = true;
                     }
-                    let secrets_cand = dir.join(format!(".secrets.{}", ext));
-                    if secrets_cand.exists() {
-                        sources.secrets = Some(secrets_cand);
+                    if let Some(cand) = find_file(&dir, ".secrets", ext) {
+                        sources.secrets = Some(cand);
                         settings_found = true;
                     }
                     if settings_found {
@@ -54,6 +120,9 @@ impl FileSources {
             }
         }
 
+        if let Some(ref settings) = sources.settings {
+            sources.fragments = find_fragments(settings, formats);
+        }
         sources
     }
 
@@ -64,6 +133,99 @@ impl FileSources {
     }
 }
 
+/// The settings file of `dir`, e.g. `/etc/<app>/settings.toml`, in any of the
+/// built-in formats or the ones registered in `formats`.
+pub fn find_settings(dir: &Path, formats: &FormatRegistry) -> Option<PathBuf> {
+    let custom = formats
+        .extensions()
+        .filter(|ext| !Format::EXTENSIONS.contains(ext));
+    Format::EXTENSIONS
+        .iter()
+        .copied()
+        .chain(custom)
+        .find_map(|ext| find_file(dir, "settings", ext))
+}
+
+/// The settings files of `candidates`, in order: in each directory, the first
+/// one found in `settings_dirs`.
+pub fn find_all_settings(
+    candidates: &[PathBuf],
+    formats: &FormatRegistry,
+    settings_dirs: &[PathBuf],
+) -> Vec<PathBuf> {
+    candidates
+        .iter()
+        .filter_map(|cand| {
+            settings_dirs
+                .iter()
+                .find_map(|dir| find_settings(&cand.join(dir), formats))
+        })
+        .collect()
+}
+
+/// Find `<name>.<ext>` in `dir`, or its version encrypted with SOPS
+/// (`<name>.enc.<ext>`) or age (`<name>.<ext>.age`).
+fn find_file(dir: &Path, name: &str, ext: &str) -> Option<PathBuf> {
+    [
+        format!("{}.{}", name, ext),
+        format!("{}.enc.{}", name, ext),
+        format!("{}.{}.age", name, ext),
+        format!("{}.{}.j2", name, ext),
+    ]
+    .iter()
+    .map(|file_name| dir.join(file_name))
+    .find(|cand| cand.exists())
+}
+
+/// The files of the `settings.d` directory next to `settings`, in
+/// lexicographic order. The ones in an unknown format are skipped.
+pub fn find_fragments(
+    settings: &Path,
+    formats: &FormatRegistry,
+) -> Vec<PathBuf> {
+    let dir = match settings.parent() {
+        Some(parent) => parent.join(FRAGMENTS_DIR),
+        None => return Vec::new(),
+    };
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Vec::new(),
+    };
+    let mut fragments: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && is_settings_file(path, formats))
+        .collect();
+    fragments.sort();
+    fragments
+}
+
+/// Whether `path` has the extension of a settings file, possibly encrypted
+/// or templated.
+fn is_settings_file(path: &Path, formats: &FormatRegistry) -> bool {
+    let hidden = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_none_or(|n| n.starts_with('.'));
+    let path = match path.extension().and_then(|e| e.to_str()) {
+        Some("age") | Some("j2") => path.with_extension(""),
+        _ => path.to_path_buf(),
+    };
+    let ext = path.extension().and_then(|e| e.to_str());
+    !hidden
+        && ext.is_some_and(|ext| {
+            Format::from_extension(ext).is_some()
+                || formats.extensions().any(|e| e == ext)
+        })
+}
+
+/// The format of a settings file, deduced from its extension.
+pub fn file_format(path: &Path) -> Option<Format> {
+    Format::from_extension(path.extension()?.to_str()?)
+}
+
+/// The directories in which the settings files are searched: `path` and its
+/// parents, up to the first one holding one of the `ROOT_MARKERS`, if any,
+/// so that the files of an unrelated parent directory are never loaded.
 pub fn walk_to_root(mut path: PathBuf) -> Vec<PathBuf> {
     let mut candidates = Vec::new();
     if path.is_file() {
@@ -71,30 +233,63 @@ pub fn walk_to_root(mut path: PathBuf) -> Vec<PathBuf> {
     }
     for ancestor in path.ancestors() {
         candidates.push(ancestor.to_path_buf());
+        if is_project_root(ancestor) {
+            break;
+        }
     }
     candidates
 }
 
+fn is_project_root(dir: &Path) -> bool {
+    ROOT_MARKERS.iter().any(|marker| dir.join(marker).exists())
+}
+
+/// The directory where the platform keeps the configuration of `app`:
+/// `$XDG_CONFIG_HOME/<app>` (by default `~/.config/<app>`) on Linux and the
+/// other Unix systems, `~/Library/Application Support/<app>` on macOS and
+/// `%APPDATA%\<app>` on Windows.
+pub fn platform_config_dir(app: &str) -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        home_dir()?.join("Library/Application Support")
+    } else {
+        match std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from) {
+            // As the XDG specification says, a relative path is ignored.
+            Some(dir) if dir.is_absolute() => dir,
+            _ => home_dir()?.join(".config"),
+        }
+    };
+    Some(base.join(app))
+}
+
+/// The directory of the cached files of the current user, e.g.
+/// `~/.cache/<app>` on Linux.
+#[cfg(any(feature = "http", feature = "git"))]
+pub(crate) fn platform_cache_dir(app: &str) -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("LOCALAPPDATA")?)
+    } else if cfg!(target_os = "macos") {
+        home_dir()?.join("Library/Caches")
+    } else {
+        match std::env::var_os("XDG_CACHE_HOME").map(PathBuf::from) {
+            Some(dir) if dir.is_absolute() => dir,
+            _ => home_dir()?.join(".cache"),
+        }
+    };
+    Some(base.join(app))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::env;
-
-    fn get_data_path(suffix: &str) -> PathBuf {
-        let mut target_dir = PathBuf::from(
-            env::current_exe()
-                .expect("exe path")
-                .parent()
-                .expect("exe parent"),
-        );
-        while target_dir.file_name() != Some(std::ffi::OsStr::new("target")) {
-            if !target_dir.pop() {
-                panic!("Cannot find target directory");
-            }
-        }
-        target_dir.pop();
-        target_dir.join(format!("tests/data{}", suffix))
-    }
+    use crate::testing::get_data_path;
 
     #[test]
     fn test_walk_to_root_dir() {
@@ -115,15 +310,50 @@ mod test {
         assert_eq!(walk_to_root(PathBuf::from("/")), vec![PathBuf::from("/")],);
     }
 
+    #[test]
+    fn test_walk_to_root_marker() {
+        let dir = get_data_path("31");
+        let nested = dir.join("project/src/bin");
+        assert_eq!(
+            walk_to_root(nested.clone()),
+            vec![nested.clone(), dir.join("project/src"), dir.join("project")],
+        );
+    }
+
+    #[test]
+    fn test_find_fragments() {
+        let data_path = get_data_path("9").join("config");
+        assert_eq!(
+            find_fragments(
+                &data_path.join("settings.toml"),
+                &FormatRegistry::new()
+            ),
+            vec![
+                data_path.join("settings.d/10-pg.toml"),
+                data_path.join("settings.d/20-pg.yaml"),
+            ],
+        );
+        assert!(find_fragments(
+            &get_data_path("").join("config/settings.toml"),
+            &FormatRegistry::new()
+        )
+        .is_empty());
+    }
+
     #[test]
     fn test_sources() {
         let data_path = get_data_path("");
         assert_eq!(
             FileSources::from_root(data_path.clone(), "development"),
             FileSources {
+                system: None,
+                parent_settings: Vec::new(),
                 settings: Some(data_path.clone().join("config/settings.toml")),
+                extra_settings: Vec::new(),
                 secrets: Some(data_path.join("config/.secrets.toml")),
                 dotenv: vec![data_path.join(".env")],
+                fragments: Vec::new(),
+                included: Vec::new(),
             },
         );
 
@@ -131,12 +361,17 @@ mod test {
         assert_eq!(
             FileSources::from_root(data_path.clone(), "development"),
             FileSources {
+                system: None,
+                parent_settings: Vec::new(),
                 settings: Some(data_path.clone().join("config/settings.toml")),
+                extra_settings: Vec::new(),
                 secrets: Some(data_path.join("config/.secrets.toml")),
                 dotenv: vec![
                     data_path.join(".env"),
                     data_path.join(".env.development")
                 ],
+                fragments: Vec::new(),
+                included: Vec::new(),
             },
         );
 
@@ -144,9 +379,14 @@ mod test {
         assert_eq!(
             FileSources::from_root(data_path.clone(), "production"),
             FileSources {
+                system: None,
+                parent_settings: Vec::new(),
                 settings: Some(data_path.clone().join("config/settings.toml")),
+                extra_settings: Vec::new(),
                 secrets: Some(data_path.join("config/.secrets.toml")),
                 dotenv: vec![data_path.join(".env")],
+                fragments: Vec::new(),
+                included: Vec::new(),
             },
         );
 
@@ -154,9 +394,14 @@ mod test {
         assert_eq!(
             FileSources::from_root(data_path.clone(), "development"),
             FileSources {
+                system: None,
+                parent_settings: Vec::new(),
                 settings: Some(data_path.clone().join("settings.toml")),
+                extra_settings: Vec::new(),
                 secrets: Some(data_path.join(".secrets.toml")),
                 dotenv: vec![data_path.join(".env")],
+                fragments: Vec::new(),
+                included: Vec::new(),
             },
         );
 
@@ -164,12 +409,17 @@ mod test {
         assert_eq!(
             FileSources::from_root(data_path.clone(), "production"),
             FileSources {
+                system: None,
+                parent_settings: Vec::new(),
                 settings: Some(data_path.clone().join("settings.toml")),
+                extra_settings: Vec::new(),
                 secrets: Some(data_path.join(".secrets.toml")),
                 dotenv: vec![
                     data_path.join(".env"),
                     data_path.join(".env.production")
                 ],
+                fragments: Vec::new(),
+                included: Vec::new(),
             },
         );
     }
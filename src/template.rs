@@ -0,0 +1,53 @@
+//! The settings files rendered with minijinja before they're parsed, named
+//! like `settings.yaml.j2`.
+
+use std::path::Path;
+
+use serde_json::Value as JsonValue;
+
+/// Whether a settings file is a template.
+pub fn is_template(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == "j2")
+}
+
+/// Render `template` with the variables of `context`. Undefined variables
+/// are errors, so that a typo doesn't render an empty value.
+#[cfg(feature = "minijinja")]
+pub fn render(template: &str, context: &JsonValue) -> Result<String, String> {
+    let mut env = minijinja::Environment::new();
+    env.set_undefined_behavior(minijinja::UndefinedBehavior::Strict);
+    env.set_keep_trailing_newline(true);
+    env.render_str(template, context).map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "minijinja"))]
+pub fn render(
+    _template: &str,
+    _context: &JsonValue,
+) -> Result<String, String> {
+    Err("hydroconf was built without the `minijinja` feature".into())
+}
+
+#[cfg(all(test, feature = "minijinja"))]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render() {
+        let context = json!({
+            "env": {"PG_HOST": "db-0"},
+            "hydro_env": "production",
+            "settings": {"default": {"pg": {"port": 5432}}},
+        });
+        let template = "[{{ hydro_env }}]\n\
+                        pg.host = '{{ env.PG_HOST }}'\n\
+                        pg.port = {{ settings.default.pg.port + 1 }}\n\
+                        {% for i in range(2) %}w{{ i }} = {{ i }}\n{% endfor %}";
+        assert_eq!(
+            render(template, &context).unwrap(),
+            "[production]\npg.host = 'db-0'\npg.port = 5433\nw0 = 0\nw1 = 1\n"
+        );
+        assert!(render("{{ env.MISSING }}", &context).is_err());
+    }
+}
@@ -0,0 +1,63 @@
+//! The helpers shared by the unit tests.
+
+use std::env;
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// The fixtures of `tests/data<suffix>`.
+pub fn get_data_path(suffix: &str) -> PathBuf {
+    let mut target_dir = PathBuf::from(
+        env::current_exe()
+            .expect("exe path")
+            .parent()
+            .expect("exe parent"),
+    );
+    while target_dir.file_name() != Some(std::ffi::OsStr::new("target")) {
+        if !target_dir.pop() {
+            panic!("Cannot find target directory");
+        }
+    }
+    target_dir.pop();
+    target_dir.join(format!("tests/data{}", suffix))
+}
+
+/// An empty directory for the files written by a test, removed when
+/// dropped.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `hydroconf-<name>-<pid>` in the temporary directory, so that `name`
+    /// must be unique among the tests.
+    pub fn new(name: &str) -> Self {
+        let dir = env::temp_dir().join(format!(
+            "hydroconf-{}-{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
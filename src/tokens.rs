@@ -0,0 +1,256 @@
+//! Values that are references to be resolved after the merge, such as
+//! `@keyring:myapp/pg` or `@file:/run/secrets/pg_password`, or generated
+//! then, such as `@uuid`.
+
+use std::path::Path;
+
+use config::ConfigError;
+use serde_json::Value as JsonValue;
+
+use crate::utils::walk_strings;
+
+/// The prefix of the values read from the platform credential store.
+#[cfg(feature = "keyring")]
+pub const KEYRING_PREFIX: &str = "@keyring:";
+
+/// The prefix of the values read from a file, e.g. a Docker secret.
+pub const FILE_PREFIX: &str = "@file:";
+
+/// The value replaced with a random UUID (version 4).
+pub const UUID_TOKEN: &str = "@uuid";
+
+/// The prefix of the values replaced with `N` random alphanumeric
+/// characters, e.g. `@random:32`.
+pub const RANDOM_PREFIX: &str = "@random:";
+
+/// Replace every `@uuid` and `@random:N` string in `json` with a new value.
+/// Return whether there was any.
+pub fn generate_values(json: &mut JsonValue) -> Result<bool, ConfigError> {
+    let mut generated = false;
+    walk_strings(json, "", &mut |path, value| {
+        let s = value.as_str().unwrap_or_default();
+        let new_value = if s == UUID_TOKEN {
+            uuid()
+        } else if let Some(len) = s.strip_prefix(RANDOM_PREFIX) {
+            let len =
+                len.parse().ok().filter(|len| *len > 0).ok_or_else(|| {
+                    ConfigError::Message(format!(
+                        "invalid {}{} for {}: expected a positive length",
+                        RANDOM_PREFIX, len, path
+                    ))
+                })?;
+            random_string(len)
+        } else {
+            return Ok(());
+        }
+        .map_err(|e| {
+            ConfigError::Message(format!(
+                "could not generate {} for {}: {}",
+                s, path, e
+            ))
+        })?;
+        *value = new_value.into();
+        generated = true;
+        Ok(())
+    })?;
+    Ok(generated)
+}
+
+/// A random UUID, version 4.
+fn uuid() -> Result<String, getrandom::Error> {
+    let mut bytes = [0u8; 16];
+    getrandom::fill(&mut bytes)?;
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// `len` random characters among `[A-Za-z0-9]`.
+fn random_string(len: usize) -> Result<String, getrandom::Error> {
+    const CHARSET: &[u8] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    let mut out = String::with_capacity(len);
+    let mut bytes = [0u8; 64];
+    while out.len() < len {
+        getrandom::fill(&mut bytes)?;
+        // Rejecting the bytes above the largest multiple of the charset
+        // length keeps the characters uniformly distributed.
+        out.extend(
+            bytes
+                .iter()
+                .filter(|&&b| (b as usize) < 256 - 256 % CHARSET.len())
+                .map(|&b| CHARSET[b as usize % CHARSET.len()] as char)
+                .take(len - out.len()),
+        );
+    }
+    Ok(out)
+}
+
+/// Replace every `@file:<path>` string in `json` with the content of the
+/// file. Return whether there was any.
+pub fn resolve_files(json: &mut JsonValue) -> Result<bool, ConfigError> {
+    let mut resolved = false;
+    walk_strings(json, "", &mut |path, value| {
+        let file =
+            match value.as_str().and_then(|s| s.strip_prefix(FILE_PREFIX)) {
+                Some(file) => file.to_string(),
+                None => return Ok(()),
+            };
+        *value = read_value_file(Path::new(&file))
+            .map_err(|e| {
+                ConfigError::Message(format!(
+                    "could not resolve {}{} for {}: {}",
+                    FILE_PREFIX, file, path, e
+                ))
+            })?
+            .into();
+        resolved = true;
+        Ok(())
+    })?;
+    Ok(resolved)
+}
+
+/// The content of a file holding a single value, without its trailing
+/// newlines.
+pub fn read_value_file(path: &Path) -> Result<String, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    Ok(text.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Replace every `@keyring:<service>/<user>` string in `json` with the
+/// password returned by `lookup(service, user)`. Return whether there was
+/// any.
+#[cfg(feature = "keyring")]
+pub fn resolve_keyring<F>(
+    json: &mut JsonValue,
+    lookup: F,
+) -> Result<bool, ConfigError>
+where
+    F: Fn(&str, &str) -> Result<String, String>,
+{
+    let mut resolved = false;
+    walk_strings(json, "", &mut |path, value| {
+        let reference = match value.as_str() {
+            Some(s) => match s.strip_prefix(KEYRING_PREFIX) {
+                Some(reference) => reference.to_string(),
+                None => return Ok(()),
+            },
+            None => return Ok(()),
+        };
+        let error = |message: String| {
+            ConfigError::Message(format!(
+                "could not resolve {}{} for {}: {}",
+                KEYRING_PREFIX, reference, path, message
+            ))
+        };
+        // The service name may contain slashes, not the user name.
+        let (service, user) = reference
+            .rsplit_once('/')
+            .filter(|(service, user)| !service.is_empty() && !user.is_empty())
+            .ok_or_else(|| error("expected <service>/<user>".into()))?;
+        *value = lookup(service, user).map_err(error)?.into();
+        resolved = true;
+        Ok(())
+    })?;
+    Ok(resolved)
+}
+
+/// Read a password from the macOS Keychain, the Windows Credential Manager
+/// or the Secret Service.
+#[cfg(feature = "keyring")]
+pub fn keyring_lookup(service: &str, user: &str) -> Result<String, String> {
+    keyring::Entry::new(service, user)
+        .and_then(|entry| entry.get_password())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::get_data_path;
+    use serde_json::json;
+
+    #[test]
+    fn test_resolve_files() {
+        let path = get_data_path("27").join("password");
+        let reference = format!("@file:{}", path.display());
+        let mut json = json!({
+            "pg": {"password": reference, "host": "localhost"},
+        });
+        assert!(resolve_files(&mut json).unwrap());
+        assert_eq!(
+            json,
+            json!({"pg": {"password": "a password", "host": "localhost"}})
+        );
+        assert!(!resolve_files(&mut json).unwrap());
+
+        let mut json = json!({"key": "@file:/nonexistent/hydroconf"});
+        let err = resolve_files(&mut json).unwrap_err().to_string();
+        assert!(err.starts_with("could not resolve @file:/nonexistent"));
+        assert!(err.contains("for key"), "{}", err);
+    }
+
+    #[test]
+    fn test_generate_values() {
+        let mut json = json!({
+            "id": "@uuid",
+            "secret": "@random:40",
+            "keys": ["@random:1", "@uuid-like"],
+        });
+        assert!(generate_values(&mut json).unwrap());
+        let id = json["id"].as_str().unwrap();
+        assert_eq!(id.len(), 36);
+        assert_eq!(&id[14..15], "4");
+        assert_eq!(id.matches('-').count(), 4);
+        let secret = json["secret"].as_str().unwrap();
+        assert_eq!(secret.len(), 40);
+        assert!(secret.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_eq!(json["keys"][0].as_str().unwrap().len(), 1);
+        assert_eq!(json["keys"][1], "@uuid-like");
+        assert!(!generate_values(&mut json).unwrap());
+
+        let mut json = json!({"secret": "@random:x"});
+        assert_eq!(
+            generate_values(&mut json).unwrap_err().to_string(),
+            "invalid @random:x for secret: expected a positive length"
+        );
+        assert!(generate_values(&mut json!({"s": "@random:0"})).is_err());
+    }
+
+    #[cfg(feature = "keyring")]
+    #[test]
+    fn test_resolve_keyring() {
+        let lookup = |service: &str, user: &str| match (service, user) {
+            ("myapp", "pg") => Ok("a password".to_string()),
+            ("myapp/prod", "redis") => Ok("another password".to_string()),
+            _ => Err("no matching entry found".to_string()),
+        };
+        let mut json = json!({
+            "pg": {"password": "@keyring:myapp/pg", "host": "localhost"},
+            "redis": ["@keyring:myapp/prod/redis"],
+        });
+        assert!(resolve_keyring(&mut json, lookup).unwrap());
+        assert_eq!(
+            json,
+            json!({
+                "pg": {"password": "a password", "host": "localhost"},
+                "redis": ["another password"],
+            })
+        );
+
+        let mut json = json!({"pg": {"password": "@keyring:myapp/missing"}});
+        let err = resolve_keyring(&mut json, lookup).unwrap_err().to_string();
+        assert!(err.contains("pg.password"), "{}", err);
+        assert!(err.contains("no matching entry"), "{}", err);
+        let mut json = json!({"password": "@keyring:myapp"});
+        assert!(resolve_keyring(&mut json, lookup).is_err());
+    }
+}
@@ -0,0 +1,275 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+const DURATION_UNITS: &[(&str, f64)] = &[
+    ("ns", 1e-9),
+    ("us", 1e-6),
+    ("ms", 1e-3),
+    ("s", 1.0),
+    ("m", 60.0),
+    ("h", 3600.0),
+    ("d", 86400.0),
+    ("w", 604_800.0),
+];
+
+const BYTE_UNITS: &[(&str, u64)] = &[
+    ("b", 1),
+    ("k", 1000),
+    ("kb", 1000),
+    ("m", 1000 * 1000),
+    ("mb", 1000 * 1000),
+    ("g", 1000 * 1000 * 1000),
+    ("gb", 1000 * 1000 * 1000),
+    ("t", 1000 * 1000 * 1000 * 1000),
+    ("tb", 1000 * 1000 * 1000 * 1000),
+    ("ki", 1 << 10),
+    ("kib", 1 << 10),
+    ("mi", 1 << 20),
+    ("mib", 1 << 20),
+    ("gi", 1 << 30),
+    ("gib", 1 << 30),
+    ("ti", 1 << 40),
+    ("tib", 1 << 40),
+];
+
+/// Split `s` into `(number, unit)` pairs, e.g. `1m30s` into
+/// `[(1, "m"), (30, "s")]`.
+fn split_units(s: &str) -> Result<Vec<(f64, String)>, String> {
+    let mut parts = Vec::new();
+    let mut chars = s.trim().chars().peekable();
+    if chars.peek().is_none() {
+        return Err("empty value".into());
+    }
+    while chars.peek().is_some() {
+        let mut number = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() || c == '.' {
+                number.push(c);
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let mut unit = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_alphabetic() {
+                unit.push(c.to_ascii_lowercase());
+                chars.next();
+            } else {
+                break;
+            }
+        }
+        while chars.peek().is_some_and(|c| c.is_whitespace()) {
+            chars.next();
+        }
+        let number = number
+            .parse::<f64>()
+            .map_err(|_| format!("invalid number in {:?}", s))?;
+        parts.push((number, unit));
+    }
+    Ok(parts)
+}
+
+/// Parse a human-friendly duration such as `30s`, `5m`, `1h30m` or `250ms`.
+/// A bare number is a number of seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let mut secs = 0.0;
+    for (number, unit) in split_units(s)? {
+        let unit = if unit.is_empty() { "s" } else { unit.as_str() };
+        let factor = DURATION_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .map(|(_, factor)| factor)
+            .ok_or_else(|| format!("unknown duration unit {:?}", unit))?;
+        secs += number * factor;
+    }
+    Duration::try_from_secs_f64(secs)
+        .map_err(|_| format!("invalid duration {:?}", s))
+}
+
+/// Parse a human-friendly size such as `512`, `10MB` or `4KiB` into a number
+/// of bytes. Decimal units (`KB`, `MB`, ...) are powers of 1000, binary units
+/// (`KiB`, `MiB`, ...) powers of 1024.
+pub fn parse_bytes(s: &str) -> Result<u64, String> {
+    let parts = split_units(s)?;
+    if parts.len() != 1 {
+        return Err(format!("invalid size {:?}", s));
+    }
+    let (number, unit) = &parts[0];
+    let unit = if unit.is_empty() { "b" } else { unit.as_str() };
+    let factor = BYTE_UNITS
+        .iter()
+        .find(|(name, _)| *name == unit)
+        .map(|(_, factor)| factor)
+        .ok_or_else(|| format!("unknown size unit {:?}", unit))?;
+    Ok((number * *factor as f64).round() as u64)
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a duration such as \"30s\" or a number of seconds")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Duration, E> {
+        parse_duration(v).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Duration, E> {
+        Ok(Duration::from_secs(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Duration, E> {
+        if v < 0 {
+            return Err(E::custom("a duration can't be negative"));
+        }
+        Ok(Duration::from_secs(v as u64))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<Duration, E> {
+        if v < 0.0 {
+            return Err(E::custom("a duration can't be negative"));
+        }
+        if !v.is_finite() {
+            return Err(E::custom("a duration must be finite"));
+        }
+        Duration::try_from_secs_f64(v)
+            .map_err(|_| E::custom("the duration is too long"))
+    }
+}
+
+struct BytesVisitor;
+
+impl<'de> Visitor<'de> for BytesVisitor {
+    type Value = u64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a size such as \"10MB\" or a number of bytes")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<u64, E> {
+        parse_bytes(v).map_err(E::custom)
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<u64, E> {
+        Ok(v)
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<u64, E> {
+        if v < 0 {
+            return Err(E::custom("a size can't be negative"));
+        }
+        Ok(v as u64)
+    }
+}
+
+/// Deserialize a `Duration` from a string like `"1m30s"` or from a number of
+/// seconds. Use it with `#[serde(deserialize_with = "...")]`:
+///
+/// ```rust
+/// # use std::time::Duration;
+/// # use serde::Deserialize;
+/// #[derive(Deserialize)]
+/// struct Server {
+///     #[serde(deserialize_with = "hydroconf::deserialize_duration")]
+///     timeout: Duration,
+/// }
+/// ```
+pub fn deserialize_duration<'de, D>(
+    deserializer: D,
+) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(DurationVisitor)
+}
+
+/// Deserialize a number of bytes from a string like `"10MB"` or from a plain
+/// number. Use it with `#[serde(deserialize_with = "...")]`.
+pub fn deserialize_bytes<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(BytesVisitor)
+}
+
+/// Wrappers used by `Hydroconf::get_duration` and `Hydroconf::get_bytes`.
+pub(crate) struct HumanDuration(pub Duration);
+
+impl<'de> Deserialize<'de> for HumanDuration {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        deserialize_duration(d).map(HumanDuration)
+    }
+}
+
+pub(crate) struct HumanBytes(pub u64);
+
+impl<'de> Deserialize<'de> for HumanBytes {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        deserialize_bytes(d).map(HumanBytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30s"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1m30s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h 30m"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1.5h"), Ok(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("12"), Ok(Duration::from_secs(12)));
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("10 parsecs").is_err());
+        assert!(parse_duration("s").is_err());
+        assert_eq!(
+            parse_duration("99999999999999999999999d"),
+            Err("invalid duration \"99999999999999999999999d\"".into())
+        );
+    }
+
+    #[test]
+    fn test_deserialize_duration() {
+        use serde::de::value::{Error, F64Deserializer};
+        use serde::de::IntoDeserializer;
+
+        let duration = |v: f64| {
+            let d: F64Deserializer<Error> = v.into_deserializer();
+            deserialize_duration(d).map_err(|e| e.to_string())
+        };
+        assert_eq!(duration(1.5), Ok(Duration::from_millis(1500)));
+        assert_eq!(duration(-1.0), Err("a duration can't be negative".into()));
+        assert_eq!(
+            duration(f64::NAN),
+            Err("a duration must be finite".into())
+        );
+        assert_eq!(
+            duration(f64::INFINITY),
+            Err("a duration must be finite".into())
+        );
+        assert_eq!(duration(1e300), Err("the duration is too long".into()));
+    }
+
+    #[test]
+    fn test_parse_bytes() {
+        assert_eq!(parse_bytes("512"), Ok(512));
+        assert_eq!(parse_bytes("10MB"), Ok(10_000_000));
+        assert_eq!(parse_bytes("10 mb"), Ok(10_000_000));
+        assert_eq!(parse_bytes("4KiB"), Ok(4096));
+        assert_eq!(parse_bytes("1.5GiB"), Ok(1_610_612_736));
+        assert!(parse_bytes("10MB 3KB").is_err());
+        assert!(parse_bytes("10XB").is_err());
+    }
+}
@@ -1,5 +1,313 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::thread;
+
+use config::{ConfigError, Value};
+use serde_json::{Map, Value as JsonValue};
 
 pub fn path_to_string(path: PathBuf) -> Option<String> {
     path.into_os_string().into_string().ok()
 }
+
+/// The environment variables, without the ones whose name or value is not
+/// valid Unicode (`std::env::vars()` panics on them).
+pub fn env_vars() -> impl Iterator<Item = (String, String)> {
+    std::env::vars_os().filter_map(|(name, value)| {
+        Some((name.into_string().ok()?, value.into_string().ok()?))
+    })
+}
+
+/// Whether a settings file path is actually an HTTP(S) or S3 URL.
+pub fn is_url(path: &Path) -> bool {
+    path.to_str().is_some_and(|p| {
+        ["http://", "https://", "s3://"]
+            .iter()
+            .any(|scheme| p.starts_with(scheme))
+    })
+}
+
+pub fn read_bytes(path: &Path) -> Result<Vec<u8>, ConfigError> {
+    std::fs::read(path).map_err(|e| ConfigError::FileParse {
+        uri: path_to_string(path.to_path_buf()),
+        cause: e.into(),
+    })
+}
+
+/// Read a text file in `encoding` (UTF-8 or latin-1), failing with a
+/// readable error if the encoding is not supported or the content of a
+/// UTF-8 file is not valid. A leading byte order mark is dropped.
+pub fn read_text(path: &Path, encoding: &str) -> Result<String, ConfigError> {
+    let latin1 = match encoding.to_lowercase().replace('_', "-").as_str() {
+        "utf-8" | "utf8" => false,
+        "latin-1" | "latin1" | "iso-8859-1" | "iso8859-1" => true,
+        _ => {
+            return Err(ConfigError::Message(format!(
+                "unsupported encoding {} (expected utf-8 or latin-1)",
+                encoding
+            )))
+        }
+    };
+    let bytes = read_bytes(path)?;
+    let text = if latin1 {
+        bytes.into_iter().map(char::from).collect()
+    } else {
+        String::from_utf8(bytes).map_err(|_| {
+            ConfigError::Message(format!(
+                "{} is not a valid UTF-8 text file",
+                path.display(),
+            ))
+        })?
+    };
+    Ok(match text.strip_prefix('\u{feff}') {
+        Some(stripped) => stripped.to_string(),
+        None => text,
+    })
+}
+
+/// The content of the standard input, which can only be read once.
+static STDIN: OnceLock<String> = OnceLock::new();
+
+/// Read the standard input until its end. Later calls (e.g. when the
+/// configuration is hydrated again) return the same content.
+pub fn read_stdin() -> Result<String, ConfigError> {
+    if let Some(text) = STDIN.get() {
+        return Ok(text.clone());
+    }
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text).map_err(|e| {
+        ConfigError::Message(format!(
+            "could not read the settings from stdin: {}",
+            e
+        ))
+    })?;
+    Ok(STDIN.get_or_init(|| text).clone())
+}
+
+/// Run `command` with `input` as its standard input, and return its standard
+/// output, or its standard error if it fails.
+pub fn pipe_through(
+    command: &mut Command,
+    input: &str,
+) -> Result<String, String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    let _ = writer.join();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(stderr.trim().to_string());
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| "the output is not valid UTF-8".to_string())
+}
+
+/// Flatten a nested table into `(dotted.key, leaf value)` pairs. Arrays are
+/// considered leaves.
+pub fn flatten_table(table: &HashMap<String, Value>) -> Vec<(String, Value)> {
+    let mut out = Vec::new();
+    flatten_into(table, "", &mut out);
+    out
+}
+
+fn flatten_into(
+    table: &HashMap<String, Value>,
+    prefix: &str,
+    out: &mut Vec<(String, Value)>,
+) {
+    for (key, value) in table {
+        let full_key = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match value.clone().into_table() {
+            Ok(ref nested) => flatten_into(nested, &full_key, out),
+            Err(_) => out.push((full_key, value.clone())),
+        }
+    }
+}
+
+/// Convert a configuration value into its JSON representation, which unlike
+/// `config::Value` can be inspected by kind.
+pub fn to_json(value: Value) -> Result<JsonValue, ConfigError> {
+    value.try_into()
+}
+
+pub fn from_json(json: JsonValue) -> Value {
+    match json {
+        JsonValue::Null => Value::from(None::<String>),
+        JsonValue::Bool(b) => Value::from(b),
+        JsonValue::Number(n) => match n.as_i64() {
+            Some(i) => Value::from(i),
+            None => Value::from(n.as_f64().unwrap_or_default()),
+        },
+        JsonValue::String(s) => Value::from(s),
+        JsonValue::Array(array) => {
+            Value::from(array.into_iter().map(from_json).collect::<Vec<_>>())
+        }
+        JsonValue::Object(map) => Value::from(
+            map.into_iter()
+                .map(|(k, v)| (k, from_json(v)))
+                .collect::<HashMap<_, _>>(),
+        ),
+    }
+}
+
+/// Call `f` on every string leaf of `value`, passing the dotted path of the
+/// leaf. The callback may replace the leaf with a value of any kind.
+pub fn walk_strings<F>(
+    value: &mut JsonValue,
+    path: &str,
+    f: &mut F,
+) -> Result<(), ConfigError>
+where
+    F: FnMut(&str, &mut JsonValue) -> Result<(), ConfigError>,
+{
+    match value {
+        JsonValue::String(_) => f(path, value)?,
+        JsonValue::Array(array) => {
+            for (i, item) in array.iter_mut().enumerate() {
+                walk_strings(item, &format!("{}[{}]", path, i), f)?;
+            }
+        }
+        JsonValue::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                let item_path = if path.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", path, key)
+                };
+                walk_strings(item, &item_path, f)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Build the sections of a key-value store from `(key, value)` pairs, where
+/// the keys are relative to the source prefix and split on `sep`: with `/`,
+/// `production/pg/port` is read as `pg.port` in the `production` section.
+/// Keys with no section are ignored.
+pub fn key_tree<K, I>(kvs: I, sep: &str) -> HashMap<String, Value>
+where
+    K: AsRef<str>,
+    I: IntoIterator<Item = (K, String)>,
+{
+    let mut root = Map::new();
+    for (key, value) in kvs {
+        let path: Vec<&str> =
+            key.as_ref().split(sep).filter(|s| !s.is_empty()).collect();
+        if path.len() < 2 {
+            continue;
+        }
+        let mut table = &mut root;
+        for segment in &path[..path.len() - 1] {
+            let entry = table
+                .entry(segment.to_string())
+                .or_insert_with(|| JsonValue::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = JsonValue::Object(Map::new());
+            }
+            table = entry.as_object_mut().unwrap();
+        }
+        table.insert(path[path.len() - 1].into(), JsonValue::String(value));
+    }
+    root.into_iter().map(|(k, v)| (k, from_json(v))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::get_data_path;
+
+    #[test]
+    fn test_read_text() {
+        let path = get_data_path("30").join("latin-1.toml");
+        assert_eq!(
+            read_text(&path, "latin-1").unwrap(),
+            "name = \"caf\u{e9}\"\n"
+        );
+        assert_eq!(
+            read_text(&path, "utf-8").unwrap_err().to_string(),
+            format!("{} is not a valid UTF-8 text file", path.display())
+        );
+        assert_eq!(
+            read_text(&path, "utf-16").unwrap_err().to_string(),
+            "unsupported encoding utf-16 (expected utf-8 or latin-1)"
+        );
+    }
+
+    #[test]
+    fn test_read_stdin() {
+        // The standard input of the tests can't be written.
+        STDIN.set("[default]\nport = 1\n".into()).unwrap();
+        assert_eq!(read_stdin().unwrap(), "[default]\nport = 1\n");
+
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_settings_from_stdin(crate::Format::Toml)
+                .set_envvar_prefix("STDINTEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("port").unwrap(), 1);
+        assert_eq!(
+            hydro.origins("port").last().unwrap().to_string(),
+            "settings (-)"
+        );
+    }
+
+    #[test]
+    fn test_flatten_table() {
+        let mut pg = HashMap::new();
+        pg.insert("host".to_string(), Value::from("localhost"));
+        pg.insert("port".to_string(), Value::from(5432i64));
+        let mut table = HashMap::new();
+        table.insert("pg".to_string(), Value::from(pg));
+        table.insert("debug".to_string(), Value::from(true));
+
+        let mut keys: Vec<String> =
+            flatten_table(&table).into_iter().map(|(k, _)| k).collect();
+        keys.sort();
+        assert_eq!(keys, vec!["debug", "pg.host", "pg.port"]);
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let json = serde_json::json!({
+            "pg": {"host": "localhost", "port": 5432, "ratio": 0.5},
+            "hosts": ["a", "b"],
+            "debug": true,
+        });
+        let value = from_json(json.clone());
+        assert_eq!(to_json(value).unwrap(), json);
+    }
+
+    #[test]
+    fn test_walk_strings() {
+        let mut json = serde_json::json!({
+            "pg": {"host": "localhost", "port": 5432},
+            "hosts": ["a", "b"],
+        });
+        let mut paths = Vec::new();
+        walk_strings(&mut json, "", &mut |path, value| {
+            paths.push(path.to_string());
+            *value = JsonValue::from(value.as_str().unwrap().to_uppercase());
+            Ok(())
+        })
+        .unwrap();
+        paths.sort();
+        assert_eq!(paths, vec!["hosts[0]", "hosts[1]", "pg.host"]);
+        assert_eq!(json["pg"]["host"], "LOCALHOST");
+    }
+}
@@ -0,0 +1,363 @@
+use std::any::{type_name, Any};
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+use std::sync::Arc;
+
+use config::{ConfigError, Value};
+use regex::Regex;
+use serde_json::Value as JsonValue;
+
+use crate::utils::to_json;
+
+type Check = dyn Fn(Option<&Value>) -> Result<(), String> + Send + Sync;
+
+/// A check of the value of a key, run after hydrating the settings and
+/// before deserializing them. It returns the reason why the value is
+/// invalid, e.g. `must not be empty`.
+///
+/// Any `Fn(&Value) -> Result<(), String>` is a validator, which is only
+/// called when the key is set.
+#[derive(Clone)]
+pub struct Validator(Arc<Check>);
+
+impl Validator {
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&Value) -> Result<(), String> + Send + Sync + 'static,
+    {
+        Self(Arc::new(move |value| value.map_or(Ok(()), &f)))
+    }
+
+    /// The key must be set.
+    pub fn must_exist() -> Self {
+        Self(Arc::new(|value| match value {
+            Some(_) => Ok(()),
+            None => Err("must be set".into()),
+        }))
+    }
+
+    /// The value must not be null, an empty string, an empty array or an
+    /// empty table.
+    pub fn non_empty() -> Self {
+        Self::new(|value| {
+            let empty = match to_json(value.clone()) {
+                Ok(JsonValue::Null) => true,
+                Ok(JsonValue::String(s)) => s.is_empty(),
+                Ok(JsonValue::Array(a)) => a.is_empty(),
+                Ok(JsonValue::Object(o)) => o.is_empty(),
+                _ => false,
+            };
+            if empty {
+                Err("must not be empty".into())
+            } else {
+                Ok(())
+            }
+        })
+    }
+
+    /// The value must be one of `values`, compared as strings.
+    pub fn one_of<I, S>(values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let values: Vec<String> = values.into_iter().map(Into::into).collect();
+        Self::new(move |value| match value.clone().into_str() {
+            Ok(s) if values.contains(&s) => Ok(()),
+            _ => Err(format!("must be one of {}", values.join(", "))),
+        })
+    }
+
+    /// The value must be an integer in `range`, e.g. `1..=65535`.
+    pub fn range<R>(range: R) -> Self
+    where
+        R: RangeBounds<i64> + Send + Sync + 'static,
+    {
+        let expected = describe(&range);
+        Self::new(move |value| match value.clone().into_int() {
+            Ok(n) if range.contains(&n) => Ok(()),
+            Ok(_) => Err(format!("must be {}", expected)),
+            Err(_) => Err(format!("must be an integer {}", expected)),
+        })
+    }
+
+    /// The value must be a number in `range`, e.g. `0.0..1.0`.
+    pub fn float_range<R>(range: R) -> Self
+    where
+        R: RangeBounds<f64> + Send + Sync + 'static,
+    {
+        let expected = describe(&range);
+        Self::new(move |value| match value.clone().into_float() {
+            Ok(n) if range.contains(&n) => Ok(()),
+            Ok(_) => Err(format!("must be {}", expected)),
+            Err(_) => Err(format!("must be a number {}", expected)),
+        })
+    }
+
+    /// The value must be a string matching `re`, which should be anchored
+    /// with `^` and `$` to match the whole value.
+    pub fn regex(re: Regex) -> Self {
+        Self::new(move |value| match value.clone().into_str() {
+            Ok(s) if re.is_match(&s) => Ok(()),
+            _ => Err(format!("must match {}", re)),
+        })
+    }
+
+    /// Check the value of a key, `None` if it's not set.
+    pub(crate) fn check(&self, value: Option<&Value>) -> Result<(), String> {
+        (self.0)(value)
+    }
+}
+
+impl<F> From<F> for Validator
+where
+    F: Fn(&Value) -> Result<(), String> + Send + Sync + 'static,
+{
+    fn from(f: F) -> Self {
+        Self::new(f)
+    }
+}
+
+/// The bounds of `range`, as in `between 1 and 65535`.
+fn describe<T: fmt::Display, R: RangeBounds<T>>(range: &R) -> String {
+    match (range.start_bound(), range.end_bound()) {
+        (Bound::Included(a), Bound::Included(b)) => {
+            format!("between {} and {}", a, b)
+        }
+        (Bound::Included(a), Bound::Excluded(b)) => {
+            format!("at least {} and less than {}", a, b)
+        }
+        (Bound::Excluded(a), Bound::Included(b)) => {
+            format!("greater than {} and at most {}", a, b)
+        }
+        (Bound::Excluded(a), Bound::Excluded(b)) => {
+            format!("greater than {} and less than {}", a, b)
+        }
+        (Bound::Included(a), Bound::Unbounded) => format!("at least {}", a),
+        (Bound::Excluded(a), Bound::Unbounded) => {
+            format!("greater than {}", a)
+        }
+        (Bound::Unbounded, Bound::Included(b)) => format!("at most {}", b),
+        (Bound::Unbounded, Bound::Excluded(b)) => format!("less than {}", b),
+        (Bound::Unbounded, Bound::Unbounded) => "any number".to_string(),
+    }
+}
+
+impl fmt::Debug for Validator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Validator")
+    }
+}
+
+/// `None` if the settings aren't of the type checked by the invariant.
+type Invariant =
+    dyn Fn(&dyn Any) -> Option<Result<(), Vec<String>>> + Send + Sync;
+
+/// The checks registered with `Hydroconf::add_invariant`, run on the
+/// deserialized settings, with the name of the type they check.
+#[derive(Clone, Default)]
+pub(crate) struct Invariants(Vec<(&'static str, Arc<Invariant>)>);
+
+impl Invariants {
+    /// Register `f`, which checks the settings of type `T`.
+    pub(crate) fn push<T, F>(&mut self, f: F)
+    where
+        T: 'static,
+        F: Fn(&T) -> Result<(), Vec<String>> + Send + Sync + 'static,
+    {
+        self.0.push((
+            type_name::<T>(),
+            Arc::new(move |settings: &dyn Any| {
+                settings.downcast_ref::<T>().map(&f)
+            }),
+        ));
+    }
+
+    /// The errors of all the checks of `settings`, starting with the ones
+    /// of its derived `HydrateValidate` implementation, or an error if an
+    /// invariant checks another type.
+    pub(crate) fn check<T: 'static>(
+        &self,
+        settings: &T,
+    ) -> Result<Vec<String>, ConfigError> {
+        let mut results = Vec::new();
+        for (name, invariant) in &self.0 {
+            match invariant(settings) {
+                Some(result) => results.push(result),
+                None => {
+                    return Err(ConfigError::Message(format!(
+                        "an invariant checks {}, not the hydrated {}",
+                        name,
+                        type_name::<T>()
+                    )))
+                }
+            }
+        }
+        #[cfg(feature = "derive")]
+        let derived = inventory::iter::<Registration>
+            .into_iter()
+            .filter_map(|registration| (registration.0)(settings as &dyn Any))
+            .filter_map(Result::err)
+            .flatten();
+        #[cfg(not(feature = "derive"))]
+        let derived = std::iter::empty();
+        Ok(derived
+            .chain(results.into_iter().filter_map(Result::err).flatten())
+            .collect())
+    }
+}
+
+impl fmt::Debug for Invariants {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invariants({})", self.0.len())
+    }
+}
+
+/// Settings whose fields are checked once deserialized, usually derived
+/// with `#[derive(HydrateValidate)]` (`derive` feature), in which case
+/// Hydroconf runs the checks automatically.
+pub trait HydrateValidate {
+    /// Push the errors of the fields to `errors`, their keys prefixed with
+    /// `prefix` (e.g. `pg.`).
+    fn validate_at(&self, prefix: &str, errors: &mut Vec<String>);
+
+    fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = Vec::new();
+        self.validate_at("", &mut errors);
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(feature = "derive")]
+type Registered = fn(&dyn Any) -> Option<Result<(), Vec<String>>>;
+
+/// A type deriving `HydrateValidate`, registered by the macro so that
+/// `Hydroconf::try_into` finds its checks.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub struct Registration(Registered);
+
+#[cfg(feature = "derive")]
+impl Registration {
+    pub const fn new<T: HydrateValidate + 'static>() -> Self {
+        Self(|settings| settings.downcast_ref::<T>().map(T::validate))
+    }
+}
+
+#[cfg(feature = "derive")]
+inventory::collect!(Registration);
+
+/// The values checked by `#[hydro(non_empty)]`; `None` is empty.
+#[cfg(feature = "derive")]
+#[doc(hidden)]
+pub trait IsEmpty {
+    fn is_empty_value(&self) -> bool;
+}
+
+#[cfg(feature = "derive")]
+mod is_empty {
+    use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+    use super::IsEmpty;
+
+    macro_rules! impl_is_empty {
+        ($($ty:ty => [$($params:tt)*]),* $(,)?) => {
+            $(impl<$($params)*> IsEmpty for $ty {
+                fn is_empty_value(&self) -> bool {
+                    self.is_empty()
+                }
+            })*
+        };
+    }
+
+    impl_is_empty! {
+        String => [],
+        &str => [],
+        Vec<T> => [T],
+        HashMap<K, V, S> => [K, V, S],
+        BTreeMap<K, V> => [K, V],
+        HashSet<T, S> => [T, S],
+        BTreeSet<T> => [T],
+    }
+
+    impl<T: IsEmpty> IsEmpty for Option<T> {
+        fn is_empty_value(&self) -> bool {
+            self.as_ref().is_none_or(IsEmpty::is_empty_value)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validators() {
+        let value = |v: &str| Value::from(v);
+        assert!(Validator::must_exist().check(Some(&value(""))).is_ok());
+        assert_eq!(
+            Validator::must_exist().check(None).unwrap_err(),
+            "must be set"
+        );
+
+        let non_empty = Validator::non_empty();
+        assert!(non_empty.check(None).is_ok());
+        assert!(non_empty.check(Some(&value("a"))).is_ok());
+        assert!(non_empty.check(Some(&Value::from(0))).is_ok());
+        assert!(non_empty.check(Some(&value(""))).is_err());
+        assert!(non_empty
+            .check(Some(&Value::from(Vec::<Value>::new())))
+            .is_err());
+
+        let one_of = Validator::one_of(["debug", "info"]);
+        assert!(one_of.check(Some(&value("info"))).is_ok());
+        assert_eq!(
+            one_of.check(Some(&value("trace"))).unwrap_err(),
+            "must be one of debug, info"
+        );
+
+        let port = Validator::range(1..=65535);
+        assert!(port.check(Some(&Value::from(5432))).is_ok());
+        // From an environment variable.
+        assert!(port.check(Some(&value("5432"))).is_ok());
+        assert_eq!(
+            port.check(Some(&Value::from(0))).unwrap_err(),
+            "must be between 1 and 65535"
+        );
+        assert_eq!(
+            port.check(Some(&value("db"))).unwrap_err(),
+            "must be an integer between 1 and 65535"
+        );
+        assert_eq!(
+            Validator::range(..10)
+                .check(Some(&Value::from(10)))
+                .unwrap_err(),
+            "must be less than 10"
+        );
+
+        let ratio = Validator::float_range(0.0..1.0);
+        assert!(ratio.check(Some(&Value::from(0.5))).is_ok());
+        assert_eq!(
+            ratio.check(Some(&Value::from(1.0))).unwrap_err(),
+            "must be at least 0 and less than 1"
+        );
+
+        let host = Validator::regex(Regex::new("^[a-z0-9.-]+$").unwrap());
+        assert!(host.check(Some(&value("db-0.local"))).is_ok());
+        assert_eq!(
+            host.check(Some(&value("db 0"))).unwrap_err(),
+            "must match ^[a-z0-9.-]+$"
+        );
+
+        let port = Validator::from(|v: &Value| match v.clone().into_int() {
+            Ok(p) if p > 0 => Ok(()),
+            _ => Err("must be a positive integer".to_string()),
+        });
+        assert!(port.check(Some(&Value::from(8080))).is_ok());
+        assert!(port.check(Some(&Value::from(-1))).is_err());
+    }
+}
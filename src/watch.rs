@@ -0,0 +1,824 @@
+//! Hot reloading of the settings when the discovered files or the watched
+//! remote sources change.
+
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use arc_swap::ArcSwap;
+use config::{ConfigError, Source, Value};
+use notify::event::{Event, EventKind, ModifyKind};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher as _};
+use serde::de::DeserializeOwned;
+#[cfg(all(unix, feature = "sighup"))]
+use signal_hook::{consts::SIGHUP, iterator::Handle, iterator::Signals};
+#[cfg(feature = "stream")]
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
+#[cfg(feature = "stream")]
+use tokio_stream::{wrappers::UnboundedReceiverStream, Stream};
+
+use crate::diff::Diff;
+use crate::hydro::Hydroconf;
+
+/// The values of the local settings files, by path, which are only read
+/// again when they change.
+pub(crate) type FileCache =
+    Arc<Mutex<HashMap<PathBuf, HashMap<String, Value>>>>;
+
+/// How long the files must stop changing before reloading, by default.
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/// How long to wait before reading the changes of a watched source again,
+/// after an error.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// The changes to a watched source, as they're notified: `next` blocks
+/// until the next one, or until an error.
+pub type Changes = Box<dyn Iterator<Item = Result<(), ConfigError>> + Send>;
+
+/// A source whose server notifies its changes (e.g. the blocking queries of
+/// Consul or the watches of etcd), so that a `Watcher` reloads the settings
+/// as soon as they change, instead of polling them with `refresh_every`.
+/// Register them with `Hydroconf::add_watched_source`.
+pub trait WatchedSource: Source {
+    /// Subscribe to the changes of the source. After an error, `next` is
+    /// called again a bit later, so the changes should be resumed from where
+    /// they were.
+    fn changes(&self) -> Changes;
+}
+
+/// A callback registered with `Hydroconf::on_change`, called with the
+/// previous and the new settings, whatever their type.
+type Callback = Arc<dyn Fn(&dyn Any, &dyn Any) + Send + Sync>;
+
+/// A callback registered with `Hydroconf::on_diff`.
+type DiffCallback = Arc<dyn Fn(&Diff) + Send + Sync>;
+
+/// The callbacks registered with `Hydroconf::on_change` and
+/// `Hydroconf::on_diff`.
+#[derive(Clone, Default)]
+pub(crate) struct Callbacks {
+    changes: Vec<Callback>,
+    diffs: Vec<DiffCallback>,
+}
+
+impl Callbacks {
+    /// Register `f`, which is only called for settings of type `T`.
+    pub(crate) fn push<T, F>(&mut self, f: F)
+    where
+        T: 'static,
+        F: Fn(&T, &T) + Send + Sync + 'static,
+    {
+        self.changes
+            .push(Arc::new(move |old: &dyn Any, new: &dyn Any| {
+                if let (Some(old), Some(new)) =
+                    (old.downcast_ref::<T>(), new.downcast_ref::<T>())
+                {
+                    f(old, new)
+                }
+            }));
+    }
+
+    pub(crate) fn push_diff<F>(&mut self, f: F)
+    where
+        F: Fn(&Diff) + Send + Sync + 'static,
+    {
+        self.diffs.push(Arc::new(f));
+    }
+
+    fn call<T: 'static>(&self, old: &T, new: &T, diff: &Diff) {
+        for callback in &self.changes {
+            callback(old, new);
+        }
+        if !diff.is_empty() {
+            for callback in &self.diffs {
+                callback(diff);
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Callbacks")
+            .field("changes", &self.changes.len())
+            .field("diffs", &self.diffs.len())
+            .finish()
+    }
+}
+
+/// The deserialized settings, which a `Watcher` swaps atomically on each
+/// reload. Cloning a handle is cheap, and all the clones see the latest
+/// settings without locking, so that e.g. each request handler of a server
+/// can hold one.
+pub struct HydratedHandle<T>(Arc<ArcSwap<T>>);
+
+impl<T> HydratedHandle<T> {
+    pub fn new(value: T) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(value)))
+    }
+
+    /// The latest settings. They don't change while they're held, even if
+    /// the settings are reloaded in the meantime.
+    pub fn get(&self) -> Arc<T> {
+        self.0.load_full()
+    }
+
+    /// Replace the settings, and return the previous ones.
+    pub(crate) fn swap(&self, value: Arc<T>) -> Arc<T> {
+        self.0.swap(value)
+    }
+}
+
+impl<T> Clone for HydratedHandle<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HydratedHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("HydratedHandle").field(&self.get()).finish()
+    }
+}
+
+/// Settings that are hydrated again whenever one of the discovered files
+/// (the settings, secrets and `.env` files) or one of the watched sources
+/// changes, until the watcher is dropped.
+///
+/// The latest settings are kept in a `HydratedHandle`, so that reading them
+/// never blocks. After each reload, the callbacks registered with
+/// `Hydroconf::on_change` are called. When the files are invalid (e.g.
+/// while they're being edited), the previous settings are kept.
+///
+/// Editors and deploy tools often write a file several times in a row: the
+/// changes are batched until none happens for a while (see `debounce`), so
+/// that a burst of writes makes a single reload.
+pub struct Watcher<T> {
+    current: HydratedHandle<T>,
+    // The notify watcher is shared with the reloading thread, which watches
+    // the files discovered by each hydration, and with the refresher, which
+    // stops with it.
+    watcher: Arc<Mutex<RecommendedWatcher>>,
+    triggers: mpsc::Sender<Trigger>,
+    debounce: Arc<Mutex<Duration>>,
+    refresh_ttl: Option<Arc<Mutex<Duration>>>,
+    #[cfg(all(unix, feature = "sighup"))]
+    signals: Option<Handle>,
+    #[cfg(feature = "stream")]
+    subscribers: Subscribers<T>,
+}
+
+/// What makes the settings reload.
+enum Trigger {
+    /// A change in the watched directories, maybe to one of the files.
+    File(notify::Result<Event>),
+    /// The refresh of the remote sources, periodic or notified by a watched
+    /// source, with the cached files.
+    Refresh,
+    #[cfg(all(unix, feature = "sighup"))]
+    Signal,
+}
+
+impl<T> Watcher<T>
+where
+    T: DeserializeOwned + Send + Sync + 'static,
+{
+    pub(crate) fn new(mut hydro: Hydroconf) -> Result<Self, ConfigError> {
+        let cache = FileCache::default();
+        hydro.set_file_cache(Arc::clone(&cache));
+        let hydrated = hydrate(&hydro)?;
+        let current = HydratedHandle::new(hydrated.value);
+        let mut table = hydrated.table;
+
+        let (tx, rx) = mpsc::channel();
+        let events = tx.clone();
+        let watcher = notify::recommended_watcher(move |e| {
+            let _ = events.send(Trigger::File(e));
+        })
+        .map_err(watch_error)?;
+        let watcher = Arc::new(Mutex::new(watcher));
+        let mut watched = Watched::default();
+        watched.update(&watcher, &hydrated.files)?;
+
+        for source in hydro.watched_sources() {
+            watch_source(source, Arc::downgrade(&watcher), tx.clone());
+        }
+
+        let shared = Arc::downgrade(&watcher);
+        let reloaded = current.clone();
+        let debounce = Arc::new(Mutex::new(DEBOUNCE));
+        let delay = Arc::clone(&debounce);
+        #[cfg(feature = "stream")]
+        let subscribers = Subscribers::default();
+        #[cfg(feature = "stream")]
+        let streams = subscribers.clone();
+        // The loop ends with the watcher, which owns the senders.
+        thread::spawn(move || {
+            while let Ok(trigger) = rx.recv() {
+                let mut pending = Pending::default();
+                let mut deadline = Instant::now();
+                if pending.add(trigger, &watched) {
+                    deadline += *delay.lock().unwrap();
+                }
+                if !pending.reload {
+                    continue;
+                }
+                // Wait for the end of the burst of file changes, batching
+                // the other triggers.
+                loop {
+                    let timeout = deadline - Instant::now().min(deadline);
+                    match rx.recv_timeout(timeout) {
+                        Ok(trigger) => {
+                            if pending.add(trigger, &watched) {
+                                deadline =
+                                    Instant::now() + *delay.lock().unwrap();
+                            }
+                        }
+                        Err(RecvTimeoutError::Timeout) => break,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+                if pending.read_files {
+                    cache.lock().unwrap().clear();
+                }
+                match hydrate::<T>(&hydro) {
+                    Ok(hydrated) => {
+                        let new = Arc::new(hydrated.value);
+                        let old = reloaded.swap(Arc::clone(&new));
+                        let _ = watched.rewatch(&shared, &hydrated.files);
+                        let diff = Diff::new(&table, &hydrated.table);
+                        table = hydrated.table;
+                        hydro.callbacks().call(&*old, &*new, &diff);
+                        #[cfg(feature = "stream")]
+                        streams.send(|| Ok(Arc::clone(&new)));
+                    }
+                    #[cfg(feature = "stream")]
+                    Err(e) => streams
+                        .send(|| Err(ConfigError::Message(e.to_string()))),
+                    #[cfg(not(feature = "stream"))]
+                    Err(_) => {}
+                }
+            }
+        });
+
+        Ok(Self {
+            current,
+            watcher,
+            triggers: tx,
+            debounce,
+            refresh_ttl: None,
+            #[cfg(all(unix, feature = "sighup"))]
+            signals: None,
+            #[cfg(feature = "stream")]
+            subscribers,
+        })
+    }
+
+    /// Wait until no file changed for `delay` before reloading the settings
+    /// (50ms by default). The other triggers (refreshes, watched sources and
+    /// signals) are not delayed.
+    pub fn debounce(&mut self, delay: Duration) -> &mut Self {
+        *self.debounce.lock().unwrap() = delay;
+        self
+    }
+
+    /// Also reload the settings every `ttl`, to fetch the remote sources
+    /// (e.g. Consul, Vault or the settings files at a URL) again. The local
+    /// files are not read again, unless they changed in the meantime.
+    pub fn refresh_every(&mut self, ttl: Duration) -> &mut Self {
+        if let Some(ref refresh_ttl) = self.refresh_ttl {
+            *refresh_ttl.lock().unwrap() = ttl;
+            return self;
+        }
+        let refresh_ttl = Arc::new(Mutex::new(ttl));
+        self.refresh_ttl = Some(Arc::clone(&refresh_ttl));
+        let alive = Arc::downgrade(&self.watcher);
+        let triggers = self.triggers.clone();
+        thread::spawn(move || loop {
+            let ttl = *refresh_ttl.lock().unwrap();
+            thread::sleep(ttl);
+            if alive.upgrade().is_none()
+                || triggers.send(Trigger::Refresh).is_err()
+            {
+                break;
+            }
+        });
+        self
+    }
+
+    /// Also reload the settings when the process receives SIGHUP, as most
+    /// daemons do, e.g. after changing the environment variables of a
+    /// service.
+    #[cfg(all(unix, feature = "sighup"))]
+    pub fn reload_on_sighup(&mut self) -> Result<&mut Self, ConfigError> {
+        if self.signals.is_some() {
+            return Ok(self);
+        }
+        let mut signals = Signals::new([SIGHUP]).map_err(|e| {
+            ConfigError::Message(format!("could not handle SIGHUP: {}", e))
+        })?;
+        self.signals = Some(signals.handle());
+        let triggers = self.triggers.clone();
+        thread::spawn(move || {
+            for _ in signals.forever() {
+                if triggers.send(Trigger::Signal).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(self)
+    }
+
+    /// The latest settings.
+    pub fn get(&self) -> Arc<T> {
+        self.current.get()
+    }
+
+    /// A stream of the settings, for async applications: each reload yields
+    /// the new settings, or the error that made it fail (the previous
+    /// settings are kept then). The stream ends when the watcher is dropped.
+    #[cfg(feature = "stream")]
+    pub fn stream(&self) -> impl Stream<Item = Result<Arc<T>, ConfigError>> {
+        let (tx, rx) = unbounded_channel();
+        self.subscribers.0.lock().unwrap().push(tx);
+        UnboundedReceiverStream::new(rx)
+    }
+
+    /// A handle to the latest settings, e.g. to hand them over to the
+    /// request handlers of a server.
+    pub fn handle(&self) -> HydratedHandle<T> {
+        self.current.clone()
+    }
+}
+
+// The signals thread holds a sender, so it must end with the watcher, and
+// so must the streams.
+#[cfg(any(all(unix, feature = "sighup"), feature = "stream"))]
+impl<T> Drop for Watcher<T> {
+    fn drop(&mut self) {
+        #[cfg(all(unix, feature = "sighup"))]
+        if let Some(signals) = self.signals.take() {
+            signals.close();
+        }
+        #[cfg(feature = "stream")]
+        self.subscribers.0.lock().unwrap().clear();
+    }
+}
+
+/// The senders of the streams returned by `Watcher::stream`.
+#[cfg(feature = "stream")]
+type Subscriber<T> = UnboundedSender<Result<Arc<T>, ConfigError>>;
+
+#[cfg(feature = "stream")]
+struct Subscribers<T>(Arc<Mutex<Vec<Subscriber<T>>>>);
+
+#[cfg(feature = "stream")]
+impl<T> Subscribers<T> {
+    /// Send an item made by `item` to each stream that wasn't dropped.
+    fn send<F>(&self, item: F)
+    where
+        F: Fn() -> Result<Arc<T>, ConfigError>,
+    {
+        self.0.lock().unwrap().retain(|s| s.send(item()).is_ok());
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T> Clone for Subscribers<T> {
+    fn clone(&self) -> Self {
+        Self(Arc::clone(&self.0))
+    }
+}
+
+#[cfg(feature = "stream")]
+impl<T> Default for Subscribers<T> {
+    fn default() -> Self {
+        Self(Arc::default())
+    }
+}
+
+/// The triggers received since the last reload.
+#[derive(Default)]
+struct Pending {
+    reload: bool,
+    // Whether the local files must be read again, rather than taken from
+    // the cache.
+    read_files: bool,
+}
+
+impl Pending {
+    /// Add `trigger`, and return whether it's a change to the files.
+    fn add(&mut self, trigger: Trigger, watched: &Watched) -> bool {
+        let file_changed = match trigger {
+            Trigger::File(event) => {
+                if !event.is_ok_and(|e| watched.is_changed(&e)) {
+                    return false;
+                }
+                true
+            }
+            Trigger::Refresh => false,
+            // The environment may have changed, and maybe the files too.
+            #[cfg(all(unix, feature = "sighup"))]
+            Trigger::Signal => {
+                self.read_files = true;
+                false
+            }
+        };
+        self.reload = true;
+        self.read_files |= file_changed;
+        file_changed
+    }
+}
+
+/// Reload the settings on each change of `source`, as long as `alive` can
+/// be upgraded.
+fn watch_source<A: Send + Sync + 'static>(
+    source: Arc<dyn WatchedSource + Send + Sync>,
+    alive: Weak<A>,
+    triggers: mpsc::Sender<Trigger>,
+) {
+    thread::spawn(move || {
+        for change in source.changes() {
+            if alive.upgrade().is_none() {
+                break;
+            }
+            match change {
+                Ok(()) => {
+                    if triggers.send(Trigger::Refresh).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => thread::sleep(RETRY_DELAY),
+            }
+        }
+    });
+}
+
+/// The settings of a hydration.
+struct Hydrated<T> {
+    value: T,
+    // The merged configuration they were deserialized from.
+    table: HashMap<String, Value>,
+    // The files they were read from.
+    files: Vec<PathBuf>,
+}
+
+/// Hydrate a copy of `hydro`.
+fn hydrate<T: DeserializeOwned + 'static>(
+    hydro: &Hydroconf,
+) -> Result<Hydrated<T>, ConfigError> {
+    let mut hydro = hydro.clone();
+    hydro.hydrate_in_place()?;
+    let table = hydro.table();
+    let files = hydro.files();
+    Ok(Hydrated {
+        value: hydro.try_into()?,
+        table,
+        files,
+    })
+}
+
+/// The watched files, and their directories: the files themselves can't
+/// be watched, since editors and deploy tools often replace them.
+#[derive(Default)]
+struct Watched {
+    files: HashSet<PathBuf>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl Watched {
+    fn update(
+        &mut self,
+        watcher: &Mutex<RecommendedWatcher>,
+        files: &[PathBuf],
+    ) -> Result<(), ConfigError> {
+        let mut watcher = watcher.lock().unwrap();
+        let files: HashSet<_> =
+            files.iter().filter_map(|f| absolute(f)).collect();
+        let dirs: HashSet<_> = files
+            .iter()
+            .filter_map(|f| f.parent())
+            .map(Path::to_path_buf)
+            .collect();
+        for dir in self.dirs.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+        for dir in dirs.difference(&self.dirs) {
+            watcher
+                .watch(dir, RecursiveMode::NonRecursive)
+                .map_err(watch_error)?;
+        }
+        self.files = files;
+        self.dirs = dirs;
+        Ok(())
+    }
+
+    fn rewatch(
+        &mut self,
+        watcher: &Weak<Mutex<RecommendedWatcher>>,
+        files: &[PathBuf],
+    ) -> Result<(), ConfigError> {
+        match watcher.upgrade() {
+            Some(watcher) => self.update(&watcher, files),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `event` is a change to one of the files. Reading them (e.g.
+    /// while reloading) is not.
+    fn is_changed(&self, event: &Event) -> bool {
+        let changed = match event.kind {
+            EventKind::Create(_) | EventKind::Remove(_) => true,
+            EventKind::Modify(kind) => {
+                !matches!(kind, ModifyKind::Metadata(_))
+            }
+            _ => false,
+        };
+        changed && event.paths.iter().any(|p| self.files.contains(p))
+    }
+}
+
+/// The path of `file` in its canonical directory, which is how the events
+/// report it: the file itself may not exist anymore.
+fn absolute(file: &Path) -> Option<PathBuf> {
+    let dir = match file.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    Some(dir.canonicalize().ok()?.join(file.file_name()?))
+}
+
+fn watch_error(e: notify::Error) -> ConfigError {
+    ConfigError::Message(format!("could not watch the settings files: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use serde::Deserialize;
+    use std::fs;
+    use std::sync::atomic::Ordering;
+    use std::time::Instant;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Settings {
+        port: i64,
+    }
+
+    fn wait_for<F: Fn() -> bool>(cond: F) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(5) {
+            if cond() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        false
+    }
+
+    #[test]
+    fn test_watch() {
+        let dir = TempDir::new("watch");
+        fs::write(dir.join("settings.toml"), "[default]\nport = 1\n").unwrap();
+        fs::write(dir.join(".env"), "").unwrap();
+        let watcher = Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(dir.to_path_buf())
+                .set_envvar_prefix("WATCHTEST".into()),
+        )
+        .watch::<Settings>()
+        .unwrap();
+        assert_eq!(watcher.get().port, 1);
+
+        let handle = watcher.handle();
+        fs::write(dir.join("settings.toml"), "[default]\nport = 2\n").unwrap();
+        assert!(wait_for(|| handle.get().port == 2));
+
+        fs::write(dir.join(".env"), "WATCHTEST_PORT=3\n").unwrap();
+        assert!(wait_for(|| watcher.get().port == 3));
+
+        // Invalid settings are ignored.
+        fs::write(dir.join("settings.toml"), "[default\n").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        assert_eq!(watcher.get().port, 3);
+    }
+
+    #[test]
+    fn test_hydrated_handle() {
+        fn shareable<T: Clone + Send + Sync>(_: &T) {}
+
+        let handle = HydratedHandle::new(Settings { port: 1 });
+        shareable(&handle);
+        let held = handle.get();
+        let clone = handle.clone();
+        let old = handle.swap(Arc::new(Settings { port: 2 }));
+        assert_eq!((old.port, held.port), (1, 1));
+        assert_eq!(clone.get().port, 2);
+        assert_eq!(
+            format!("{:?}", clone),
+            "HydratedHandle(Settings { port: 2 })"
+        );
+    }
+
+    #[test]
+    fn test_on_change() {
+        let dir = TempDir::new("on-change");
+        fs::write(dir.join("settings.toml"), "[default]\nport = 1\n").unwrap();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let mut hydro = Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(dir.to_path_buf())
+                .set_envvar_prefix("ONCHANGETEST".into()),
+        );
+        let recorded = Arc::clone(&changes);
+        hydro.on_change(move |old: &Settings, new: &Settings| {
+            recorded.lock().unwrap().push((old.port, new.port));
+        });
+        // Not the type of the watched settings.
+        hydro.on_change(|_: &String, _: &String| panic!("wrong type"));
+        let diffs = Arc::new(Mutex::new(Vec::<Vec<String>>::new()));
+        let recorded = Arc::clone(&diffs);
+        hydro.on_diff(move |diff| {
+            let keys = diff.iter().map(|(k, _)| k.clone()).collect();
+            recorded.lock().unwrap().push(keys);
+        });
+        let _watcher = hydro.watch::<Settings>().unwrap();
+        assert!(changes.lock().unwrap().is_empty());
+
+        fs::write(dir.join("settings.toml"), "[default]\nport = 2\nx = 1\n")
+            .unwrap();
+        assert!(wait_for(|| !changes.lock().unwrap().is_empty()));
+        assert_eq!(changes.lock().unwrap()[0], (1, 2));
+        assert!(wait_for(|| !diffs.lock().unwrap().is_empty()));
+        assert_eq!(diffs.lock().unwrap()[0], vec!["port", "x"]);
+    }
+
+    #[test]
+    fn test_debounce() {
+        let dir = TempDir::new("debounce");
+        fs::write(dir.join("settings.toml"), "[default]\nport = 1\n").unwrap();
+        let reloads = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut hydro = Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(dir.to_path_buf())
+                .set_envvar_prefix("DEBOUNCETEST".into()),
+        );
+        let counter = Arc::clone(&reloads);
+        hydro.on_change(move |_: &Settings, _: &Settings| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let mut watcher = hydro.watch::<Settings>().unwrap();
+        watcher.debounce(Duration::from_millis(300));
+
+        for port in 2..=5 {
+            let settings = format!("[default]\nport = {}\n", port);
+            fs::write(dir.join("settings.toml"), settings).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(wait_for(|| watcher.get().port == 5));
+        thread::sleep(Duration::from_millis(400));
+        assert_eq!(reloads.load(Ordering::SeqCst), 1);
+    }
+
+    /// A remote source whose version changes each time it's fetched.
+    #[derive(Clone, Debug, Default)]
+    struct Counter(Arc<std::sync::atomic::AtomicI64>);
+
+    impl config::Source for Counter {
+        fn clone_into_box(&self) -> Box<dyn config::Source + Send + Sync> {
+            Box::new(self.clone())
+        }
+
+        fn collect(&self) -> Result<HashMap<String, Value>, ConfigError> {
+            let version = self.0.fetch_add(1, Ordering::SeqCst);
+            let mut default = HashMap::new();
+            default.insert("version".to_string(), Value::from(version));
+            Ok(HashMap::from([("default".to_string(), default.into())]))
+        }
+    }
+
+    /// A change every 20ms.
+    impl WatchedSource for Counter {
+        fn changes(&self) -> Changes {
+            Box::new(std::iter::repeat_with(|| {
+                thread::sleep(Duration::from_millis(20));
+                Ok(())
+            }))
+        }
+    }
+
+    #[derive(Debug, Deserialize)]
+    struct Versioned {
+        port: i64,
+        version: i64,
+    }
+
+    #[test]
+    fn test_refresh_every() {
+        let dir = TempDir::new("refresh");
+        fs::write(dir.join("settings.toml"), "[default]\nport = 1\n").unwrap();
+        let mut hydro = Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(dir.to_path_buf())
+                .set_envvar_prefix("REFRESHTEST".into()),
+        );
+        hydro.add_source(Counter::default());
+
+        // With a cache, the files are read once.
+        let mut cached = hydro.clone();
+        cached.set_file_cache(FileCache::default());
+        let first = hydrate::<Versioned>(&cached).unwrap().value;
+        fs::write(dir.join("settings.toml"), "[default]\nport = 2\n").unwrap();
+        let second = hydrate::<Versioned>(&cached).unwrap().value;
+        assert_eq!((first.port, second.port), (1, 1));
+        assert!(second.version > first.version);
+
+        let mut watcher = hydro.watch::<Versioned>().unwrap();
+        let version = watcher.get().version;
+        watcher.refresh_every(Duration::from_millis(20));
+        assert!(wait_for(|| watcher.get().version > version + 1));
+        assert_eq!(watcher.get().port, 2);
+    }
+
+    #[test]
+    fn test_watched_source() {
+        let mut hydro = Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_envvar_prefix("WATCHEDTEST".into()),
+        );
+        hydro
+            .skip_discovery(true)
+            .add_watched_source(Counter::default());
+        std::env::set_var("WATCHEDTEST_PORT", "1");
+        let watcher = hydro.watch::<Versioned>().unwrap();
+        let version = watcher.get().version;
+        assert!(wait_for(|| watcher.get().version > version + 1));
+        std::env::remove_var("WATCHEDTEST_PORT");
+    }
+
+    #[cfg(feature = "stream")]
+    #[tokio::test]
+    async fn test_stream() {
+        use tokio::time::timeout;
+        use tokio_stream::StreamExt;
+
+        let dir = TempDir::new("stream");
+        fs::write(dir.join("settings.toml"), "[default]\nport = 1\n").unwrap();
+        let watcher = Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(dir.to_path_buf())
+                .set_envvar_prefix("STREAMTEST".into()),
+        )
+        .watch::<Settings>()
+        .unwrap();
+        let mut stream = Box::pin(watcher.stream());
+        let wait = Duration::from_secs(5);
+
+        fs::write(dir.join("settings.toml"), "[default]\nport = 2\n").unwrap();
+        let settings = timeout(wait, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!(settings.port, 2);
+
+        fs::write(dir.join("settings.toml"), "[default\n").unwrap();
+        assert!(timeout(wait, stream.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .is_err());
+        assert_eq!(watcher.get().port, 2);
+
+        drop(watcher);
+        assert!(timeout(wait, stream.next()).await.unwrap().is_none());
+    }
+
+    #[cfg(all(unix, feature = "sighup"))]
+    #[test]
+    fn test_reload_on_sighup() {
+        let dir = TempDir::new("sighup");
+        fs::write(dir.join("settings.toml"), "[default]\nport = 1\n").unwrap();
+        let mut watcher = Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(dir.to_path_buf())
+                .set_envvar_prefix("SIGHUPTEST".into()),
+        )
+        .watch::<Settings>()
+        .unwrap();
+        watcher.reload_on_sighup().unwrap();
+
+        // The environment is not watched.
+        std::env::set_var("SIGHUPTEST_PORT", "2");
+        signal_hook::low_level::raise(SIGHUP).unwrap();
+        assert!(wait_for(|| watcher.get().port == 2));
+        std::env::remove_var("SIGHUPTEST_PORT");
+    }
+}
@@ -0,0 +1,171 @@
+//! Support for the XML settings files, such as
+//!
+//! ```xml
+//! <settings>
+//!   <default>
+//!     <pg host="localhost" port="5432"/>
+//!     <hosts>a</hosts>
+//!     <hosts>b</hosts>
+//!   </default>
+//! </settings>
+//! ```
+//!
+//! The children of the root element are the tables, an element with children
+//! or attributes is a table, and the elements repeated in a table are
+//! arrays.
+
+use config::ConfigError;
+use roxmltree::{Document, Node};
+use serde_json::{Map, Value as JsonValue};
+
+use crate::env::FromVar;
+
+/// The key of the text of an element that is also a table.
+const TEXT_KEY: &str = "_text";
+
+/// How the attributes of the XML elements are read.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum XmlAttributes {
+    /// Like child elements: `<pg port="5432"/>` is `pg.port`.
+    #[default]
+    Keys,
+    /// Prefixed with `_`, so they can't clash with the child elements:
+    /// `<pg port="5432"/>` is `pg._port`.
+    Prefixed,
+    /// Not read at all.
+    Ignore,
+}
+
+impl FromVar for XmlAttributes {
+    fn parse(var: String) -> Option<Self> {
+        match var.trim().to_lowercase().as_str() {
+            "keys" => Some(XmlAttributes::Keys),
+            "prefixed" => Some(XmlAttributes::Prefixed),
+            "ignore" => Some(XmlAttributes::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// Convert an XML document into the tree of its root element.
+pub fn parse(
+    text: &str,
+    attributes: XmlAttributes,
+) -> Result<JsonValue, ConfigError> {
+    let doc = Document::parse(text).map_err(|e| ConfigError::FileParse {
+        uri: None,
+        cause: Box::new(e),
+    })?;
+    match element(doc.root_element(), attributes) {
+        table @ JsonValue::Object(_) => Ok(table),
+        _ => Ok(JsonValue::Object(Map::new())),
+    }
+}
+
+fn element(node: Node, attributes: XmlAttributes) -> JsonValue {
+    let mut table = Map::new();
+    if attributes != XmlAttributes::Ignore {
+        for attr in node.attributes() {
+            let key = match attributes {
+                XmlAttributes::Prefixed => format!("_{}", attr.name()),
+                _ => attr.name().to_string(),
+            };
+            table.insert(key, attr.value().into());
+        }
+    }
+    for child in node.children().filter(Node::is_element) {
+        let value = element(child, attributes);
+        match table.get_mut(child.tag_name().name()) {
+            Some(JsonValue::Array(values)) => values.push(value),
+            Some(first) => {
+                *first = JsonValue::Array(vec![first.take(), value])
+            }
+            None => {
+                table.insert(child.tag_name().name().into(), value);
+            }
+        }
+    }
+
+    let text: String = node
+        .children()
+        .filter(Node::is_text)
+        .filter_map(|n| n.text())
+        .collect();
+    let text = text.trim();
+    if table.is_empty() {
+        return text.into();
+    }
+    if !text.is_empty() {
+        table.insert(TEXT_KEY.into(), text.into());
+    }
+    JsonValue::Object(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::get_data_path;
+    use serde_json::json;
+
+    const TEXT: &str = r#"<?xml version="1.0"?>
+<!-- The defaults. -->
+<settings>
+  <default>
+    <pg host="localhost" port="5432">
+      <user>app</user>
+    </pg>
+    <hosts>a</hosts>
+    <hosts>b</hosts>
+    <name lang="en">Hydroconf</name>
+  </default>
+  <production><debug>false</debug></production>
+</settings>
+"#;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse(TEXT, XmlAttributes::Keys).unwrap(),
+            json!({
+                "default": {
+                    "pg": {"host": "localhost", "port": "5432", "user": "app"},
+                    "hosts": ["a", "b"],
+                    "name": {"lang": "en", "_text": "Hydroconf"},
+                },
+                "production": {"debug": "false"},
+            })
+        );
+        assert_eq!(
+            parse(TEXT, XmlAttributes::Prefixed).unwrap()["default"]["pg"],
+            json!({"_host": "localhost", "_port": "5432", "user": "app"})
+        );
+        assert_eq!(
+            parse(TEXT, XmlAttributes::Ignore).unwrap()["default"],
+            json!({
+                "pg": {"user": "app"},
+                "hosts": ["a", "b"],
+                "name": "Hydroconf",
+            })
+        );
+        assert_eq!(
+            parse("<settings/>", XmlAttributes::Keys).unwrap(),
+            json!({})
+        );
+        assert!(matches!(
+            parse("<settings>", XmlAttributes::Keys),
+            Err(ConfigError::FileParse { uri: None, .. })
+        ));
+    }
+
+    #[test]
+    fn test_hydrate() {
+        let mut hydro = crate::Hydroconf::new(
+            crate::HydroSettings::default()
+                .set_root_path(get_data_path("26"))
+                .set_xml_attributes(XmlAttributes::Prefixed)
+                .set_envvar_prefix("XMLTEST".into()),
+        );
+        hydro.hydrate_in_place().unwrap();
+        assert_eq!(hydro.get_int("pg._port").unwrap(), 1);
+    }
+}
@@ -0,0 +1,66 @@
+//! Support for the YAML files holding several documents.
+
+use config::{Config, ConfigError, File, FileFormat};
+use yaml_rust::{YamlEmitter, YamlLoader};
+
+/// Split a YAML stream into its documents, separated by `---`.
+pub fn documents(text: &str) -> Result<Vec<String>, ConfigError> {
+    let docs = YamlLoader::load_from_str(text).map_err(|e| {
+        ConfigError::FileParse {
+            uri: None,
+            cause: Box::new(e),
+        }
+    })?;
+    if docs.len() < 2 {
+        return Ok(vec![text.to_string()]);
+    }
+    docs.iter()
+        .map(|doc| {
+            let mut out = String::new();
+            YamlEmitter::new(&mut out).dump(doc).map_err(|e| {
+                ConfigError::Message(format!("invalid YAML document: {:?}", e))
+            })?;
+            Ok(out)
+        })
+        .collect()
+}
+
+/// Merge the documents of a YAML stream in order, so that the later ones
+/// (e.g. the overrides) win over the earlier ones (e.g. the base).
+pub fn merge_documents(text: &str) -> Result<Config, ConfigError> {
+    let mut config = Config::default();
+    for doc in documents(text)? {
+        config.merge(File::from_str(&doc, FileFormat::Yaml))?;
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_documents() {
+        let text = "\
+default:
+  pg:
+    host: localhost
+    port: 5432
+---
+# Overrides
+default:
+  pg:
+    host: db-0
+production:
+  debug: false
+";
+        let config = merge_documents(text).unwrap();
+        assert_eq!(config.get_str("default.pg.host").unwrap(), "db-0");
+        assert_eq!(config.get_int("default.pg.port").unwrap(), 5432);
+        assert!(!config.get_bool("production.debug").unwrap());
+
+        let config = merge_documents("default:\n  port: 1\n").unwrap();
+        assert_eq!(config.get_int("default.port").unwrap(), 1);
+        assert!(merge_documents("default: [1\n").is_err());
+    }
+}
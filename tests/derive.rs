@@ -0,0 +1,85 @@
+#![cfg(feature = "derive")]
+
+use serde::Deserialize;
+use hydroconf::{FileFormat, Hydroconf, HydrateValidate};
+
+#[derive(Debug, Deserialize, HydrateValidate)]
+struct Config {
+    #[hydro(non_empty)]
+    name: String,
+    #[hydro(range(min = 1, max = 65535))]
+    port: u16,
+    #[hydro(range(min = 0.0, max = 1.0))]
+    ratio: Option<f64>,
+    #[hydro(nested)]
+    pg: Postgres,
+}
+
+#[derive(Debug, Deserialize, HydrateValidate)]
+struct Postgres {
+    #[hydro(non_empty)]
+    hosts: Vec<String>,
+    #[hydro(range(min = 1))]
+    pool: u32,
+}
+
+#[derive(Debug, Deserialize, HydrateValidate)]
+struct Wrapper<T> {
+    #[hydro(nested)]
+    inner: T,
+}
+
+fn hydro(settings: &str) -> Hydroconf {
+    Hydroconf::builder()
+        .envvar_prefix("DERIVETEST")
+        .with_inline(settings, FileFormat::Toml)
+        .skip_discovery()
+        .build()
+}
+
+const VALID: &str = "[default]
+name = 'app'
+port = 8080
+[default.pg]
+hosts = ['db']
+pool = 4
+";
+
+#[test]
+fn test_derive() {
+    let conf: Config = hydro(VALID).hydrate().unwrap();
+    assert_eq!(conf.port, 8080);
+    assert!(conf.validate().is_ok());
+
+    let settings = "[default]
+name = ''
+port = 0
+ratio = 1.5
+[default.pg]
+hosts = []
+pool = 0
+";
+    let err = hydro(settings).hydrate::<Config>().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid settings: name must not be empty; \
+         port must be between 1 and 65535; \
+         ratio must be between 0 and 1; \
+         pg.hosts must not be empty; pg.pool must be at least 1"
+    );
+}
+
+#[test]
+fn test_derive_generic() {
+    // Generic structs aren't checked automatically.
+    let settings = "[default.inner]\nhosts = []\npool = 1\n";
+    let conf: Wrapper<Postgres> = hydro(settings).hydrate().unwrap();
+    assert_eq!(
+        conf.validate().unwrap_err(),
+        vec!["inner.hosts must not be empty".to_string()]
+    );
+
+    let mut hydro = hydro(settings);
+    hydro.add_invariant(Wrapper::<Postgres>::validate);
+    assert!(hydro.hydrate::<Wrapper<Postgres>>().is_err());
+}
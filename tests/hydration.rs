@@ -1,7 +1,15 @@
 use std::env;
-use std::path::PathBuf;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use regex::Regex;
 use serde::Deserialize;
-use hydroconf::{ConfigError, Hydroconf, HydroSettings};
+use hydroconf::{
+    Change, ConfigError, FileFormat, Hydroconf, HydroSettings,
+    HydroconfBuilder, Layer, ListMerge, Origin, Stage, UnknownKeys,
+    Validator,
+};
 
 #[derive(Debug, PartialEq, Deserialize)]
 struct Config {
@@ -31,6 +39,33 @@ fn get_data_path(suffix: &str) -> PathBuf {
     target_dir.join(format!("tests/data{}", suffix))
 }
 
+/// An empty directory for the files written by a test, removed when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let dir = env::temp_dir()
+            .join(format!("hydroconf-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        Self(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn test_default_hydration() {
     env::set_var("ROOT_PATH_FOR_HYDRO", get_data_path("").into_os_string().into_string().unwrap());
@@ -211,3 +246,1858 @@ fn test_multiple_dotenvs() {
         },
     });
 }
+
+#[test]
+fn test_audit_secret_leakage() {
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path("4"))
+        .set_env("development".into());
+    let mut hydro = Hydroconf::new(settings);
+    hydro.discover_sources();
+    hydro.load_settings().unwrap();
+    assert_eq!(
+        hydro.audit_secret_leakage(hydroconf::SECRET_PATTERNS),
+        vec!["default.api_token", "production.pg.Password"],
+    );
+    assert_eq!(
+        hydro.audit_secret_leakage(&["host"]),
+        vec!["default.pg.host", "production.pg.host"],
+    );
+}
+
+#[test]
+fn test_root_path_by_env() {
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path(""))
+        .set_root_path_for_env("production".into(), get_data_path("4"))
+        .set_envvar_prefix("ROOT_BY_ENV".into())
+        .set_env("production".into());
+    let conf: Result<Config, ConfigError> = Hydroconf::new(settings.clone()).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-0".into(),
+            port: 5432,
+            password: "a password".into(),
+        },
+    });
+
+    let conf: Result<Config, ConfigError> =
+        Hydroconf::new(settings.set_env("staging".into())).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "localhost".into(),
+            port: 5432,
+            password: "a password".into(),
+        },
+    });
+}
+
+#[test]
+fn test_env_regex_map() {
+    env::set_var("REGEXTEST_SVC_A_DB_HOST", "a.example.com");
+    env::set_var("REGEXTEST_SVC_B_DB_HOST", "b.example.com");
+    env::set_var("REGEXTEST_OTHER", "ignored");
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path(""))
+        .set_env("development".into())
+        .set_envvar_prefix("REGEXTEST".into())
+        .add_env_regex(
+            Regex::new("^REGEXTEST_SVC_([A-Z]+)_DB_HOST$").unwrap(),
+            "services.${1}.db.host".into(),
+        );
+    let mut hydro = Hydroconf::new(settings);
+    hydro.discover_sources();
+    hydro.load_settings().unwrap();
+    hydro.merge_settings().unwrap();
+    hydro.override_from_env().unwrap();
+    assert_eq!(hydro.get_str("services.a.db.host").unwrap(), "a.example.com");
+    assert_eq!(hydro.get_str("services.b.db.host").unwrap(), "b.example.com");
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "localhost");
+    env::remove_var("REGEXTEST_SVC_A_DB_HOST");
+    env::remove_var("REGEXTEST_SVC_B_DB_HOST");
+    env::remove_var("REGEXTEST_OTHER");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_env_regex_like_prefix() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    // The variables that aren't valid Unicode are skipped.
+    let invalid = OsStr::from_bytes(b"\xff\xfe");
+    env::set_var("REGEXLIKETEST_BAD", invalid);
+    env::set_var(invalid, "x");
+    env::set_var("REGEXLIKETEST_PORT_1", "8001");
+    env::set_var("REGEXLIKETEST_HOST", "db");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("REGEXLIKETESTPREFIX")
+        .with_inline("[default]\nports = [8000, 8000]\n", FileFormat::Toml)
+        .skip_discovery()
+        .env_regex(
+            Regex::new("^REGEXLIKETEST_PORT_([0-9])$").unwrap(),
+            "ports.${1}",
+        )
+        .env_regex(
+            Regex::new("^REGEXLIKETEST_([A-Z]+)$").unwrap(),
+            "svc.${1}",
+        )
+        .build();
+    hydro.hydrate_in_place().unwrap();
+    // The keys are set like the ones of the prefixed variables.
+    assert_eq!(
+        hydro.get::<Vec<i64>>("ports").unwrap(),
+        vec![8000, 8001]
+    );
+    assert_eq!(hydro.get_str("svc.host").unwrap(), "db");
+    assert!(hydro.get_str("svc.bad").is_err());
+    assert_eq!(hydro.origins("svc.host"), &[Origin::Environment]);
+    env::remove_var("REGEXLIKETEST_BAD");
+    env::remove_var(invalid);
+    env::remove_var("REGEXLIKETEST_PORT_1");
+    env::remove_var("REGEXLIKETEST_HOST");
+}
+
+#[test]
+fn test_env_array_index() {
+    env::set_var("INDEXTEST_SERVERS__1__HOST", "b.example.com");
+    env::set_var("INDEXTEST_PORTS__0", "8000");
+    env::set_var("INDEXTEST_CODES__404", "not found");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("INDEXTEST")
+        .with_inline(
+            "[default]\nports = [80, 443]\n\
+             [[default.servers]]\nhost = 'a'\nport = 1\n\
+             [[default.servers]]\nhost = 'b'\nport = 2\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    hydro.hydrate_in_place().unwrap();
+    let servers = hydro.get_array("servers").unwrap();
+    assert_eq!(servers.len(), 2);
+    assert_eq!(hydro.get_str("servers[0].host").unwrap(), "a");
+    assert_eq!(hydro.get_str("servers[1].host").unwrap(), "b.example.com");
+    assert_eq!(hydro.get_int("servers[1].port").unwrap(), 2);
+    assert_eq!(hydro.get_int("ports[0]").unwrap(), 8000);
+    assert_eq!(hydro.get_int("ports[1]").unwrap(), 443);
+    // Not an array: the index is a key.
+    assert_eq!(hydro.get_str("codes.404").unwrap(), "not found");
+    env::remove_var("INDEXTEST_SERVERS__1__HOST");
+    env::remove_var("INDEXTEST_PORTS__0");
+    env::remove_var("INDEXTEST_CODES__404");
+}
+
+#[test]
+fn test_env_array_index_out_of_range() {
+    let hydrate = || {
+        Hydroconf::builder()
+            .envvar_prefix("OUTOFRANGETEST")
+            .with_inline("[default]\nports = [80, 443]\n", FileFormat::Toml)
+            .skip_discovery()
+            .build()
+            .hydrate::<serde_json::Value>()
+    };
+    env::set_var("OUTOFRANGETEST_PORTS__2", "8000");
+    assert_eq!(
+        hydrate().unwrap_err().to_string(),
+        "index 2 of ports.2 is out of range (ports has 2 items)"
+    );
+    env::remove_var("OUTOFRANGETEST_PORTS__2");
+    // A huge index fails the same way, instead of growing the array.
+    env::set_var("OUTOFRANGETEST_PORTS__4000000000", "8000");
+    assert!(hydrate().is_err());
+    env::remove_var("OUTOFRANGETEST_PORTS__4000000000");
+}
+
+#[test]
+fn test_refresh() {
+    env::set_var("REFRESHTEST_PG__PORT", "5433");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("REFRESHTEST")
+        .with_inline(
+            "[default]\nname = 'app'\n[default.pg]\nhost = 'a'\nport = 1\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    hydro.hydrate_in_place().unwrap();
+    hydro.set("debug", true).unwrap();
+    hydro.set_default("workers", 4).unwrap();
+    assert_eq!(hydro.get_int("pg.port").unwrap(), 5433);
+
+    assert!(hydro.refresh().unwrap().is_empty());
+    env::set_var("REFRESHTEST_PG__PORT", "5434");
+    env::set_var("REFRESHTEST_REGION", "eu");
+    let changed = hydro.refresh().unwrap();
+    assert_eq!(changed.into_iter().collect::<Vec<_>>(), ["pg", "region"]);
+    assert_eq!(hydro.get_int("pg.port").unwrap(), 5434);
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "a");
+    assert!(hydro.get_bool("debug").unwrap());
+    assert_eq!(hydro.get_int("workers").unwrap(), 4);
+    assert_eq!(hydro.origins("debug"), &[Origin::Override]);
+
+    env::remove_var("REFRESHTEST_REGION");
+    let changed = hydro.refresh().unwrap();
+    assert_eq!(changed.into_iter().collect::<Vec<_>>(), ["region"]);
+
+    env::set_var("REFRESHTEST_PG__PORT", "5435");
+    let diff = hydro.refresh_diff().unwrap();
+    assert_eq!(diff.len(), 1);
+    assert!(diff.contains("pg"));
+    match diff.get("pg.port") {
+        Some(Change::Modified { old, new }) => {
+            assert_eq!(old.clone().into_int().unwrap(), 5434);
+            assert_eq!(new.clone().into_int().unwrap(), 5435);
+        }
+        change => panic!("unexpected change: {:?}", change),
+    }
+    env::remove_var("REFRESHTEST_PG__PORT");
+}
+
+#[test]
+fn test_trim_values() {
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path("5"))
+        .set_env("development".into())
+        .set_envvar_prefix("TRIMTEST".into());
+    let conf: Result<Config, ConfigError> =
+        Hydroconf::new(settings.clone()).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "localhost\n".into(),
+            port: 5432,
+            password: "  a password\n".into(),
+        },
+    });
+
+    let conf: Result<Config, ConfigError> =
+        Hydroconf::new(settings.set_trim_values(true)).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "localhost".into(),
+            port: 5432,
+            password: "a password".into(),
+        },
+    });
+}
+
+#[test]
+fn test_builder_with_inline() {
+    let conf: Result<Config, ConfigError> = Hydroconf::builder()
+        .env("production")
+        .envvar_prefix("BUILDERTEST")
+        .with_inline(
+            "[default]\npg.host = 'localhost'\npg.port = 5432\n\
+             [production]\npg.host = 'db-0'",
+            FileFormat::Toml,
+        )
+        .with_inline(
+            r#"{"production": {"pg": {"password": "a strong password"}}}"#,
+            FileFormat::Json,
+        )
+        .skip_discovery()
+        .build()
+        .hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-0".into(),
+            port: 5432,
+            password: "a strong password".into(),
+        },
+    });
+}
+
+#[test]
+fn test_env_normalization() {
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path(""))
+        .set_envvar_prefix("ENVNORMTEST".into())
+        .set_env(" Production ".into());
+    let conf: Result<Config, ConfigError> = Hydroconf::new(settings).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-0".into(),
+            port: 5432,
+            password: "a strong password".into(),
+        },
+    });
+
+    let conf: Result<Config, ConfigError> = Hydroconf::builder()
+        .env("STAGING")
+        .envvar_prefix("ENVNORMTEST")
+        .with_inline(
+            "[Default]\npg.host = 'localhost'\npg.port = 5432\n\
+             [Staging]\npg.password = 'a staging password'",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build()
+        .hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "localhost".into(),
+            port: 5432,
+            password: "a staging password".into(),
+        },
+    });
+}
+
+#[test]
+fn test_with_base_config() {
+    let mut base = hydroconf::Config::default();
+    base.set("pg.host", "base-host").unwrap();
+    base.set("pg.password", "a base password").unwrap();
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path(""))
+        .set_envvar_prefix("BASETEST".into())
+        .set_env("development".into());
+    let conf: Result<Config, ConfigError> = Hydroconf::new(settings)
+        .with_base_config(base.clone())
+        .hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "localhost".into(),
+            port: 5432,
+            password: "a password".into(),
+        },
+    });
+
+    base.set("pg.port", 6543).unwrap();
+    let conf: Result<Config, ConfigError> = Hydroconf::builder()
+        .envvar_prefix("BASETEST")
+        .base_config(base)
+        .skip_discovery()
+        .build()
+        .hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "base-host".into(),
+            port: 6543,
+            password: "a base password".into(),
+        },
+    });
+}
+
+#[test]
+fn test_explain() {
+    env::set_var("EXPLAINTEST_PG__PORT", "7777");
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path(""))
+        .set_envvar_prefix("EXPLAINTEST".into())
+        .set_env("production".into());
+    let mut hydro = Hydroconf::new(settings);
+    hydro.hydrate_in_place().unwrap();
+    env::remove_var("EXPLAINTEST_PG__PORT");
+
+    let data_path = get_data_path("");
+    assert_eq!(
+        hydro.origins("pg.host"),
+        &[
+            Origin::Settings(data_path.join("config/settings.toml")),
+            Origin::Settings(data_path.join("config/settings.toml")),
+        ],
+    );
+    assert_eq!(hydro.origins("pg.port").last(), Some(&Origin::Environment));
+
+    let settings_path = data_path.join("config/settings.toml");
+    let secrets_path = data_path.join("config/.secrets.toml");
+    assert_eq!(
+        hydro.explain(),
+        format!(
+            "KEY          VALUE     ORIGIN\n\
+             pg.host      \"db-0\"    settings ({})\n\
+             pg.password  ********  secrets ({})\n\
+             pg.port      \"7777\"    environment\n",
+            settings_path.display(),
+            secrets_path.display(),
+        ),
+    );
+}
+
+#[test]
+fn test_settings_file_not_utf8() {
+    let settings_path = get_data_path("6").join("settings.toml");
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path("6"))
+        .set_settings_file(settings_path.clone())
+        .set_envvar_prefix("UTF8TEST".into());
+    let conf: Result<Config, ConfigError> = Hydroconf::new(settings).hydrate();
+    match conf {
+        Err(ConfigError::Message(msg)) => assert_eq!(
+            msg,
+            format!(
+                "{} is not a valid UTF-8 text file",
+                settings_path.display(),
+            ),
+        ),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn test_secrets_win_over_env() {
+    env::set_var("SECRETWINTEST_PG__PASSWORD", "a leaked password");
+    env::set_var("SECRETWINTEST_PG__PORT", "1234");
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path(""))
+        .set_envvar_prefix("SECRETWINTEST".into())
+        .set_env("production".into());
+    let conf: Result<Config, ConfigError> =
+        Hydroconf::new(settings.clone()).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-0".into(),
+            port: 1234,
+            password: "a leaked password".into(),
+        },
+    });
+
+    let conf: Result<Config, ConfigError> =
+        Hydroconf::new(settings.set_secrets_win_over_env(true)).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-0".into(),
+            port: 1234,
+            password: "a strong password".into(),
+        },
+    });
+    env::remove_var("SECRETWINTEST_PG__PASSWORD");
+    env::remove_var("SECRETWINTEST_PG__PORT");
+}
+
+#[test]
+fn test_human_units() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct HttpConfig {
+        #[serde(deserialize_with = "hydroconf::deserialize_duration")]
+        timeout: Duration,
+        #[serde(deserialize_with = "hydroconf::deserialize_bytes")]
+        max_body: u64,
+        #[serde(deserialize_with = "hydroconf::deserialize_duration")]
+        keepalive: Duration,
+    }
+
+    let builder = Hydroconf::builder()
+        .envvar_prefix("UNITSTEST")
+        .with_inline(
+            "[default]\ntimeout = '1m30s'\nmax_body = '10MB'\nkeepalive = 75\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery();
+    let mut hydro = builder.build();
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_duration("timeout").unwrap(), Duration::from_secs(90));
+    assert_eq!(hydro.get_bytes("max_body").unwrap(), 10_000_000);
+    assert!(hydro.get_bytes("timeout").is_err());
+
+    let conf: HttpConfig = hydro.try_into().unwrap();
+    assert_eq!(conf, HttpConfig {
+        timeout: Duration::from_secs(90),
+        max_body: 10_000_000,
+        keepalive: Duration::from_secs(75),
+    });
+}
+
+#[test]
+fn test_namespaces() {
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path("7"))
+        .set_envvar_prefix("NAMESPACETEST".into())
+        .set_env("production".into());
+    let conf: Result<Config, ConfigError> =
+        Hydroconf::new(settings.clone().set_namespace("app1".into())).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-app1".into(),
+            port: 5432,
+            password: "app1 password".into(),
+        },
+    });
+
+    let conf: Result<Config, ConfigError> =
+        Hydroconf::new(settings.set_namespace("app2".into())).hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-app2".into(),
+            port: 6543,
+            password: "app2 strong password".into(),
+        },
+    });
+}
+
+#[test]
+fn test_add_directory() {
+    let mut hydro = Hydroconf::new(
+        HydroSettings::default()
+            .set_root_path(get_data_path("8"))
+            .set_envvar_prefix("DIRECTORYTEST".into())
+            .set_env("production".into()),
+    );
+    hydro.add_directory(get_data_path("8").join("configmap"));
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "db-k8s");
+    assert_eq!(hydro.get_str("pg.password").unwrap(), "k8s password");
+    assert_eq!(hydro.get_int("pg.port").unwrap(), 5432);
+    let origins = hydro.origins("pg.host");
+    assert_eq!(
+        origins.last().unwrap(),
+        &Origin::Source(format!(
+            "directory {}",
+            get_data_path("8").join("configmap").display()
+        ))
+    );
+
+    // The directory is read in the environment being hydrated.
+    let mut staging = hydro.for_env("staging").unwrap();
+    staging.hydrate_in_place().unwrap();
+    assert_eq!(staging.get_str("pg.host").unwrap(), "db-k8s");
+    assert_eq!(staging.get_str("pg.password").unwrap(), "k8s password");
+}
+
+#[test]
+fn test_add_secrets_directory() {
+    env::set_var("SECRETSDIRTEST_PG__PASSWORD", "env password");
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path("8"))
+        .set_envvar_prefix("SECRETSDIRTEST".into())
+        .set_env("development".into());
+    let mut hydro = Hydroconf::new(settings.clone());
+    hydro.add_secrets_directory(get_data_path("8").join("secrets"));
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("pg.password").unwrap(), "env password");
+    assert!(hydro.origins("pg.password")[1].is_secret());
+
+    let mut hydro = Hydroconf::new(settings.set_secrets_win_over_env(true));
+    hydro.add_secrets_directory(get_data_path("8").join("secrets"));
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("pg.password").unwrap(), "k8s secret password");
+    env::remove_var("SECRETSDIRTEST_PG__PASSWORD");
+}
+
+#[test]
+fn test_credentials_directory() {
+    let conf: Result<Config, ConfigError> = Hydroconf::new(
+        HydroSettings::default()
+            .set_root_path(get_data_path("8"))
+            .set_envvar_prefix("CREDENTIALSTEST".into())
+            .set_env("development".into())
+            .set_credentials_directory(get_data_path("8").join("secrets")),
+    )
+    .hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "localhost".into(),
+            port: 5432,
+            password: "k8s secret password".into(),
+        },
+    });
+}
+
+#[test]
+fn test_validators() {
+    let builder = || {
+        Hydroconf::builder()
+            .envvar_prefix("VALIDATORTEST")
+            .with_inline(
+                "[default]\nlog = 'trace'\n[default.pg]\nhost = ''\n",
+                FileFormat::Toml,
+            )
+            .skip_discovery()
+            .validator("pg.host", Validator::non_empty())
+            .validator("pg.password", Validator::must_exist())
+    };
+    let err = builder()
+        .validator("log", Validator::one_of(["debug", "info"]))
+        .build()
+        .hydrate_in_place()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid settings: pg.host must not be empty (from source #1); \
+         pg.password must be set; log must be one of debug, info (from \
+         source #1)"
+    );
+
+    env::set_var("VALIDATORTEST_PG__HOST", "db");
+    env::set_var("VALIDATORTEST_PG__PASSWORD", "secret");
+    let mut hydro = builder().build();
+    hydro.add_validator("log", |v: &hydroconf::Value| {
+        match v.clone().into_str() {
+            Ok(ref log) if log == "trace" => Ok(()),
+            _ => Err("must be trace".to_string()),
+        }
+    });
+    assert!(hydro.hydrate_in_place().is_ok());
+    env::remove_var("VALIDATORTEST_PG__HOST");
+    env::remove_var("VALIDATORTEST_PG__PASSWORD");
+}
+
+#[test]
+fn test_required() {
+    let settings = "required = ['pg.host', 'name']\n\
+                    [default]\nname = 'app'\n\
+                    [production.pg]\nhost = 'db'\npassword = 'a'\n\
+                    [staging.pg]\npassword = 'b'\n";
+    let hydro = |env: &str| {
+        Hydroconf::builder()
+            .env(env)
+            .envvar_prefix("REQUIREDTEST")
+            .with_inline(settings, FileFormat::Toml)
+            .skip_discovery()
+            .required(["pg.password", "pg.host", "pg.port"])
+            .build()
+    };
+    let err = hydro("development").hydrate_in_place().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "missing required settings: pg.password (set in production, \
+         staging); pg.host (set in production); pg.port (not set in any \
+         environment)"
+    );
+
+    env::set_var("REQUIREDTEST_PG__PORT", "5432");
+    assert!(hydro("production").hydrate_in_place().is_ok());
+    env::remove_var("REQUIREDTEST_PG__PORT");
+}
+
+#[test]
+fn test_unknown_keys() {
+    let hydro = |unknown_keys: UnknownKeys| {
+        Hydroconf::builder()
+            .envvar_prefix("UNKNOWNKEYSTEST")
+            .with_inline(
+                "[default]\ndebug = true\n\
+                 [default.pg]\nhost = 'db'\nport = 5432\nprot = 5433\n\
+                 password = ''\n",
+                FileFormat::Toml,
+            )
+            .skip_discovery()
+            .unknown_keys(unknown_keys)
+            .build()
+    };
+    assert!(hydro(UnknownKeys::Allow).hydrate::<Config>().is_ok());
+    let err = hydro(UnknownKeys::Deny).hydrate::<Config>().unwrap_err();
+    assert_eq!(err.to_string(), "unknown settings: debug, pg.prot");
+
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let mut hydro = hydro(UnknownKeys::Warn);
+    let recorded = Arc::clone(&warnings);
+    hydro.on_warning(move |w| recorded.lock().unwrap().push(w.to_string()));
+    assert_eq!(hydro.hydrate::<Config>().unwrap().pg.port, 5432);
+    assert_eq!(
+        *warnings.lock().unwrap(),
+        [
+            "unknown setting debug, ignored",
+            "unknown setting pg.prot, ignored"
+        ]
+    );
+}
+
+#[derive(Debug, Deserialize)]
+struct TlsConfig {
+    tls: Tls,
+}
+
+#[derive(Debug, Deserialize)]
+struct Tls {
+    cert: Option<String>,
+    key: Option<String>,
+    port: u16,
+}
+
+#[test]
+fn test_invariants() {
+    let hydro = |settings: &str| {
+        let mut hydro = Hydroconf::builder()
+            .envvar_prefix("INVARIANTTEST")
+            .with_inline(settings, FileFormat::Toml)
+            .skip_discovery()
+            .build();
+        hydro.add_invariant(|c: &TlsConfig| {
+            let mut errors = Vec::new();
+            if c.tls.cert.is_some() && c.tls.key.is_none() {
+                errors.push("tls.cert requires tls.key".to_string());
+            }
+            if c.tls.cert.is_none() && c.tls.port == 443 {
+                errors.push("tls.port 443 requires tls.cert".to_string());
+            }
+            if errors.is_empty() {
+                Ok(())
+            } else {
+                Err(errors)
+            }
+        });
+        hydro
+    };
+    let err = hydro("[default.tls]\nport = 443\n")
+        .hydrate::<TlsConfig>()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid settings: tls.port 443 requires tls.cert"
+    );
+
+    let err = hydro("[default.tls]\ncert = 'a.pem'\nport = 443\n")
+        .hydrate::<TlsConfig>()
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "invalid settings: tls.cert requires tls.key"
+    );
+
+    let conf = hydro("[default.tls]\ncert = 'a.pem'\nkey = 'a.key'\nport = 443\n")
+        .hydrate::<TlsConfig>()
+        .unwrap();
+    assert_eq!(conf.tls.key.as_deref(), Some("a.key"));
+
+    // An invariant of another type than the settings isn't skipped.
+    let mut hydro = hydro("[default.tls]\nport = 80\n");
+    hydro.add_invariant(|_: &Config| Ok(()));
+    let err = hydro.hydrate::<TlsConfig>().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "an invariant checks hydration::Config, not the hydrated \
+         hydration::TlsConfig"
+    );
+}
+
+#[test]
+fn test_require_env() {
+    let hydro = |env: &str| {
+        Hydroconf::builder()
+            .envvar_prefix("REQUIREENVTEST")
+            .env(env)
+            .require_env(true)
+            .with_inline(
+                "[default]\nport = 80\n[production]\nport = 443\n",
+                FileFormat::Toml,
+            )
+            .skip_discovery()
+            .build()
+    };
+    let mut hydro_ok = hydro("Production");
+    hydro_ok.hydrate_in_place().unwrap();
+    assert_eq!(hydro_ok.get_int("port").unwrap(), 443);
+
+    let err = hydro("prodcution").hydrate_in_place().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "the settings have no [prodcution] table for the environment \
+         prodcution"
+    );
+}
+
+#[test]
+fn test_secret_keys() {
+    let settings = HydroSettings::default()
+        .set_root_path(get_data_path("4"))
+        .set_envvar_prefix("SECRETKEYSTEST".into())
+        .set_secret_keys(vec!["*.password".into(), "api_token".into()]);
+    let err = Hydroconf::new(settings).hydrate_in_place().unwrap_err();
+    let settings_path = get_data_path("4").join("config/settings.toml");
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "secret settings outside of the secrets: \
+             api_token (from settings ({0})); \
+             pg.Password (from settings ({0}))",
+            settings_path.display(),
+        )
+    );
+
+    let dir = TempDir::new("secret-keys");
+    std::fs::create_dir_all(dir.join("config")).unwrap();
+    std::fs::write(
+        dir.join("config/settings.toml"),
+        "[default]\npg.host = 'localhost'\n",
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("config/.secrets.toml"),
+        "[default]\npg.password = 'a password'\n",
+    )
+    .unwrap();
+    let hydro = || {
+        Hydroconf::builder()
+            .root_path(dir.to_path_buf())
+            .envvar_prefix("SECRETKEYSTEST")
+            .secret_keys(["pg.*"])
+            .build()
+    };
+    assert!(hydro().hydrate_in_place().is_err());
+
+    let hydro = || {
+        Hydroconf::builder()
+            .root_path(dir.to_path_buf())
+            .envvar_prefix("SECRETKEYSTEST")
+            .secret_keys(["*.password"])
+            .build()
+    };
+    let mut hydro_ok = hydro();
+    hydro_ok.hydrate_in_place().unwrap();
+    assert_eq!(hydro_ok.get_str("pg.password").unwrap(), "a password");
+
+    std::fs::write(
+        dir.join(".env"),
+        "SECRETKEYSTEST_PG__PASSWORD=dotenv\n",
+    )
+    .unwrap();
+    let err = hydro().hydrate_in_place().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "secret settings outside of the secrets: \
+             pg.password (from dotenv ({}))",
+            dir.join(".env").display(),
+        )
+    );
+}
+
+#[test]
+fn test_allowed_envs() {
+    let hydro = |env: &str| {
+        Hydroconf::builder()
+            .envvar_prefix("ALLOWEDENVSTEST")
+            .env(env)
+            .allowed_envs(["development", "Production"])
+            .skip_discovery()
+            .build()
+    };
+    assert!(hydro(" production ").hydrate_in_place().is_ok());
+    let err = hydro("prodcution").hydrate_in_place().unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "unknown environment prodcution (expected one of development, \
+         production)"
+    );
+}
+
+#[test]
+fn test_shadowed() {
+    env::set_var("SHADOWEDTEST_PG__PORT", "7777");
+    let warnings = Arc::new(Mutex::new(Vec::new()));
+    let sink = warnings.clone();
+    let mut hydro = Hydroconf::builder()
+        .root_path(get_data_path(""))
+        .envvar_prefix("SHADOWEDTEST")
+        .env("production")
+        .warn_shadowed(true)
+        .build();
+    hydro.on_warning(move |w| sink.lock().unwrap().push(w.to_string()));
+    hydro.set_default("pg.host", "default").unwrap();
+    hydro.hydrate_in_place().unwrap();
+    env::remove_var("SHADOWEDTEST_PG__PORT");
+
+    let settings_path = get_data_path("").join("config/settings.toml");
+    // The default table and the production one of the same file, and the
+    // value given to `set_default`, aren't reported.
+    assert_eq!(
+        hydro.shadowed(),
+        vec![(
+            "pg.port".to_string(),
+            vec![Origin::Settings(settings_path.clone())],
+            Origin::Environment,
+        )],
+    );
+    assert_eq!(
+        *warnings.lock().unwrap(),
+        vec![format!(
+            "pg.port from settings ({}) is overridden by environment",
+            settings_path.display(),
+        )],
+    );
+}
+
+#[test]
+fn test_interpolation() {
+    let dir = get_data_path("12");
+    env::set_var("INTERPOLATIONTEST_USER", "app");
+    let hydro = |strict: bool| {
+        Hydroconf::builder()
+            .root_path(dir.clone())
+            .envvar_prefix("INTERPOLATIONTEST")
+            .env("production")
+            .strict_interpolation(strict)
+            .build()
+    };
+    let mut hydro_ok = hydro(false);
+    hydro_ok.hydrate_in_place().unwrap();
+    assert_eq!(hydro_ok.get_str("url").unwrap(), "postgres://app@db:5432");
+    assert_eq!(
+        hydro_ok.get::<Vec<String>>("hosts").unwrap(),
+        vec!["app.local", "${HOME}"]
+    );
+    // Kept as it is, like the values that aren't references.
+    assert_eq!(
+        hydro_ok.get_str("token").unwrap(),
+        "${INTERPOLATIONTEST_TOKEN}"
+    );
+
+    let err = hydro(true).hydrate_in_place().unwrap_err();
+    env::remove_var("INTERPOLATIONTEST_USER");
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "undefined environment variable INTERPOLATIONTEST_TOKEN in \
+             production.token (settings ({}))",
+            dir.join("config/settings.toml").display(),
+        )
+    );
+}
+
+#[test]
+fn test_interpolation_malformed() {
+    let dir = get_data_path("13");
+    let hydro = |strict: bool| {
+        let mut hydro = Hydroconf::builder()
+            .root_path(dir.clone())
+            .envvar_prefix("MALFORMEDTEST")
+            .strict_interpolation(strict)
+            .build();
+        hydro.hydrate_in_place().map(|hydro| {
+            (hydro.get_str("password").unwrap(), hydro.get_str("dsn").unwrap())
+        })
+    };
+    let values = hydro(false);
+    let err = hydro(true).unwrap_err();
+    assert_eq!(values.unwrap(), ("pa${ss".into(), "${pg.host".into()));
+    assert!(err.to_string().starts_with("unclosed ${ in "));
+}
+
+#[test]
+fn test_key_references() {
+    env::set_var("KEYREFTEST_REDIS__HOST", "cache-0");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("KEYREFTEST")
+        .with_inline(
+            "[default]\n\
+             redis = { host = 'localhost', port = 6379 }\n\
+             redis_url = 'redis://${redis.host}:${redis.port}'\n\
+             port = '${redis.port}'\n\
+             literal = '$${redis.host}'\n\
+             formatted = '@format {this.redis.host}/{this.port}'\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    hydro.hydrate_in_place().unwrap();
+    env::remove_var("KEYREFTEST_REDIS__HOST");
+    // Resolved with the value of the upper layer.
+    assert_eq!(hydro.get_str("redis_url").unwrap(), "redis://cache-0:6379");
+    assert_eq!(hydro.get_int("port").unwrap(), 6379);
+    assert_eq!(hydro.get_str("literal").unwrap(), "${redis.host}");
+    assert_eq!(hydro.get_str("formatted").unwrap(), "cache-0/6379");
+
+    let err = Hydroconf::builder()
+        .envvar_prefix("KEYREFTEST")
+        .with_inline("[default]\nurl = '${redis.hots}'\n", FileFormat::Toml)
+        .skip_discovery()
+        .build()
+        .hydrate_in_place()
+        .unwrap_err();
+    assert_eq!(err.to_string(), "undefined key redis.hots in url");
+}
+
+#[test]
+fn test_key_references_literal_layers() {
+    // The environment variables and the secrets aren't resolved.
+    env::set_var("LITERALREFTEST_PG__PASSWORD", "ab${cd");
+    env::set_var("LITERALREFTEST_API__TOKEN", "$${x}-${x.y}");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("LITERALREFTEST")
+        .with_inline(
+            "[default]\n\
+             pg = { host = 'db', password = '' }\n\
+             dsn = 'postgres://${pg.host}/${pg.password}'\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    hydro.add_secrets_source(config::File::from_str(
+        "[default]\nredis = { password = 'p$${q}${r.s}' }\n",
+        FileFormat::Toml,
+    ));
+    let result = hydro.hydrate_in_place().map(drop);
+    env::remove_var("LITERALREFTEST_PG__PASSWORD");
+    env::remove_var("LITERALREFTEST_API__TOKEN");
+    result.unwrap();
+    assert_eq!(hydro.get_str("pg.password").unwrap(), "ab${cd");
+    assert_eq!(hydro.get_str("api.token").unwrap(), "$${x}-${x.y}");
+    assert_eq!(hydro.get_str("redis.password").unwrap(), "p$${q}${r.s}");
+    // A settings value referencing them gets them as they are.
+    assert_eq!(hydro.get_str("dsn").unwrap(), "postgres://db/ab${cd");
+}
+
+#[test]
+fn test_cast_tokens() {
+    env::set_var("CASTTEST_PORT", "@int 5432");
+    env::set_var("CASTTEST_DEBUG", "@bool true");
+    env::set_var("CASTTEST_RATIO", "@float 0.25");
+    env::set_var("CASTTEST_HOSTS", r#"@json ["a", "b"]"#);
+    env::set_var("CASTTEST_NAME", "@str @int");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("CASTTEST")
+        .skip_discovery()
+        .build();
+    hydro.hydrate_in_place().unwrap();
+    let json = |key| hydro.get::<serde_json::Value>(key).unwrap();
+    assert_eq!(json("port"), serde_json::json!(5432));
+    assert_eq!(json("debug"), serde_json::json!(true));
+    assert_eq!(json("ratio"), serde_json::json!(0.25));
+    assert_eq!(json("hosts"), serde_json::json!(["a", "b"]));
+    assert_eq!(json("name"), serde_json::json!("@int"));
+
+    env::set_var("CASTTEST_PORT", "@int 5432x");
+    let err = Hydroconf::builder()
+        .envvar_prefix("CASTTEST")
+        .skip_discovery()
+        .build()
+        .hydrate_in_place()
+        .unwrap_err();
+    for name in ["PORT", "DEBUG", "RATIO", "HOSTS", "NAME"] {
+        env::remove_var(format!("CASTTEST_{}", name));
+    }
+    assert_eq!(
+        err.to_string(),
+        "invalid @int value \"5432x\" in port (environment)"
+    );
+}
+
+#[test]
+fn test_file_values() {
+    let dir = get_data_path("14");
+    env::set_var(
+        "FILEVALUESTEST_PG__PASSWORD_FILE",
+        dir.join("pg_password").into_os_string(),
+    );
+    env::set_var("FILEVALUESTEST_LOG_FILE", "/var/log/app.log");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("FILEVALUESTEST")
+        .with_inline(
+            &format!(
+                "[default]\ntoken = '@file:{}'\nlog_file = 'app.log'\n\
+                 pg = {{ password = '' }}\n",
+                dir.join("token").display(),
+            ),
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    hydro.hydrate_in_place().unwrap();
+    env::remove_var("FILEVALUESTEST_PG__PASSWORD_FILE");
+    env::remove_var("FILEVALUESTEST_LOG_FILE");
+    assert_eq!(hydro.get_str("pg.password").unwrap(), "a password");
+    assert_eq!(hydro.get_str("token").unwrap(), "a token");
+    // A setting of the files, not a path.
+    assert_eq!(hydro.get_str("log_file").unwrap(), "/var/log/app.log");
+    assert!(hydro.get_str("log").is_err());
+}
+
+#[test]
+fn test_plain_file_env_var() {
+    // Without a `log` setting, `_FILE` isn't a path to read.
+    env::set_var("PLAINFILETEST_LOG_FILE", "/var/log/app.log");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("PLAINFILETEST")
+        .with_inline("[default]\nport = 5432\n", FileFormat::Toml)
+        .skip_discovery()
+        .build();
+    let result = hydro.hydrate_in_place().map(drop);
+    env::remove_var("PLAINFILETEST_LOG_FILE");
+    result.unwrap();
+    assert_eq!(hydro.get_str("log_file").unwrap(), "/var/log/app.log");
+    assert!(hydro.get_str("log").is_err());
+}
+
+#[cfg(feature = "minijinja")]
+#[test]
+fn test_templates() {
+    env::set_var("TEMPLATESTEST_HOST", "db-0");
+    let mut hydro = Hydroconf::builder()
+        .root_path(get_data_path("15"))
+        .envvar_prefix("TEMPLATESTEST")
+        .env("production")
+        .build();
+    let result = hydro.hydrate_in_place();
+    env::remove_var("TEMPLATESTEST_HOST");
+    result.unwrap();
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "db-0");
+    assert!(!hydro.get_bool("debug").unwrap());
+    assert_eq!(hydro.get_int("workers").unwrap(), 4);
+}
+
+#[test]
+fn test_merge_markers() {
+    env::set_var("MERGEMARKERSTEST_PLUGINS", "@merge z");
+    env::set_var("MERGEMARKERSTEST_PG", "@merge port=5433");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("MERGEMARKERSTEST")
+        .env("production")
+        .with_inline(
+            "[default]\n\
+             hosts = ['a', 'b']\n\
+             plugins = ['x']\n\
+             pg = { host = 'localhost', port = 5432 }\n\
+             cache = { ttl = 60, size = 100 }\n\
+             [production]\n\
+             hosts = ['dynaconf_merge', 'c']\n\
+             plugins = '@merge y'\n\
+             cache = { dynaconf_merge = false, ttl = 5 }\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    let result = hydro.hydrate_in_place().map(|_| ());
+    env::remove_var("MERGEMARKERSTEST_PLUGINS");
+    env::remove_var("MERGEMARKERSTEST_PG");
+    result.unwrap();
+    assert_eq!(
+        hydro.get::<Vec<String>>("hosts").unwrap(),
+        vec!["a", "b", "c"]
+    );
+    assert_eq!(
+        hydro.get::<Vec<String>>("plugins").unwrap(),
+        vec!["x", "y", "z"]
+    );
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "localhost");
+    assert_eq!(hydro.get_int("pg.port").unwrap(), 5433);
+    assert_eq!(hydro.get_int("cache.ttl").unwrap(), 5);
+    assert!(hydro.get_int("cache.size").is_err());
+    assert!(hydro.get_bool("cache.dynaconf_merge").is_err());
+}
+
+#[test]
+fn test_merge_markers_keep_overrides() {
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("MARKEROVERRIDESTEST")
+        .env("production")
+        .with_inline(
+            "[default]\n\
+             region = 'eu'\n\
+             cache = { ttl = 60, size = 100 }\n\
+             [production]\n\
+             cache = { dynaconf_merge = false, ttl = 5 }\n\
+             region = 'us'\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    hydro.set("region", "local").unwrap();
+    hydro.set("cache.size", 10).unwrap();
+    hydro.hydrate_in_place().unwrap();
+    // The values given to `set()` still win after the marker.
+    assert_eq!(hydro.get_str("region").unwrap(), "local");
+    assert_eq!(hydro.get_int("cache.size").unwrap(), 10);
+    assert_eq!(hydro.get_int("cache.ttl").unwrap(), 5);
+}
+
+#[test]
+fn test_resolved_values_keep_layers() {
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("RESOLVEDLAYERSTEST")
+        .with_inline(
+            "[default]\n\
+             net.region = 'eu'\n\
+             net.host = 'db.${net.region}.example.com'\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    hydro.set_default("net.timeout", 30).unwrap();
+    hydro.set("net.region", "local").unwrap();
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("net.region").unwrap(), "local");
+    assert_eq!(hydro.get_str("net.host").unwrap(), "db.local.example.com");
+    // Resolving the references leaves the other values in their layers:
+    // the default is still a default, and the override still wins.
+    hydro.set_default("net.timeout", 10).unwrap();
+    hydro.set_default("net.region", "default").unwrap();
+    assert_eq!(hydro.get_int("net.timeout").unwrap(), 10);
+    assert_eq!(hydro.get_str("net.region").unwrap(), "local");
+}
+
+#[cfg(feature = "encryption")]
+#[test]
+fn test_encrypted_values() {
+    let key = hydroconf::generate_key();
+    let password = hydroconf::encrypt_value(&key, "a password").unwrap();
+    env::set_var("DECRYPT_KEY_FOR_HYDRO", &key);
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("ENCRYPTEDVALUESTEST")
+        .with_inline(
+            &format!(
+                "[default]\npg.host = 'db'\npg.password = '{}'\n",
+                password
+            ),
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    let result = hydro.hydrate_in_place().map(|_| ());
+    env::remove_var("DECRYPT_KEY_FOR_HYDRO");
+    result.unwrap();
+    assert_eq!(hydro.get_str("pg.password").unwrap(), "a password");
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "db");
+}
+
+#[test]
+fn test_base64_values() {
+    env::set_var("BASE64VALUESTEST_TLS__KEY", "@base64 AAH/");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("BASE64VALUESTEST")
+        .with_inline(
+            "[default]\ntls.cert = '@base64 LS0tLS1CRUdJTiBDRVJUSUZJQ0FURS0tLS0t'\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    let result = hydro.hydrate_in_place().map(|_| ());
+    env::remove_var("BASE64VALUESTEST_TLS__KEY");
+    result.unwrap();
+    assert_eq!(
+        hydro.get_str("tls.cert").unwrap(),
+        "-----BEGIN CERTIFICATE-----"
+    );
+    assert_eq!(hydro.get::<Vec<u8>>("tls.key").unwrap(), vec![0, 1, 255]);
+}
+
+#[test]
+fn test_path_keys() {
+    let dir = get_data_path("16");
+    env::set_var("PATHKEYSTEST_PLUGINS__DIRS", "@json [\"c\"]");
+    let mut hydro = Hydroconf::builder()
+        .root_path(dir.clone())
+        .envvar_prefix("PATHKEYSTEST")
+        .path_keys(["tls.*", "*.dirs"])
+        .build();
+    let result = hydro.hydrate_in_place().map(|_| ());
+    env::remove_var("PATHKEYSTEST_PLUGINS__DIRS");
+    result.unwrap();
+    assert_eq!(
+        hydro.get_str("tls.cert").unwrap(),
+        dir.join("config/certs/server.pem").to_string_lossy()
+    );
+    assert_eq!(hydro.get_str("tls.ca").unwrap(), "/etc/ssl/ca.pem");
+    assert_eq!(
+        hydro.get_str("tls.key").unwrap(),
+        dir.join("server.key").to_string_lossy()
+    );
+    // Set by an environment variable, relative to the working directory.
+    assert_eq!(hydro.get::<Vec<String>>("plugins.dirs").unwrap(), vec!["c"]);
+    assert_eq!(hydro.get_str("log").unwrap(), "app.log");
+}
+
+#[test]
+fn test_generated_values() {
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("GENERATEDVALUESTEST")
+        .with_inline(
+            "[default]\n\
+             worker.id = '@uuid'\n\
+             worker.name = 'worker-${worker.id}'\n\
+             secret_key = '@random:50'\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    hydro.hydrate_in_place().unwrap();
+    let id = hydro.get_str("worker.id").unwrap();
+    assert_eq!(id.len(), 36);
+    assert_eq!(
+        hydro.get_str("worker.name").unwrap(),
+        format!("worker-{}", id)
+    );
+    let secret_key = hydro.get_str("secret_key").unwrap();
+    assert_eq!(secret_key.len(), 50);
+    assert_ne!(secret_key, "@random:50");
+}
+
+#[test]
+fn test_env_inheritance() {
+    let settings = "[default]\n\
+                    debug = true\n\
+                    pg.port = 5432\n\
+                    [production]\n\
+                    debug = false\n\
+                    pg.host = 'db.prod'\n\
+                    pg.pool_size = 20\n\
+                    [staging]\n\
+                    _inherit = 'production'\n\
+                    pg.host = 'db.staging'\n\
+                    [review]\n\
+                    _inherit = 'staging'\n\
+                    pg.pool_size = 2\n\
+                    [a]\n\
+                    _inherit = 'b'\n\
+                    [b]\n\
+                    _inherit = 'a'\n\
+                    [c]\n\
+                    _inherit = 'd'\n";
+    let hydro = |env: &str| {
+        let mut hydro = Hydroconf::builder()
+            .envvar_prefix("ENVINHERITANCETEST")
+            .env(env)
+            .with_inline(settings, FileFormat::Toml)
+            .skip_discovery()
+            .build();
+        hydro.hydrate_in_place()?;
+        Ok::<_, ConfigError>(hydro)
+    };
+    let review = hydro("review").unwrap();
+    assert!(!review.get_bool("debug").unwrap());
+    assert_eq!(review.get_int("pg.port").unwrap(), 5432);
+    assert_eq!(review.get_str("pg.host").unwrap(), "db.staging");
+    assert_eq!(review.get_int("pg.pool_size").unwrap(), 2);
+    assert!(review.get_str("_inherit").is_err());
+    let staging = hydro("staging").unwrap();
+    assert_eq!(staging.get_int("pg.pool_size").unwrap(), 20);
+
+    assert_eq!(
+        hydro("a").unwrap_err().to_string(),
+        "circular environment inheritance a -> b -> a"
+    );
+    assert_eq!(
+        hydro("c").unwrap_err().to_string(),
+        "[c] inherits from d, which has no table"
+    );
+}
+
+#[test]
+fn test_global_env() {
+    env::set_var("GLOBALENVTEST_NAME", "from env");
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("GLOBALENVTEST")
+        .env("production")
+        .with_inline(
+            "[default]\n\
+             region = 'us'\n\
+             name = 'default'\n\
+             [production]\n\
+             region = 'eu'\n\
+             debug = false\n\
+             [global]\n\
+             region = 'global'\n\
+             name = 'global'\n",
+            FileFormat::Toml,
+        )
+        .skip_discovery()
+        .build();
+    let result = hydro.hydrate_in_place().map(|_| ());
+    env::remove_var("GLOBALENVTEST_NAME");
+    result.unwrap();
+    assert_eq!(hydro.get_str("region").unwrap(), "global");
+    assert_eq!(hydro.get_str("name").unwrap(), "from env");
+    assert!(!hydro.get_bool("debug").unwrap());
+}
+
+#[test]
+fn test_multiple_envs() {
+    let settings = "[default]\n\
+                    region = 'us'\n\
+                    debug = true\n\
+                    pg.host = 'localhost'\n\
+                    [eu]\n\
+                    region = 'eu'\n\
+                    pg.host = 'db.eu'\n\
+                    [production]\n\
+                    debug = false\n\
+                    pg.pool_size = 20\n";
+    let hydro = |env: &str| {
+        let mut hydro = Hydroconf::builder()
+            .envvar_prefix("MULTIPLEENVSTEST")
+            .env(env)
+            .require_env(true)
+            .allowed_envs(["eu", "production", "default"])
+            .with_inline(settings, FileFormat::Toml)
+            .skip_discovery()
+            .build();
+        hydro.hydrate_in_place()?;
+        Ok::<_, ConfigError>(hydro)
+    };
+    let hydro_ok = hydro(" Default, EU ,production").unwrap();
+    assert_eq!(hydro_ok.get_str("region").unwrap(), "eu");
+    assert_eq!(hydro_ok.get_str("pg.host").unwrap(), "db.eu");
+    assert!(!hydro_ok.get_bool("debug").unwrap());
+    assert_eq!(hydro_ok.get_int("pg.pool_size").unwrap(), 20);
+
+    assert_eq!(
+        hydro("eu,staging").unwrap_err().to_string(),
+        "unknown environment staging (expected one of eu, production, \
+         default)"
+    );
+}
+
+#[test]
+fn test_list_merge() {
+    let settings = "[default]\n\
+                    hosts = ['a', 'b']\n\
+                    plugins = ['x']\n\
+                    [production]\n\
+                    hosts = ['b', 'c']\n\
+                    plugins = ['y']\n";
+    let hydro = |builder: HydroconfBuilder| {
+        let mut hydro = builder
+            .envvar_prefix("LISTMERGETEST")
+            .env("production")
+            .with_inline(settings, FileFormat::Toml)
+            .skip_discovery()
+            .build();
+        hydro.hydrate_in_place().unwrap();
+        (
+            hydro.get::<Vec<String>>("hosts").unwrap(),
+            hydro.get::<Vec<String>>("plugins").unwrap(),
+        )
+    };
+    assert_eq!(
+        hydro(Hydroconf::builder()),
+        (vec!["b".into(), "c".into()], vec!["y".into()])
+    );
+    assert_eq!(
+        hydro(
+            Hydroconf::builder()
+                .list_merge(ListMerge::Unique)
+                .list_merge_for_key("plugins", ListMerge::Prepend)
+        ),
+        (
+            vec!["a".into(), "b".into(), "c".into()],
+            vec!["y".into(), "x".into()]
+        )
+    );
+}
+
+#[test]
+fn test_shallow_merge() {
+    let settings = "[default]\n\
+                    pg = { host = 'db', port = 5432 }\n\
+                    redis = { host = 'cache', port = 6379 }\n\
+                    [production]\n\
+                    pg = { port = 5433 }\n\
+                    redis = { _replace = false, port = 6380 }\n";
+    let hydro = |builder: HydroconfBuilder| {
+        let mut hydro = builder
+            .envvar_prefix("SHALLOWMERGETEST")
+            .env("production")
+            .with_inline(settings, FileFormat::Toml)
+            .skip_discovery()
+            .build();
+        hydro.hydrate_in_place().unwrap();
+        (
+            hydro.get::<String>("pg.host").ok(),
+            hydro.get::<String>("redis.host").ok(),
+        )
+    };
+    assert_eq!(
+        hydro(Hydroconf::builder()),
+        (Some("db".into()), Some("cache".into()))
+    );
+    assert_eq!(
+        hydro(Hydroconf::builder().shallow_merge(true)),
+        (None, Some("cache".into()))
+    );
+}
+
+#[test]
+fn test_settings_fragments() {
+    let mut hydro = Hydroconf::new(
+        HydroSettings::default()
+            .set_root_path(get_data_path("9"))
+            .set_envvar_prefix("FRAGMENTSTEST".into())
+            .set_env("development".into()),
+    );
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "db-0");
+    assert_eq!(hydro.get_int("pg.port").unwrap(), 5434);
+    assert_eq!(hydro.get_str("pg.password").unwrap(), "a password");
+    let fragment = get_data_path("9").join("config/settings.d/20-pg.yaml");
+    assert_eq!(
+        hydro.origins("pg.port").last().unwrap(),
+        &Origin::Settings(fragment)
+    );
+}
+
+#[test]
+fn test_include() {
+    let mut hydro = Hydroconf::new(
+        HydroSettings::default()
+            .set_root_path(get_data_path("10"))
+            .set_envvar_prefix("INCLUDETEST".into())
+            .set_env("production".into()),
+    );
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("log_level").unwrap(), "warn");
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "localhost");
+    assert_eq!(hydro.get_str("pg.password").unwrap(), "a password");
+    assert_eq!(hydro.get_str("replica.host").unwrap(), "db-1");
+    assert!(hydro.get_str("include").is_err());
+    let replica = get_data_path("10").join("config/db/replica.toml");
+    assert_eq!(
+        hydro.origins("replica.host").last().unwrap(),
+        &Origin::Settings(replica)
+    );
+
+    let dir = get_data_path("17");
+    let error = Hydroconf::new(
+        HydroSettings::default()
+            .set_settings_file(dir.join("settings.toml"))
+            .set_envvar_prefix("INCLUDETEST".into()),
+    )
+    .hydrate::<serde_json::Value>()
+    .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "circular include {} -> {} -> {}",
+            dir.join("settings.toml").display(),
+            dir.join("other.toml").display(),
+            dir.join("settings.toml").display()
+        )
+    );
+}
+
+#[test]
+fn test_settings_files() {
+    let conf: Result<Config, ConfigError> = Hydroconf::builder()
+        .root_path(get_data_path(""))
+        .add_settings_file(get_data_path("11").join("service.toml"))
+        .envvar_prefix("SETTINGSFILESTEST")
+        .env("production")
+        .build()
+        .hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-service".into(),
+            port: 5433,
+            password: "a strong password".into(),
+        },
+    });
+}
+
+#[test]
+fn test_layer_order() {
+    env::set_var("LAYERORDERTEST_PG__HOST", "env-host");
+    env::set_var("LAYERORDERTEST_PG__PASSWORD", "env password");
+    let hydro = |builder: HydroconfBuilder| {
+        let mut hydro = builder
+            .root_path(get_data_path(""))
+            .envvar_prefix("LAYERORDERTEST")
+            .env("production")
+            .build();
+        hydro.hydrate_in_place()?;
+        Ok::<_, ConfigError>((
+            hydro.get_str("pg.host")?,
+            hydro.get_str("pg.password")?,
+        ))
+    };
+    assert_eq!(
+        hydro(Hydroconf::builder()).unwrap(),
+        ("env-host".into(), "env password".into())
+    );
+    let files_win = [Layer::Dotenv, Layer::Env, Layer::Settings, Layer::Secrets];
+    assert_eq!(
+        hydro(Hydroconf::builder().layer_order(files_win)).unwrap(),
+        ("db-0".into(), "a strong password".into())
+    );
+    let secrets_win = [Layer::Settings, Layer::Env, Layer::Secrets];
+    assert_eq!(
+        hydro(Hydroconf::builder().layer_order(secrets_win))
+            .unwrap_err()
+            .to_string(),
+        "the layer order must list settings, secrets, dotenv and env once, \
+         got [Settings, Env, Secrets]"
+    );
+    env::remove_var("LAYERORDERTEST_PG__HOST");
+    env::remove_var("LAYERORDERTEST_PG__PASSWORD");
+}
+
+struct Uppercase;
+
+impl Stage for Uppercase {
+    fn name(&self) -> &str {
+        "uppercase"
+    }
+
+    fn run(&self, hydro: &mut Hydroconf) -> Result<(), ConfigError> {
+        let host = hydro.get_str("pg.host")?.to_uppercase();
+        hydro.set("pg.host", host)?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_stages() {
+    env::set_var("STAGESTEST_PG__PORT", "5433");
+    let settings = "[default]\npg.host = 'db'\npg.port = 5432\n";
+    let mut hydro = Hydroconf::builder()
+        .envvar_prefix("STAGESTEST")
+        .env("development")
+        .with_inline(settings, FileFormat::Toml)
+        .skip_discovery()
+        .build();
+    let names = hydro.stage_names().unwrap();
+    let position = |name| names.iter().position(|n| n == name).unwrap();
+    assert_eq!(position("check_env"), 0);
+    assert!(position("load_settings") < position("merge_settings"));
+    assert!(position("override_from_dotenv") < position("override_from_env"));
+    assert_eq!(names.last().unwrap(), "validate");
+    hydro
+        .remove_stage("override_from_env")
+        .unwrap()
+        .insert_stage_before("validate", Uppercase)
+        .unwrap();
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "DB");
+    assert_eq!(hydro.get_int("pg.port").unwrap(), 5432);
+    assert_eq!(
+        hydro
+            .insert_stage_after("validate", Uppercase)
+            .unwrap_err()
+            .to_string(),
+        "there's already a stage named uppercase in the pipeline"
+    );
+    assert_eq!(
+        hydro.remove_stage("override_from_env").unwrap_err().to_string(),
+        "there's no stage named override_from_env in the pipeline"
+    );
+    env::remove_var("STAGESTEST_PG__PORT");
+}
+
+#[test]
+fn test_flat_files() {
+    env::set_var("FLATFILESTEST_PG__PORT", "5433");
+    let conf: Result<Config, ConfigError> = Hydroconf::builder()
+        .root_path(get_data_path("18"))
+        .envvar_prefix("FLATFILESTEST")
+        .env("production")
+        .flat_files(true)
+        .build()
+        .hydrate();
+    assert_eq!(conf.unwrap(), Config {
+        pg: PostgresConfig {
+            host: "db-0".into(),
+            port: 5433,
+            password: "a password".into(),
+        },
+    });
+    env::remove_var("FLATFILESTEST_PG__PORT");
+}
+
+#[test]
+fn test_for_env() {
+    let hydro = Hydroconf::builder()
+        .root_path(get_data_path(""))
+        .envvar_prefix("FORENVTEST")
+        .env("development")
+        .build();
+    let mut production = hydro.for_env("production").unwrap();
+    production.hydrate_in_place().unwrap();
+    assert_eq!(production.get_str("pg.host").unwrap(), "db-0");
+
+    let mut development = hydro.for_env("development").unwrap();
+    development.set("pg.port", 6543).unwrap();
+    development.hydrate_in_place().unwrap();
+    assert_eq!(development.get_str("pg.host").unwrap(), "localhost");
+    let mut production = development.with_env("Production").unwrap();
+    production.hydrate_in_place().unwrap();
+    assert_eq!(production.get_str("pg.host").unwrap(), "db-0");
+    assert_eq!(production.get_int("pg.port").unwrap(), 6543);
+    assert_eq!(
+        production.get_str("pg.password").unwrap(),
+        "a strong password"
+    );
+}
+
+#[test]
+fn test_tenants() {
+    let settings = "[default]\n\
+                    [default.pg]\n\
+                    host = 'localhost'\n\
+                    port = 5432\n\
+                    password = ''\n\
+                    [default.tenants.acme]\n\
+                    pg = { port = 6432 }\n\
+                    [production]\n\
+                    pg = { host = 'db-0' }\n\
+                    [production.tenants.ACME]\n\
+                    pg = { host = 'acme-db' }\n\
+                    [production.tenants.globex]\n\
+                    pg = { host = 'globex-db' }\n";
+    let builder = || {
+        Hydroconf::builder()
+            .envvar_prefix("TENANTSTEST")
+            .env("production")
+            .with_inline(settings, FileFormat::Toml)
+            .skip_discovery()
+    };
+    let mut hydro = builder().build();
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "db-0");
+    assert_eq!(hydro.get_int("pg.port").unwrap(), 5432);
+    assert!(hydro.get_table("tenants").is_ok());
+
+    let mut hydro = builder().tenant("acme").build();
+    hydro.hydrate_in_place().unwrap();
+    assert_eq!(hydro.get_str("pg.host").unwrap(), "acme-db");
+    assert_eq!(hydro.get_int("pg.port").unwrap(), 6432);
+    assert!(hydro.get_table("tenants").is_err());
+
+    let config: Config = builder()
+        .build()
+        .hydrate_for_tenant("globex")
+        .unwrap();
+    assert_eq!(config.pg.host, "globex-db");
+    assert_eq!(config.pg.port, 5432);
+}
+
+#[test]
+fn test_settings_dirs() {
+    let hydro = |builder: HydroconfBuilder| {
+        let mut hydro = builder
+            .root_path(get_data_path("19"))
+            .envvar_prefix("SETTINGSDIRSTEST")
+            .build();
+        hydro.hydrate_in_place().unwrap();
+        hydro.get_str("host").ok()
+    };
+    assert_eq!(hydro(Hydroconf::builder()), None);
+    assert_eq!(
+        hydro(Hydroconf::builder().settings_dirs(vec![".", "deploy/config"])),
+        Some("db-0".into())
+    );
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+#[test]
+fn test_platform_config_dir() {
+    let dir = TempDir::new("platform-dir");
+    std::fs::create_dir_all(dir.join("project")).unwrap();
+    std::fs::write(dir.join("project/.hydroconf-root"), "").unwrap();
+    std::fs::create_dir_all(dir.join("xdg/myapp")).unwrap();
+    std::fs::write(
+        dir.join("xdg/myapp/settings.toml"),
+        "[default]\nhost = 'db-0'\n",
+    )
+    .unwrap();
+    env::set_var("XDG_CONFIG_HOME", dir.join("xdg"));
+    let hydro = |builder: HydroconfBuilder| {
+        let mut hydro = builder
+            .root_path(dir.join("project"))
+            .envvar_prefix("PLATFORMDIRTEST")
+            .build();
+        hydro.hydrate_in_place().unwrap();
+        hydro.get_str("host").ok()
+    };
+    assert_eq!(hydro(Hydroconf::builder()), None);
+    assert_eq!(
+        hydro(Hydroconf::builder().app_name("myapp")),
+        Some("db-0".into())
+    );
+
+    std::fs::write(
+        dir.join("project/settings.toml"),
+        "[default]\nhost = 'localhost'\n",
+    )
+    .unwrap();
+    assert_eq!(
+        hydro(Hydroconf::builder().app_name("myapp")),
+        Some("localhost".into())
+    );
+    env::remove_var("XDG_CONFIG_HOME");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_system_settings() {
+    let app = format!("hydroconf-system-{}", std::process::id());
+    let system_dir = PathBuf::from("/etc").join(&app);
+    // The test needs to write in /etc.
+    if std::fs::create_dir_all(&system_dir).is_err() {
+        return;
+    }
+    std::fs::write(
+        system_dir.join("settings.toml"),
+        "[default]\nhost = 'db-0'\nport = 5432\n",
+    )
+    .unwrap();
+    let dir = TempDir::new("system");
+    std::fs::write(dir.join("settings.toml"), "[default]\nport = 5433\n")
+        .unwrap();
+    let mut hydro = Hydroconf::builder()
+        .root_path(dir.to_path_buf())
+        .envvar_prefix("SYSTEMSETTINGSTEST")
+        .app_name(app.as_str())
+        .build();
+    let result = hydro.hydrate_in_place().map(|hydro| {
+        (hydro.get_str("host").unwrap(), hydro.get_int("port").unwrap())
+    });
+    std::fs::remove_dir_all(system_dir).unwrap();
+    assert_eq!(result.unwrap(), ("db-0".into(), 5433));
+}
+
+#[test]
+fn test_max_walk_depth() {
+    let hydro = |builder: HydroconfBuilder| {
+        let mut hydro = builder
+            .root_path(get_data_path("20").join("a/b"))
+            .envvar_prefix("WALKDEPTHTEST")
+            .build();
+        hydro.hydrate_in_place().unwrap();
+        hydro.get_str("host").ok()
+    };
+    assert_eq!(hydro(Hydroconf::builder()), Some("db-0".into()));
+    assert_eq!(
+        hydro(Hydroconf::builder().max_walk_depth(2)),
+        Some("db-0".into())
+    );
+    assert_eq!(hydro(Hydroconf::builder().max_walk_depth(1)), None);
+}
+
+#[test]
+fn test_root_paths() {
+    // Other tests set ROOT_PATH_FOR_HYDRO.
+    let mut settings =
+        HydroSettings::default().set_envvar_prefix("ROOTPATHSTEST".into());
+    settings.root_path = None;
+    let hydro = Hydroconf::new(settings);
+    assert_eq!(
+        hydro.root_paths(),
+        vec![env::current_dir().unwrap(), env::current_exe().unwrap()]
+    );
+    let hydro = Hydroconf::builder()
+        .root_path(get_data_path(""))
+        .envvar_prefix("ROOTPATHSTEST")
+        .build();
+    assert_eq!(hydro.root_paths(), vec![get_data_path("")]);
+}
+
+#[test]
+fn test_cascade_settings() {
+    let hydro = |builder: HydroconfBuilder| {
+        let mut hydro = builder
+            .root_path(get_data_path("21").join("pkg"))
+            .envvar_prefix("CASCADETEST")
+            .build();
+        hydro.hydrate_in_place().unwrap();
+        (hydro.get_str("host").ok(), hydro.get_int("port").unwrap())
+    };
+    assert_eq!(hydro(Hydroconf::builder()), (None, 5433));
+    assert_eq!(
+        hydro(Hydroconf::builder().cascade_settings(true)),
+        (Some("db-0".into()), 5433)
+    );
+}
+
+#[test]
+fn test_settings_file_glob() {
+    let dir = get_data_path("22");
+    let hydro = |pattern: &str| {
+        let mut hydro = Hydroconf::builder()
+            .settings_file(dir.join(pattern))
+            .envvar_prefix("SETTINGSGLOBTEST")
+            .build();
+        hydro.hydrate_in_place().unwrap();
+        (hydro.get_str("host").ok(), hydro.get_int("port").unwrap())
+    };
+    assert_eq!(hydro("settings.*.toml"), (Some("db-0".into()), 5432));
+    assert_eq!(hydro("conf/**/*.toml"), (None, 5434));
+}